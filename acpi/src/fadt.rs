@@ -0,0 +1,100 @@
+use super::{
+    AcpiError,
+    sdt::{
+        AddressSpace, GENERIC_ADDRESS_SIZE, GenericAddress, read_u16, read_u32, read_u64, validate,
+    },
+};
+
+/// FADT flag: the PM timer counts in 32 bits instead of 24
+pub const FADT_TMR_VAL_EXT: u32 = 1 << 8;
+/// FADT flag: `reset_reg` is supported
+pub const FADT_RESET_REG_SUP: u32 = 1 << 10;
+
+/// IA-PC boot architecture flag: an 8042 (or compatible) keyboard controller exists
+pub const BOOT_ARCH_8042: u16 = 1 << 1;
+
+/// ACPI PM timer frequency in Hz
+pub const PM_TIMER_FREQUENCY: u64 = 3_579_545;
+
+const ACPI_1_FADT_SIZE: usize = 116;
+const RESET_REG_OFFSET: usize = 116;
+const RESET_VALUE_OFFSET: usize = 128;
+const X_DSDT_OFFSET: usize = 140;
+const X_PM_TMR_BLK_OFFSET: usize = 208;
+
+/// Fixed ACPI Description Table ("FACP")
+#[derive(Clone, Copy, Debug)]
+pub struct Fadt {
+    pub revision: u8,
+    pub dsdt: u64,
+    pub sci_interrupt: u16,
+    pub iapc_boot_arch: u16,
+    pub flags: u32,
+    /// ACPI PM timer register; `None` if the platform has none
+    pub pm_timer: Option<GenericAddress>,
+    /// Reset register and the value to write to it
+    pub reset: Option<(GenericAddress, u8)>,
+}
+
+impl Fadt {
+    pub fn parse(bytes: &[u8]) -> Result<Self, AcpiError> {
+        let table = validate(bytes, b"FACP")?;
+        if table.len() < ACPI_1_FADT_SIZE {
+            return Err(AcpiError::TooShort);
+        }
+
+        let flags = read_u32(table, 112);
+        let mut dsdt = read_u32(table, 40) as u64;
+        let pm_tmr_blk = read_u32(table, 76);
+        let pm_tmr_len = table[91];
+
+        let mut pm_timer = (pm_tmr_blk != 0 && pm_tmr_len == 4).then_some(GenericAddress {
+            address_space: AddressSpace::SystemIo,
+            bit_width: 32,
+            bit_offset: 0,
+            access_size: 3,
+            address: pm_tmr_blk as u64,
+        });
+
+        let has = |offset: usize, size: usize| table.len() >= offset + size;
+        let mut reset = None;
+        if has(RESET_VALUE_OFFSET, 1) && flags & FADT_RESET_REG_SUP != 0 {
+            let reg = GenericAddress::parse(table, RESET_REG_OFFSET);
+            if !reg.is_null() {
+                reset = Some((reg, table[RESET_VALUE_OFFSET]));
+            }
+        }
+        if has(X_DSDT_OFFSET, 8) {
+            let x_dsdt = read_u64(table, X_DSDT_OFFSET);
+            if x_dsdt != 0 {
+                dsdt = x_dsdt;
+            }
+        }
+        if has(X_PM_TMR_BLK_OFFSET, GENERIC_ADDRESS_SIZE) {
+            let x_pm_timer = GenericAddress::parse(table, X_PM_TMR_BLK_OFFSET);
+            if !x_pm_timer.is_null() {
+                pm_timer = Some(x_pm_timer);
+            }
+        }
+
+        Ok(Self {
+            revision: table[8],
+            dsdt,
+            sci_interrupt: read_u16(table, 46),
+            iapc_boot_arch: read_u16(table, 109),
+            flags,
+            pm_timer,
+            reset,
+        })
+    }
+
+    /// Whether the PM timer counter is 32 bits wide (24 bits otherwise)
+    pub fn pm_timer_is_32bit(&self) -> bool {
+        self.flags & FADT_TMR_VAL_EXT != 0
+    }
+
+    /// Whether firmware reports an 8042; ACPI 1.0 tables lack the field, so assume yes
+    pub fn has_8042(&self) -> bool {
+        self.revision < 3 || self.iapc_boot_arch & BOOT_ARCH_8042 != 0
+    }
+}
@@ -0,0 +1,35 @@
+use super::{
+    AcpiError,
+    sdt::{GenericAddress, SDT_HEADER_SIZE, read_u16, read_u32, validate},
+};
+
+/// High Precision Event Timer description table ("HPET")
+#[derive(Clone, Copy, Debug)]
+pub struct Hpet {
+    pub event_timer_block_id: u32,
+    pub base_address: GenericAddress,
+    pub hpet_number: u8,
+    pub minimum_tick: u16,
+    pub page_protection: u8,
+}
+
+impl Hpet {
+    pub fn parse(bytes: &[u8]) -> Result<Self, AcpiError> {
+        let table = validate(bytes, b"HPET")?;
+        if table.len() < SDT_HEADER_SIZE + 20 {
+            return Err(AcpiError::TooShort);
+        }
+        Ok(Self {
+            event_timer_block_id: read_u32(table, 36),
+            base_address: GenericAddress::parse(table, 40),
+            hpet_number: table[52],
+            minimum_tick: read_u16(table, 53),
+            page_protection: table[55],
+        })
+    }
+
+    /// Number of comparators in this timer block
+    pub fn comparator_count(&self) -> u8 {
+        (((self.event_timer_block_id >> 8) & 0x1f) + 1) as u8
+    }
+}
@@ -0,0 +1,101 @@
+//! ACPI table discovery
+//!
+//! The parsers only look at byte slices so that they can be fed with table
+//! dumps (e.g. from `acpidump`) as well as with firmware memory. Kept free
+//! of kernel dependencies so that it also builds for the host.
+#![no_std]
+
+pub mod fadt;
+pub mod hpet;
+pub mod madt;
+pub mod mcfg;
+pub mod rsdp;
+pub mod sdt;
+
+use fadt::Fadt;
+use hpet::Hpet;
+use madt::Madt;
+use mcfg::Mcfg;
+use rsdp::{RSDP_V2_SIZE, Rsdp};
+use sdt::{RootTable, SDT_HEADER_SIZE, SdtHeader, checksum_ok};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AcpiError {
+    NoRsdp,
+    InvalidSignature,
+    InvalidChecksum,
+    TooShort,
+}
+
+/// Tables the kernel cares about, borrowed from the memory they live in
+#[derive(Clone, Copy, Debug, Default)]
+pub struct AcpiTables<'a> {
+    pub madt: Option<Madt<'a>>,
+    pub fadt: Option<Fadt>,
+    pub hpet: Option<Hpet>,
+    pub mcfg: Option<Mcfg<'a>>,
+}
+
+impl<'a> AcpiTables<'a> {
+    /// Walk the XSDT (or the RSDT for ACPI 1.0) and pick up the known tables
+    ///
+    /// `memory(addr, len)` must return the `len` bytes at physical address
+    /// `addr`. Tables with a broken header or checksum are skipped.
+    pub fn parse<F>(rsdp_addr: u64, memory: F) -> Result<Self, AcpiError>
+    where
+        F: Fn(u64, usize) -> &'a [u8],
+    {
+        if rsdp_addr == 0 {
+            return Err(AcpiError::NoRsdp);
+        }
+        let rsdp = Rsdp::parse(memory(rsdp_addr, RSDP_V2_SIZE))?;
+
+        let root = match rsdp.xsdt_address {
+            0 => RootTable::parse_rsdt(table_at(&memory, rsdp.rsdt_address as u64)?)?,
+            xsdt => match RootTable::parse_xsdt(table_at(&memory, xsdt)?) {
+                Ok(root) => root,
+                Err(_) => RootTable::parse_rsdt(table_at(&memory, rsdp.rsdt_address as u64)?)?,
+            },
+        };
+
+        let mut tables = Self::default();
+        for addr in root.iter() {
+            let Ok(bytes) = table_at(&memory, addr) else {
+                continue;
+            };
+            match &bytes[..4] {
+                b"APIC" => tables.madt = Madt::parse(bytes).ok(),
+                b"FACP" => tables.fadt = Fadt::parse(bytes).ok(),
+                b"HPET" => tables.hpet = Hpet::parse(bytes).ok(),
+                b"MCFG" => tables.mcfg = Mcfg::parse(bytes).ok(),
+                _ => {}
+            }
+        }
+        Ok(tables)
+    }
+}
+
+/// Read the header at `addr` and return the whole table it describes,
+/// after checking its length and checksum
+fn table_at<'a, F>(memory: &F, addr: u64) -> Result<&'a [u8], AcpiError>
+where
+    F: Fn(u64, usize) -> &'a [u8],
+{
+    if addr == 0 {
+        return Err(AcpiError::TooShort);
+    }
+    let header = SdtHeader::parse(memory(addr, SDT_HEADER_SIZE))?;
+    // length はファームウェアの値なので、ヘッダより短いものは捨てる
+    let length = header.length as usize;
+    if length < SDT_HEADER_SIZE {
+        return Err(AcpiError::TooShort);
+    }
+    let table = memory(addr, length);
+    if !checksum_ok(table) {
+        return Err(AcpiError::InvalidChecksum);
+    }
+    Ok(table)
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,204 @@
+use super::{
+    AcpiError,
+    sdt::{SDT_HEADER_SIZE, read_u16, read_u32, read_u64, validate},
+};
+
+/// MADT flag: the system also has dual 8259 PICs that must be masked
+pub const MADT_PCAT_COMPAT: u32 = 1 << 0;
+
+/// Multiple APIC Description Table ("APIC")
+#[derive(Clone, Copy, Debug)]
+pub struct Madt<'a> {
+    pub local_apic_address: u32,
+    pub flags: u32,
+    entries: &'a [u8],
+}
+
+/// Polarity and trigger mode bits (MPS INTI flags) of overrides and NMIs
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MpsIntiFlags(pub u16);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Polarity {
+    /// Conforms to the bus specification (active high for ISA)
+    BusDefault,
+    ActiveHigh,
+    ActiveLow,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TriggerMode {
+    /// Conforms to the bus specification (edge for ISA)
+    BusDefault,
+    Edge,
+    Level,
+}
+
+impl MpsIntiFlags {
+    pub fn polarity(&self) -> Polarity {
+        match self.0 & 0b11 {
+            1 => Polarity::ActiveHigh,
+            3 => Polarity::ActiveLow,
+            _ => Polarity::BusDefault,
+        }
+    }
+
+    pub fn trigger_mode(&self) -> TriggerMode {
+        match (self.0 >> 2) & 0b11 {
+            1 => TriggerMode::Edge,
+            3 => TriggerMode::Level,
+            _ => TriggerMode::BusDefault,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MadtEntry {
+    LocalApic {
+        processor_uid: u8,
+        apic_id: u8,
+        flags: u32,
+    },
+    IoApic {
+        id: u8,
+        address: u32,
+        gsi_base: u32,
+    },
+    InterruptSourceOverride {
+        bus: u8,
+        source: u8,
+        gsi: u32,
+        flags: MpsIntiFlags,
+    },
+    NmiSource {
+        flags: MpsIntiFlags,
+        gsi: u32,
+    },
+    LocalApicNmi {
+        processor_uid: u8,
+        flags: MpsIntiFlags,
+        lint: u8,
+    },
+    LocalApicAddressOverride {
+        address: u64,
+    },
+    LocalX2Apic {
+        x2apic_id: u32,
+        flags: u32,
+        processor_uid: u32,
+    },
+    Unknown {
+        entry_type: u8,
+    },
+}
+
+/// Local APIC flag: the processor is ready to use
+pub const LOCAL_APIC_ENABLED: u32 = 1 << 0;
+
+impl<'a> Madt<'a> {
+    pub fn parse(bytes: &'a [u8]) -> Result<Self, AcpiError> {
+        let table = validate(bytes, b"APIC")?;
+        if table.len() < SDT_HEADER_SIZE + 8 {
+            return Err(AcpiError::TooShort);
+        }
+        Ok(Self {
+            local_apic_address: read_u32(table, SDT_HEADER_SIZE),
+            flags: read_u32(table, SDT_HEADER_SIZE + 4),
+            entries: &table[SDT_HEADER_SIZE + 8..],
+        })
+    }
+
+    pub fn has_8259(&self) -> bool {
+        self.flags & MADT_PCAT_COMPAT != 0
+    }
+
+    /// Local APIC MMIO base, honouring a 64-bit address override entry
+    pub fn local_apic_base(&self) -> u64 {
+        self.entries()
+            .find_map(|entry| match entry {
+                MadtEntry::LocalApicAddressOverride { address } => Some(address),
+                _ => None,
+            })
+            .unwrap_or(self.local_apic_address as u64)
+    }
+
+    pub fn entries(&self) -> MadtEntries<'a> {
+        MadtEntries {
+            bytes: self.entries,
+        }
+    }
+
+    /// The override for ISA `irq`, if the firmware rerouted it
+    pub fn isa_override(&self, irq: u8) -> Option<(u32, MpsIntiFlags)> {
+        self.entries().find_map(|entry| match entry {
+            MadtEntry::InterruptSourceOverride {
+                bus: 0,
+                source,
+                gsi,
+                flags,
+            } if source == irq => Some((gsi, flags)),
+            _ => None,
+        })
+    }
+}
+
+pub struct MadtEntries<'a> {
+    bytes: &'a [u8],
+}
+
+impl Iterator for MadtEntries<'_> {
+    type Item = MadtEntry;
+
+    fn next(&mut self) -> Option<MadtEntry> {
+        if self.bytes.len() < 2 {
+            return None;
+        }
+        let entry_type = self.bytes[0];
+        let length = self.bytes[1] as usize;
+        if length < 2 || self.bytes.len() < length {
+            // 壊れたエントリ以降は信用できないので打ち切る
+            self.bytes = &[];
+            return None;
+        }
+        let e = &self.bytes[..length];
+        self.bytes = &self.bytes[length..];
+
+        let entry = match (entry_type, length) {
+            (0, 8..) => MadtEntry::LocalApic {
+                processor_uid: e[2],
+                apic_id: e[3],
+                flags: read_u32(e, 4),
+            },
+            (1, 12..) => MadtEntry::IoApic {
+                id: e[2],
+                address: read_u32(e, 4),
+                gsi_base: read_u32(e, 8),
+            },
+            (2, 10..) => MadtEntry::InterruptSourceOverride {
+                bus: e[2],
+                source: e[3],
+                gsi: read_u32(e, 4),
+                flags: MpsIntiFlags(read_u16(e, 8)),
+            },
+            (3, 8..) => MadtEntry::NmiSource {
+                flags: MpsIntiFlags(read_u16(e, 2)),
+                gsi: read_u32(e, 4),
+            },
+            (4, 6..) => MadtEntry::LocalApicNmi {
+                processor_uid: e[2],
+                flags: MpsIntiFlags(read_u16(e, 3)),
+                lint: e[5],
+            },
+            (5, 12..) => MadtEntry::LocalApicAddressOverride {
+                address: read_u64(e, 4),
+            },
+            (9, 16..) => MadtEntry::LocalX2Apic {
+                x2apic_id: read_u32(e, 4),
+                flags: read_u32(e, 8),
+                processor_uid: read_u32(e, 12),
+            },
+            _ => MadtEntry::Unknown { entry_type },
+        };
+        Some(entry)
+    }
+}
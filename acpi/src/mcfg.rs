@@ -0,0 +1,63 @@
+use super::{
+    AcpiError,
+    sdt::{SDT_HEADER_SIZE, read_u16, read_u64, validate},
+};
+
+const MCFG_ENTRIES_OFFSET: usize = SDT_HEADER_SIZE + 8;
+const MCFG_ENTRY_SIZE: usize = 16;
+
+/// PCI Express memory mapped configuration space table ("MCFG")
+#[derive(Clone, Copy, Debug)]
+pub struct Mcfg<'a> {
+    entries: &'a [u8],
+}
+
+/// One ECAM region covering `start_bus..=end_bus` of a PCI segment group
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct EcamRegion {
+    pub base_address: u64,
+    pub segment_group: u16,
+    pub start_bus: u8,
+    pub end_bus: u8,
+}
+
+impl EcamRegion {
+    /// Physical address of the 4 KiB configuration space of a function
+    pub fn config_address(&self, bus: u8, device: u8, function: u8) -> Option<u64> {
+        if bus < self.start_bus || bus > self.end_bus || device >= 32 || function >= 8 {
+            return None;
+        }
+        let offset =
+            ((bus - self.start_bus) as u64) << 20 | (device as u64) << 15 | (function as u64) << 12;
+        Some(self.base_address + offset)
+    }
+}
+
+impl<'a> Mcfg<'a> {
+    pub fn parse(bytes: &'a [u8]) -> Result<Self, AcpiError> {
+        let table = validate(bytes, b"MCFG")?;
+        if table.len() < MCFG_ENTRIES_OFFSET {
+            return Err(AcpiError::TooShort);
+        }
+        Ok(Self {
+            entries: &table[MCFG_ENTRIES_OFFSET..],
+        })
+    }
+
+    pub fn regions(&self) -> impl Iterator<Item = EcamRegion> + 'a {
+        self.entries
+            .chunks_exact(MCFG_ENTRY_SIZE)
+            .map(|e| EcamRegion {
+                base_address: read_u64(e, 0),
+                segment_group: read_u16(e, 8),
+                start_bus: e[10],
+                end_bus: e[11],
+            })
+    }
+
+    /// The region that covers `bus` on segment group 0
+    pub fn region_for_bus(&self, bus: u8) -> Option<EcamRegion> {
+        self.regions()
+            .find(|r| r.segment_group == 0 && r.start_bus <= bus && bus <= r.end_bus)
+    }
+}
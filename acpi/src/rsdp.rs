@@ -0,0 +1,53 @@
+use super::{
+    AcpiError,
+    sdt::{checksum_ok, read_array, read_u32, read_u64},
+};
+
+pub const RSDP_V1_SIZE: usize = 20;
+pub const RSDP_V2_SIZE: usize = 36;
+
+/// Root System Description Pointer
+#[derive(Clone, Copy, Debug)]
+pub struct Rsdp {
+    pub oem_id: [u8; 6],
+    pub revision: u8,
+    pub rsdt_address: u32,
+    /// `0` for ACPI 1.0 RSDPs
+    pub xsdt_address: u64,
+}
+
+impl Rsdp {
+    pub fn parse(bytes: &[u8]) -> Result<Self, AcpiError> {
+        if bytes.len() < RSDP_V1_SIZE {
+            return Err(AcpiError::TooShort);
+        }
+        if &bytes[..8] != b"RSD PTR " {
+            return Err(AcpiError::InvalidSignature);
+        }
+        if !checksum_ok(&bytes[..RSDP_V1_SIZE]) {
+            return Err(AcpiError::InvalidChecksum);
+        }
+
+        let revision = bytes[15];
+        let mut rsdp = Self {
+            oem_id: read_array(bytes, 9),
+            revision,
+            rsdt_address: read_u32(bytes, 16),
+            xsdt_address: 0,
+        };
+        if revision >= 2 {
+            if bytes.len() < RSDP_V2_SIZE {
+                return Err(AcpiError::TooShort);
+            }
+            let length = read_u32(bytes, 20) as usize;
+            if length < RSDP_V2_SIZE || bytes.len() < length {
+                return Err(AcpiError::TooShort);
+            }
+            if !checksum_ok(&bytes[..length]) {
+                return Err(AcpiError::InvalidChecksum);
+            }
+            rsdp.xsdt_address = read_u64(bytes, 24);
+        }
+        Ok(rsdp)
+    }
+}
@@ -0,0 +1,155 @@
+use super::AcpiError;
+
+pub const SDT_HEADER_SIZE: usize = 36;
+
+/// Common header shared by every System Description Table
+#[derive(Clone, Copy, Debug)]
+pub struct SdtHeader {
+    pub signature: [u8; 4],
+    pub length: u32,
+    pub revision: u8,
+    pub oem_id: [u8; 6],
+    pub oem_table_id: [u8; 8],
+    pub oem_revision: u32,
+}
+
+impl SdtHeader {
+    pub fn parse(bytes: &[u8]) -> Result<Self, AcpiError> {
+        if bytes.len() < SDT_HEADER_SIZE {
+            return Err(AcpiError::TooShort);
+        }
+        Ok(Self {
+            signature: read_array(bytes, 0),
+            length: read_u32(bytes, 4),
+            revision: bytes[8],
+            oem_id: read_array(bytes, 10),
+            oem_table_id: read_array(bytes, 16),
+            oem_revision: read_u32(bytes, 24),
+        })
+    }
+}
+
+/// Validate the header of `bytes` and return the table body trimmed to its `length`
+pub fn validate<'a>(bytes: &'a [u8], signature: &[u8; 4]) -> Result<&'a [u8], AcpiError> {
+    let header = SdtHeader::parse(bytes)?;
+    if &header.signature != signature {
+        return Err(AcpiError::InvalidSignature);
+    }
+    let length = header.length as usize;
+    if length < SDT_HEADER_SIZE || bytes.len() < length {
+        return Err(AcpiError::TooShort);
+    }
+    let table = &bytes[..length];
+    if !checksum_ok(table) {
+        return Err(AcpiError::InvalidChecksum);
+    }
+    Ok(table)
+}
+
+/// ACPI checksums are valid when all bytes sum to zero (mod 256)
+pub fn checksum_ok(bytes: &[u8]) -> bool {
+    bytes.iter().fold(0u8, |sum, b| sum.wrapping_add(*b)) == 0
+}
+
+/// Entries of an RSDT (32-bit pointers) or XSDT (64-bit pointers)
+#[derive(Clone, Copy, Debug)]
+pub struct RootTable<'a> {
+    entries: &'a [u8],
+    entry_size: usize,
+}
+
+impl<'a> RootTable<'a> {
+    pub fn parse_xsdt(bytes: &'a [u8]) -> Result<Self, AcpiError> {
+        let table = validate(bytes, b"XSDT")?;
+        Ok(Self {
+            entries: &table[SDT_HEADER_SIZE..],
+            entry_size: 8,
+        })
+    }
+
+    pub fn parse_rsdt(bytes: &'a [u8]) -> Result<Self, AcpiError> {
+        let table = validate(bytes, b"RSDT")?;
+        Ok(Self {
+            entries: &table[SDT_HEADER_SIZE..],
+            entry_size: 4,
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len() / self.entry_size
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Physical addresses of the tables listed in this root table
+    pub fn iter(&self) -> impl Iterator<Item = u64> + 'a {
+        let entry_size = self.entry_size;
+        self.entries
+            .chunks_exact(entry_size)
+            .map(move |entry| match entry_size {
+                8 => read_u64(entry, 0),
+                _ => read_u32(entry, 0) as u64,
+            })
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AddressSpace {
+    SystemMemory,
+    SystemIo,
+    PciConfig,
+    Other(u8),
+}
+
+/// Generic Address Structure (GAS)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct GenericAddress {
+    pub address_space: AddressSpace,
+    pub bit_width: u8,
+    pub bit_offset: u8,
+    pub access_size: u8,
+    pub address: u64,
+}
+
+pub const GENERIC_ADDRESS_SIZE: usize = 12;
+
+impl GenericAddress {
+    pub fn parse(bytes: &[u8], offset: usize) -> Self {
+        Self {
+            address_space: match bytes[offset] {
+                0 => AddressSpace::SystemMemory,
+                1 => AddressSpace::SystemIo,
+                2 => AddressSpace::PciConfig,
+                other => AddressSpace::Other(other),
+            },
+            bit_width: bytes[offset + 1],
+            bit_offset: bytes[offset + 2],
+            access_size: bytes[offset + 3],
+            address: read_u64(bytes, offset + 4),
+        }
+    }
+
+    pub fn is_null(&self) -> bool {
+        self.address == 0
+    }
+}
+
+pub(super) fn read_u16(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes(read_array(bytes, offset))
+}
+
+pub(super) fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(read_array(bytes, offset))
+}
+
+pub(super) fn read_u64(bytes: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(read_array(bytes, offset))
+}
+
+pub(super) fn read_array<const N: usize>(bytes: &[u8], offset: usize) -> [u8; N] {
+    let mut buf = [0u8; N];
+    buf.copy_from_slice(&bytes[offset..offset + N]);
+    buf
+}
@@ -0,0 +1,306 @@
+//! Tests over tables captured from a Firecracker microVM
+//!
+//! `testdata/firecracker/*.dat` are the APIC, FACP and MCFG tables read from
+//! `/sys/firmware/acpi/tables` inside the guest. The RSDP and XSDT that point
+//! at them are rebuilt here with the addresses the guest kernel logged, and
+//! the HPET is synthetic since Firecracker does not provide one.
+
+extern crate std;
+
+use std::{vec, vec::Vec};
+
+use super::*;
+use fadt::PM_TIMER_FREQUENCY;
+use madt::{MadtEntry, Polarity, TriggerMode};
+use mcfg::EcamRegion;
+use sdt::AddressSpace;
+
+const APIC: &[u8] = include_bytes!("../testdata/firecracker/apic.dat");
+const FACP: &[u8] = include_bytes!("../testdata/firecracker/facp.dat");
+const MCFG: &[u8] = include_bytes!("../testdata/firecracker/mcfg.dat");
+
+const RSDP_ADDR: u64 = 0xe0000;
+const XSDT_ADDR: u64 = 0xa0e13;
+const FACP_ADDR: u64 = 0xa0c83;
+const APIC_ADDR: u64 = 0xa0d97;
+const MCFG_ADDR: u64 = 0xa0dd7;
+const HPET_ADDR: u64 = 0xa0f00;
+const RSDT_ADDR: u64 = 0xa1000;
+
+/// Fill in the checksum byte at `offset` so that `bytes` sums to zero
+fn fix_checksum(bytes: &mut [u8], offset: usize) {
+    bytes[offset] = 0;
+    let sum = bytes.iter().fold(0u8, |sum, b| sum.wrapping_add(*b));
+    bytes[offset] = sum.wrapping_neg();
+}
+
+/// An SDT with a valid header around `body`
+fn sdt(signature: &[u8; 4], revision: u8, body: &[u8]) -> Vec<u8> {
+    let mut table = vec![0u8; SDT_HEADER_SIZE];
+    table[..4].copy_from_slice(signature);
+    table[4..8].copy_from_slice(&((SDT_HEADER_SIZE + body.len()) as u32).to_le_bytes());
+    table[8] = revision;
+    table[10..16].copy_from_slice(b"FIRECK");
+    table[16..24].copy_from_slice(b"TESTTABL");
+    table.extend_from_slice(body);
+    fix_checksum(&mut table, 9);
+    table
+}
+
+fn rsdp(revision: u8, rsdt: u32, xsdt: u64) -> Vec<u8> {
+    let mut rsdp = vec![0u8; RSDP_V2_SIZE];
+    rsdp[..8].copy_from_slice(b"RSD PTR ");
+    rsdp[9..15].copy_from_slice(b"FIRECK");
+    rsdp[15] = revision;
+    rsdp[16..20].copy_from_slice(&rsdt.to_le_bytes());
+    rsdp[20..24].copy_from_slice(&(RSDP_V2_SIZE as u32).to_le_bytes());
+    rsdp[24..32].copy_from_slice(&xsdt.to_le_bytes());
+    fix_checksum(&mut rsdp[..20], 8);
+    fix_checksum(&mut rsdp, 32);
+    rsdp
+}
+
+fn hpet() -> Vec<u8> {
+    let mut body = vec![0u8; 20];
+    // 8086 製、コンパレータ 3 個、64 ビットカウンタ
+    body[..4].copy_from_slice(&0x8086_a201u32.to_le_bytes());
+    body[4] = 0; // SystemMemory
+    body[5] = 64;
+    body[8..16].copy_from_slice(&0xfed0_0000u64.to_le_bytes());
+    body[17..19].copy_from_slice(&0x80u16.to_le_bytes());
+    sdt(b"HPET", 1, &body)
+}
+
+/// Physical memory below 1 MiB with the tables placed where the guest had them
+struct Memory(Vec<u8>);
+
+impl Memory {
+    fn new() -> Self {
+        Self(vec![0u8; 0x100000])
+    }
+
+    fn put(&mut self, addr: u64, bytes: &[u8]) {
+        let addr = addr as usize;
+        self.0[addr..addr + bytes.len()].copy_from_slice(bytes);
+    }
+
+    fn read(&self, addr: u64, len: usize) -> &[u8] {
+        &self.0[addr as usize..addr as usize + len]
+    }
+
+    fn firecracker() -> Self {
+        let mut memory = Self::new();
+        let entries: Vec<u8> = [FACP_ADDR, APIC_ADDR, MCFG_ADDR, HPET_ADDR]
+            .iter()
+            .flat_map(|addr| addr.to_le_bytes())
+            .collect();
+        memory.put(RSDP_ADDR, &rsdp(2, 0, XSDT_ADDR));
+        memory.put(XSDT_ADDR, &sdt(b"XSDT", 1, &entries));
+        memory.put(FACP_ADDR, FACP);
+        memory.put(APIC_ADDR, APIC);
+        memory.put(MCFG_ADDR, MCFG);
+        memory.put(HPET_ADDR, &hpet());
+        memory
+    }
+}
+
+#[test]
+fn captured_tables_have_valid_checksums() {
+    for table in [APIC, FACP, MCFG] {
+        assert!(checksum_ok(table));
+        assert_eq!(
+            SdtHeader::parse(table).unwrap().length as usize,
+            table.len()
+        );
+    }
+}
+
+#[test]
+fn madt_from_firecracker() {
+    let madt = Madt::parse(APIC).unwrap();
+    assert_eq!(madt.local_apic_address, 0xfee0_0000);
+    assert_eq!(madt.local_apic_base(), 0xfee0_0000);
+    assert!(!madt.has_8259());
+    let entries: Vec<_> = madt.entries().collect();
+    assert_eq!(
+        entries,
+        [
+            MadtEntry::IoApic {
+                id: 0,
+                address: 0xfec0_0000,
+                gsi_base: 0,
+            },
+            MadtEntry::LocalApic {
+                processor_uid: 0,
+                apic_id: 0,
+                flags: madt::LOCAL_APIC_ENABLED,
+            },
+        ]
+    );
+    assert_eq!(madt.isa_override(0), None);
+}
+
+#[test]
+fn madt_interrupt_source_override() {
+    let mut body = vec![0u8; 8];
+    body[..4].copy_from_slice(&0xfee0_0000u32.to_le_bytes());
+    // IRQ0 -> GSI2 (エッジ・アクティブハイ), IRQ9 -> GSI9 (レベル・アクティブロー)
+    body.extend_from_slice(&[2, 10, 0, 0, 2, 0, 0, 0, 0b0101, 0]);
+    body.extend_from_slice(&[2, 10, 0, 9, 9, 0, 0, 0, 0b1111, 0]);
+    let table = sdt(b"APIC", 4, &body);
+    let madt = Madt::parse(&table).unwrap();
+
+    let (gsi, flags) = madt.isa_override(0).unwrap();
+    assert_eq!(gsi, 2);
+    assert_eq!(flags.polarity(), Polarity::ActiveHigh);
+    assert_eq!(flags.trigger_mode(), TriggerMode::Edge);
+    let (gsi, flags) = madt.isa_override(9).unwrap();
+    assert_eq!(gsi, 9);
+    assert_eq!(flags.polarity(), Polarity::ActiveLow);
+    assert_eq!(flags.trigger_mode(), TriggerMode::Level);
+    assert_eq!(madt.isa_override(1), None);
+}
+
+#[test]
+fn madt_stops_at_a_truncated_entry() {
+    let mut body = vec![0u8; 8];
+    body.extend_from_slice(&[0, 8, 0, 0, 1, 0, 0, 0]);
+    body.extend_from_slice(&[1, 12, 0, 0]);
+    let table = sdt(b"APIC", 4, &body);
+    let madt = Madt::parse(&table).unwrap();
+    assert_eq!(madt.entries().count(), 1);
+}
+
+#[test]
+fn fadt_from_firecracker() {
+    let fadt = Fadt::parse(FACP).unwrap();
+    assert_eq!(fadt.revision, 6);
+    assert_eq!(fadt.dsdt, 0x9fd30);
+    assert_eq!(fadt.sci_interrupt, 0);
+    assert_eq!(fadt.flags, 0x0010_0030);
+    assert_eq!(fadt.iapc_boot_arch, 0x0004);
+    // Firecracker には 8042 も PM タイマーもリセットレジスタもない
+    assert!(!fadt.has_8042());
+    assert!(fadt.pm_timer.is_none());
+    assert!(fadt.reset.is_none());
+}
+
+#[test]
+fn fadt_acpi_1_uses_legacy_fields() {
+    let mut body = vec![0u8; 116 - SDT_HEADER_SIZE];
+    body[40 - SDT_HEADER_SIZE..44 - SDT_HEADER_SIZE].copy_from_slice(&0x1234u32.to_le_bytes());
+    body[76 - SDT_HEADER_SIZE..80 - SDT_HEADER_SIZE].copy_from_slice(&0x608u32.to_le_bytes());
+    body[91 - SDT_HEADER_SIZE] = 4;
+    let table = sdt(b"FACP", 1, &body);
+    let fadt = Fadt::parse(&table).unwrap();
+    assert_eq!(fadt.dsdt, 0x1234);
+    assert!(fadt.has_8042());
+    assert!(!fadt.pm_timer_is_32bit());
+    let pm_timer = fadt.pm_timer.unwrap();
+    assert_eq!(pm_timer.address_space, AddressSpace::SystemIo);
+    assert_eq!(pm_timer.address, 0x608);
+    assert!(fadt.reset.is_none());
+    assert_eq!(PM_TIMER_FREQUENCY, 3_579_545);
+}
+
+#[test]
+fn mcfg_from_firecracker() {
+    let mcfg = Mcfg::parse(MCFG).unwrap();
+    let region = EcamRegion {
+        base_address: 0xeec0_0000,
+        segment_group: 0,
+        start_bus: 0,
+        end_bus: 0,
+    };
+    assert_eq!(mcfg.regions().collect::<Vec<_>>(), [region]);
+    assert_eq!(mcfg.region_for_bus(0), Some(region));
+    assert_eq!(mcfg.region_for_bus(1), None);
+    assert_eq!(
+        region.config_address(0, 3, 1),
+        Some(0xeec0_0000 | 3 << 15 | 1 << 12)
+    );
+    assert_eq!(region.config_address(1, 0, 0), None);
+    assert_eq!(region.config_address(0, 32, 0), None);
+}
+
+#[test]
+fn parsers_reject_other_signatures_and_bad_checksums() {
+    assert_eq!(Madt::parse(FACP).unwrap_err(), AcpiError::InvalidSignature);
+    assert_eq!(Fadt::parse(&FACP[..100]).unwrap_err(), AcpiError::TooShort);
+    let mut broken = MCFG.to_vec();
+    broken[44] ^= 1;
+    assert_eq!(
+        Mcfg::parse(&broken).unwrap_err(),
+        AcpiError::InvalidChecksum
+    );
+}
+
+#[test]
+fn rsdp_revisions() {
+    let v2 = Rsdp::parse(&rsdp(2, 0x1000, 0x2000)).unwrap();
+    assert_eq!(v2.rsdt_address, 0x1000);
+    assert_eq!(v2.xsdt_address, 0x2000);
+    let v1 = Rsdp::parse(&rsdp(0, 0x1000, 0x2000)[..20]).unwrap();
+    assert_eq!(v1.xsdt_address, 0);
+
+    let mut bad = rsdp(2, 0x1000, 0x2000);
+    bad[0] = b'X';
+    assert_eq!(Rsdp::parse(&bad).unwrap_err(), AcpiError::InvalidSignature);
+    let mut bad = rsdp(2, 0x1000, 0x2000);
+    bad[30] ^= 1;
+    assert_eq!(Rsdp::parse(&bad).unwrap_err(), AcpiError::InvalidChecksum);
+}
+
+#[test]
+fn parse_walks_the_xsdt() {
+    let memory = Memory::firecracker();
+    let tables = AcpiTables::parse(RSDP_ADDR, |addr, len| memory.read(addr, len)).unwrap();
+    assert_eq!(tables.madt.unwrap().local_apic_address, 0xfee0_0000);
+    assert_eq!(tables.fadt.unwrap().dsdt, 0x9fd30);
+    assert_eq!(tables.mcfg.unwrap().regions().count(), 1);
+    let hpet = tables.hpet.unwrap();
+    assert_eq!(hpet.base_address.address, 0xfed0_0000);
+    assert_eq!(hpet.comparator_count(), 3);
+    assert_eq!(hpet.minimum_tick, 0x80);
+}
+
+#[test]
+fn parse_without_rsdp() {
+    let memory = Memory::new();
+    let result = AcpiTables::parse(0, |addr, len| memory.read(addr, len));
+    assert_eq!(result.unwrap_err(), AcpiError::NoRsdp);
+}
+
+#[test]
+fn parse_falls_back_to_the_rsdt() {
+    let mut memory = Memory::firecracker();
+    let entries: Vec<u8> = [APIC_ADDR as u32, MCFG_ADDR as u32]
+        .iter()
+        .flat_map(|addr| addr.to_le_bytes())
+        .collect();
+    memory.put(RSDT_ADDR, &sdt(b"RSDT", 1, &entries));
+    memory.put(RSDP_ADDR, &rsdp(2, RSDT_ADDR as u32, XSDT_ADDR));
+    // XSDT のシグネチャが違えば RSDT が使われる
+    let xsdt = XSDT_ADDR as usize;
+    memory.0[xsdt] = b'Y';
+    fix_checksum(&mut memory.0[xsdt..xsdt + SDT_HEADER_SIZE + 32], 9);
+    let tables = AcpiTables::parse(RSDP_ADDR, |addr, len| memory.read(addr, len)).unwrap();
+    assert!(tables.madt.is_some());
+    assert!(tables.mcfg.is_some());
+    assert!(tables.fadt.is_none());
+    assert!(tables.hpet.is_none());
+}
+
+#[test]
+fn parse_skips_broken_tables() {
+    let mut memory = Memory::firecracker();
+    // header.length がヘッダより短い表は読まずに捨てる
+    memory.0[APIC_ADDR as usize + 4..APIC_ADDR as usize + 8].copy_from_slice(&8u32.to_le_bytes());
+    // チェックサムの合わない表も捨てる
+    memory.0[MCFG_ADDR as usize + 44] ^= 1;
+    let tables = AcpiTables::parse(RSDP_ADDR, |addr, len| memory.read(addr, len)).unwrap();
+    assert!(tables.madt.is_none());
+    assert!(tables.mcfg.is_none());
+    assert!(tables.fadt.is_some());
+    assert!(tables.hpet.is_some());
+}
@@ -0,0 +1,44 @@
+//! Copy files, or standard input if none are given, to standard output
+
+#![no_std]
+#![no_main]
+
+use userland::{
+    Result, entry, eprintln,
+    fs::File,
+    io::{self, Fd},
+    syscall,
+};
+
+entry!(main);
+
+fn copy(fd: Fd) -> Result<()> {
+    let mut buf = [0; 512];
+    loop {
+        match syscall::read(fd, &mut buf)? {
+            0 => return Ok(()),
+            len => io::write_all(io::STDOUT, &buf[..len])?,
+        }
+    }
+}
+
+fn main() -> i32 {
+    let mut status = 0;
+    let mut paths = userland::env::args().skip(1).peekable();
+    if paths.peek().is_none() {
+        return match copy(io::STDIN) {
+            Ok(()) => 0,
+            Err(error) => {
+                eprintln!("cat: {:?}", error);
+                1
+            }
+        };
+    }
+    for path in paths {
+        if let Err(error) = File::open(path).and_then(|file| copy(file.fd())) {
+            eprintln!("cat: {}: {:?}", path, error);
+            status = 1;
+        }
+    }
+    status
+}
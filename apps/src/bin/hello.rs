@@ -0,0 +1,15 @@
+//! Print a greeting and the arguments
+
+#![no_std]
+#![no_main]
+
+use userland::{entry, env, println};
+
+entry!(main);
+
+fn main() {
+    println!("Hello, world!");
+    for (i, arg) in env::args().enumerate().skip(1) {
+        println!("argv[{}] = {}", i, arg);
+    }
+}
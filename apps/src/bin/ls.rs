@@ -0,0 +1,46 @@
+//! List directory entries; directories are shown with a trailing `/`
+
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+use alloc::{vec, vec::Vec};
+
+use userland::{Result, entry, env, eprintln, fs, println, syscall::DirEntry};
+
+entry!(main);
+
+fn list(path: &str) -> Result<()> {
+    for entry in fs::read_dir(path)? {
+        let entry = entry?;
+        let suffix = if entry.kind == DirEntry::DIRECTORY {
+            "/"
+        } else {
+            ""
+        };
+        println!("{}{}", entry.name(), suffix);
+    }
+    Ok(())
+}
+
+fn main() -> i32 {
+    let mut paths: Vec<&str> = env::args().skip(1).collect();
+    if paths.is_empty() {
+        paths = vec!["/"];
+    }
+    let mut status = 0;
+    for (i, path) in paths.iter().enumerate() {
+        if paths.len() > 1 {
+            if i > 0 {
+                println!();
+            }
+            println!("{}:", path);
+        }
+        if let Err(error) = list(path) {
+            eprintln!("ls: {}: {:?}", path, error);
+            status = 1;
+        }
+    }
+    status
+}
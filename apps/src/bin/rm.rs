@@ -0,0 +1,24 @@
+//! Remove files and empty directories
+
+#![no_std]
+#![no_main]
+
+use userland::{entry, env, eprintln, fs};
+
+entry!(main);
+
+fn main() -> i32 {
+    let mut status = 0;
+    let mut paths = env::args().skip(1).peekable();
+    if paths.peek().is_none() {
+        eprintln!("usage: rm path...");
+        return 1;
+    }
+    for path in paths {
+        if let Err(error) = fs::remove(path) {
+            eprintln!("rm: {}: {:?}", path, error);
+            status = 1;
+        }
+    }
+    status
+}
@@ -0,0 +1,56 @@
+//! Open a window, draw into it and echo typed keys until it is closed
+
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+use alloc::string::String;
+
+use userland::{
+    Result, entry,
+    syscall::{Event, get_time},
+    window::{self, Window},
+};
+
+entry!(main);
+
+const WIDTH: u32 = 240;
+const HEIGHT: u32 = 120;
+const BACKGROUND: u32 = 0xffffff;
+const TEXT: u32 = 0x000000;
+const CHAR_WIDTH: u32 = 8;
+
+fn main() -> Result<()> {
+    let window = Window::open(WIDTH, HEIGHT, 80, 80, "window")?;
+    window.fill_rect(0, 0, WIDTH, HEIGHT, BACKGROUND)?;
+    window.fill_rect(8, 8, 32, 32, 0xff0000)?;
+    window.fill_rect(48, 8, 32, 32, 0x00ff00)?;
+    window.fill_rect(88, 8, 32, 32, 0x0000ff)?;
+    window.write_string(8, 48, TEXT, "Type something:")?;
+
+    let started = get_time();
+    let mut typed = String::new();
+    loop {
+        let event = window::read_event()?;
+        match event.kind {
+            Event::CLOSE if event.window as u64 == window.id() => break,
+            Event::KEY => {
+                match event.value as u8 {
+                    b'\x08' => {
+                        typed.pop();
+                    }
+                    byte @ b' '..=b'~' if (typed.len() as u32 + 1) * CHAR_WIDTH < WIDTH - 16 => {
+                        typed.push(byte as char)
+                    }
+                    _ => continue,
+                }
+                window.fill_rect(8, 68, WIDTH - 16, 16, BACKGROUND)?;
+                window.write_string(8, 68, TEXT, &typed)?;
+            }
+            _ => {}
+        }
+    }
+    userland::println!("window was open for {} ms", get_time() - started);
+    Ok(())
+}
@@ -6,11 +6,13 @@
 extern crate alloc;
 
 use alloc::format;
+use common::boot_info::{BootInfo, PixelFormat};
+use common::elf::Elf;
 use core::ptr::null;
 use core::{arch::asm, panic::PanicInfo};
 use uefi::allocator::init_allocator;
 use uefi::status::EfiStatus;
-use uefi::system_table::EfiSystemTable;
+use uefi::system_table::{EfiConfigurationTableKind, EfiSystemTable};
 
 mod uefi;
 
@@ -20,19 +22,20 @@ mod utils;
 use uefi::{
     boot_services::EfiBootServices,
     file_systems::{
-        EFI_OPEN_PROTOCOL_BY_HANDLE_PROTOCOL, EfiFileProtocol, EfiLoadedImageProtocol,
-        EfiSimpleFileSystemProtocol,
+        EFI_OPEN_PROTOCOL_BY_HANDLE_PROTOCOL, EfiDevicePathProtocol, EfiFileProtocol,
+        EfiLoadedImageProtocol, EfiSimpleFileSystemProtocol,
+    },
+    guids::{
+        EFI_DEVICE_PATH_PROTOCOL_GUID, EFI_LOADED_IMAGE_PROTOCOL_GUID,
+        EFI_SIMPLE_FILE_SYSTEM_PROTOCOL_GUID,
     },
-    guids::{EFI_LOADED_IMAGE_PROTOCOL_GUID, EFI_SIMPLE_FILE_SYSTEM_PROTOCOL_GUID},
     types::{EfiAllocateType, EfiHandle, EfiMemoryType},
 };
 use utils::print::setup_console;
 
 use crate::uefi::graphics::EfiGraphicsOutputProtocol;
 use crate::uefi::guids::EFI_GRAPHICS_OUTPUT_PROTOCOL_GUID;
-use crate::uefi::types::EfiLocateSearchType;
-
-const KERNEL_BASE_ADDR: u64 = 0x0010_0000;
+use crate::uefi::types::{EfiGraphicsPixelFormat, EfiLocateSearchType};
 
 /// Wrapper for memory map buffer and metadata
 struct MemoryMap<'a> {
@@ -159,8 +162,59 @@ fn open_root_dir(
     fs.open_volume()
 }
 
-type KernelMainT = unsafe extern "sysv64" fn(*mut u64, u64);
-/// Load kernel binary and return its entry point function pointer
+/// Unique GUID of the GPT partition this image was loaded from
+fn boot_partition_guid(image_handle: EfiHandle, bs: &EfiBootServices) -> Option<[u8; 16]> {
+    let null_handle = EfiHandle(core::ptr::null_mut());
+
+    let loaded = bs
+        .open_protocol::<EfiLoadedImageProtocol>(
+            image_handle,
+            &EFI_LOADED_IMAGE_PROTOCOL_GUID,
+            image_handle,
+            null_handle,
+            EFI_OPEN_PROTOCOL_BY_HANDLE_PROTOCOL,
+        )
+        .ok()?;
+    let loaded = unsafe { loaded.as_ref()? };
+
+    let path = bs
+        .open_protocol::<EfiDevicePathProtocol>(
+            loaded.device_handle,
+            &EFI_DEVICE_PATH_PROTOCOL_GUID,
+            image_handle,
+            null_handle,
+            EFI_OPEN_PROTOCOL_BY_HANDLE_PROTOCOL,
+        )
+        .ok()?;
+    unsafe { path.as_ref()? }.partition_guid()
+}
+
+/// Walk the UEFI configuration tables and fill in the pointers the kernel needs
+fn collect_config_tables(system_table: &EfiSystemTable, boot_info: &mut BootInfo) {
+    let mut acpi10 = 0;
+    for table in system_table.config_tables() {
+        let kind = table.kind();
+        let addr = table.vendor_table as u64;
+        match kind {
+            EfiConfigurationTableKind::Unknown => {
+                uefi_println!("Config table: {:?} at {:#x}", table.vendor_guid, addr);
+                continue;
+            }
+            EfiConfigurationTableKind::Acpi10 => acpi10 = addr,
+            EfiConfigurationTableKind::Acpi20 => boot_info.acpi_rsdp = addr,
+            EfiConfigurationTableKind::Smbios => boot_info.smbios = addr,
+            EfiConfigurationTableKind::Smbios3 => boot_info.smbios3 = addr,
+            EfiConfigurationTableKind::DeviceTree => {}
+        }
+        uefi_println!("Config table: {} at {:#x}", kind.name(), addr);
+    }
+    if boot_info.acpi_rsdp == 0 {
+        boot_info.acpi_rsdp = acpi10;
+    }
+}
+
+type KernelMainT = unsafe extern "sysv64" fn(&BootInfo);
+/// Load the kernel ELF file, place its `PT_LOAD` segments and return the entry point
 fn load_kernel(root: &EfiFileProtocol, bs: &EfiBootServices) -> Result<KernelMainT, EfiStatus> {
     let kernel = root.open(
         "\\rust_mikan_os_kernel",
@@ -169,37 +223,113 @@ fn load_kernel(root: &EfiFileProtocol, bs: &EfiBootServices) -> Result<KernelMai
     )?;
     let info = kernel.get_info()?;
     let size = info.file_size as usize;
-    let pages = (size + 0xfff) / 0x1000;
 
+    // 一時バッファに読み込んでから各セグメントを最終位置へコピーする
+    let buffer = bs.allocate_pool(EfiMemoryType::EfiLoaderData, size)?;
+    kernel.read(size, buffer as u64)?;
+    kernel.close().ok();
+
+    let image = unsafe { core::slice::from_raw_parts(buffer as *const u8, size) };
+    let Some((elf, (first, last))) = Elf::parse(image)
+        .ok()
+        .and_then(|elf| Some((elf, elf.load_address_range()?)))
+    else {
+        uefi_println!("Kernel is not a loadable ELF file");
+        return Err(EfiStatus::EfiLoadError);
+    };
+    let first = first & !0xfff;
+    let pages = (last - first).div_ceil(0x1000) as usize;
     bs.allocate_pages(
         EfiAllocateType::AllocateAddress,
         EfiMemoryType::EfiLoaderData,
         pages,
-        KERNEL_BASE_ADDR,
+        first,
     )?;
+    unsafe { copy_load_segments(&elf) };
+    uefi_println!("Kernel: {:#x} - {:#x}", first, last);
 
-    kernel.read(size, KERNEL_BASE_ADDR)?;
+    let entry = elf.entry();
+    bs.free_pool(buffer as *const core::ffi::c_void)?;
 
-    const KERNEL_ENTRY_OFFSET: u64 = 24;
-    let entry = unsafe { *((KERNEL_BASE_ADDR + KERNEL_ENTRY_OFFSET) as *const u64) };
     uefi_println!("Kernel entry point: {:#x}", entry);
     Ok(unsafe { core::mem::transmute::<*const (), KernelMainT>(entry as usize as *const ()) })
 }
 
+/// Copy every `PT_LOAD` segment to its virtual address and clear `.bss`
+///
+/// # Safety
+///
+/// The destination range must already be allocated
+unsafe fn copy_load_segments(elf: &Elf) {
+    for phdr in elf.load_segments() {
+        let Ok(data) = elf.segment_data(&phdr) else {
+            continue;
+        };
+        unsafe {
+            let dest = phdr.p_vaddr as *mut u8;
+            core::ptr::copy_nonoverlapping(data.as_ptr(), dest, data.len());
+            core::ptr::write_bytes(
+                dest.add(data.len()),
+                0,
+                (phdr.p_memsz - phdr.p_filesz) as usize,
+            );
+        }
+    }
+}
+
+/// Load the optional FAT volume image into `EfiLoaderData` pages and return
+/// its physical range
+fn load_volume_image(root: &EfiFileProtocol, bs: &EfiBootServices) -> Option<(u64, u64)> {
+    let image = root
+        .open(
+            "\\fs.img",
+            uefi::types::EfiFileOpenMode::Read,
+            uefi::types::EfiFileAttribute::None,
+        )
+        .ok()?;
+    let size = image.get_info().ok()?.file_size;
+    let pages = (size as usize).div_ceil(0x1000);
+    // カーネルの配置後に確保するので、カーネルの領域とは重ならない
+    let base = bs
+        .allocate_pages(
+            EfiAllocateType::AllocateAnyPages,
+            EfiMemoryType::EfiLoaderData,
+            pages,
+            0,
+        )
+        .ok()?;
+    let result = image.read(size as usize, base);
+    image.close().ok();
+    if result.is_err() {
+        uefi_println!("Failed to read volume image");
+        return None;
+    }
+    uefi_println!("Volume image: {:#x} - {:#x}", base, base + size);
+    Some((base, size))
+}
+
 /// Exit boot services and jump to kernel entry
 fn exit_and_jump(
     bs: &EfiBootServices,
     image_handle: EfiHandle,
-    map_key: usize,
+    memmap: &mut MemoryMap,
     entry: KernelMainT,
-    frame_buffer_base: *mut u64,
-    frame_buffer_size: u64,
+    boot_info: &BootInfo,
 ) -> ! {
+    uefi_println!("Exiting boot services and jumping to kernel...");
+    if bs.exit_boot_service(image_handle, memmap.map_key).is_err() {
+        // メモリマップが古くなっている場合は取り直して再試行する
+        if memmap.acquire(bs).is_err()
+            || bs.exit_boot_service(image_handle, memmap.map_key).is_err()
+        {
+            uefi_println!("Failed to exit boot services");
+            loop {
+                unsafe { asm!("hlt") };
+            }
+        }
+    }
     unsafe {
-        let _ = bs.exit_boot_service(image_handle, map_key);
-        uefi_println!("Exiting boot services and jumping to kernel...");
-        entry(frame_buffer_base, frame_buffer_size);
-        uefi_println!("Kernel entry function returned unexpectedly");
+        entry(boot_info);
         loop {
             asm!("hlt");
         }
@@ -278,20 +408,39 @@ pub extern "efiapi" fn efi_main(
         gop.mode.info.vertical_resolution
     );
 
-    match load_kernel(root, bs) {
-        Ok(entry) => exit_and_jump(
-            bs,
-            image_handle,
-            memmap.map_key,
-            entry,
-            gop.mode.frame_buffer_base as *mut u64,
-            gop.mode.frame_buffer_size as u64,
-        ),
+    let pixel_format = match gop.mode.info.pixel_format {
+        EfiGraphicsPixelFormat::PixelRedGreenBlueReserved8BitPerClolor => PixelFormat::Rgb,
+        EfiGraphicsPixelFormat::PixelBlueGreenRedReserved8BitPerColor => PixelFormat::Bgr,
+        _ => PixelFormat::Unsupported,
+    };
+    let mut boot_info = BootInfo {
+        frame_buffer_base: gop.mode.frame_buffer_base,
+        frame_buffer_size: gop.mode.frame_buffer_size as u64,
+        horizontal_resolution: gop.mode.info.horizontal_resolution,
+        vertical_resolution: gop.mode.info.vertical_resolution,
+        pixels_per_scan_line: gop.mode.info.pixel_per_scan_line,
+        pixel_format,
+        acpi_rsdp: 0,
+        smbios: 0,
+        smbios3: 0,
+        volume_image_base: 0,
+        volume_image_size: 0,
+        boot_partition_guid: boot_partition_guid(image_handle, bs).unwrap_or_default(),
+    };
+    collect_config_tables(system_table, &mut boot_info);
+
+    let entry = match load_kernel(root, bs) {
+        Ok(entry) => entry,
         Err(_) => {
             uefi_println!("Kernel load error");
-            EfiStatus::EfiLoadError
+            return EfiStatus::EfiLoadError;
         }
+    };
+    if let Some((base, size)) = load_volume_image(root, bs) {
+        boot_info.volume_image_base = base;
+        boot_info.volume_image_size = size;
     }
+    exit_and_jump(bs, image_handle, &mut memmap, entry, &boot_info)
 }
 
 #[panic_handler]
@@ -156,14 +156,9 @@ impl EfiBootServices {
         &self,
         allocate_type: EfiAllocateType,
         memory_type: EfiMemoryType,
-        mut pages: usize,
+        pages: usize,
         mut memory: EfiPhysicalAddress,
     ) -> Result<EfiPhysicalAddress, EfiStatus> {
-        if (pages % 0x1000) != 0 {
-            //4KiBアライメント
-            pages = pages.div_ceil(0x1000);
-        }
-
         let _res = (self.allocate_pages)(allocate_type, memory_type, pages, &mut memory);
         if _res == EfiStatus::Success {
             Ok(memory)
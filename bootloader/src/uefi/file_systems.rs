@@ -12,8 +12,55 @@ pub const EFI_OPEN_PROTOCOL_BY_HANDLE_PROTOCOL: u32 = 0x00000001;
 
 pub struct EfiFileIoToken {}
 
+const DEVICE_PATH_MEDIA: u8 = 0x04;
+const DEVICE_PATH_MEDIA_HARD_DRIVE: u8 = 0x01;
+const DEVICE_PATH_END: u8 = 0x7f;
+const HARD_DRIVE_SIGNATURE_GUID: u8 = 0x02;
+
+/// Header of one device path node; the node data follows it
 #[repr(C)]
-pub struct EfiDevicePathProtocol {}
+pub struct EfiDevicePathProtocol {
+    kind: u8,
+    sub_type: u8,
+    length: [u8; 2],
+}
+
+impl EfiDevicePathProtocol {
+    fn length(&self) -> usize {
+        u16::from_le_bytes(self.length) as usize
+    }
+
+    /// Nodes of the path up to the end node
+    fn nodes(&self) -> impl Iterator<Item = &EfiDevicePathProtocol> {
+        let mut node = Some(self);
+        core::iter::from_fn(move || {
+            let current = node.filter(|n| n.kind != DEVICE_PATH_END && n.length() >= 4)?;
+            // 次のノードは Length バイト先にある
+            node = unsafe {
+                ((current as *const Self as *const u8).add(current.length()) as *const Self)
+                    .as_ref()
+            };
+            Some(current)
+        })
+    }
+
+    /// Partition GUID of the first GPT hard drive node in the path
+    pub fn partition_guid(&self) -> Option<[u8; 16]> {
+        self.nodes().find_map(|node| {
+            if node.kind != DEVICE_PATH_MEDIA
+                || node.sub_type != DEVICE_PATH_MEDIA_HARD_DRIVE
+                || node.length() < 42
+            {
+                return None;
+            }
+            // PartitionNumber(4) PartitionStart(8) PartitionSize(8) Signature(16) MBRType(1) SignatureType(1)
+            let data = unsafe {
+                core::slice::from_raw_parts(node as *const Self as *const u8, node.length())
+            };
+            (data[41] == HARD_DRIVE_SIGNATURE_GUID).then(|| data[24..40].try_into().unwrap())
+        })
+    }
+}
 
 #[repr(C)]
 pub struct EfiLoadedImageProtocol<'a> {
@@ -1,5 +1,5 @@
 #[repr(C)]
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub struct EfiGuid {
     data_1: u32,
     data_2: u16,
@@ -14,6 +14,13 @@ pub const EFI_LOADED_IMAGE_PROTOCOL_GUID: EfiGuid = EfiGuid {
     data_4: [0x8e, 0x3f, 0x00, 0xa0, 0xc9, 0x69, 0x72, 0x3b],
 };
 
+pub const EFI_DEVICE_PATH_PROTOCOL_GUID: EfiGuid = EfiGuid {
+    data_1: 0x09576e91,
+    data_2: 0x6d3f,
+    data_3: 0x11d2,
+    data_4: [0x8e, 0x39, 0x00, 0xa0, 0xc9, 0x69, 0x72, 0x3b],
+};
+
 pub const EFI_SIMPLE_FILE_SYSTEM_PROTOCOL_GUID: EfiGuid = EfiGuid {
     data_1: 0x0964e5b22,
     data_2: 0x6459,
@@ -34,3 +41,38 @@ pub const EFI_GRAPHICS_OUTPUT_PROTOCOL_GUID: EfiGuid = EfiGuid {
     data_3: 0x4a38,
     data_4: [0x96, 0xfb, 0x7a, 0xde, 0xd0, 0x80, 0x51, 0x6a],
 };
+
+pub const EFI_ACPI_TABLE_GUID: EfiGuid = EfiGuid {
+    data_1: 0xeb9d2d30,
+    data_2: 0x2d88,
+    data_3: 0x11d3,
+    data_4: [0x9a, 0x16, 0x00, 0x90, 0x27, 0x3f, 0xc1, 0x4d],
+};
+
+pub const EFI_ACPI_20_TABLE_GUID: EfiGuid = EfiGuid {
+    data_1: 0x8868e871,
+    data_2: 0xe4f1,
+    data_3: 0x11d3,
+    data_4: [0xbc, 0x22, 0x00, 0x80, 0xc7, 0x3c, 0x88, 0x81],
+};
+
+pub const SMBIOS_TABLE_GUID: EfiGuid = EfiGuid {
+    data_1: 0xeb9d2d31,
+    data_2: 0x2d88,
+    data_3: 0x11d3,
+    data_4: [0x9a, 0x16, 0x00, 0x90, 0x27, 0x3f, 0xc1, 0x4d],
+};
+
+pub const SMBIOS3_TABLE_GUID: EfiGuid = EfiGuid {
+    data_1: 0xf2fd1544,
+    data_2: 0x9794,
+    data_3: 0x4a2c,
+    data_4: [0x99, 0x2e, 0xe5, 0xbb, 0xcf, 0x20, 0xe3, 0x94],
+};
+
+pub const EFI_DTB_TABLE_GUID: EfiGuid = EfiGuid {
+    data_1: 0xb1b621d5,
+    data_2: 0xf19c,
+    data_3: 0x41a5,
+    data_4: [0x83, 0x0b, 0xd9, 0x15, 0x2c, 0x69, 0xaa, 0xe0],
+};
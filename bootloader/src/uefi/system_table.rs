@@ -1,11 +1,60 @@
+use core::ffi::c_void;
+
 use super::{
     boot_services::EfiBootServices,
     console::{EfiSimpleTextInputProtocol, EfiSimpleTextOutputProtocol},
+    guids::{
+        EFI_ACPI_20_TABLE_GUID, EFI_ACPI_TABLE_GUID, EFI_DTB_TABLE_GUID, EfiGuid,
+        SMBIOS_TABLE_GUID, SMBIOS3_TABLE_GUID,
+    },
     types::*,
 };
 
 pub struct EfiRuntimeService {}
-pub struct EfiConfigurationTable {}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct EfiConfigurationTable {
+    pub vendor_guid: EfiGuid,
+    pub vendor_table: *const c_void,
+}
+
+/// Configuration tables the bootloader knows how to forward
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EfiConfigurationTableKind {
+    Acpi10,
+    Acpi20,
+    Smbios,
+    Smbios3,
+    DeviceTree,
+    Unknown,
+}
+
+impl EfiConfigurationTable {
+    pub fn kind(&self) -> EfiConfigurationTableKind {
+        match self.vendor_guid {
+            EFI_ACPI_TABLE_GUID => EfiConfigurationTableKind::Acpi10,
+            EFI_ACPI_20_TABLE_GUID => EfiConfigurationTableKind::Acpi20,
+            SMBIOS_TABLE_GUID => EfiConfigurationTableKind::Smbios,
+            SMBIOS3_TABLE_GUID => EfiConfigurationTableKind::Smbios3,
+            EFI_DTB_TABLE_GUID => EfiConfigurationTableKind::DeviceTree,
+            _ => EfiConfigurationTableKind::Unknown,
+        }
+    }
+}
+
+impl EfiConfigurationTableKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            EfiConfigurationTableKind::Acpi10 => "ACPI 1.0 RSDP",
+            EfiConfigurationTableKind::Acpi20 => "ACPI 2.0 RSDP",
+            EfiConfigurationTableKind::Smbios => "SMBIOS",
+            EfiConfigurationTableKind::Smbios3 => "SMBIOS3",
+            EfiConfigurationTableKind::DeviceTree => "Device Tree",
+            EfiConfigurationTableKind::Unknown => "Unknown",
+        }
+    }
+}
 
 #[repr(C)]
 pub struct EfiSystemTable {
@@ -36,4 +85,11 @@ impl<'a> EfiSystemTable {
     pub fn boot_services(&'a self) -> &'a EfiBootServices {
         unsafe { &*self.boot_services }
     }
+
+    pub fn config_tables(&'a self) -> &'a [EfiConfigurationTable] {
+        if self.config_table.is_null() {
+            return &[];
+        }
+        unsafe { core::slice::from_raw_parts(self.config_table, self.number_of_table_entries) }
+    }
 }
@@ -157,7 +157,7 @@ pub struct EfiGraphicsOutputModeInformation {
     version: u32,
     pub horizontal_resolution: u32,
     pub vertical_resolution: u32,
-    pixel_format: EfiGraphicsPixelFormat,
+    pub pixel_format: EfiGraphicsPixelFormat,
     pixel_information: EfiPixelBitmask,
-    pixel_per_scan_line: u32,
+    pub pixel_per_scan_line: u32,
 }
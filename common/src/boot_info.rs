@@ -0,0 +1,39 @@
+/// Information handed from the bootloader to `kernel_main`
+///
+/// Physical addresses are `0` when the firmware did not provide the table.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct BootInfo {
+    pub frame_buffer_base: u64,
+    pub frame_buffer_size: u64,
+    pub horizontal_resolution: u32,
+    pub vertical_resolution: u32,
+    /// Pixels per line in memory; may exceed `horizontal_resolution`
+    pub pixels_per_scan_line: u32,
+    pub pixel_format: PixelFormat,
+    /// ACPI RSDP (2.0 if available, otherwise 1.0)
+    pub acpi_rsdp: u64,
+    /// SMBIOS 2.x entry point structure (`_SM_`)
+    pub smbios: u64,
+    /// SMBIOS 3.x entry point structure (`_SM3_`)
+    pub smbios3: u64,
+    /// FAT volume image (`\fs.img` on the boot volume) loaded into memory
+    pub volume_image_base: u64,
+    /// Size of the volume image in bytes; `0` when there is none
+    pub volume_image_size: u64,
+    /// Unique GUID (on-disk byte order) of the GPT partition the bootloader
+    /// was loaded from; all zero when unknown
+    pub boot_partition_guid: [u8; 16],
+}
+
+/// Layout of a 32-bit frame buffer pixel
+#[repr(u32)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PixelFormat {
+    /// Byte 0 is red, byte 1 green, byte 2 blue
+    Rgb,
+    /// Byte 0 is blue, byte 1 green, byte 2 red
+    Bgr,
+    /// Anything the kernel cannot draw to directly (bitmask or blt-only modes)
+    Unsupported,
+}
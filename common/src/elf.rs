@@ -0,0 +1,368 @@
+//! Minimal ELF64 parsing, used to load the kernel and applications
+//!
+//! Only statically linked x86_64 executables are accepted. Headers are read
+//! with unaligned loads, so the image can sit anywhere in memory.
+
+pub const ELF_MAGIC: [u8; 4] = [0x7f, b'E', b'L', b'F'];
+const ELFCLASS64: u8 = 2;
+const ELFDATA2LSB: u8 = 1;
+pub const ET_EXEC: u16 = 2;
+pub const EM_X86_64: u16 = 62;
+
+pub const PT_LOAD: u32 = 1;
+pub const PF_X: u32 = 1 << 0;
+pub const PF_W: u32 = 1 << 1;
+pub const PF_R: u32 = 1 << 2;
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct Elf64Ehdr {
+    pub e_ident: [u8; 16],
+    pub e_type: u16,
+    pub e_machine: u16,
+    pub e_version: u32,
+    pub e_entry: u64,
+    pub e_phoff: u64,
+    pub e_shoff: u64,
+    pub e_flags: u32,
+    pub e_ehsize: u16,
+    pub e_phentsize: u16,
+    pub e_phnum: u16,
+    pub e_shentsize: u16,
+    pub e_shnum: u16,
+    pub e_shstrndx: u16,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct Elf64Phdr {
+    pub p_type: u32,
+    pub p_flags: u32,
+    pub p_offset: u64,
+    pub p_vaddr: u64,
+    pub p_paddr: u64,
+    pub p_filesz: u64,
+    pub p_memsz: u64,
+    pub p_align: u64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ElfError {
+    /// No ELF magic
+    NotElf,
+    /// Not a 64-bit little-endian x86_64 executable
+    Unsupported,
+    /// A header or segment lies outside the image, or sizes do not add up
+    Malformed,
+}
+
+/// A validated ELF image
+#[derive(Clone, Copy)]
+pub struct Elf<'a> {
+    image: &'a [u8],
+    header: Elf64Ehdr,
+}
+
+/// Read a `T` at `offset`, if it fits
+fn read<T: Copy>(image: &[u8], offset: Option<u64>) -> Option<T> {
+    let offset = offset?;
+    let start = usize::try_from(offset).ok()?;
+    let bytes = image.get(start..start.checked_add(size_of::<T>())?)?;
+    Some(unsafe { (bytes.as_ptr() as *const T).read_unaligned() })
+}
+
+fn phdr_offset(header: &Elf64Ehdr, index: u16) -> Option<u64> {
+    header
+        .e_phoff
+        .checked_add(index as u64 * size_of::<Elf64Phdr>() as u64)
+}
+
+impl<'a> Elf<'a> {
+    /// Check the header, the program header table and every `PT_LOAD`
+    /// segment's file range
+    pub fn parse(image: &'a [u8]) -> Result<Self, ElfError> {
+        let header: Elf64Ehdr = read(image, Some(0)).ok_or(ElfError::NotElf)?;
+        if header.e_ident[..4] != ELF_MAGIC {
+            return Err(ElfError::NotElf);
+        }
+        if header.e_ident[4] != ELFCLASS64
+            || header.e_ident[5] != ELFDATA2LSB
+            || header.e_type != ET_EXEC
+            || header.e_machine != EM_X86_64
+        {
+            return Err(ElfError::Unsupported);
+        }
+        if header.e_phnum > 0 && header.e_phentsize as usize != size_of::<Elf64Phdr>() {
+            return Err(ElfError::Malformed);
+        }
+        let elf = Self { image, header };
+        for i in 0..header.e_phnum {
+            read::<Elf64Phdr>(image, phdr_offset(&header, i)).ok_or(ElfError::Malformed)?;
+        }
+        for phdr in elf.load_segments() {
+            if phdr.p_filesz > phdr.p_memsz || phdr.p_vaddr.checked_add(phdr.p_memsz).is_none() {
+                return Err(ElfError::Malformed);
+            }
+            elf.segment_data(&phdr)?;
+        }
+        Ok(elf)
+    }
+
+    pub fn header(&self) -> &Elf64Ehdr {
+        &self.header
+    }
+
+    pub fn entry(&self) -> u64 {
+        self.header.e_entry
+    }
+
+    pub fn program_headers(&self) -> impl Iterator<Item = Elf64Phdr> + 'a {
+        let (image, header) = (self.image, self.header);
+        (0..header.e_phnum).filter_map(move |i| read(image, phdr_offset(&header, i)))
+    }
+
+    pub fn load_segments(&self) -> impl Iterator<Item = Elf64Phdr> + 'a {
+        self.program_headers().filter(|p| p.p_type == PT_LOAD)
+    }
+
+    /// Address range `[first, last)` covered by the `PT_LOAD` segments;
+    /// `None` if there are none
+    pub fn load_address_range(&self) -> Option<(u64, u64)> {
+        self.load_segments().fold(None, |range, phdr| {
+            let (first, last) = range.unwrap_or((u64::MAX, 0));
+            Some((
+                first.min(phdr.p_vaddr),
+                last.max(phdr.p_vaddr + phdr.p_memsz),
+            ))
+        })
+    }
+
+    /// The bytes of `phdr` stored in the file; the rest up to `p_memsz` is
+    /// zero
+    pub fn segment_data(&self, phdr: &Elf64Phdr) -> Result<&'a [u8], ElfError> {
+        let start = usize::try_from(phdr.p_offset).map_err(|_| ElfError::Malformed)?;
+        let len = usize::try_from(phdr.p_filesz).map_err(|_| ElfError::Malformed)?;
+        start
+            .checked_add(len)
+            .and_then(|end| self.image.get(start..end))
+            .ok_or(ElfError::Malformed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use std::{vec, vec::Vec};
+
+    use super::*;
+
+    const EHDR_SIZE: usize = size_of::<Elf64Ehdr>();
+    const PHDR_SIZE: usize = size_of::<Elf64Phdr>();
+    const CODE: &[u8] = &[0xf4, 0xeb, 0xfd, 0x90];
+
+    fn put<T: Copy>(image: &mut [u8], offset: usize, value: T) {
+        let bytes =
+            unsafe { core::slice::from_raw_parts(&value as *const T as *const u8, size_of::<T>()) };
+        image[offset..offset + bytes.len()].copy_from_slice(bytes);
+    }
+
+    fn header(phnum: u16) -> Elf64Ehdr {
+        let mut e_ident = [0; 16];
+        e_ident[..4].copy_from_slice(&ELF_MAGIC);
+        e_ident[4] = ELFCLASS64;
+        e_ident[5] = ELFDATA2LSB;
+        e_ident[6] = 1;
+        Elf64Ehdr {
+            e_ident,
+            e_type: ET_EXEC,
+            e_machine: EM_X86_64,
+            e_version: 1,
+            e_entry: 0x40_0000 + (EHDR_SIZE + 2 * PHDR_SIZE) as u64,
+            e_phoff: EHDR_SIZE as u64,
+            e_shoff: 0,
+            e_flags: 0,
+            e_ehsize: EHDR_SIZE as u16,
+            e_phentsize: PHDR_SIZE as u16,
+            e_phnum: phnum,
+            e_shentsize: 0,
+            e_shnum: 0,
+            e_shstrndx: 0,
+        }
+    }
+
+    /// A minimal ET_EXEC image as `ld -static` lays it out: the headers and
+    /// code in one R+X segment mapped from offset 0, then a .bss-only RW
+    /// segment, with a non-loadable GNU_STACK header in between
+    fn image() -> Vec<u8> {
+        let code_offset = EHDR_SIZE + 3 * PHDR_SIZE;
+        let mut image = vec![0; code_offset + CODE.len()];
+        let mut header = header(3);
+        header.e_entry = 0x40_0000 + code_offset as u64;
+        put(&mut image, 0, header);
+        let text = Elf64Phdr {
+            p_type: PT_LOAD,
+            p_flags: PF_R | PF_X,
+            p_offset: 0,
+            p_vaddr: 0x40_0000,
+            p_paddr: 0x40_0000,
+            p_filesz: image.len() as u64,
+            p_memsz: image.len() as u64,
+            p_align: 0x1000,
+        };
+        let stack = Elf64Phdr {
+            p_type: 0x6474_e551,
+            p_flags: PF_R | PF_W,
+            p_offset: 0,
+            p_vaddr: 0,
+            p_paddr: 0,
+            p_filesz: 0,
+            p_memsz: 0,
+            p_align: 16,
+        };
+        let bss = Elf64Phdr {
+            p_type: PT_LOAD,
+            p_flags: PF_R | PF_W,
+            p_offset: image.len() as u64,
+            p_vaddr: 0x40_1000,
+            p_paddr: 0x40_1000,
+            p_filesz: 0,
+            p_memsz: 0x2000,
+            p_align: 0x1000,
+        };
+        put(&mut image, EHDR_SIZE, text);
+        put(&mut image, EHDR_SIZE + PHDR_SIZE, stack);
+        put(&mut image, EHDR_SIZE + 2 * PHDR_SIZE, bss);
+        image[code_offset..].copy_from_slice(CODE);
+        image
+    }
+
+    fn phdr_at(image: &[u8], index: usize) -> Elf64Phdr {
+        read(image, Some((EHDR_SIZE + index * PHDR_SIZE) as u64)).unwrap()
+    }
+
+    fn with_phdr(index: usize, f: impl FnOnce(&mut Elf64Phdr)) -> Vec<u8> {
+        let mut image = image();
+        let mut phdr = phdr_at(&image, index);
+        f(&mut phdr);
+        put(&mut image, EHDR_SIZE + index * PHDR_SIZE, phdr);
+        image
+    }
+
+    #[test]
+    fn valid_minimal_executable() {
+        let image = image();
+        let elf = Elf::parse(&image).unwrap();
+        assert_eq!(elf.entry(), 0x40_0000 + (EHDR_SIZE + 3 * PHDR_SIZE) as u64);
+        assert_eq!(elf.program_headers().count(), 3);
+        let loads: Vec<_> = elf.load_segments().collect();
+        assert_eq!(loads.len(), 2);
+        assert_eq!(elf.load_address_range(), Some((0x40_0000, 0x40_3000)));
+
+        let text = elf.segment_data(&loads[0]).unwrap();
+        assert_eq!(&text[text.len() - CODE.len()..], CODE);
+        assert!(elf.segment_data(&loads[1]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn executable_without_segments() {
+        let mut image = vec![0; EHDR_SIZE];
+        let mut header = header(0);
+        header.e_phentsize = 0;
+        put(&mut image, 0, header);
+        let elf = Elf::parse(&image).unwrap();
+        assert_eq!(elf.load_address_range(), None);
+    }
+
+    #[test]
+    fn bad_magic() {
+        let mut broken = image();
+        broken[0] = 0x7e;
+        assert_eq!(Elf::parse(&broken).err(), Some(ElfError::NotElf));
+        assert_eq!(Elf::parse(b"#!/bin/sh\n").err(), Some(ElfError::NotElf));
+        assert_eq!(
+            Elf::parse(&image()[..EHDR_SIZE - 1]).err(),
+            Some(ElfError::NotElf)
+        );
+    }
+
+    #[test]
+    fn bad_class_data_type_and_machine() {
+        for (offset, value) in [
+            (4, 1u8),  // ELFCLASS32
+            (5, 2u8),  // ELFDATA2MSB
+            (16, 3u8), // ET_DYN
+            (18, 3u8), // EM_386
+            (18, 183), // EM_AARCH64
+        ] {
+            let mut image = image();
+            image[offset] = value;
+            assert_eq!(Elf::parse(&image).err(), Some(ElfError::Unsupported));
+        }
+    }
+
+    #[test]
+    fn program_headers_past_the_end() {
+        let image_len = image().len() as u64;
+        for (phoff, phnum) in [
+            (image_len, 1),
+            (image_len - PHDR_SIZE as u64 + 1, 1),
+            (EHDR_SIZE as u64, 200),
+            (u64::MAX - 8, 1),
+            (u64::MAX - PHDR_SIZE as u64 * 2, 3),
+        ] {
+            let mut image = image();
+            let mut header: Elf64Ehdr = read(&image, Some(0)).unwrap();
+            header.e_phoff = phoff;
+            header.e_phnum = phnum;
+            put(&mut image, 0, header);
+            assert_eq!(Elf::parse(&image).err(), Some(ElfError::Malformed));
+        }
+
+        let mut image = image();
+        image[54] = 32; // e_phentsize
+        assert_eq!(Elf::parse(&image).err(), Some(ElfError::Malformed));
+    }
+
+    #[test]
+    fn segment_file_range_past_the_end() {
+        let len = image().len() as u64;
+        for (offset, filesz) in [
+            (0, len + 1),
+            (len, 1),
+            (u64::MAX, 1),
+            (u64::MAX - 4, 0x10),
+            (8, u64::MAX - 4),
+        ] {
+            let image = with_phdr(0, |phdr| {
+                // 仮想アドレス側の桁あふれで弾かれないようにする
+                phdr.p_vaddr = 0;
+                phdr.p_offset = offset;
+                phdr.p_filesz = filesz;
+                phdr.p_memsz = phdr.p_memsz.max(filesz);
+            });
+            assert_eq!(Elf::parse(&image).err(), Some(ElfError::Malformed));
+        }
+    }
+
+    #[test]
+    fn filesz_larger_than_memsz() {
+        let image = with_phdr(0, |phdr| phdr.p_memsz = phdr.p_filesz - 1);
+        assert_eq!(Elf::parse(&image).err(), Some(ElfError::Malformed));
+    }
+
+    #[test]
+    fn segment_wrapping_the_address_space() {
+        let image = with_phdr(2, |phdr| phdr.p_vaddr = u64::MAX - 0xfff);
+        assert_eq!(Elf::parse(&image).err(), Some(ElfError::Malformed));
+    }
+
+    #[test]
+    fn non_load_segments_are_not_checked() {
+        let image = with_phdr(1, |phdr| {
+            phdr.p_offset = u64::MAX;
+            phdr.p_filesz = 0x1000;
+        });
+        assert!(Elf::parse(&image).is_ok());
+    }
+}
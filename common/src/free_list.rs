@@ -0,0 +1,202 @@
+//! First-fit free list shared by the kernel heap and the application heap
+//!
+//! Free blocks are kept sorted by address so that neighbours can be merged
+//! when memory is returned. The owner hands memory to the list with
+//! [`FreeList::insert`] and does the locking.
+
+use core::{alloc::Layout, ptr::null_mut};
+
+struct FreeBlock {
+    size: usize,
+    next: *mut FreeBlock,
+}
+
+/// Smallest block the list can keep track of
+pub const MIN_BLOCK: usize = size_of::<FreeBlock>();
+pub const BLOCK_ALIGN: usize = align_of::<FreeBlock>();
+
+pub struct FreeList {
+    head: *mut FreeBlock,
+}
+
+unsafe impl Send for FreeList {}
+
+impl Default for FreeList {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FreeList {
+    pub const fn new() -> Self {
+        Self { head: null_mut() }
+    }
+
+    /// Add `addr..addr + size` to the free blocks
+    ///
+    /// # Safety
+    ///
+    /// `addr..addr + size` は確保済みで書き込める、[`BLOCK_ALIGN`] に揃った
+    /// メモリで、どのブロックとも重なっていない必要があります。`size` は
+    /// [`MIN_BLOCK`] 以上でなければなりません。
+    pub unsafe fn insert(&mut self, addr: usize, size: usize) {
+        let mut prev: *mut FreeBlock = null_mut();
+        let mut next = self.head;
+        while !next.is_null() && (next as usize) < addr {
+            prev = next;
+            next = unsafe { (*next).next };
+        }
+
+        let block = addr as *mut FreeBlock;
+        unsafe { block.write(FreeBlock { size, next }) };
+        if !next.is_null() && addr + size == next as usize {
+            unsafe {
+                (*block).size += (*next).size;
+                (*block).next = (*next).next;
+            }
+        }
+        if prev.is_null() {
+            self.head = block;
+        } else if prev as usize + unsafe { (*prev).size } == addr {
+            unsafe {
+                (*prev).size += (*block).size;
+                (*prev).next = (*block).next;
+            }
+        } else {
+            unsafe { (*prev).next = block };
+        }
+    }
+
+    /// Take `size` bytes aligned to `align` from the first block they fit
+    /// in; null if none does
+    ///
+    /// `size` and `align` should come from [`block_size`] and
+    /// [`block_align`] so that the memory can be inserted again later.
+    pub fn find(&mut self, size: usize, align: usize) -> *mut u8 {
+        let mut prev: *mut FreeBlock = null_mut();
+        let mut current = self.head;
+        while !current.is_null() {
+            let start = current as usize;
+            let FreeBlock {
+                size: free_size,
+                next,
+            } = unsafe { current.read() };
+            let end = start + free_size;
+
+            let mut alloc_start = start.next_multiple_of(align);
+            // 前側の余りもブロックとして残せる大きさにする
+            if alloc_start != start && alloc_start - start < MIN_BLOCK {
+                alloc_start = (start + MIN_BLOCK).next_multiple_of(align);
+            }
+            let alloc_end = alloc_start + size;
+            if alloc_end <= end && (end - alloc_end == 0 || end - alloc_end >= MIN_BLOCK) {
+                if prev.is_null() {
+                    self.head = next;
+                } else {
+                    unsafe { (*prev).next = next };
+                }
+                unsafe {
+                    if alloc_start != start {
+                        self.insert(start, alloc_start - start);
+                    }
+                    if alloc_end != end {
+                        self.insert(alloc_end, end - alloc_end);
+                    }
+                }
+                return alloc_start as *mut u8;
+            }
+            prev = current;
+            current = next;
+        }
+        null_mut()
+    }
+
+    /// Bytes in all free blocks
+    pub fn free_bytes(&self) -> usize {
+        let mut total = 0;
+        let mut block = self.head;
+        while !block.is_null() {
+            unsafe {
+                total += (*block).size;
+                block = (*block).next;
+            }
+        }
+        total
+    }
+}
+
+/// Bytes to take from the list for `layout`; a freed block must be able to
+/// hold the list's bookkeeping
+pub fn block_size(layout: &Layout) -> usize {
+    layout.size().max(MIN_BLOCK).next_multiple_of(BLOCK_ALIGN)
+}
+
+pub fn block_align(layout: &Layout) -> usize {
+    layout.align().max(BLOCK_ALIGN)
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use std::{vec, vec::Vec};
+
+    use super::*;
+
+    /// A list over a fresh `len`-byte region; the region must outlive the list
+    fn list_over(len: usize) -> (FreeList, Vec<u64>) {
+        let mut region = vec![0u64; len / 8];
+        let mut list = FreeList::new();
+        unsafe { list.insert(region.as_mut_ptr() as usize, len) };
+        (list, region)
+    }
+
+    #[test]
+    fn allocations_are_aligned_and_disjoint() {
+        let (mut list, region) = list_over(4096);
+        let base = region.as_ptr() as usize;
+        let a = list.find(24, 8) as usize;
+        let b = list.find(64, 64) as usize;
+        let c = list.find(16, 8) as usize;
+        assert!(a != 0 && b != 0 && c != 0);
+        assert_eq!(b % 64, 0);
+        let mut ranges = [(a, a + 24), (b, b + 64), (c, c + 16)];
+        ranges.sort();
+        assert!(ranges[0].0 >= base && ranges[2].1 <= base + 4096);
+        assert!(ranges.windows(2).all(|w| w[0].1 <= w[1].0));
+        assert_eq!(list.free_bytes(), 4096 - 24 - 64 - 16);
+    }
+
+    #[test]
+    fn freed_neighbours_merge_back_into_one_block() {
+        let (mut list, _region) = list_over(1024);
+        let blocks: Vec<usize> = (0..4).map(|_| list.find(256, 8) as usize).collect();
+        assert!(list.find(16, 8).is_null());
+        // 前、後ろ、間の順に返して、どの向きの結合も通す
+        for &i in &[0, 3, 1, 2] {
+            unsafe { list.insert(blocks[i], 256) };
+        }
+        assert_eq!(list.free_bytes(), 1024);
+        assert_eq!(list.find(1024, 8) as usize, blocks[0]);
+    }
+
+    #[test]
+    fn remainders_too_small_for_a_block_are_not_split_off() {
+        let (mut list, _region) = list_over(64);
+        // 残り 8 バイトでは FreeBlock を置けない
+        assert!(list.find(56, 8).is_null());
+        assert!(!list.find(64 - MIN_BLOCK, 8).is_null());
+        assert!(!list.find(MIN_BLOCK, 8).is_null());
+        assert_eq!(list.free_bytes(), 0);
+    }
+
+    #[test]
+    fn block_size_leaves_room_for_the_bookkeeping() {
+        let layout = Layout::from_size_align(1, 1).unwrap();
+        assert_eq!(block_size(&layout), MIN_BLOCK);
+        assert_eq!(block_align(&layout), BLOCK_ALIGN);
+        let layout = Layout::from_size_align(17, 32).unwrap();
+        assert_eq!(block_size(&layout), 24);
+        assert_eq!(block_align(&layout), 32);
+    }
+}
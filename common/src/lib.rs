@@ -0,0 +1,7 @@
+//! Definitions shared between the bootloader, the kernel and applications
+#![no_std]
+
+pub mod boot_info;
+pub mod elf;
+pub mod free_list;
+pub mod syscall;
@@ -0,0 +1,280 @@
+//! System call ABI shared by the kernel and applications
+//!
+//! Applications issue `syscall` with the number in `rax` and up to six
+//! arguments in `rdi`, `rsi`, `rdx`, `r10`, `r8` and `r9`. The result comes
+//! back in `rax`; [`encode`] and [`decode`] fold errors into the top 4095
+//! values. All registers other than `rax`, `rcx` and `r11` are preserved.
+//! Strings are passed as a pointer and a byte length.
+
+/// System call numbers
+pub mod number {
+    /// `exit(code)`
+    pub const EXIT: u64 = 0;
+    /// `read(fd, buf, len) -> len`
+    pub const READ: u64 = 1;
+    /// `write(fd, buf, len) -> len`
+    pub const WRITE: u64 = 2;
+    /// `open(path, path_len, flags) -> fd`
+    pub const OPEN: u64 = 3;
+    /// `close(fd)`
+    pub const CLOSE: u64 = 4;
+    /// `mmap(len) -> address` of zeroed, writable memory
+    pub const MMAP: u64 = 5;
+    /// `sleep(ms)`
+    pub const SLEEP: u64 = 6;
+    /// `get_time() -> ms` since boot
+    pub const GET_TIME: u64 = 7;
+    /// `open_window(width, height, x, y, title, title_len) -> window`;
+    /// the size is that of the client area
+    pub const OPEN_WINDOW: u64 = 8;
+    /// `close_window(window)`
+    pub const CLOSE_WINDOW: u64 = 9;
+    /// `window_fill_rect(window, x, y, width, height, 0xRRGGBB)`
+    pub const WINDOW_FILL_RECT: u64 = 10;
+    /// `window_write_string(window, x, y, 0xRRGGBB, text, text_len)`
+    pub const WINDOW_WRITE_STRING: u64 = 11;
+    /// `window_read_event(event: *mut Event, flags) -> 1`, or `0` when
+    /// [`NONBLOCK`](super::event_flag::NONBLOCK) is given and nothing is
+    /// pending
+    pub const WINDOW_READ_EVENT: u64 = 12;
+    /// `seek(fd, offset, whence) -> position`; see [`SeekFrom`](super::SeekFrom)
+    pub const SEEK: u64 = 13;
+    /// `unlink(path, path_len)` removes a file or an empty directory
+    pub const UNLINK: u64 = 14;
+    /// `read_dir(fd, entry: *mut DirEntry) -> 1`, or `0` after the last
+    /// entry
+    pub const READ_DIR: u64 = 15;
+}
+
+/// Flags for [`number::OPEN`]
+pub mod open_flag {
+    pub const READ: u32 = 1 << 0;
+    pub const WRITE: u32 = 1 << 1;
+    /// Create the file if it does not exist
+    pub const CREATE: u32 = 1 << 2;
+    /// With [`CREATE`], fail if the file exists
+    pub const EXCLUSIVE: u32 = 1 << 3;
+    /// Empty the file when opening it for writing
+    pub const TRUNCATE: u32 = 1 << 4;
+    /// Every write goes to the end of the file
+    pub const APPEND: u32 = 1 << 5;
+    pub const ALL: u32 = READ | WRITE | CREATE | EXCLUSIVE | TRUNCATE | APPEND;
+}
+
+/// Target position of [`number::SEEK`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SeekFrom {
+    Start(u64),
+    Current(i64),
+    End(i64),
+}
+
+impl SeekFrom {
+    /// `whence` values of [`number::SEEK`]
+    pub const START: u64 = 0;
+    pub const CURRENT: u64 = 1;
+    pub const END: u64 = 2;
+
+    pub fn from_raw(offset: u64, whence: u64) -> Option<Self> {
+        match whence {
+            Self::START => Some(Self::Start(offset)),
+            Self::CURRENT => Some(Self::Current(offset as i64)),
+            Self::END => Some(Self::End(offset as i64)),
+            _ => None,
+        }
+    }
+
+    /// `(offset, whence)` as passed to [`number::SEEK`]
+    pub fn into_raw(self) -> (u64, u64) {
+        match self {
+            Self::Start(offset) => (offset, Self::START),
+            Self::Current(offset) => (offset as u64, Self::CURRENT),
+            Self::End(offset) => (offset as u64, Self::END),
+        }
+    }
+
+    /// New position in a file of `size` bytes read up to `position`; `None`
+    /// if it would be negative or does not fit in a `u64`
+    pub fn resolve(self, position: u64, size: u64) -> Option<u64> {
+        match self {
+            Self::Start(offset) => Some(offset),
+            Self::Current(offset) => position.checked_add_signed(offset),
+            Self::End(offset) => size.checked_add_signed(offset),
+        }
+    }
+}
+
+/// Flags for [`number::WINDOW_READ_EVENT`]
+pub mod event_flag {
+    /// Return `0` instead of waiting when no event is pending
+    pub const NONBLOCK: u64 = 1 << 0;
+}
+
+/// Window event filled in by [`number::WINDOW_READ_EVENT`]
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Event {
+    pub kind: u32,
+    pub window: u32,
+    /// ASCII code for [`Event::KEY`], otherwise `0`
+    pub value: u64,
+}
+
+impl Event {
+    /// The close button was clicked
+    pub const CLOSE: u32 = 1;
+    pub const ACTIVATED: u32 = 2;
+    pub const DEACTIVATED: u32 = 3;
+    /// A key was pressed while the window was active
+    pub const KEY: u32 = 4;
+}
+
+/// Directory entry filled in by [`number::READ_DIR`]
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct DirEntry {
+    pub kind: u32,
+    pub name_len: u32,
+    /// UTF-8, cut at a character boundary if it does not fit
+    pub name: [u8; DirEntry::MAX_NAME],
+}
+
+impl DirEntry {
+    pub const MAX_NAME: usize = 256;
+    pub const FILE: u32 = 1;
+    pub const DIRECTORY: u32 = 2;
+    pub const CHAR_DEVICE: u32 = 3;
+
+    pub const EMPTY: Self = Self {
+        kind: 0,
+        name_len: 0,
+        name: [0; Self::MAX_NAME],
+    };
+
+    pub fn name(&self) -> &str {
+        let len = (self.name_len as usize).min(Self::MAX_NAME);
+        core::str::from_utf8(&self.name[..len]).unwrap_or("")
+    }
+}
+
+#[repr(u64)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// Unknown system call number
+    NoSyscall = 1,
+    InvalidArgument,
+    /// A pointer argument is outside user memory or not mapped
+    BadAddress,
+    OutOfMemory,
+    NotFound,
+    NotADirectory,
+    IsADirectory,
+    AlreadyExists,
+    DirectoryNotEmpty,
+    InvalidPath,
+    ReadOnly,
+    NoSpace,
+    FileTooLarge,
+    Busy,
+    BadDescriptor,
+    TooManyOpenFiles,
+    Unsupported,
+    PermissionDenied,
+    Io,
+    /// Writing to a pipe whose read end is closed
+    BrokenPipe,
+    /// The task was killed while it waited
+    Interrupted,
+}
+
+impl Error {
+    const ALL: [Self; 21] = [
+        Self::NoSyscall,
+        Self::InvalidArgument,
+        Self::BadAddress,
+        Self::OutOfMemory,
+        Self::NotFound,
+        Self::NotADirectory,
+        Self::IsADirectory,
+        Self::AlreadyExists,
+        Self::DirectoryNotEmpty,
+        Self::InvalidPath,
+        Self::ReadOnly,
+        Self::NoSpace,
+        Self::FileTooLarge,
+        Self::Busy,
+        Self::BadDescriptor,
+        Self::TooManyOpenFiles,
+        Self::Unsupported,
+        Self::PermissionDenied,
+        Self::Io,
+        Self::BrokenPipe,
+        Self::Interrupted,
+    ];
+
+    pub fn from_code(code: u64) -> Option<Self> {
+        Self::ALL.into_iter().find(|e| *e as u64 == code)
+    }
+}
+
+/// Results at or above this value are negated error codes
+const ERROR_START: u64 = -4095i64 as u64;
+
+pub fn encode(result: Result<u64, Error>) -> u64 {
+    match result {
+        Ok(value) => value,
+        Err(error) => (error as u64).wrapping_neg(),
+    }
+}
+
+/// Undo [`encode`]; unknown codes read as [`Error::Io`]
+pub fn decode(raw: u64) -> Result<u64, Error> {
+    if raw < ERROR_START {
+        return Ok(raw);
+    }
+    Err(Error::from_code(raw.wrapping_neg()).unwrap_or(Error::Io))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn seek_from_start_ignores_position_and_size() {
+        assert_eq!(SeekFrom::Start(7).resolve(100, 3), Some(7));
+        assert_eq!(SeekFrom::Start(u64::MAX).resolve(0, 0), Some(u64::MAX));
+    }
+
+    #[test]
+    fn seek_from_current_moves_both_ways() {
+        assert_eq!(SeekFrom::Current(5).resolve(10, 0), Some(15));
+        assert_eq!(SeekFrom::Current(-10).resolve(10, 0), Some(0));
+        assert_eq!(SeekFrom::Current(-11).resolve(10, 0), None);
+        assert_eq!(SeekFrom::Current(1).resolve(u64::MAX, 0), None);
+    }
+
+    #[test]
+    fn seek_from_end_is_relative_to_size() {
+        assert_eq!(SeekFrom::End(0).resolve(3, 42), Some(42));
+        assert_eq!(SeekFrom::End(-2).resolve(0, 42), Some(40));
+        // 末尾より後ろへの移動は許す (書き込むと間が埋まる)
+        assert_eq!(SeekFrom::End(8).resolve(0, 42), Some(50));
+        assert_eq!(SeekFrom::End(-43).resolve(0, 42), None);
+    }
+
+    #[test]
+    fn seek_from_survives_the_register_encoding() {
+        for target in [
+            SeekFrom::Start(0),
+            SeekFrom::Start(u64::MAX),
+            SeekFrom::Current(-1),
+            SeekFrom::Current(i64::MIN),
+            SeekFrom::End(i64::MAX),
+            SeekFrom::End(-4096),
+        ] {
+            let (offset, whence) = target.into_raw();
+            assert_eq!(SeekFrom::from_raw(offset, whence), Some(target));
+        }
+        assert_eq!(SeekFrom::from_raw(0, 3), None);
+    }
+}
@@ -0,0 +1,108 @@
+//! Block device interface shared by storage drivers and filesystems
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BlockError {
+    /// The request reaches past the end of the device
+    OutOfRange,
+    /// The buffer is not a whole number of blocks
+    Unaligned,
+    ReadOnly,
+    /// The device reported an error
+    Io,
+}
+
+/// Storage addressed in fixed-size blocks
+pub trait BlockDevice {
+    fn block_size(&self) -> usize;
+    fn block_count(&self) -> u64;
+
+    /// Read `buf.len() / block_size()` blocks starting at `lba`
+    fn read_blocks(&mut self, lba: u64, buf: &mut [u8]) -> Result<(), BlockError>;
+
+    /// Write `buf.len() / block_size()` blocks starting at `lba`
+    fn write_blocks(&mut self, lba: u64, buf: &[u8]) -> Result<(), BlockError>;
+
+    /// Make previous writes durable
+    fn flush(&mut self) -> Result<(), BlockError> {
+        Ok(())
+    }
+
+    /// Check that `len` bytes at `lba` are whole blocks inside the device
+    fn check_range(&self, lba: u64, len: usize) -> Result<(), BlockError> {
+        if !len.is_multiple_of(self.block_size()) {
+            return Err(BlockError::Unaligned);
+        }
+        let count = (len / self.block_size()) as u64;
+        if lba
+            .checked_add(count)
+            .is_none_or(|end| end > self.block_count())
+        {
+            return Err(BlockError::OutOfRange);
+        }
+        Ok(())
+    }
+}
+
+impl<D: BlockDevice + ?Sized> BlockDevice for &mut D {
+    fn block_size(&self) -> usize {
+        (**self).block_size()
+    }
+
+    fn block_count(&self) -> u64 {
+        (**self).block_count()
+    }
+
+    fn read_blocks(&mut self, lba: u64, buf: &mut [u8]) -> Result<(), BlockError> {
+        (**self).read_blocks(lba, buf)
+    }
+
+    fn write_blocks(&mut self, lba: u64, buf: &[u8]) -> Result<(), BlockError> {
+        (**self).write_blocks(lba, buf)
+    }
+
+    fn flush(&mut self) -> Result<(), BlockError> {
+        (**self).flush()
+    }
+}
+
+/// A block device backed by a byte slice, e.g. a RAM disk or an image file
+/// loaded into memory
+pub struct MemoryDevice<'a> {
+    data: &'a mut [u8],
+    block_size: usize,
+}
+
+impl<'a> MemoryDevice<'a> {
+    /// Trailing bytes that do not fill a whole block are not accessible
+    pub fn new(data: &'a mut [u8], block_size: usize) -> Self {
+        Self { data, block_size }
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        self.data
+    }
+}
+
+impl BlockDevice for MemoryDevice<'_> {
+    fn block_size(&self) -> usize {
+        self.block_size
+    }
+
+    fn block_count(&self) -> u64 {
+        (self.data.len() / self.block_size) as u64
+    }
+
+    fn read_blocks(&mut self, lba: u64, buf: &mut [u8]) -> Result<(), BlockError> {
+        self.check_range(lba, buf.len())?;
+        let start = lba as usize * self.block_size;
+        buf.copy_from_slice(&self.data[start..start + buf.len()]);
+        Ok(())
+    }
+
+    fn write_blocks(&mut self, lba: u64, buf: &[u8]) -> Result<(), BlockError> {
+        self.check_range(lba, buf.len())?;
+        let start = lba as usize * self.block_size;
+        self.data[start..start + buf.len()].copy_from_slice(buf);
+        Ok(())
+    }
+}
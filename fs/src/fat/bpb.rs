@@ -0,0 +1,158 @@
+//! BIOS parameter block (boot sector) parsing
+
+use super::FatError;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FatType {
+    Fat12,
+    Fat16,
+    Fat32,
+}
+
+/// Geometry read from the boot sector
+#[derive(Clone, Copy, Debug)]
+pub struct Bpb {
+    pub bytes_per_sector: u16,
+    pub sectors_per_cluster: u8,
+    pub reserved_sectors: u16,
+    pub num_fats: u8,
+    /// Entries in the fixed root directory; `0` on FAT32
+    pub root_entry_count: u16,
+    pub total_sectors: u32,
+    /// Sectors per FAT
+    pub fat_size: u32,
+    /// First cluster of the root directory (FAT32 only)
+    pub root_cluster: u32,
+    /// FSInfo sector number (FAT32 only)
+    pub fs_info_sector: u16,
+    /// FAT32 ExtFlags; bit 7 set means only the active FAT is kept up to date
+    pub ext_flags: u16,
+    pub volume_label: [u8; 11],
+    pub fat_type: FatType,
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([bytes[offset], bytes[offset + 1]])
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([
+        bytes[offset],
+        bytes[offset + 1],
+        bytes[offset + 2],
+        bytes[offset + 3],
+    ])
+}
+
+impl Bpb {
+    pub fn parse(sector: &[u8]) -> Result<Self, FatError> {
+        if sector.len() < 512 || sector[510] != 0x55 || sector[511] != 0xaa {
+            return Err(FatError::InvalidBpb);
+        }
+        let bytes_per_sector = read_u16(sector, 11);
+        let sectors_per_cluster = sector[13];
+        let reserved_sectors = read_u16(sector, 14);
+        let num_fats = sector[16];
+        let root_entry_count = read_u16(sector, 17);
+        let total_sectors = match read_u16(sector, 19) {
+            0 => read_u32(sector, 32),
+            n => n as u32,
+        };
+        let fat_size = match read_u16(sector, 22) {
+            0 => read_u32(sector, 36),
+            n => n as u32,
+        };
+
+        if !matches!(bytes_per_sector, 512 | 1024 | 2048 | 4096)
+            || !sectors_per_cluster.is_power_of_two()
+            || reserved_sectors == 0
+            || num_fats == 0
+            || fat_size == 0
+            || total_sectors == 0
+        {
+            return Err(FatError::InvalidBpb);
+        }
+        // 細工されたイメージでも桁あふれさせない (以降の計算はこれより小さい)
+        let root_dir_sectors = (root_entry_count as u32 * 32).div_ceil(bytes_per_sector as u32);
+        (num_fats as u32)
+            .checked_mul(fat_size)
+            .and_then(|sectors| sectors.checked_add(reserved_sectors as u32))
+            .and_then(|sectors| sectors.checked_add(root_dir_sectors))
+            .filter(|&first_data_sector| first_data_sector < total_sectors)
+            .ok_or(FatError::InvalidBpb)?;
+
+        let mut bpb = Self {
+            bytes_per_sector,
+            sectors_per_cluster,
+            reserved_sectors,
+            num_fats,
+            root_entry_count,
+            total_sectors,
+            fat_size,
+            root_cluster: 0,
+            fs_info_sector: 0,
+            ext_flags: 0,
+            volume_label: [b' '; 11],
+            fat_type: FatType::Fat12,
+        };
+        // FAT の種類はクラスタ数だけで決まる
+        bpb.fat_type = match bpb.cluster_count() {
+            0..4085 => FatType::Fat12,
+            4085..65525 => FatType::Fat16,
+            _ => FatType::Fat32,
+        };
+        let label_offset = if bpb.fat_type == FatType::Fat32 {
+            bpb.root_cluster = read_u32(sector, 44);
+            bpb.fs_info_sector = read_u16(sector, 48);
+            bpb.ext_flags = read_u16(sector, 40);
+            if bpb.root_cluster < 2
+                || bpb.root_cluster - 2 >= bpb.cluster_count()
+                || bpb.active_fat() >= num_fats
+            {
+                return Err(FatError::InvalidBpb);
+            }
+            71
+        } else {
+            43
+        };
+        bpb.volume_label
+            .copy_from_slice(&sector[label_offset..label_offset + 11]);
+        Ok(bpb)
+    }
+
+    pub fn root_dir_sectors(&self) -> u32 {
+        (self.root_entry_count as u32 * 32).div_ceil(self.bytes_per_sector as u32)
+    }
+
+    pub fn first_root_dir_sector(&self) -> u32 {
+        self.reserved_sectors as u32 + self.num_fats as u32 * self.fat_size
+    }
+
+    pub fn first_data_sector(&self) -> u32 {
+        self.first_root_dir_sector() + self.root_dir_sectors()
+    }
+
+    /// Number of data clusters; valid cluster numbers are `2..cluster_count() + 2`
+    pub fn cluster_count(&self) -> u32 {
+        (self.total_sectors - self.first_data_sector()) / self.sectors_per_cluster as u32
+    }
+
+    pub fn cluster_size(&self) -> usize {
+        self.bytes_per_sector as usize * self.sectors_per_cluster as usize
+    }
+
+    /// FAT copies that have to be written, as indices
+    pub fn fats_to_update(&self) -> core::ops::Range<u8> {
+        if self.fat_type == FatType::Fat32 && self.ext_flags & 0x80 != 0 {
+            let active = (self.ext_flags & 0x0f) as u8;
+            active..active + 1
+        } else {
+            0..self.num_fats
+        }
+    }
+
+    /// FAT copy to read from
+    pub fn active_fat(&self) -> u8 {
+        self.fats_to_update().start
+    }
+}
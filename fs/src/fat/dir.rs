@@ -0,0 +1,435 @@
+//! Directory entries, 8.3 short names and VFAT long file names
+
+use alloc::{string::String, vec::Vec};
+
+pub const ENTRY_SIZE: usize = 32;
+
+pub const ATTR_READ_ONLY: u8 = 0x01;
+pub const ATTR_HIDDEN: u8 = 0x02;
+pub const ATTR_SYSTEM: u8 = 0x04;
+pub const ATTR_VOLUME_ID: u8 = 0x08;
+pub const ATTR_DIRECTORY: u8 = 0x10;
+pub const ATTR_ARCHIVE: u8 = 0x20;
+pub const ATTR_LONG_NAME: u8 = ATTR_READ_ONLY | ATTR_HIDDEN | ATTR_SYSTEM | ATTR_VOLUME_ID;
+
+/// First name byte of a free slot
+pub const DELETED: u8 = 0xe5;
+/// First name byte of the slot that ends the directory
+pub const END_OF_DIRECTORY: u8 = 0x00;
+
+/// NT reserved byte flags: base name / extension are stored upper case but
+/// displayed lower case
+const LOWER_CASE_BASE: u8 = 0x08;
+const LOWER_CASE_EXT: u8 = 0x10;
+
+const LFN_LAST: u8 = 0x40;
+const LFN_CHARS: usize = 13;
+/// Byte offsets of the 13 UTF-16 units in a long name slot
+const LFN_OFFSETS: [usize; LFN_CHARS] = [1, 3, 5, 7, 9, 14, 16, 18, 20, 22, 24, 28, 30];
+
+pub const MAX_NAME_LEN: usize = 255;
+
+/// Timestamp in FAT resolution (2 seconds, years 1980-2107)
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct DateTime {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+impl DateTime {
+    pub const EPOCH: Self = Self {
+        year: 1980,
+        month: 1,
+        day: 1,
+        hour: 0,
+        minute: 0,
+        second: 0,
+    };
+
+    fn from_fat(date: u16, time: u16) -> Self {
+        Self {
+            year: 1980 + (date >> 9),
+            month: (date >> 5 & 0x0f) as u8,
+            day: (date & 0x1f) as u8,
+            hour: (time >> 11) as u8,
+            minute: (time >> 5 & 0x3f) as u8,
+            second: (time & 0x1f) as u8 * 2,
+        }
+    }
+
+    /// `(date, time)` as stored in a directory entry
+    fn to_fat(self) -> (u16, u16) {
+        let date =
+            (self.year.clamp(1980, 2107) - 1980) << 9 | (self.month as u16) << 5 | self.day as u16;
+        let time = (self.hour as u16) << 11 | (self.minute as u16) << 5 | (self.second as u16 / 2);
+        (date, time)
+    }
+}
+
+/// Position of a 32-byte slot on the volume
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SlotLocation {
+    pub sector: u64,
+    pub offset: usize,
+}
+
+#[derive(Clone, Debug)]
+pub struct DirEntry {
+    /// Long name if present, otherwise the short name
+    pub name: String,
+    pub short_name: [u8; 11],
+    pub attributes: u8,
+    /// `0` for empty files (and for the root directory on FAT12/16)
+    pub first_cluster: u32,
+    pub size: u32,
+    pub modified: DateTime,
+    /// Long name slots followed by the short entry; empty for the root
+    pub(super) slots: Vec<SlotLocation>,
+}
+
+impl DirEntry {
+    pub fn is_dir(&self) -> bool {
+        self.attributes & ATTR_DIRECTORY != 0
+    }
+
+    pub fn is_file(&self) -> bool {
+        !self.is_dir()
+    }
+
+    pub(super) fn is_root(&self) -> bool {
+        self.slots.is_empty()
+    }
+
+    pub(super) fn is_dot(&self) -> bool {
+        self.short_name[0] == b'.'
+    }
+
+    pub(super) fn root(first_cluster: u32) -> Self {
+        Self {
+            name: String::from("/"),
+            short_name: *b"/          ",
+            attributes: ATTR_DIRECTORY,
+            first_cluster,
+            size: 0,
+            modified: DateTime::EPOCH,
+            slots: Vec::new(),
+        }
+    }
+
+    pub(super) fn short_slot(&self) -> SlotLocation {
+        *self.slots.last().unwrap()
+    }
+
+    /// Case-insensitive match against the long or the short name
+    pub(super) fn matches(&self, name: &str) -> bool {
+        self.name.eq_ignore_ascii_case(name)
+            || display_short_name(&self.short_name, 0).eq_ignore_ascii_case(name)
+    }
+
+    /// Copy the mutable fields into a raw short entry
+    pub(super) fn store(&self, raw: &mut [u8]) {
+        raw[11] = self.attributes;
+        raw[20..22].copy_from_slice(&((self.first_cluster >> 16) as u16).to_le_bytes());
+        raw[26..28].copy_from_slice(&(self.first_cluster as u16).to_le_bytes());
+        raw[28..32].copy_from_slice(&self.size.to_le_bytes());
+        let (date, time) = self.modified.to_fat();
+        raw[22..24].copy_from_slice(&time.to_le_bytes());
+        raw[24..26].copy_from_slice(&date.to_le_bytes());
+        raw[18..20].copy_from_slice(&date.to_le_bytes());
+    }
+}
+
+pub(super) fn short_entry(short_name: &[u8; 11], nt_flags: u8, created: DateTime) -> [u8; 32] {
+    let mut raw = [0; ENTRY_SIZE];
+    raw[..11].copy_from_slice(short_name);
+    raw[12] = nt_flags;
+    let (date, time) = created.to_fat();
+    raw[14..16].copy_from_slice(&time.to_le_bytes());
+    raw[16..18].copy_from_slice(&date.to_le_bytes());
+    raw
+}
+
+fn display_short_name(short_name: &[u8; 11], nt_flags: u8) -> String {
+    let mut name = String::new();
+    let base = trim_padding(&short_name[..8]);
+    let ext = trim_padding(&short_name[8..]);
+    // 0x05 は先頭が 0xe5 の名前を削除済みと区別するための置き換え
+    for (i, &byte) in base.iter().enumerate() {
+        let byte = if i == 0 && byte == 0x05 {
+            DELETED
+        } else {
+            byte
+        };
+        push_short_char(&mut name, byte, nt_flags & LOWER_CASE_BASE != 0);
+    }
+    if !ext.is_empty() {
+        name.push('.');
+        for &byte in ext {
+            push_short_char(&mut name, byte, nt_flags & LOWER_CASE_EXT != 0);
+        }
+    }
+    name
+}
+
+fn trim_padding(bytes: &[u8]) -> &[u8] {
+    let len = bytes.iter().rposition(|&b| b != b' ').map_or(0, |i| i + 1);
+    &bytes[..len]
+}
+
+fn push_short_char(name: &mut String, byte: u8, lower: bool) {
+    // OEM コードページ外の文字は置き換える
+    let c = if byte.is_ascii() {
+        byte as char
+    } else {
+        '\u{fffd}'
+    };
+    name.push(if lower { c.to_ascii_lowercase() } else { c });
+}
+
+pub(super) fn lfn_checksum(short_name: &[u8; 11]) -> u8 {
+    short_name
+        .iter()
+        .fold(0u8, |sum, &b| sum.rotate_right(1).wrapping_add(b))
+}
+
+/// Accumulates long name slots, which precede their short entry in reverse
+/// order
+#[derive(Default)]
+pub(super) struct EntryParser {
+    units: Vec<u16>,
+    checksum: u8,
+    /// Sequence number expected next; `0` when no long name is in progress
+    expected: u8,
+    slots: Vec<SlotLocation>,
+}
+
+impl EntryParser {
+    /// Feed one slot; returns the entry completed by a short entry
+    pub fn push(&mut self, location: SlotLocation, raw: &[u8]) -> Option<DirEntry> {
+        if raw[0] == DELETED || raw[0] == END_OF_DIRECTORY {
+            self.reset();
+            return None;
+        }
+        if raw[11] & 0x3f == ATTR_LONG_NAME {
+            self.push_long(location, raw);
+            return None;
+        }
+        if raw[11] & ATTR_VOLUME_ID != 0 {
+            self.reset();
+            return None;
+        }
+
+        let mut short_name = [0; 11];
+        short_name.copy_from_slice(&raw[..11]);
+        let long_name = if self.expected == 1 && self.checksum == lfn_checksum(&short_name) {
+            // 終端の 0x0000 と埋め草の 0xffff を落とす
+            let len = self
+                .units
+                .iter()
+                .position(|&u| u == 0)
+                .unwrap_or(self.units.len());
+            Some(
+                char::decode_utf16(self.units[..len].iter().copied())
+                    .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+                    .collect::<String>(),
+            )
+        } else {
+            self.slots.clear();
+            None
+        };
+        let name = long_name.unwrap_or_else(|| display_short_name(&short_name, raw[12]));
+
+        let mut slots = core::mem::take(&mut self.slots);
+        slots.push(location);
+        self.reset();
+
+        let high = u16::from_le_bytes([raw[20], raw[21]]) as u32;
+        let low = u16::from_le_bytes([raw[26], raw[27]]) as u32;
+        Some(DirEntry {
+            name,
+            short_name,
+            attributes: raw[11],
+            first_cluster: high << 16 | low,
+            size: u32::from_le_bytes([raw[28], raw[29], raw[30], raw[31]]),
+            modified: DateTime::from_fat(
+                u16::from_le_bytes([raw[24], raw[25]]),
+                u16::from_le_bytes([raw[22], raw[23]]),
+            ),
+            slots,
+        })
+    }
+
+    fn push_long(&mut self, location: SlotLocation, raw: &[u8]) {
+        let order = raw[0] & !LFN_LAST;
+        if raw[0] & LFN_LAST != 0 {
+            self.reset();
+            if order == 0 || order as usize * LFN_CHARS > MAX_NAME_LEN + LFN_CHARS {
+                return;
+            }
+            self.units = alloc::vec![0xffff; order as usize * LFN_CHARS];
+            self.checksum = raw[13];
+        } else if order == 0 || order + 1 != self.expected || raw[13] != self.checksum {
+            self.reset();
+            return;
+        }
+        let base = (order as usize - 1) * LFN_CHARS;
+        for (i, &offset) in LFN_OFFSETS.iter().enumerate() {
+            self.units[base + i] = u16::from_le_bytes([raw[offset], raw[offset + 1]]);
+        }
+        self.expected = order;
+        self.slots.push(location);
+    }
+
+    fn reset(&mut self) {
+        self.units.clear();
+        self.slots.clear();
+        self.expected = 0;
+    }
+}
+
+/// Whether `name` may be stored in a directory
+pub(super) fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name != "."
+        && name != ".."
+        && name.encode_utf16().count() <= MAX_NAME_LEN
+        && !name.ends_with([' ', '.'])
+        && !name
+            .chars()
+            .any(|c| c < ' ' || matches!(c, '"' | '*' | '/' | ':' | '<' | '>' | '?' | '\\' | '|'))
+}
+
+fn is_short_char(c: u8) -> bool {
+    c.is_ascii_uppercase()
+        || c.is_ascii_digit()
+        || matches!(
+            c,
+            b'!' | b'#'
+                | b'$'
+                | b'%'
+                | b'&'
+                | b'\''
+                | b'('
+                | b')'
+                | b'-'
+                | b'@'
+                | b'^'
+                | b'_'
+                | b'`'
+                | b'{'
+                | b'}'
+                | b'~'
+        )
+}
+
+/// Short name and NT case flags if `name` is a plain 8.3 name that needs no
+/// long name slots
+pub(super) fn exact_short_name(name: &str) -> Option<([u8; 11], u8)> {
+    let (base, ext) = match name.rsplit_once('.') {
+        Some((base, ext)) => (base, ext),
+        None => (name, ""),
+    };
+    if base.is_empty() || base.len() > 8 || ext.len() > 3 {
+        return None;
+    }
+
+    let mut short_name = [b' '; 11];
+    let mut flags = 0;
+    for (part, range, lower_flag) in [(base, 0..8, LOWER_CASE_BASE), (ext, 8..11, LOWER_CASE_EXT)] {
+        // 大文字と小文字が混ざった部分は NT フラグでは表せない
+        let has_lower = part.bytes().any(|b| b.is_ascii_lowercase());
+        let has_upper = part.bytes().any(|b| b.is_ascii_uppercase());
+        if has_lower && has_upper {
+            return None;
+        }
+        if has_lower {
+            flags |= lower_flag;
+        }
+        for (dst, b) in short_name[range].iter_mut().zip(part.bytes()) {
+            let b = b.to_ascii_uppercase();
+            if !is_short_char(b) {
+                return None;
+            }
+            *dst = b;
+        }
+    }
+    Some((short_name, flags))
+}
+
+/// Short name for a long name with the numeric tail `~n`
+pub(super) fn generated_short_name(name: &str, n: u32) -> [u8; 11] {
+    let name = name.trim_start_matches('.');
+    let (base, ext) = match name.rsplit_once('.') {
+        Some((base, ext)) => (base, ext),
+        None => (name, ""),
+    };
+    let convert = |s: &str| {
+        s.chars()
+            .filter(|&c| c != ' ' && c != '.')
+            .map(|c| {
+                let b = c.to_ascii_uppercase();
+                if b.is_ascii() && is_short_char(b as u8) {
+                    b as u8
+                } else {
+                    b'_'
+                }
+            })
+            .collect::<Vec<u8>>()
+    };
+
+    let mut short_name = [b' '; 11];
+    let mut tail = [0; 8];
+    let tail = {
+        let mut len = 0;
+        let mut rest = n;
+        while rest > 0 || len == 0 {
+            tail[7 - len] = b'0' + (rest % 10) as u8;
+            rest /= 10;
+            len += 1;
+        }
+        tail[7 - len] = b'~';
+        &tail[7 - len..]
+    };
+    let base = convert(base);
+    let base_len = base.len().min(8 - tail.len()).max(1);
+    let base = if base.is_empty() {
+        &b"_"[..]
+    } else {
+        &base[..base_len]
+    };
+    short_name[..base.len()].copy_from_slice(base);
+    short_name[base.len()..base.len() + tail.len()].copy_from_slice(tail);
+    for (dst, b) in short_name[8..].iter_mut().zip(convert(ext)) {
+        *dst = b;
+    }
+    short_name
+}
+
+/// Long name slots for `name` in on-disk order (highest sequence first)
+pub(super) fn long_name_slots(name: &str, checksum: u8) -> Vec<[u8; 32]> {
+    let mut units: Vec<u16> = name.encode_utf16().collect();
+    let count = units.len().div_ceil(LFN_CHARS);
+    if !units.len().is_multiple_of(LFN_CHARS) {
+        units.push(0);
+        units.resize(count * LFN_CHARS, 0xffff);
+    }
+
+    (1..=count)
+        .rev()
+        .map(|order| {
+            let mut raw = [0; ENTRY_SIZE];
+            raw[0] = order as u8 | if order == count { LFN_LAST } else { 0 };
+            raw[11] = ATTR_LONG_NAME;
+            raw[13] = checksum;
+            let chunk = &units[(order - 1) * LFN_CHARS..order * LFN_CHARS];
+            for (&offset, unit) in LFN_OFFSETS.iter().zip(chunk) {
+                raw[offset..offset + 2].copy_from_slice(&unit.to_le_bytes());
+            }
+            raw
+        })
+        .collect()
+}
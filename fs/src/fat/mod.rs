@@ -0,0 +1,869 @@
+//! FAT12/16/32 filesystem with VFAT long file names
+//!
+//! Works on any [`BlockDevice`] whose block size divides the sector size.
+//! Files are addressed by path (`/`-separated, case-insensitive) or by a
+//! [`DirEntry`] obtained from a lookup.
+
+mod bpb;
+mod dir;
+#[cfg(test)]
+mod tests;
+
+use alloc::{vec, vec::Vec};
+
+use crate::block::{BlockDevice, BlockError};
+
+pub use bpb::{Bpb, FatType};
+pub use dir::{
+    ATTR_ARCHIVE, ATTR_DIRECTORY, ATTR_HIDDEN, ATTR_READ_ONLY, ATTR_SYSTEM, DateTime, DirEntry,
+};
+
+use dir::{ENTRY_SIZE, EntryParser, SlotLocation};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FatError {
+    Device(BlockError),
+    /// The boot sector does not describe a FAT volume
+    InvalidBpb,
+    /// The device block size does not divide the sector size
+    UnsupportedBlockSize,
+    NotFound,
+    NotADirectory,
+    IsADirectory,
+    AlreadyExists,
+    DirectoryNotEmpty,
+    InvalidName,
+    /// No free cluster, or the fixed root directory is full
+    NoSpace,
+    /// A cluster chain points outside the volume or loops
+    Corrupted,
+    FileTooLarge,
+}
+
+impl From<BlockError> for FatError {
+    fn from(error: BlockError) -> Self {
+        Self::Device(error)
+    }
+}
+
+pub type Result<T> = core::result::Result<T, FatError>;
+
+const FS_INFO_LEAD_SIGNATURE: u32 = 0x4161_5252;
+const FS_INFO_STRUCT_SIGNATURE: u32 = 0x6141_7272;
+const FS_INFO_TRAIL_SIGNATURE: u32 = 0xaa55_0000;
+const FS_INFO_UNKNOWN: u32 = 0xffff_ffff;
+
+/// Where the entries of a directory live
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Dir {
+    /// FAT12/16 root directory between the FATs and the data area
+    FixedRoot,
+    Chain(u32),
+}
+
+pub struct FileSystem<D: BlockDevice> {
+    device: D,
+    bpb: Bpb,
+    /// Device blocks per FAT sector
+    blocks_per_sector: u64,
+    /// Cached FAT sector
+    fat_cache: Option<(u64, Vec<u8>)>,
+    /// Free cluster count, `None` until counted or read from FSInfo
+    free_count: Option<u32>,
+    /// Cluster to start the next free cluster search from
+    next_free: u32,
+    fs_info_dirty: bool,
+    clock: fn() -> DateTime,
+}
+
+impl<D: BlockDevice> FileSystem<D> {
+    pub fn mount(mut device: D) -> Result<Self> {
+        let block_size = device.block_size();
+        if block_size < 512 || !block_size.is_power_of_two() {
+            return Err(FatError::UnsupportedBlockSize);
+        }
+        let mut sector = vec![0; block_size];
+        device.read_blocks(0, &mut sector)?;
+        let bpb = Bpb::parse(&sector)?;
+
+        let bytes_per_sector = bpb.bytes_per_sector as usize;
+        if !bytes_per_sector.is_multiple_of(block_size) {
+            return Err(FatError::UnsupportedBlockSize);
+        }
+        let blocks_per_sector = (bytes_per_sector / block_size) as u64;
+        if bpb.total_sectors as u64 * blocks_per_sector > device.block_count() {
+            return Err(FatError::InvalidBpb);
+        }
+
+        let mut fs = Self {
+            device,
+            bpb,
+            blocks_per_sector,
+            fat_cache: None,
+            free_count: None,
+            next_free: 2,
+            fs_info_dirty: false,
+            clock: || DateTime::EPOCH,
+        };
+        if fs.bpb.fat_type == FatType::Fat32 {
+            fs.read_fs_info()?;
+        }
+        Ok(fs)
+    }
+
+    pub fn bpb(&self) -> &Bpb {
+        &self.bpb
+    }
+
+    pub fn fat_type(&self) -> FatType {
+        self.bpb.fat_type
+    }
+
+    pub fn device(&mut self) -> &mut D {
+        &mut self.device
+    }
+
+    pub fn into_device(self) -> D {
+        self.device
+    }
+
+    /// Set the time source for modification timestamps
+    pub fn set_clock(&mut self, clock: fn() -> DateTime) {
+        self.clock = clock;
+    }
+
+    pub fn cluster_size(&self) -> usize {
+        self.bpb.cluster_size()
+    }
+
+    pub fn root(&self) -> DirEntry {
+        DirEntry::root(self.bpb.root_cluster)
+    }
+
+    /// Find the entry at `path`; `/` is the root directory
+    pub fn lookup(&mut self, path: &str) -> Result<DirEntry> {
+        let mut entry = self.root();
+        for name in path.split('/').filter(|n| !n.is_empty() && *n != ".") {
+            if !entry.is_dir() {
+                return Err(FatError::NotADirectory);
+            }
+            if name == ".." && entry.is_root() {
+                continue;
+            }
+            entry = self
+                .entries(self.dir_of(&entry))?
+                .into_iter()
+                .find(|e| e.matches(name))
+                .ok_or(FatError::NotFound)?;
+            if entry.is_dot() && entry.first_cluster == 0 {
+                // ".." のクラスタ番号 0 はルートを指す
+                entry = self.root();
+            }
+        }
+        Ok(entry)
+    }
+
+    /// Entries of the directory at `path`, without `.` and `..`
+    pub fn read_dir(&mut self, path: &str) -> Result<Vec<DirEntry>> {
+        let dir = self.lookup(path)?;
+        self.read_dir_entry(&dir)
+    }
+
+    pub fn read_dir_entry(&mut self, dir: &DirEntry) -> Result<Vec<DirEntry>> {
+        if !dir.is_dir() {
+            return Err(FatError::NotADirectory);
+        }
+        let mut entries = self.entries(self.dir_of(dir))?;
+        entries.retain(|e| !e.is_dot());
+        Ok(entries)
+    }
+
+    /// Read from `entry` at `offset`; returns the number of bytes read, which
+    /// is short only at the end of the file
+    pub fn read(&mut self, entry: &DirEntry, offset: u64, buf: &mut [u8]) -> Result<usize> {
+        if entry.is_dir() {
+            return Err(FatError::IsADirectory);
+        }
+        let size = entry.size as u64;
+        if offset >= size {
+            return Ok(0);
+        }
+        let len = buf.len().min((size - offset) as usize);
+        let chain = self.chain(entry.first_cluster)?;
+        self.transfer(&chain, offset, len, false, |sector_buf, range, done| {
+            buf[done..done + range.len()].copy_from_slice(&sector_buf[range]);
+        })?;
+        Ok(len)
+    }
+
+    /// Read the whole file at `path`
+    pub fn read_file(&mut self, path: &str) -> Result<Vec<u8>> {
+        let entry = self.lookup(path)?;
+        let mut data = vec![0; entry.size as usize];
+        self.read(&entry, 0, &mut data)?;
+        Ok(data)
+    }
+
+    /// Write `data` to `entry` at `offset`, growing the file as needed
+    ///
+    /// A gap between the old end of the file and `offset` reads as zeros.
+    pub fn write(&mut self, entry: &mut DirEntry, offset: u64, data: &[u8]) -> Result<()> {
+        if entry.is_dir() {
+            return Err(FatError::IsADirectory);
+        }
+        let end = offset + data.len() as u64;
+        if end > u32::MAX as u64 {
+            return Err(FatError::FileTooLarge);
+        }
+
+        let old_size = entry.size as u64;
+        let mut chain = self.chain(entry.first_cluster)?;
+        let needed = end.div_ceil(self.cluster_size() as u64) as usize;
+        let result = self.extend_chain(&mut chain, needed).and_then(|()| {
+            if offset > old_size {
+                self.fill_zero(&chain, old_size, (offset - old_size) as usize)?;
+            }
+            self.transfer(
+                &chain,
+                offset,
+                data.len(),
+                true,
+                |sector_buf, range, done| {
+                    sector_buf[range.clone()].copy_from_slice(&data[done..done + range.len()]);
+                },
+            )
+        });
+
+        // 途中で失敗しても確保済みのクラスタをエントリから辿れるようにしておく
+        entry.first_cluster = chain.first().copied().unwrap_or(0);
+        if result.is_ok() {
+            entry.size = entry.size.max(end as u32);
+        }
+        entry.attributes |= ATTR_ARCHIVE;
+        entry.modified = (self.clock)();
+        self.store_entry(entry)?;
+        self.sync_fs_info()?;
+        result
+    }
+
+    /// Append `data` to the file at `path`
+    pub fn append(&mut self, path: &str, data: &[u8]) -> Result<()> {
+        let mut entry = self.lookup(path)?;
+        let size = entry.size as u64;
+        self.write(&mut entry, size, data)
+    }
+
+    /// Shrink or grow `entry` to `size` bytes
+    pub fn truncate(&mut self, entry: &mut DirEntry, size: u32) -> Result<()> {
+        if entry.is_dir() {
+            return Err(FatError::IsADirectory);
+        }
+        if size > entry.size {
+            return self.write(entry, size as u64, &[]);
+        }
+
+        let keep = (size as usize).div_ceil(self.cluster_size());
+        let chain = self.chain(entry.first_cluster)?;
+        if keep < chain.len() {
+            if keep == 0 {
+                entry.first_cluster = 0;
+            } else {
+                self.set_fat_entry(chain[keep - 1], self.end_of_chain())?;
+            }
+            self.free_chain(chain[keep])?;
+        }
+        entry.size = size;
+        entry.modified = (self.clock)();
+        self.store_entry(entry)?;
+        self.sync_fs_info()
+    }
+
+    /// Create an empty file; fails if `path` already exists
+    pub fn create_file(&mut self, path: &str) -> Result<DirEntry> {
+        let (parent, name) = self.parent_of(path)?;
+        self.add_entry(&parent, name, ATTR_ARCHIVE, 0)
+    }
+
+    pub fn create_dir(&mut self, path: &str) -> Result<DirEntry> {
+        let (parent, name) = self.parent_of(path)?;
+        if !dir::is_valid_name(name) {
+            return Err(FatError::InvalidName);
+        }
+        let cluster = self.allocate_cluster(None)?;
+        let result = self
+            .init_dir(cluster, &parent)
+            .and_then(|()| self.add_entry(&parent, name, ATTR_DIRECTORY, cluster));
+        if result.is_err() {
+            self.free_chain(cluster)?;
+        }
+        self.sync_fs_info()?;
+        result
+    }
+
+    /// Delete a file or an empty directory
+    pub fn remove(&mut self, path: &str) -> Result<()> {
+        let entry = self.lookup(path)?;
+        if entry.is_root() || entry.is_dot() {
+            return Err(FatError::InvalidName);
+        }
+        if entry.is_dir() && !self.read_dir_entry(&entry)?.is_empty() {
+            return Err(FatError::DirectoryNotEmpty);
+        }
+
+        for &slot in &entry.slots {
+            self.update_slot(slot, |raw| raw[0] = dir::DELETED)?;
+        }
+        if entry.first_cluster != 0 {
+            self.free_chain(entry.first_cluster)?;
+        }
+        self.sync_fs_info()
+    }
+
+    /// Number of free clusters, counting them on first use if FSInfo had no
+    /// valid hint
+    pub fn free_clusters(&mut self) -> Result<u32> {
+        if let Some(count) = self.free_count {
+            return Ok(count);
+        }
+        let mut count = 0;
+        for cluster in 2..self.bpb.cluster_count() + 2 {
+            if self.fat_entry(cluster)? == 0 {
+                count += 1;
+            }
+        }
+        self.free_count = Some(count);
+        Ok(count)
+    }
+
+    /// Write back FSInfo and flush the device
+    pub fn flush(&mut self) -> Result<()> {
+        self.sync_fs_info()?;
+        self.device.flush()?;
+        Ok(())
+    }
+
+    // --- sectors ---
+
+    fn read_sector(&mut self, sector: u64, buf: &mut [u8]) -> Result<()> {
+        self.device
+            .read_blocks(sector * self.blocks_per_sector, buf)?;
+        Ok(())
+    }
+
+    fn write_sector(&mut self, sector: u64, buf: &[u8]) -> Result<()> {
+        self.device
+            .write_blocks(sector * self.blocks_per_sector, buf)?;
+        Ok(())
+    }
+
+    fn sector_buf(&self) -> Vec<u8> {
+        vec![0; self.bpb.bytes_per_sector as usize]
+    }
+
+    fn cluster_to_sector(&self, cluster: u32) -> u64 {
+        self.bpb.first_data_sector() as u64
+            + (cluster as u64 - 2) * self.bpb.sectors_per_cluster as u64
+    }
+
+    /// Walk the byte range `offset..offset + len` of a chain sector by
+    /// sector; `f` gets the sector, the range inside it and the bytes done so
+    /// far. With `write` the sectors are written back after `f`.
+    fn transfer(
+        &mut self,
+        chain: &[u32],
+        offset: u64,
+        len: usize,
+        write: bool,
+        mut f: impl FnMut(&mut [u8], core::ops::Range<usize>, usize),
+    ) -> Result<()> {
+        let bytes_per_sector = self.bpb.bytes_per_sector as u64;
+        let cluster_size = self.cluster_size() as u64;
+        let mut buf = self.sector_buf();
+        let mut done = 0;
+        while done < len {
+            let pos = offset + done as u64;
+            let cluster = *chain
+                .get((pos / cluster_size) as usize)
+                .ok_or(FatError::Corrupted)?;
+            let sector = self.cluster_to_sector(cluster) + pos % cluster_size / bytes_per_sector;
+            let start = (pos % bytes_per_sector) as usize;
+            let end = (start + len - done).min(buf.len());
+
+            // セクタ全体を書き換えるときは読み込みを省く
+            if !write || end - start != buf.len() {
+                self.read_sector(sector, &mut buf)?;
+            }
+            f(&mut buf, start..end, done);
+            if write {
+                self.write_sector(sector, &buf)?;
+            }
+            done += end - start;
+        }
+        Ok(())
+    }
+
+    fn fill_zero(&mut self, chain: &[u32], offset: u64, len: usize) -> Result<()> {
+        self.transfer(chain, offset, len, true, |buf, range, _| buf[range].fill(0))
+    }
+
+    // --- FAT ---
+
+    fn end_of_chain(&self) -> u32 {
+        match self.bpb.fat_type {
+            FatType::Fat12 => 0xfff,
+            FatType::Fat16 => 0xffff,
+            FatType::Fat32 => 0x0fff_ffff,
+        }
+    }
+
+    fn is_valid_cluster(&self, cluster: u32) -> bool {
+        (2..self.bpb.cluster_count() + 2).contains(&cluster)
+    }
+
+    /// Byte offset of the entry of `cluster` inside a FAT
+    fn fat_offset(&self, cluster: u32) -> u64 {
+        let cluster = cluster as u64;
+        match self.bpb.fat_type {
+            FatType::Fat12 => cluster + cluster / 2,
+            FatType::Fat16 => cluster * 2,
+            FatType::Fat32 => cluster * 4,
+        }
+    }
+
+    fn fat_byte(&mut self, offset: u64) -> Result<u8> {
+        let bytes_per_sector = self.bpb.bytes_per_sector as u64;
+        let sector = self.bpb.reserved_sectors as u64
+            + self.bpb.active_fat() as u64 * self.bpb.fat_size as u64
+            + offset / bytes_per_sector;
+        if self.fat_cache.as_ref().is_none_or(|(s, _)| *s != sector) {
+            let mut buf = self.sector_buf();
+            self.read_sector(sector, &mut buf)?;
+            self.fat_cache = Some((sector, buf));
+        }
+        let (_, buf) = self.fat_cache.as_ref().unwrap();
+        Ok(buf[(offset % bytes_per_sector) as usize])
+    }
+
+    fn set_fat_byte(&mut self, offset: u64, value: u8) -> Result<()> {
+        let bytes_per_sector = self.bpb.bytes_per_sector as u64;
+        let mut buf = self.sector_buf();
+        for fat in self.bpb.fats_to_update() {
+            let sector = self.bpb.reserved_sectors as u64
+                + fat as u64 * self.bpb.fat_size as u64
+                + offset / bytes_per_sector;
+            self.read_sector(sector, &mut buf)?;
+            buf[(offset % bytes_per_sector) as usize] = value;
+            self.write_sector(sector, &buf)?;
+            if let Some((cached, cache)) = &mut self.fat_cache
+                && *cached == sector
+            {
+                cache.copy_from_slice(&buf);
+            }
+        }
+        Ok(())
+    }
+
+    fn fat_entry(&mut self, cluster: u32) -> Result<u32> {
+        let offset = self.fat_offset(cluster);
+        match self.bpb.fat_type {
+            FatType::Fat12 => {
+                // 12 ビットのエントリはセクタ境界をまたぐことがある
+                let value =
+                    u16::from_le_bytes([self.fat_byte(offset)?, self.fat_byte(offset + 1)?]);
+                Ok(if cluster.is_multiple_of(2) {
+                    value & 0xfff
+                } else {
+                    value >> 4
+                } as u32)
+            }
+            FatType::Fat16 => {
+                Ok(u16::from_le_bytes([self.fat_byte(offset)?, self.fat_byte(offset + 1)?]) as u32)
+            }
+            FatType::Fat32 => Ok(self.fat_entry_raw32(offset)? & 0x0fff_ffff),
+        }
+    }
+
+    fn set_fat_entry(&mut self, cluster: u32, value: u32) -> Result<()> {
+        let offset = self.fat_offset(cluster);
+        let bytes: Vec<u8> = match self.bpb.fat_type {
+            FatType::Fat12 => {
+                let old = u16::from_le_bytes([self.fat_byte(offset)?, self.fat_byte(offset + 1)?]);
+                let new = if cluster.is_multiple_of(2) {
+                    old & 0xf000 | value as u16 & 0xfff
+                } else {
+                    old & 0x000f | (value as u16) << 4
+                };
+                new.to_le_bytes().into()
+            }
+            FatType::Fat16 => (value as u16).to_le_bytes().into(),
+            FatType::Fat32 => {
+                // 上位 4 ビットは予約なので保存する
+                let old = self.fat_entry_raw32(offset)?;
+                (old & 0xf000_0000 | value & 0x0fff_ffff)
+                    .to_le_bytes()
+                    .into()
+            }
+        };
+        for (i, byte) in bytes.into_iter().enumerate() {
+            self.set_fat_byte(offset + i as u64, byte)?;
+        }
+        Ok(())
+    }
+
+    fn fat_entry_raw32(&mut self, offset: u64) -> Result<u32> {
+        let mut bytes = [0; 4];
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            *byte = self.fat_byte(offset + i as u64)?;
+        }
+        Ok(u32::from_le_bytes(bytes))
+    }
+
+    /// Clusters of the chain starting at `start`; empty for `0`
+    fn chain(&mut self, start: u32) -> Result<Vec<u32>> {
+        let mut chain = Vec::new();
+        let mut cluster = start;
+        while cluster != 0 {
+            if !self.is_valid_cluster(cluster) || chain.len() >= self.bpb.cluster_count() as usize {
+                return Err(FatError::Corrupted);
+            }
+            chain.push(cluster);
+            let next = self.fat_entry(cluster)?;
+            // 0xff8 以上 (FAT16/32 も同様) が終端
+            if next >= self.end_of_chain() & !7 {
+                break;
+            }
+            cluster = next;
+        }
+        Ok(chain)
+    }
+
+    /// Allocate clusters until `chain` has `len` of them
+    fn extend_chain(&mut self, chain: &mut Vec<u32>, len: usize) -> Result<()> {
+        while chain.len() < len {
+            let cluster = self.allocate_cluster(chain.last().copied())?;
+            chain.push(cluster);
+        }
+        Ok(())
+    }
+
+    /// Take a free cluster, mark it as the end of a chain and link it after
+    /// `previous`
+    fn allocate_cluster(&mut self, previous: Option<u32>) -> Result<u32> {
+        if self.free_count == Some(0) {
+            return Err(FatError::NoSpace);
+        }
+        let count = self.bpb.cluster_count();
+        let start = if self.is_valid_cluster(self.next_free) {
+            self.next_free
+        } else {
+            2
+        };
+        let mut found = None;
+        for i in 0..count {
+            let cluster = 2 + (start - 2 + i) % count;
+            if self.fat_entry(cluster)? == 0 {
+                found = Some(cluster);
+                break;
+            }
+        }
+        let cluster = found.ok_or(FatError::NoSpace)?;
+
+        self.set_fat_entry(cluster, self.end_of_chain())?;
+        if let Some(previous) = previous {
+            self.set_fat_entry(previous, cluster)?;
+        }
+        self.free_count = self.free_count.map(|n| n - 1);
+        self.next_free = cluster + 1;
+        self.fs_info_dirty = true;
+        Ok(cluster)
+    }
+
+    fn free_chain(&mut self, start: u32) -> Result<()> {
+        for cluster in self.chain(start)? {
+            self.set_fat_entry(cluster, 0)?;
+            self.free_count = self.free_count.map(|n| n + 1);
+        }
+        self.fs_info_dirty = true;
+        Ok(())
+    }
+
+    // --- FSInfo ---
+
+    fn read_fs_info(&mut self) -> Result<()> {
+        let sector = self.bpb.fs_info_sector as u64;
+        if sector == 0 || sector >= self.bpb.reserved_sectors as u64 {
+            return Ok(());
+        }
+        let mut buf = self.sector_buf();
+        self.read_sector(sector, &mut buf)?;
+        let field = |offset: usize| u32::from_le_bytes(buf[offset..offset + 4].try_into().unwrap());
+        if field(0) != FS_INFO_LEAD_SIGNATURE
+            || field(484) != FS_INFO_STRUCT_SIGNATURE
+            || field(508) != FS_INFO_TRAIL_SIGNATURE
+        {
+            return Ok(());
+        }
+        let (free_count, next_free) = (field(488), field(492));
+        // 値はあくまでヒントなので、範囲外なら使わない
+        if free_count != FS_INFO_UNKNOWN && free_count <= self.bpb.cluster_count() {
+            self.free_count = Some(free_count);
+        }
+        if self.is_valid_cluster(next_free) {
+            self.next_free = next_free;
+        }
+        Ok(())
+    }
+
+    fn sync_fs_info(&mut self) -> Result<()> {
+        if !self.fs_info_dirty {
+            return Ok(());
+        }
+        self.fs_info_dirty = false;
+        let sector = self.bpb.fs_info_sector as u64;
+        if self.bpb.fat_type != FatType::Fat32
+            || sector == 0
+            || sector >= self.bpb.reserved_sectors as u64
+        {
+            return Ok(());
+        }
+        let mut buf = self.sector_buf();
+        self.read_sector(sector, &mut buf)?;
+        if u32::from_le_bytes(buf[0..4].try_into().unwrap()) != FS_INFO_LEAD_SIGNATURE {
+            return Ok(());
+        }
+        let free_count = self.free_count.unwrap_or(FS_INFO_UNKNOWN);
+        buf[488..492].copy_from_slice(&free_count.to_le_bytes());
+        buf[492..496].copy_from_slice(&self.next_free.to_le_bytes());
+        self.write_sector(sector, &buf)
+    }
+
+    // --- directories ---
+
+    fn dir_of(&self, entry: &DirEntry) -> Dir {
+        match entry.first_cluster {
+            0 if self.bpb.fat_type == FatType::Fat32 => Dir::Chain(self.bpb.root_cluster),
+            0 => Dir::FixedRoot,
+            cluster => Dir::Chain(cluster),
+        }
+    }
+
+    fn dir_sectors(&mut self, dir: Dir) -> Result<Vec<u64>> {
+        match dir {
+            Dir::FixedRoot => {
+                let first = self.bpb.first_root_dir_sector() as u64;
+                Ok((first..first + self.bpb.root_dir_sectors() as u64).collect())
+            }
+            Dir::Chain(start) => {
+                let per_cluster = self.bpb.sectors_per_cluster as u64;
+                Ok(self
+                    .chain(start)?
+                    .into_iter()
+                    .flat_map(|c| {
+                        let first = self.cluster_to_sector(c);
+                        first..first + per_cluster
+                    })
+                    .collect())
+            }
+        }
+    }
+
+    /// Call `f` with each slot up to the end marker until it returns `false`
+    fn scan_dir(&mut self, dir: Dir, mut f: impl FnMut(SlotLocation, &[u8]) -> bool) -> Result<()> {
+        let mut buf = self.sector_buf();
+        for sector in self.dir_sectors(dir)? {
+            self.read_sector(sector, &mut buf)?;
+            for (i, raw) in buf.chunks_exact(ENTRY_SIZE).enumerate() {
+                let location = SlotLocation {
+                    sector,
+                    offset: i * ENTRY_SIZE,
+                };
+                if !f(location, raw) || raw[0] == dir::END_OF_DIRECTORY {
+                    return Ok(());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// All entries of a directory including `.` and `..`
+    fn entries(&mut self, dir: Dir) -> Result<Vec<DirEntry>> {
+        let mut parser = EntryParser::default();
+        let mut entries = Vec::new();
+        self.scan_dir(dir, |location, raw| {
+            entries.extend(parser.push(location, raw));
+            true
+        })?;
+        Ok(entries)
+    }
+
+    fn update_slot(&mut self, slot: SlotLocation, f: impl FnOnce(&mut [u8])) -> Result<()> {
+        let mut buf = self.sector_buf();
+        self.read_sector(slot.sector, &mut buf)?;
+        f(&mut buf[slot.offset..slot.offset + ENTRY_SIZE]);
+        self.write_sector(slot.sector, &buf)
+    }
+
+    fn store_entry(&mut self, entry: &DirEntry) -> Result<()> {
+        if entry.is_root() {
+            return Ok(());
+        }
+        self.update_slot(entry.short_slot(), |raw| entry.store(raw))
+    }
+
+    fn parent_of<'p>(&mut self, path: &'p str) -> Result<(DirEntry, &'p str)> {
+        let path = path.trim_end_matches('/');
+        let (parent, name) = path.rsplit_once('/').unwrap_or(("", path));
+        let parent = self.lookup(parent)?;
+        if !parent.is_dir() {
+            return Err(FatError::NotADirectory);
+        }
+        Ok((parent, name))
+    }
+
+    /// Zero a new directory cluster and write its `.` and `..` entries
+    fn init_dir(&mut self, cluster: u32, parent: &DirEntry) -> Result<()> {
+        let chain = [cluster];
+        self.fill_zero(&chain, 0, self.cluster_size())?;
+
+        let now = (self.clock)();
+        let parent_cluster = if parent.is_root() {
+            0
+        } else {
+            parent.first_cluster
+        };
+        for (i, (name, first_cluster)) in [
+            (*b".          ", cluster),
+            (*b"..         ", parent_cluster),
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            let mut raw = dir::short_entry(&name, 0, now);
+            let entry = DirEntry {
+                name: Default::default(),
+                short_name: name,
+                attributes: ATTR_DIRECTORY,
+                first_cluster,
+                size: 0,
+                modified: now,
+                slots: Vec::new(),
+            };
+            entry.store(&mut raw);
+            let slot = SlotLocation {
+                sector: self.cluster_to_sector(cluster),
+                offset: i * ENTRY_SIZE,
+            };
+            self.update_slot(slot, |dst| dst.copy_from_slice(&raw))?;
+        }
+        Ok(())
+    }
+
+    /// Store a new entry named `name` in `parent`, with long name slots if the
+    /// name is not a plain 8.3 name
+    fn add_entry(
+        &mut self,
+        parent: &DirEntry,
+        name: &str,
+        attributes: u8,
+        first_cluster: u32,
+    ) -> Result<DirEntry> {
+        if !dir::is_valid_name(name) {
+            return Err(FatError::InvalidName);
+        }
+        let dir = self.dir_of(parent);
+        let existing = self.entries(dir)?;
+        if existing.iter().any(|e| e.matches(name)) {
+            return Err(FatError::AlreadyExists);
+        }
+        let taken = |short: &[u8; 11]| existing.iter().any(|e| e.short_name == *short);
+
+        let (short_name, nt_flags, long_slots) = match dir::exact_short_name(name) {
+            Some((short, flags)) if !taken(&short) => (short, flags, Vec::new()),
+            _ => {
+                let short = (1..1_000_000)
+                    .map(|n| dir::generated_short_name(name, n))
+                    .find(|short| !taken(short))
+                    .ok_or(FatError::AlreadyExists)?;
+                (
+                    short,
+                    0,
+                    dir::long_name_slots(name, dir::lfn_checksum(&short)),
+                )
+            }
+        };
+
+        let slots = self.find_free_slots(dir, long_slots.len() + 1)?;
+        let now = (self.clock)();
+        for (&slot, raw) in slots.iter().zip(&long_slots) {
+            self.update_slot(slot, |dst| dst.copy_from_slice(raw))?;
+        }
+        let entry = DirEntry {
+            name: name.into(),
+            short_name,
+            attributes,
+            first_cluster,
+            size: 0,
+            modified: now,
+            slots,
+        };
+        let mut raw = dir::short_entry(&short_name, nt_flags, now);
+        entry.store(&mut raw);
+        self.update_slot(entry.short_slot(), |dst| dst.copy_from_slice(&raw))?;
+        self.sync_fs_info()?;
+        Ok(entry)
+    }
+
+    /// Locations of `count` consecutive free slots, growing the directory if
+    /// there are none
+    fn find_free_slots(&mut self, dir: Dir, count: usize) -> Result<Vec<SlotLocation>> {
+        let mut run = Vec::new();
+        let mut at_end = false;
+        self.scan_dir(dir, |location, raw| {
+            if raw[0] == dir::DELETED || raw[0] == dir::END_OF_DIRECTORY {
+                run.push(location);
+            } else {
+                run.clear();
+            }
+            at_end = raw[0] == dir::END_OF_DIRECTORY;
+            run.len() < count
+        })?;
+        if run.len() == count {
+            return Ok(run);
+        }
+
+        // 終端マーカー以降はすべて空きなので、残りのスロットも使える
+        let per_sector = self.bpb.bytes_per_sector as usize / ENTRY_SIZE;
+        if at_end {
+            let sectors = self.dir_sectors(dir)?;
+            let last = *run.last().unwrap();
+            let index = sectors.iter().position(|&s| s == last.sector).unwrap() * per_sector
+                + last.offset / ENTRY_SIZE;
+            let total = sectors.len() * per_sector;
+            for i in index + 1..total.min(index + 1 + count - run.len()) {
+                run.push(SlotLocation {
+                    sector: sectors[i / per_sector],
+                    offset: i % per_sector * ENTRY_SIZE,
+                });
+            }
+        }
+
+        // 末尾の空きに続けて、足りない分のクラスタを追加する
+        if run.len() < count {
+            let Dir::Chain(start) = dir else {
+                return Err(FatError::NoSpace);
+            };
+            let mut last = *self.chain(start)?.last().ok_or(FatError::Corrupted)?;
+            while run.len() < count {
+                last = self.allocate_cluster(Some(last))?;
+                self.fill_zero(&[last], 0, self.cluster_size())?;
+                let first = self.cluster_to_sector(last);
+                let slots = (0..self.cluster_size() / ENTRY_SIZE).map(|i| SlotLocation {
+                    sector: first + (i / per_sector) as u64,
+                    offset: i % per_sector * ENTRY_SIZE,
+                });
+                run.extend(slots.take(count - run.len()));
+            }
+        }
+        Ok(run)
+    }
+}
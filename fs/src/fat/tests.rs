@@ -0,0 +1,731 @@
+//! Tests over freshly formatted images
+//!
+//! The images are laid out the way `mkfs.fat` lays them out for the flags
+//! given on each [`Format`], so that each test can start from a known
+//! layout. Raw FAT and directory bytes are checked with decoders written
+//! independently of the code under test; `tests/images.rs` covers images
+//! made by another implementation.
+
+extern crate std;
+
+use std::{string::String, vec, vec::Vec};
+
+use super::*;
+use crate::block::MemoryDevice;
+
+const SECTOR: usize = 512;
+
+struct Format {
+    fat_type: FatType,
+    total_sectors: u32,
+    sectors_per_cluster: u8,
+    reserved_sectors: u16,
+    root_entries: u16,
+    media: u8,
+}
+
+/// `mkfs.fat -F 12 -C fat12.img 1440` (a 1.44 MB floppy)
+const FAT12: Format = Format {
+    fat_type: FatType::Fat12,
+    total_sectors: 2880,
+    sectors_per_cluster: 1,
+    reserved_sectors: 1,
+    root_entries: 224,
+    media: 0xf0,
+};
+
+/// `mkfs.fat -F 16 -s 2 -R 1 -r 512 -C fat16.img 8192`
+const FAT16: Format = Format {
+    fat_type: FatType::Fat16,
+    total_sectors: 16384,
+    sectors_per_cluster: 2,
+    reserved_sectors: 1,
+    root_entries: 512,
+    media: 0xf8,
+};
+
+/// `mkfs.fat -F 32 -s 1 -R 32 -C fat32.img 33792`
+const FAT32: Format = Format {
+    fat_type: FatType::Fat32,
+    total_sectors: 67584,
+    sectors_per_cluster: 1,
+    reserved_sectors: 32,
+    root_entries: 0,
+    media: 0xf8,
+};
+
+const FS_INFO_SECTOR: usize = 1;
+const BACKUP_BOOT_SECTOR: usize = 6;
+
+impl Format {
+    fn entry_bits(&self) -> u32 {
+        match self.fat_type {
+            FatType::Fat12 => 12,
+            FatType::Fat16 => 16,
+            FatType::Fat32 => 32,
+        }
+    }
+
+    fn root_dir_sectors(&self) -> u32 {
+        (self.root_entries as u32 * 32).div_ceil(SECTOR as u32)
+    }
+
+    /// Smallest FAT that covers every cluster of the data area it leaves
+    fn fat_size(&self) -> u32 {
+        let mut fat_size = 1;
+        loop {
+            let data = self.total_sectors
+                - self.reserved_sectors as u32
+                - 2 * fat_size
+                - self.root_dir_sectors();
+            let clusters = data / self.sectors_per_cluster as u32;
+            let needed = ((clusters + 2) * self.entry_bits()).div_ceil(8 * SECTOR as u32);
+            if needed <= fat_size {
+                return fat_size;
+            }
+            fat_size = needed;
+        }
+    }
+
+    fn cluster_count(&self) -> u32 {
+        (self.total_sectors
+            - self.reserved_sectors as u32
+            - 2 * self.fat_size()
+            - self.root_dir_sectors())
+            / self.sectors_per_cluster as u32
+    }
+
+    fn mkfs(&self) -> Vec<u8> {
+        let mut image = vec![0u8; self.total_sectors as usize * SECTOR];
+        let fat_size = self.fat_size();
+
+        let boot = &mut image[..SECTOR];
+        boot[..3].copy_from_slice(&[0xeb, 0x3c, 0x90]);
+        boot[3..11].copy_from_slice(b"mkfs.fat");
+        boot[11..13].copy_from_slice(&(SECTOR as u16).to_le_bytes());
+        boot[13] = self.sectors_per_cluster;
+        boot[14..16].copy_from_slice(&self.reserved_sectors.to_le_bytes());
+        boot[16] = 2;
+        boot[17..19].copy_from_slice(&self.root_entries.to_le_bytes());
+        if self.total_sectors < 0x10000 {
+            boot[19..21].copy_from_slice(&(self.total_sectors as u16).to_le_bytes());
+        } else {
+            boot[32..36].copy_from_slice(&self.total_sectors.to_le_bytes());
+        }
+        boot[21] = self.media;
+        boot[24..26].copy_from_slice(&32u16.to_le_bytes());
+        boot[26..28].copy_from_slice(&64u16.to_le_bytes());
+        let ebpb = if self.fat_type == FatType::Fat32 {
+            boot[0..3].copy_from_slice(&[0xeb, 0x58, 0x90]);
+            boot[36..40].copy_from_slice(&fat_size.to_le_bytes());
+            boot[44..48].copy_from_slice(&2u32.to_le_bytes());
+            boot[48..50].copy_from_slice(&(FS_INFO_SECTOR as u16).to_le_bytes());
+            boot[50..52].copy_from_slice(&(BACKUP_BOOT_SECTOR as u16).to_le_bytes());
+            64
+        } else {
+            boot[22..24].copy_from_slice(&(fat_size as u16).to_le_bytes());
+            36
+        };
+        boot[ebpb] = 0x80;
+        boot[ebpb + 2] = 0x29;
+        boot[ebpb + 3..ebpb + 7].copy_from_slice(&0x1234_5678u32.to_le_bytes());
+        boot[ebpb + 7..ebpb + 18].copy_from_slice(b"NO NAME    ");
+        boot[ebpb + 18..ebpb + 26].copy_from_slice(match self.fat_type {
+            FatType::Fat12 => b"FAT12   ",
+            FatType::Fat16 => b"FAT16   ",
+            FatType::Fat32 => b"FAT32   ",
+        });
+        boot[510..512].copy_from_slice(&[0x55, 0xaa]);
+
+        if self.fat_type == FatType::Fat32 {
+            let info = &mut image[FS_INFO_SECTOR * SECTOR..][..SECTOR];
+            info[0..4].copy_from_slice(&0x4161_5252u32.to_le_bytes());
+            info[484..488].copy_from_slice(&0x6141_7272u32.to_le_bytes());
+            // ルートディレクトリがクラスタ 2 を使っている
+            info[488..492].copy_from_slice(&(self.cluster_count() - 1).to_le_bytes());
+            info[492..496].copy_from_slice(&3u32.to_le_bytes());
+            info[508..512].copy_from_slice(&0xaa55_0000u32.to_le_bytes());
+            image.copy_within(0..2 * SECTOR, BACKUP_BOOT_SECTOR * SECTOR);
+        }
+
+        let reserved: &[u8] = match self.fat_type {
+            FatType::Fat12 => &[self.media, 0xff, 0xff],
+            FatType::Fat16 => &[self.media, 0xff, 0xff, 0xff],
+            FatType::Fat32 => &[
+                self.media, 0xff, 0xff, 0x0f, 0xff, 0xff, 0xff, 0x0f, 0xff, 0xff, 0xff, 0x0f,
+            ],
+        };
+        for fat in 0..2 {
+            let start = (self.reserved_sectors as usize + fat * fat_size as usize) * SECTOR;
+            image[start..start + reserved.len()].copy_from_slice(reserved);
+        }
+        image
+    }
+}
+
+/// FAT entry `cluster` of the first FAT, decoded straight from the image
+fn raw_fat_entry(image: &[u8], bpb: &Bpb, cluster: u32) -> u32 {
+    let fat = &image[bpb.reserved_sectors as usize * SECTOR..];
+    let n = cluster as usize;
+    match bpb.fat_type {
+        FatType::Fat12 => {
+            let pair = u16::from_le_bytes([fat[n * 3 / 2], fat[n * 3 / 2 + 1]]);
+            (if n.is_multiple_of(2) {
+                pair & 0xfff
+            } else {
+                pair >> 4
+            }) as u32
+        }
+        FatType::Fat16 => u16::from_le_bytes([fat[n * 2], fat[n * 2 + 1]]) as u32,
+        FatType::Fat32 => {
+            u32::from_le_bytes(fat[n * 4..n * 4 + 4].try_into().unwrap()) & 0x0fff_ffff
+        }
+    }
+}
+
+fn set_raw_fat_entry(image: &mut [u8], bpb: &Bpb, cluster: u32, value: u32) {
+    for copy in 0..bpb.num_fats as usize {
+        let start = (bpb.reserved_sectors as usize + copy * bpb.fat_size as usize) * SECTOR;
+        let fat = &mut image[start..];
+        let n = cluster as usize;
+        match bpb.fat_type {
+            FatType::Fat12 => {
+                let at = n * 3 / 2;
+                let old = u16::from_le_bytes([fat[at], fat[at + 1]]);
+                let new = if n.is_multiple_of(2) {
+                    old & 0xf000 | value as u16
+                } else {
+                    old & 0x000f | (value as u16) << 4
+                };
+                fat[at..at + 2].copy_from_slice(&new.to_le_bytes());
+            }
+            FatType::Fat16 => fat[n * 2..n * 2 + 2].copy_from_slice(&(value as u16).to_le_bytes()),
+            FatType::Fat32 => fat[n * 4..n * 4 + 4].copy_from_slice(&value.to_le_bytes()),
+        }
+    }
+}
+
+fn end_of_chain(bpb: &Bpb) -> u32 {
+    match bpb.fat_type {
+        FatType::Fat12 => 0xfff,
+        FatType::Fat16 => 0xffff,
+        FatType::Fat32 => 0x0fff_ffff,
+    }
+}
+
+/// Clusters reachable from `start` in the image
+fn raw_chain(image: &[u8], bpb: &Bpb, start: u32) -> Vec<u32> {
+    let mut chain = vec![start];
+    loop {
+        let next = raw_fat_entry(image, bpb, *chain.last().unwrap());
+        if next >= end_of_chain(bpb) - 7 {
+            return chain;
+        }
+        chain.push(next);
+    }
+}
+
+fn cluster_offset(bpb: &Bpb, cluster: u32) -> usize {
+    (bpb.first_data_sector() as usize + (cluster as usize - 2) * bpb.sectors_per_cluster as usize)
+        * SECTOR
+}
+
+fn root_dir_offset(bpb: &Bpb) -> usize {
+    match bpb.fat_type {
+        FatType::Fat32 => cluster_offset(bpb, bpb.root_cluster),
+        _ => bpb.first_root_dir_sector() as usize * SECTOR,
+    }
+}
+
+/// LFN checksum as written in the VFAT specification
+fn spec_checksum(short_name: &[u8; 11]) -> u8 {
+    let mut sum: u8 = 0;
+    for &c in short_name {
+        sum = (if sum & 1 != 0 { 0x80u8 } else { 0 })
+            .wrapping_add(sum >> 1)
+            .wrapping_add(c);
+    }
+    sum
+}
+
+/// Raw short entry for a file
+fn raw_short_entry(short_name: &[u8; 11], cluster: u32, size: u32) -> [u8; 32] {
+    let mut raw = [0u8; 32];
+    raw[..11].copy_from_slice(short_name);
+    raw[11] = ATTR_ARCHIVE;
+    raw[20..22].copy_from_slice(&((cluster >> 16) as u16).to_le_bytes());
+    raw[26..28].copy_from_slice(&(cluster as u16).to_le_bytes());
+    raw[28..32].copy_from_slice(&size.to_le_bytes());
+    raw
+}
+
+/// Long name slots the way Linux vfat writes them, highest sequence first
+fn raw_long_slots(name: &str, short_name: &[u8; 11]) -> Vec<[u8; 32]> {
+    let mut units: Vec<u16> = name.encode_utf16().collect();
+    let count = units.len().div_ceil(13);
+    if !units.len().is_multiple_of(13) {
+        units.push(0);
+    }
+    units.resize(count * 13, 0xffff);
+    let checksum = spec_checksum(short_name);
+    (0..count)
+        .rev()
+        .map(|i| {
+            let mut raw = [0u8; 32];
+            raw[0] = (i + 1) as u8 | if i + 1 == count { 0x40 } else { 0 };
+            raw[11] = 0x0f;
+            raw[13] = checksum;
+            let chunk = &units[i * 13..i * 13 + 13];
+            let bytes = chunk.iter().flat_map(|u| u.to_le_bytes());
+            let mut positions = (1..11).chain(14..26).chain(28..32);
+            for byte in bytes {
+                raw[positions.next().unwrap()] = byte;
+            }
+            raw
+        })
+        .collect()
+}
+
+fn mount(image: &mut [u8]) -> FileSystem<MemoryDevice<'_>> {
+    FileSystem::mount(MemoryDevice::new(image, SECTOR)).unwrap()
+}
+
+fn pattern(len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i * 7 + i / 251) as u8).collect()
+}
+
+#[test]
+fn bpb_fat12() {
+    let image = FAT12.mkfs();
+    let bpb = Bpb::parse(&image[..SECTOR]).unwrap();
+    assert_eq!(bpb.fat_type, FatType::Fat12);
+    assert_eq!(bpb.fat_size, 9);
+    assert_eq!(bpb.root_entry_count, 224);
+    assert_eq!(bpb.root_dir_sectors(), 14);
+    assert_eq!(bpb.first_root_dir_sector(), 19);
+    assert_eq!(bpb.first_data_sector(), 33);
+    assert_eq!(bpb.cluster_count(), 2847);
+    assert_eq!(bpb.cluster_size(), 512);
+    assert_eq!(&bpb.volume_label, b"NO NAME    ");
+}
+
+#[test]
+fn bpb_fat16() {
+    let image = FAT16.mkfs();
+    let bpb = Bpb::parse(&image[..SECTOR]).unwrap();
+    assert_eq!(bpb.fat_type, FatType::Fat16);
+    assert_eq!(bpb.fat_size, 32);
+    assert_eq!(bpb.root_dir_sectors(), 32);
+    assert_eq!(bpb.first_data_sector(), 1 + 64 + 32);
+    assert_eq!(bpb.cluster_count(), FAT16.cluster_count());
+    assert_eq!(bpb.cluster_size(), 1024);
+    assert_eq!(bpb.fats_to_update(), 0..2);
+}
+
+#[test]
+fn bpb_fat32() {
+    let mut image = FAT32.mkfs();
+    let bpb = Bpb::parse(&image[..SECTOR]).unwrap();
+    assert_eq!(bpb.fat_type, FatType::Fat32);
+    assert_eq!(bpb.root_entry_count, 0);
+    assert_eq!(bpb.total_sectors, 67584);
+    assert_eq!(bpb.fat_size, FAT32.fat_size());
+    assert_eq!(bpb.root_cluster, 2);
+    assert_eq!(bpb.fs_info_sector, 1);
+    assert!(bpb.cluster_count() >= 65525);
+    assert_eq!(&bpb.volume_label, b"NO NAME    ");
+
+    // ExtFlags でミラーリングを止めると、アクティブな FAT だけを書く
+    image[40..42].copy_from_slice(&0x0081u16.to_le_bytes());
+    let bpb = Bpb::parse(&image[..SECTOR]).unwrap();
+    assert_eq!(bpb.fats_to_update(), 1..2);
+    image[40..42].copy_from_slice(&0x0082u16.to_le_bytes());
+    assert_eq!(
+        Bpb::parse(&image[..SECTOR]).unwrap_err(),
+        FatError::InvalidBpb
+    );
+}
+
+#[test]
+fn bpb_rejects_bad_geometry() {
+    let image = FAT16.mkfs();
+    let mut sector = image[..SECTOR].to_vec();
+    sector[510] = 0;
+    assert_eq!(Bpb::parse(&sector).unwrap_err(), FatError::InvalidBpb);
+
+    let mut sector = image[..SECTOR].to_vec();
+    sector[11..13].copy_from_slice(&500u16.to_le_bytes());
+    assert_eq!(Bpb::parse(&sector).unwrap_err(), FatError::InvalidBpb);
+
+    // 255 個の FAT × 巨大な FAT サイズは 32 ビットに収まらない
+    let mut sector = image[..SECTOR].to_vec();
+    sector[16] = 255;
+    sector[22..24].copy_from_slice(&0u16.to_le_bytes());
+    sector[36..40].copy_from_slice(&0x0200_0000u32.to_le_bytes());
+    assert_eq!(Bpb::parse(&sector).unwrap_err(), FatError::InvalidBpb);
+
+    // FAT とルートディレクトリだけでボリュームが埋まる
+    let mut sector = image[..SECTOR].to_vec();
+    sector[22..24].copy_from_slice(&8192u16.to_le_bytes());
+    assert_eq!(Bpb::parse(&sector).unwrap_err(), FatError::InvalidBpb);
+
+    let mut image = FAT32.mkfs();
+    image[44..48].copy_from_slice(&1u32.to_le_bytes());
+    assert_eq!(
+        Bpb::parse(&image[..SECTOR]).unwrap_err(),
+        FatError::InvalidBpb
+    );
+}
+
+#[test]
+fn mount_rejects_a_truncated_device() {
+    let mut image = FAT12.mkfs();
+    image.truncate(2000 * SECTOR);
+    let result = FileSystem::mount(MemoryDevice::new(&mut image, SECTOR));
+    assert_eq!(result.err(), Some(FatError::InvalidBpb));
+}
+
+#[test]
+fn fresh_volumes_are_empty() {
+    for format in [FAT12, FAT16, FAT32] {
+        let mut image = format.mkfs();
+        let mut fs = mount(&mut image);
+        assert_eq!(fs.fat_type(), format.fat_type);
+        assert!(fs.read_dir("/").unwrap().is_empty());
+        let used = if format.fat_type == FatType::Fat32 {
+            1
+        } else {
+            0
+        };
+        assert_eq!(fs.free_clusters().unwrap(), format.cluster_count() - used);
+    }
+}
+
+#[test]
+fn fat12_chain_packs_entries_in_12_bits() {
+    let mut image = FAT12.mkfs();
+    let data = pattern(5 * 512 + 100);
+    {
+        let mut fs = mount(&mut image);
+        let mut file = fs.create_file("/DATA.BIN").unwrap();
+        fs.write(&mut file, 0, &data).unwrap();
+    }
+
+    let bpb = Bpb::parse(&image[..SECTOR]).unwrap();
+    let raw = &image[root_dir_offset(&bpb)..][..32];
+    assert_eq!(&raw[..11], b"DATA    BIN");
+    let first = u16::from_le_bytes([raw[26], raw[27]]) as u32;
+    let chain = raw_chain(&image, &bpb, first);
+    assert_eq!(chain, [2, 3, 4, 5, 6, 7]);
+    // クラスタ 2 と 3 は 3 バイトに詰めて格納される
+    let fat = &image[SECTOR..];
+    assert_eq!(&fat[3..6], &[0x03, 0x40, 0x00]);
+    for (i, &cluster) in chain.iter().enumerate() {
+        let start = i * 512;
+        let end = (start + 512).min(data.len());
+        assert_eq!(
+            &image[cluster_offset(&bpb, cluster)..][..end - start],
+            &data[start..end]
+        );
+    }
+}
+
+#[test]
+fn reads_a_fragmented_chain() {
+    for format in [FAT12, FAT16, FAT32] {
+        let mut image = format.mkfs();
+        let bpb = Bpb::parse(&image[..SECTOR]).unwrap();
+        let cluster_size = bpb.cluster_size();
+        // 逆順にばらけたチェーン
+        let chain = [40, 11, 25];
+        let data = pattern(cluster_size * 2 + 10);
+        for (i, pair) in chain.windows(2).enumerate() {
+            set_raw_fat_entry(&mut image, &bpb, pair[0], pair[1]);
+            let at = cluster_offset(&bpb, pair[0]);
+            image[at..at + cluster_size].copy_from_slice(&data[i * cluster_size..][..cluster_size]);
+        }
+        set_raw_fat_entry(&mut image, &bpb, 25, end_of_chain(&bpb));
+        let at = cluster_offset(&bpb, 25);
+        image[at..at + 10].copy_from_slice(&data[2 * cluster_size..]);
+        let at = root_dir_offset(&bpb);
+        image[at..at + 32].copy_from_slice(&raw_short_entry(b"FRAG    DAT", 40, data.len() as u32));
+
+        let mut fs = mount(&mut image);
+        assert_eq!(fs.read_file("/frag.dat").unwrap(), data);
+        let file = fs.lookup("/FRAG.DAT").unwrap();
+        let mut buf = vec![0; 20];
+        assert_eq!(
+            fs.read(&file, cluster_size as u64 * 2 - 10, &mut buf)
+                .unwrap(),
+            20
+        );
+        assert_eq!(buf, &data[cluster_size * 2 - 10..]);
+    }
+}
+
+#[test]
+fn chain_loops_and_out_of_range_links_are_corrupted() {
+    let mut image = FAT16.mkfs();
+    let bpb = Bpb::parse(&image[..SECTOR]).unwrap();
+    set_raw_fat_entry(&mut image, &bpb, 10, 11);
+    set_raw_fat_entry(&mut image, &bpb, 11, 10);
+    set_raw_fat_entry(&mut image, &bpb, 20, 0xfff0);
+    let at = root_dir_offset(&bpb);
+    image[at..at + 32].copy_from_slice(&raw_short_entry(b"LOOP    BIN", 10, 1 << 20));
+    image[at + 32..at + 64].copy_from_slice(&raw_short_entry(b"WILD    BIN", 20, 4096));
+
+    let mut fs = mount(&mut image);
+    assert_eq!(fs.read_file("/LOOP.BIN").unwrap_err(), FatError::Corrupted);
+    assert_eq!(fs.read_file("/WILD.BIN").unwrap_err(), FatError::Corrupted);
+    assert_eq!(fs.remove("/LOOP.BIN").unwrap_err(), FatError::Corrupted);
+}
+
+#[test]
+fn reads_long_names_written_by_another_driver() {
+    for format in [FAT12, FAT32] {
+        let mut image = format.mkfs();
+        let bpb = Bpb::parse(&image[..SECTOR]).unwrap();
+        let name = "A rather long file name.text";
+        let short = b"ARATHE~1TEX";
+        let mut at = root_dir_offset(&bpb);
+        for slot in raw_long_slots(name, short) {
+            image[at..at + 32].copy_from_slice(&slot);
+            at += 32;
+        }
+        image[at..at + 32].copy_from_slice(&raw_short_entry(short, 0, 0));
+        // チェックサムの合わない長い名前は無視して短い名前を使う
+        at += 32;
+        let mut orphan = raw_long_slots("Orphaned name", b"ORPHAN~1   ");
+        orphan[0][13] ^= 0xff;
+        for slot in orphan {
+            image[at..at + 32].copy_from_slice(&slot);
+            at += 32;
+        }
+        image[at..at + 32].copy_from_slice(&raw_short_entry(b"ORPHAN~1   ", 0, 0));
+
+        let mut fs = mount(&mut image);
+        let names: Vec<String> = fs
+            .read_dir("/")
+            .unwrap()
+            .into_iter()
+            .map(|e| e.name)
+            .collect();
+        assert_eq!(names, [name, "ORPHAN~1"]);
+        assert!(fs.lookup("/a RATHER long FILE name.TEXT").is_ok());
+        assert!(fs.lookup("/ARATHE~1.TEX").is_ok());
+    }
+}
+
+#[test]
+fn creates_long_names_other_drivers_can_read() {
+    let mut image = FAT16.mkfs();
+    {
+        let mut fs = mount(&mut image);
+        fs.create_file("/Long file name.txt").unwrap();
+        fs.create_file("/Long file name two.txt").unwrap();
+        fs.create_file("/lower.txt").unwrap();
+    }
+
+    let bpb = Bpb::parse(&image[..SECTOR]).unwrap();
+    let root = &image[root_dir_offset(&bpb)..];
+    let slots: Vec<&[u8]> = root.chunks_exact(32).take(7).collect();
+    // 19 文字 -> LFN スロット 2 個 + 短いエントリ
+    assert_eq!(slots[0][0], 0x42);
+    assert_eq!(slots[1][0], 0x01);
+    assert_eq!(slots[0][11], 0x0f);
+    assert_eq!(&slots[2][..11], b"LONGFI~1TXT");
+    assert_eq!(slots[0][13], spec_checksum(b"LONGFI~1TXT"));
+    assert_eq!(slots[1][13], spec_checksum(b"LONGFI~1TXT"));
+    assert_eq!(
+        raw_long_slots("Long file name.txt", b"LONGFI~1TXT"),
+        [
+            <[u8; 32]>::try_from(slots[0]).unwrap(),
+            <[u8; 32]>::try_from(slots[1]).unwrap(),
+        ]
+    );
+    // 短い名前が重なったら数字を進める
+    assert_eq!(&slots[5][..11], b"LONGFI~2TXT");
+    // 小文字だけの 8.3 名は NT フラグで表し、LFN を使わない
+    assert_eq!(&slots[6][..11], b"LOWER   TXT");
+    assert_eq!(slots[6][12], 0x18);
+
+    let mut fs = mount(&mut image);
+    let names: Vec<String> = fs
+        .read_dir("/")
+        .unwrap()
+        .into_iter()
+        .map(|e| e.name)
+        .collect();
+    assert_eq!(
+        names,
+        ["Long file name.txt", "Long file name two.txt", "lower.txt"]
+    );
+    assert_eq!(
+        fs.create_file("/LONG FILE NAME.TXT").unwrap_err(),
+        FatError::AlreadyExists
+    );
+    assert_eq!(
+        fs.create_file("/bad:name").unwrap_err(),
+        FatError::InvalidName
+    );
+}
+
+#[test]
+fn create_append_truncate_delete() {
+    for format in [FAT12, FAT16, FAT32] {
+        let mut image = format.mkfs();
+        let mut fs = mount(&mut image);
+        let cluster_size = fs.cluster_size();
+        let free = fs.free_clusters().unwrap();
+
+        fs.create_dir("/docs").unwrap();
+        let mut file = fs.create_file("/docs/notes.txt").unwrap();
+        assert_eq!(fs.free_clusters().unwrap(), free - 1);
+        assert_eq!(file.first_cluster, 0);
+
+        let data = pattern(cluster_size * 3 + 1);
+        fs.write(&mut file, 0, &data[..100]).unwrap();
+        fs.append("/docs/notes.txt", &data[100..]).unwrap();
+        assert_eq!(fs.read_file("/docs/notes.txt").unwrap(), data);
+        assert_eq!(fs.free_clusters().unwrap(), free - 1 - 4);
+
+        let mut file = fs.lookup("/docs/notes.txt").unwrap();
+        assert_eq!(file.size as usize, data.len());
+        fs.truncate(&mut file, cluster_size as u32 + 5).unwrap();
+        assert_eq!(fs.free_clusters().unwrap(), free - 1 - 2);
+        assert_eq!(
+            fs.read_file("/docs/notes.txt").unwrap(),
+            &data[..cluster_size + 5]
+        );
+
+        // 伸ばした部分はゼロで読める
+        fs.truncate(&mut file, cluster_size as u32 * 2 + 5).unwrap();
+        let grown = fs.read_file("/docs/notes.txt").unwrap();
+        assert_eq!(&grown[..cluster_size + 5], &data[..cluster_size + 5]);
+        assert!(grown[cluster_size + 5..].iter().all(|&b| b == 0));
+
+        fs.truncate(&mut file, 0).unwrap();
+        assert_eq!(file.first_cluster, 0);
+        assert_eq!(fs.free_clusters().unwrap(), free - 1);
+
+        assert_eq!(fs.remove("/docs").unwrap_err(), FatError::DirectoryNotEmpty);
+        fs.remove("/docs/notes.txt").unwrap();
+        assert_eq!(
+            fs.lookup("/docs/notes.txt").unwrap_err(),
+            FatError::NotFound
+        );
+        fs.remove("/docs").unwrap();
+        assert!(fs.read_dir("/").unwrap().is_empty());
+        assert_eq!(fs.free_clusters().unwrap(), free);
+    }
+}
+
+#[test]
+fn changes_survive_a_remount() {
+    let mut image = FAT12.mkfs();
+    let data = pattern(3000);
+    {
+        let mut fs = mount(&mut image);
+        fs.create_dir("/a").unwrap();
+        fs.create_dir("/a/b").unwrap();
+        let mut file = fs.create_file("/a/b/file with spaces.bin").unwrap();
+        fs.write(&mut file, 0, &data).unwrap();
+        fs.flush().unwrap();
+    }
+    let mut fs = mount(&mut image);
+    assert_eq!(fs.read_file("/a/b/file with spaces.bin").unwrap(), data);
+    assert_eq!(
+        fs.read_file("/a/b/../b/./file with spaces.bin").unwrap(),
+        data
+    );
+    assert!(fs.lookup("/a/b/..").unwrap().is_dir());
+    assert_eq!(fs.read_dir("/a").unwrap().len(), 1);
+}
+
+#[test]
+fn fixed_root_directory_holds_512_entries() {
+    let mut image = FAT16.mkfs();
+    let mut fs = mount(&mut image);
+    let free = fs.free_clusters().unwrap();
+    for i in 0..512 {
+        fs.create_file(&std::format!("/F{:05}.TXT", i)).unwrap();
+    }
+    assert_eq!(fs.read_dir("/").unwrap().len(), 512);
+    // 固定長のルートはクラスタを足して伸ばせない
+    assert_eq!(fs.create_file("/ONE_MORE").unwrap_err(), FatError::NoSpace);
+    assert_eq!(fs.create_dir("/DIR").unwrap_err(), FatError::NoSpace);
+    assert_eq!(fs.free_clusters().unwrap(), free);
+
+    // 削除したスロットは再利用するが、LFN は連続した空きが要る
+    fs.remove("/F00100.TXT").unwrap();
+    assert_eq!(
+        fs.create_file("/a long name").unwrap_err(),
+        FatError::NoSpace
+    );
+    fs.create_file("/LAST.TXT").unwrap();
+    assert_eq!(fs.read_dir("/").unwrap().len(), 512);
+}
+
+#[test]
+fn subdirectories_grow_past_one_cluster() {
+    let mut image = FAT12.mkfs();
+    let mut fs = mount(&mut image);
+    fs.create_dir("/many").unwrap();
+    // 512 バイトのクラスタに 16 スロット
+    for i in 0..40 {
+        fs.create_file(&std::format!("/many/{}.txt", i)).unwrap();
+    }
+    assert_eq!(fs.read_dir("/many").unwrap().len(), 40);
+    let dir = fs.lookup("/many").unwrap();
+    assert_eq!(fs.chain(dir.first_cluster).unwrap().len(), 3);
+}
+
+#[test]
+fn fs_info_free_count_follows_allocations() {
+    let mut image = FAT32.mkfs();
+    let info = FS_INFO_SECTOR * SECTOR;
+    let free_count =
+        |image: &[u8]| u32::from_le_bytes(image[info + 488..info + 492].try_into().unwrap());
+    let initial = free_count(&image);
+    assert_eq!(initial, FAT32.cluster_count() - 1);
+
+    {
+        let mut fs = mount(&mut image);
+        let mut file = fs.create_file("/big.bin").unwrap();
+        fs.write(&mut file, 0, &pattern(512 * 5)).unwrap();
+        fs.flush().unwrap();
+    }
+    assert_eq!(free_count(&image), initial - 5);
+    let next_free = u32::from_le_bytes(image[info + 492..info + 496].try_into().unwrap());
+    assert_eq!(next_free, 8);
+
+    {
+        let mut fs = mount(&mut image);
+        assert_eq!(fs.free_clusters().unwrap(), initial - 5);
+        fs.remove("/big.bin").unwrap();
+        fs.flush().unwrap();
+    }
+    assert_eq!(free_count(&image), initial);
+}
+
+#[test]
+fn fs_info_hint_is_trusted_only_when_in_range() {
+    let mut image = FAT32.mkfs();
+    let info = FS_INFO_SECTOR * SECTOR;
+    image[info + 488..info + 492].copy_from_slice(&1000u32.to_le_bytes());
+    assert_eq!(mount(&mut image).free_clusters().unwrap(), 1000);
+
+    // 範囲外や未知 (0xffffffff) なら数え直す
+    for hint in [0xffff_ffffu32, FAT32.cluster_count() + 1] {
+        image[info + 488..info + 492].copy_from_slice(&hint.to_le_bytes());
+        assert_eq!(
+            mount(&mut image).free_clusters().unwrap(),
+            FAT32.cluster_count() - 1
+        );
+    }
+    image[info..info + 4].fill(0);
+    assert_eq!(
+        mount(&mut image).free_clusters().unwrap(),
+        FAT32.cluster_count() - 1
+    );
+}
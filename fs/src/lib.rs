@@ -0,0 +1,12 @@
+//! Block device abstraction, partition tables and filesystems used by the
+//! kernel
+//!
+//! Kept free of kernel dependencies so that it also builds for the host
+//! and can be exercised against disk image files.
+#![no_std]
+
+extern crate alloc;
+
+pub mod block;
+pub mod fat;
+pub mod partition;
@@ -0,0 +1,158 @@
+//! GUID Partition Table
+
+use alloc::{string::String, vec, vec::Vec};
+
+use super::{Guid, PartitionError, PartitionInfo, PartitionKind, crc32::crc32, read_u32, read_u64};
+use crate::block::{BlockDevice, BlockError};
+
+const SIGNATURE: &[u8; 8] = b"EFI PART";
+const MIN_HEADER_SIZE: usize = 92;
+const MIN_ENTRY_SIZE: usize = 128;
+/// Upper bound on the entry array; the usual 128 entries take 16 KiB
+const MAX_ENTRY_ARRAY: usize = 1024 * 1024;
+const NAME_OFFSET: usize = 56;
+const NAME_LENGTH: usize = 36;
+
+struct Header {
+    alternate_lba: u64,
+    first_usable_lba: u64,
+    last_usable_lba: u64,
+    entries_lba: u64,
+    entry_count: usize,
+    entry_size: usize,
+    entries_crc: u32,
+}
+
+impl Header {
+    /// Parse and validate the header stored at `lba`
+    fn parse(block: &[u8], lba: u64, block_count: u64) -> Option<Self> {
+        if &block[0..8] != SIGNATURE {
+            return None;
+        }
+        let size = read_u32(block, 12) as usize;
+        if !(MIN_HEADER_SIZE..=block.len()).contains(&size) {
+            return None;
+        }
+        // CRC は自身のフィールドを 0 として計算する
+        let mut header = block[..size].to_vec();
+        header[16..20].fill(0);
+        if crc32(&header) != read_u32(block, 16) || read_u64(block, 24) != lba {
+            return None;
+        }
+
+        let header = Self {
+            alternate_lba: read_u64(block, 32),
+            first_usable_lba: read_u64(block, 40),
+            last_usable_lba: read_u64(block, 48),
+            entries_lba: read_u64(block, 72),
+            entry_count: read_u32(block, 80) as usize,
+            entry_size: read_u32(block, 84) as usize,
+            entries_crc: read_u32(block, 88),
+        };
+        let valid = header.entry_size >= MIN_ENTRY_SIZE
+            && header.entry_size.is_power_of_two()
+            && header
+                .entry_count
+                .checked_mul(header.entry_size)
+                .is_some_and(|len| len <= MAX_ENTRY_ARRAY)
+            && header.first_usable_lba <= header.last_usable_lba
+            && header.last_usable_lba < block_count
+            && header.entries_lba < block_count;
+        valid.then_some(header)
+    }
+
+    fn entries_len(&self) -> usize {
+        self.entry_count * self.entry_size
+    }
+}
+
+/// Read the table from the primary header, or from the backup one if the
+/// primary header or its entry array is damaged
+pub(super) fn read<D: BlockDevice>(device: &mut D) -> Result<Vec<PartitionInfo>, PartitionError> {
+    let last_lba = device.block_count() - 1;
+    let primary = read_header(device, 1)?;
+    if let Some(header) = &primary
+        && let Some(entries) = read_entries(device, header)?
+    {
+        return Ok(parse_entries(header, &entries));
+    }
+
+    // バックアップは通常ディスクの最終ブロックにある
+    let mut candidates = vec![last_lba];
+    if let Some(header) = &primary
+        && (2..last_lba).contains(&header.alternate_lba)
+    {
+        candidates.insert(0, header.alternate_lba);
+    }
+    for lba in candidates {
+        if let Some(header) = read_header(device, lba)?
+            && let Some(entries) = read_entries(device, &header)?
+        {
+            return Ok(parse_entries(&header, &entries));
+        }
+    }
+    Err(PartitionError::Corrupted)
+}
+
+fn read_header<D: BlockDevice>(device: &mut D, lba: u64) -> Result<Option<Header>, BlockError> {
+    let mut block = vec![0; device.block_size()];
+    device.read_blocks(lba, &mut block)?;
+    Ok(Header::parse(&block, lba, device.block_count()))
+}
+
+/// The entry array of `header`, or `None` if it fails the CRC check
+fn read_entries<D: BlockDevice>(
+    device: &mut D,
+    header: &Header,
+) -> Result<Option<Vec<u8>>, BlockError> {
+    let len = header.entries_len();
+    let blocks = len.div_ceil(device.block_size()) as u64;
+    if header.entries_lba + blocks > device.block_count() {
+        return Ok(None);
+    }
+    let mut entries = vec![0; blocks as usize * device.block_size()];
+    device.read_blocks(header.entries_lba, &mut entries)?;
+    entries.truncate(len);
+    Ok((crc32(&entries) == header.entries_crc).then_some(entries))
+}
+
+fn parse_entries(header: &Header, entries: &[u8]) -> Vec<PartitionInfo> {
+    entries
+        .chunks_exact(header.entry_size)
+        .enumerate()
+        .filter_map(|(i, raw)| {
+            let type_guid = Guid(raw[0..16].try_into().unwrap());
+            let first_lba = read_u64(raw, 32);
+            let last_lba = read_u64(raw, 40);
+            if type_guid.is_null()
+                || first_lba > last_lba
+                || first_lba < header.first_usable_lba
+                || last_lba > header.last_usable_lba
+            {
+                return None;
+            }
+            Some(PartitionInfo {
+                number: i as u32 + 1,
+                start_lba: first_lba,
+                block_count: last_lba - first_lba + 1,
+                kind: PartitionKind::Gpt {
+                    type_guid,
+                    unique_guid: Guid(raw[16..32].try_into().unwrap()),
+                    attributes: read_u64(raw, 48),
+                    name: decode_name(&raw[NAME_OFFSET..NAME_OFFSET + NAME_LENGTH * 2]),
+                },
+            })
+        })
+        .collect()
+}
+
+/// Partition names are NUL-padded UTF-16LE
+fn decode_name(raw: &[u8]) -> String {
+    let units = raw
+        .chunks_exact(2)
+        .map(|c| u16::from_le_bytes([c[0], c[1]]))
+        .take_while(|&unit| unit != 0);
+    char::decode_utf16(units)
+        .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+        .collect()
+}
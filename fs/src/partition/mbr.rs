@@ -0,0 +1,120 @@
+//! Master Boot Record with extended (EBR-chained) partitions
+
+use alloc::{vec, vec::Vec};
+
+use super::{PartitionError, PartitionInfo, PartitionKind, read_u32};
+use crate::block::BlockDevice;
+
+/// System ID of the single partition in a GPT protective MBR
+pub(super) const PROTECTIVE: u8 = 0xee;
+/// CHS, LBA and Linux extended partitions
+const EXTENDED: [u8; 3] = [0x05, 0x0f, 0x85];
+const TABLE_OFFSET: usize = 446;
+const ENTRY_SIZE: usize = 16;
+const BOOTABLE: u8 = 0x80;
+/// Bound on the length of the EBR chain
+const MAX_LOGICAL: u32 = 128;
+const FIRST_LOGICAL: u32 = 5;
+
+#[derive(Clone, Copy, Debug)]
+pub(super) struct Entry {
+    pub bootable: bool,
+    pub system_id: u8,
+    pub start_lba: u32,
+    pub sectors: u32,
+}
+
+impl Entry {
+    fn is_used(&self) -> bool {
+        self.system_id != 0 && self.sectors != 0
+    }
+
+    fn is_extended(&self) -> bool {
+        EXTENDED.contains(&self.system_id)
+    }
+}
+
+/// The four entries of an MBR or EBR, or `None` if `sector` holds no table
+pub(super) fn parse_table(sector: &[u8]) -> Option<[Entry; 4]> {
+    if sector[510] != 0x55 || sector[511] != 0xaa {
+        return None;
+    }
+    let mut entries = [Entry {
+        bootable: false,
+        system_id: 0,
+        start_lba: 0,
+        sectors: 0,
+    }; 4];
+    for (i, entry) in entries.iter_mut().enumerate() {
+        let raw = &sector[TABLE_OFFSET + i * ENTRY_SIZE..][..ENTRY_SIZE];
+        // ブートフラグが 0x00/0x80 以外ならパーティションテーブルではない (VBR など)
+        if raw[0] & !BOOTABLE != 0 {
+            return None;
+        }
+        *entry = Entry {
+            bootable: raw[0] == BOOTABLE,
+            system_id: raw[4],
+            start_lba: read_u32(raw, 8),
+            sectors: read_u32(raw, 12),
+        };
+    }
+    entries.iter().any(Entry::is_used).then_some(entries)
+}
+
+/// Primary partitions numbered 1-4, followed by the logical partitions of the
+/// first extended partition
+pub(super) fn read<D: BlockDevice>(
+    device: &mut D,
+    entries: &[Entry; 4],
+) -> Result<Vec<PartitionInfo>, PartitionError> {
+    let block_count = device.block_count();
+    let mut partitions = Vec::new();
+    let mut push = |number, start_lba: u64, entry: &Entry| {
+        let end = start_lba + entry.sectors as u64;
+        if start_lba != 0 && end <= block_count {
+            partitions.push(PartitionInfo {
+                number,
+                start_lba,
+                block_count: entry.sectors as u64,
+                kind: PartitionKind::Mbr {
+                    system_id: entry.system_id,
+                    bootable: entry.bootable,
+                },
+            });
+        }
+    };
+
+    for (i, entry) in entries.iter().enumerate() {
+        if entry.is_used() && !entry.is_extended() {
+            push(i as u32 + 1, entry.start_lba as u64, entry);
+        }
+    }
+
+    let Some(extended) = entries.iter().find(|e| e.is_used() && e.is_extended()) else {
+        return Ok(partitions);
+    };
+    // EBR の論理パーティションは EBR から、次の EBR は拡張パーティション先頭からの相対位置
+    let base = extended.start_lba as u64;
+    let mut ebr = base;
+    let mut visited = Vec::new();
+    let mut sector = vec![0; device.block_size()];
+    for number in FIRST_LOGICAL..FIRST_LOGICAL + MAX_LOGICAL {
+        // 壊れたリンクで同じ EBR に戻ってきたら打ち切る
+        if ebr >= block_count || visited.contains(&ebr) {
+            break;
+        }
+        visited.push(ebr);
+        device.read_blocks(ebr, &mut sector)?;
+        let Some([logical, link, ..]) = parse_table(&sector) else {
+            break;
+        };
+        if logical.is_used() {
+            push(number, ebr + logical.start_lba as u64, &logical);
+        }
+        if !link.is_used() || !link.is_extended() || link.start_lba == 0 {
+            break;
+        }
+        ebr = base + link.start_lba as u64;
+    }
+    Ok(partitions)
+}
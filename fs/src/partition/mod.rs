@@ -0,0 +1,220 @@
+//! Partition table discovery (GPT and MBR)
+//!
+//! [`scan`] reads the table of a whole-disk [`BlockDevice`], and
+//! [`Partition`] exposes one of the partitions found as a block device of its
+//! own.
+
+mod crc32;
+mod gpt;
+mod mbr;
+#[cfg(test)]
+mod tests;
+
+use alloc::{string::String, vec, vec::Vec};
+use core::fmt;
+
+use crate::block::{BlockDevice, BlockError};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PartitionError {
+    Device(BlockError),
+    /// Neither the primary nor the backup GPT header is valid
+    Corrupted,
+}
+
+impl From<BlockError> for PartitionError {
+    fn from(error: BlockError) -> Self {
+        Self::Device(error)
+    }
+}
+
+/// GUID in its on-disk byte order (the first three fields little-endian)
+#[derive(Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct Guid(pub [u8; 16]);
+
+impl Guid {
+    pub const NULL: Self = Self([0; 16]);
+    pub const EFI_SYSTEM: Self = Self::from_fields(
+        0xc12a_7328,
+        0xf81f,
+        0x11d2,
+        [0xba, 0x4b, 0x00, 0xa0, 0xc9, 0x3e, 0xc9, 0x3b],
+    );
+    pub const MICROSOFT_BASIC_DATA: Self = Self::from_fields(
+        0xebd0_a0a2,
+        0xb9e5,
+        0x4433,
+        [0x87, 0xc0, 0x68, 0xb6, 0xb7, 0x26, 0x99, 0xc7],
+    );
+    pub const LINUX_FILESYSTEM: Self = Self::from_fields(
+        0x0fc6_3daf,
+        0x8483,
+        0x4772,
+        [0x8e, 0x79, 0x3d, 0x69, 0xd8, 0x47, 0x7d, 0xe4],
+    );
+
+    /// Build a GUID from the fields of its text form
+    /// (`data1-data2-data3-data4[0..2]-data4[2..8]`)
+    pub const fn from_fields(data1: u32, data2: u16, data3: u16, data4: [u8; 8]) -> Self {
+        let a = data1.to_le_bytes();
+        let b = data2.to_le_bytes();
+        let c = data3.to_le_bytes();
+        Self([
+            a[0], a[1], a[2], a[3], b[0], b[1], c[0], c[1], data4[0], data4[1], data4[2], data4[3],
+            data4[4], data4[5], data4[6], data4[7],
+        ])
+    }
+
+    pub fn is_null(&self) -> bool {
+        *self == Self::NULL
+    }
+}
+
+impl fmt::Display for Guid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let b = &self.0;
+        write!(
+            f,
+            "{:08x}-{:04x}-{:04x}-{:02x}{:02x}-",
+            u32::from_le_bytes([b[0], b[1], b[2], b[3]]),
+            u16::from_le_bytes([b[4], b[5]]),
+            u16::from_le_bytes([b[6], b[7]]),
+            b[8],
+            b[9]
+        )?;
+        b[10..].iter().try_for_each(|byte| write!(f, "{byte:02x}"))
+    }
+}
+
+impl fmt::Debug for Guid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+/// Table-specific description of a partition
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PartitionKind {
+    Gpt {
+        type_guid: Guid,
+        unique_guid: Guid,
+        attributes: u64,
+        name: String,
+    },
+    Mbr {
+        system_id: u8,
+        bootable: bool,
+    },
+}
+
+/// MBR system ID of an EFI System Partition
+pub const MBR_EFI_SYSTEM: u8 = 0xef;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PartitionInfo {
+    /// 1-based; MBR logical partitions are numbered from 5
+    pub number: u32,
+    pub start_lba: u64,
+    pub block_count: u64,
+    pub kind: PartitionKind,
+}
+
+impl PartitionInfo {
+    pub fn type_guid(&self) -> Option<Guid> {
+        match self.kind {
+            PartitionKind::Gpt { type_guid, .. } => Some(type_guid),
+            PartitionKind::Mbr { .. } => None,
+        }
+    }
+
+    pub fn unique_guid(&self) -> Option<Guid> {
+        match self.kind {
+            PartitionKind::Gpt { unique_guid, .. } => Some(unique_guid),
+            PartitionKind::Mbr { .. } => None,
+        }
+    }
+
+    pub fn is_efi_system(&self) -> bool {
+        match self.kind {
+            PartitionKind::Gpt { type_guid, .. } => type_guid == Guid::EFI_SYSTEM,
+            PartitionKind::Mbr { system_id, .. } => system_id == MBR_EFI_SYSTEM,
+        }
+    }
+}
+
+/// Read the partition table of `device`
+///
+/// A protective MBR selects GPT, falling back to the backup header when the
+/// primary one is damaged. A device without a table has no partitions.
+pub fn scan<D: BlockDevice>(device: &mut D) -> Result<Vec<PartitionInfo>, PartitionError> {
+    if device.block_size() < 512 || device.block_count() < 2 {
+        return Ok(Vec::new());
+    }
+    let mut sector = vec![0; device.block_size()];
+    device.read_blocks(0, &mut sector)?;
+    let Some(entries) = mbr::parse_table(&sector) else {
+        return Ok(Vec::new());
+    };
+    if entries.iter().any(|e| e.system_id == mbr::PROTECTIVE) {
+        gpt::read(device)
+    } else {
+        mbr::read(device, &entries)
+    }
+}
+
+/// A partition of `D` as a block device of its own
+pub struct Partition<D> {
+    device: D,
+    start_lba: u64,
+    block_count: u64,
+}
+
+impl<D: BlockDevice> Partition<D> {
+    pub fn new(device: D, info: &PartitionInfo) -> Self {
+        Self {
+            device,
+            start_lba: info.start_lba,
+            block_count: info.block_count,
+        }
+    }
+
+    pub fn start_lba(&self) -> u64 {
+        self.start_lba
+    }
+
+    pub fn into_inner(self) -> D {
+        self.device
+    }
+}
+
+impl<D: BlockDevice> BlockDevice for Partition<D> {
+    fn block_size(&self) -> usize {
+        self.device.block_size()
+    }
+
+    fn block_count(&self) -> u64 {
+        self.block_count
+    }
+
+    fn read_blocks(&mut self, lba: u64, buf: &mut [u8]) -> Result<(), BlockError> {
+        self.check_range(lba, buf.len())?;
+        self.device.read_blocks(self.start_lba + lba, buf)
+    }
+
+    fn write_blocks(&mut self, lba: u64, buf: &[u8]) -> Result<(), BlockError> {
+        self.check_range(lba, buf.len())?;
+        self.device.write_blocks(self.start_lba + lba, buf)
+    }
+
+    fn flush(&mut self) -> Result<(), BlockError> {
+        self.device.flush()
+    }
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+}
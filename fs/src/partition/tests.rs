@@ -0,0 +1,373 @@
+//! Tests over synthetic in-memory disk images
+
+extern crate std;
+
+use std::{string::ToString, vec, vec::Vec};
+
+use super::{crc32::crc32, *};
+use crate::block::MemoryDevice;
+
+const BLOCK: usize = 512;
+const DISK_BLOCKS: u64 = 256;
+const ENTRY_COUNT: usize = 128;
+const ENTRY_SIZE: usize = 128;
+const ENTRY_BLOCKS: u64 = (ENTRY_COUNT * ENTRY_SIZE / BLOCK) as u64;
+const LAST_LBA: u64 = DISK_BLOCKS - 1;
+const FIRST_USABLE: u64 = 2 + ENTRY_BLOCKS;
+const LAST_USABLE: u64 = LAST_LBA - ENTRY_BLOCKS - 1;
+
+const DISK_GUID: Guid = Guid::from_fields(0x1111_2222, 0x3333, 0x4444, [5, 5, 6, 6, 6, 6, 6, 6]);
+const ESP_GUID: Guid = Guid::from_fields(0xaaaa_0001, 0, 0, [0; 8]);
+const ROOT_GUID: Guid = Guid::from_fields(0xaaaa_0002, 0, 0, [0; 8]);
+
+fn block(disk: &mut [u8], lba: u64) -> &mut [u8] {
+    &mut disk[lba as usize * BLOCK..][..BLOCK]
+}
+
+/// MBR partition entry `index` of the table in `sector`
+fn set_mbr_entry(sector: &mut [u8], index: usize, flag: u8, system_id: u8, start: u32, len: u32) {
+    let raw = &mut sector[446 + index * 16..][..16];
+    raw[0] = flag;
+    raw[4] = system_id;
+    raw[8..12].copy_from_slice(&start.to_le_bytes());
+    raw[12..16].copy_from_slice(&len.to_le_bytes());
+    sector[510] = 0x55;
+    sector[511] = 0xaa;
+}
+
+fn gpt_entries() -> Vec<u8> {
+    let mut entries = vec![0u8; ENTRY_COUNT * ENTRY_SIZE];
+    let mut put =
+        |index: usize, type_guid: Guid, unique: Guid, first: u64, last: u64, name: &str| {
+            let raw = &mut entries[index * ENTRY_SIZE..][..ENTRY_SIZE];
+            raw[0..16].copy_from_slice(&type_guid.0);
+            raw[16..32].copy_from_slice(&unique.0);
+            raw[32..40].copy_from_slice(&first.to_le_bytes());
+            raw[40..48].copy_from_slice(&last.to_le_bytes());
+            raw[48..56].copy_from_slice(&1u64.to_le_bytes());
+            for (i, unit) in name.encode_utf16().enumerate() {
+                raw[56 + i * 2..58 + i * 2].copy_from_slice(&unit.to_le_bytes());
+            }
+        };
+    put(
+        0,
+        Guid::EFI_SYSTEM,
+        ESP_GUID,
+        FIRST_USABLE,
+        99,
+        "EFI system partition",
+    );
+    // エントリ 2 は空きで、番号は配列の位置で決まる
+    put(
+        2,
+        Guid::LINUX_FILESYSTEM,
+        ROOT_GUID,
+        100,
+        LAST_USABLE,
+        "root ✓",
+    );
+    entries
+}
+
+fn gpt_header(lba: u64, alternate: u64, entries_lba: u64, entries: &[u8]) -> Vec<u8> {
+    let mut header = vec![0u8; BLOCK];
+    header[0..8].copy_from_slice(b"EFI PART");
+    header[8..12].copy_from_slice(&0x0001_0000u32.to_le_bytes());
+    header[12..16].copy_from_slice(&92u32.to_le_bytes());
+    header[24..32].copy_from_slice(&lba.to_le_bytes());
+    header[32..40].copy_from_slice(&alternate.to_le_bytes());
+    header[40..48].copy_from_slice(&FIRST_USABLE.to_le_bytes());
+    header[48..56].copy_from_slice(&LAST_USABLE.to_le_bytes());
+    header[56..72].copy_from_slice(&DISK_GUID.0);
+    header[72..80].copy_from_slice(&entries_lba.to_le_bytes());
+    header[80..84].copy_from_slice(&(ENTRY_COUNT as u32).to_le_bytes());
+    header[84..88].copy_from_slice(&(ENTRY_SIZE as u32).to_le_bytes());
+    header[88..92].copy_from_slice(&crc32(entries).to_le_bytes());
+    let crc = crc32(&header[..92]);
+    header[16..20].copy_from_slice(&crc.to_le_bytes());
+    header
+}
+
+/// A disk laid out like `sgdisk` leaves it: protective MBR, primary header
+/// and entries at the front, backup entries and header at the end
+fn gpt_disk() -> Vec<u8> {
+    let mut disk = vec![0u8; DISK_BLOCKS as usize * BLOCK];
+    set_mbr_entry(block(&mut disk, 0), 0, 0, 0xee, 1, LAST_LBA as u32);
+    let entries = gpt_entries();
+    let backup_entries = LAST_LBA - ENTRY_BLOCKS;
+    block(&mut disk, 1).copy_from_slice(&gpt_header(1, LAST_LBA, 2, &entries));
+    block(&mut disk, LAST_LBA).copy_from_slice(&gpt_header(LAST_LBA, 1, backup_entries, &entries));
+    disk[2 * BLOCK..][..entries.len()].copy_from_slice(&entries);
+    disk[backup_entries as usize * BLOCK..][..entries.len()].copy_from_slice(&entries);
+    disk
+}
+
+fn scan_image(disk: &mut [u8]) -> Result<Vec<PartitionInfo>, PartitionError> {
+    scan(&mut MemoryDevice::new(disk, BLOCK))
+}
+
+fn expected_gpt() -> Vec<PartitionInfo> {
+    vec![
+        PartitionInfo {
+            number: 1,
+            start_lba: FIRST_USABLE,
+            block_count: 100 - FIRST_USABLE,
+            kind: PartitionKind::Gpt {
+                type_guid: Guid::EFI_SYSTEM,
+                unique_guid: ESP_GUID,
+                attributes: 1,
+                name: "EFI system partition".to_string(),
+            },
+        },
+        PartitionInfo {
+            number: 3,
+            start_lba: 100,
+            block_count: LAST_USABLE - 100 + 1,
+            kind: PartitionKind::Gpt {
+                type_guid: Guid::LINUX_FILESYSTEM,
+                unique_guid: ROOT_GUID,
+                attributes: 1,
+                name: "root ✓".to_string(),
+            },
+        },
+    ]
+}
+
+#[test]
+fn crc32_check_values() {
+    assert_eq!(crc32(b""), 0);
+    assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+    assert_eq!(
+        crc32(b"The quick brown fox jumps over the lazy dog"),
+        0x414f_a339
+    );
+    assert_eq!(crc32(&[0; 32]), 0x190a_55ad);
+}
+
+#[test]
+fn guid_text_form() {
+    assert_eq!(
+        Guid::EFI_SYSTEM.to_string(),
+        "c12a7328-f81f-11d2-ba4b-00a0c93ec93b"
+    );
+    assert_eq!(
+        &Guid::EFI_SYSTEM.0[..4],
+        &[0x28, 0x73, 0x2a, 0xc1],
+        "data1 is stored little-endian"
+    );
+}
+
+#[test]
+fn gpt_primary() {
+    let mut disk = gpt_disk();
+    let partitions = scan_image(&mut disk).unwrap();
+    assert_eq!(partitions, expected_gpt());
+    assert!(partitions[0].is_efi_system());
+    assert!(!partitions[1].is_efi_system());
+    assert_eq!(partitions[1].type_guid(), Some(Guid::LINUX_FILESYSTEM));
+    assert_eq!(partitions[1].unique_guid(), Some(ROOT_GUID));
+}
+
+#[test]
+fn gpt_corrupt_primary_header_falls_back_to_backup() {
+    let mut disk = gpt_disk();
+    // 署名は正しいまま中身を壊し、CRC で弾かせる
+    block(&mut disk, 1)[48] ^= 0x01;
+    assert_eq!(scan_image(&mut disk).unwrap(), expected_gpt());
+
+    let mut disk = gpt_disk();
+    block(&mut disk, 1).fill(0);
+    assert_eq!(scan_image(&mut disk).unwrap(), expected_gpt());
+}
+
+#[test]
+fn gpt_corrupt_primary_entries_fall_back_to_backup() {
+    let mut disk = gpt_disk();
+    disk[2 * BLOCK + 40] ^= 0xff;
+    assert_eq!(scan_image(&mut disk).unwrap(), expected_gpt());
+}
+
+#[test]
+fn gpt_header_at_the_wrong_lba_is_rejected() {
+    let mut disk = gpt_disk();
+    // バックアップのコピーを LBA 1 に置いても MyLBA が合わない
+    let backup = block(&mut disk, LAST_LBA).to_vec();
+    block(&mut disk, 1).copy_from_slice(&backup);
+    assert_eq!(scan_image(&mut disk).unwrap(), expected_gpt());
+
+    block(&mut disk, LAST_LBA).fill(0);
+    assert_eq!(
+        scan_image(&mut disk).unwrap_err(),
+        PartitionError::Corrupted
+    );
+}
+
+#[test]
+fn gpt_backup_found_through_alternate_lba() {
+    // ディスクが後ろに伸びて、バックアップが最終ブロックにない
+    let mut disk = gpt_disk();
+    disk.extend(vec![0u8; 16 * BLOCK]);
+    disk[2 * BLOCK + 40] ^= 0xff;
+    assert_eq!(scan_image(&mut disk).unwrap(), expected_gpt());
+
+    // ヘッダも壊れていれば AlternateLBA は使えず、最終ブロックを見るしかない
+    block(&mut disk, 1)[48] ^= 0x01;
+    assert_eq!(
+        scan_image(&mut disk).unwrap_err(),
+        PartitionError::Corrupted
+    );
+}
+
+#[test]
+fn gpt_with_both_headers_corrupt() {
+    let mut disk = gpt_disk();
+    block(&mut disk, 1)[0] = b'X';
+    block(&mut disk, LAST_LBA)[0] = b'X';
+    assert_eq!(
+        scan_image(&mut disk).unwrap_err(),
+        PartitionError::Corrupted
+    );
+}
+
+#[test]
+fn protective_mbr_selects_gpt() {
+    // 0xee が最初のエントリ以外にあっても GPT とみなす
+    let mut disk = gpt_disk();
+    let mbr = block(&mut disk, 0);
+    mbr[446..462].fill(0);
+    set_mbr_entry(mbr, 3, 0, 0xee, 1, LAST_LBA as u32);
+    assert_eq!(scan_image(&mut disk).unwrap(), expected_gpt());
+
+    // 保護 MBR がなければ GPT ヘッダは読まない
+    let mut disk = gpt_disk();
+    set_mbr_entry(block(&mut disk, 0), 0, 0, 0x83, 1, LAST_LBA as u32);
+    let partitions = scan_image(&mut disk).unwrap();
+    assert_eq!(partitions.len(), 1);
+    assert_eq!(partitions[0].type_guid(), None);
+}
+
+#[test]
+fn disks_without_a_table() {
+    let mut disk = vec![0u8; DISK_BLOCKS as usize * BLOCK];
+    assert!(scan_image(&mut disk).unwrap().is_empty());
+
+    // パーティションのない FAT ボリュームのブートセクタ
+    let boot = block(&mut disk, 0);
+    boot[..3].copy_from_slice(&[0xeb, 0x3c, 0x90]);
+    boot[446] = 0x12;
+    boot[510] = 0x55;
+    boot[511] = 0xaa;
+    assert!(scan_image(&mut disk).unwrap().is_empty());
+}
+
+/// MBR with two primaries and an extended partition at 100..200 holding
+/// logical partitions, one per EBR
+fn mbr_disk(logicals: &[(u32, u32)]) -> Vec<u8> {
+    let mut disk = vec![0u8; DISK_BLOCKS as usize * BLOCK];
+    let mbr = block(&mut disk, 0);
+    set_mbr_entry(mbr, 0, 0x80, 0x0c, 2, 30);
+    set_mbr_entry(mbr, 1, 0, 0x83, 40, 50);
+    set_mbr_entry(mbr, 2, 0, 0x0f, 100, 100);
+
+    // 論理パーティションの位置は EBR から、次の EBR は拡張パーティション先頭からの相対
+    let mut ebr = 0;
+    for (i, &(start, len)) in logicals.iter().enumerate() {
+        let sector = block(&mut disk, 100 + ebr as u64);
+        set_mbr_entry(sector, 0, 0, 0x83, start, len);
+        if let Some(&(next_start, _)) = logicals.get(i + 1) {
+            let next_ebr = ebr + start + len;
+            set_mbr_entry(sector, 1, 0, 0x05, next_ebr, next_start + 1);
+        }
+        ebr += start + len;
+    }
+    disk
+}
+
+fn mbr_partition(number: u32, start_lba: u64, block_count: u64, system_id: u8) -> PartitionInfo {
+    PartitionInfo {
+        number,
+        start_lba,
+        block_count,
+        kind: PartitionKind::Mbr {
+            system_id,
+            bootable: number == 1,
+        },
+    }
+}
+
+#[test]
+fn mbr_primaries_and_ebr_chain() {
+    let mut disk = mbr_disk(&[(1, 20), (2, 10), (1, 30)]);
+    assert_eq!(
+        scan_image(&mut disk).unwrap(),
+        [
+            mbr_partition(1, 2, 30, 0x0c),
+            mbr_partition(2, 40, 50, 0x83),
+            mbr_partition(5, 101, 20, 0x83),
+            mbr_partition(6, 123, 10, 0x83),
+            mbr_partition(7, 134, 30, 0x83),
+        ]
+    );
+}
+
+#[test]
+fn mbr_looping_ebr_chain() {
+    let mut disk = mbr_disk(&[(1, 20), (2, 10)]);
+    // 2 つ目の EBR が最初の EBR を指し返す
+    set_mbr_entry(block(&mut disk, 121), 1, 0, 0x05, 0, 21);
+    let partitions = scan_image(&mut disk).unwrap();
+    // 相対位置 0 のリンクは終端扱い
+    assert_eq!(partitions.len(), 4);
+
+    set_mbr_entry(block(&mut disk, 121), 1, 0, 0x05, 21, 12);
+    let partitions = scan_image(&mut disk).unwrap();
+    let numbers: Vec<u32> = partitions.iter().map(|p| p.number).collect();
+    assert_eq!(numbers, [1, 2, 5, 6]);
+
+    // 自分自身を指すより長いループ
+    let mut disk = mbr_disk(&[(1, 20), (2, 10), (1, 30)]);
+    set_mbr_entry(block(&mut disk, 133), 1, 0, 0x05, 21, 12);
+    let numbers: Vec<u32> = scan_image(&mut disk)
+        .unwrap()
+        .iter()
+        .map(|p| p.number)
+        .collect();
+    assert_eq!(numbers, [1, 2, 5, 6, 7]);
+}
+
+#[test]
+fn mbr_skips_partitions_past_the_end() {
+    let mut disk = mbr_disk(&[]);
+    set_mbr_entry(block(&mut disk, 0), 3, 0, 0x83, 200, 100);
+    // 拡張パーティションの先頭に EBR がない
+    let numbers: Vec<u32> = scan_image(&mut disk)
+        .unwrap()
+        .iter()
+        .map(|p| p.number)
+        .collect();
+    assert_eq!(numbers, [1, 2]);
+}
+
+#[test]
+fn partition_device_is_offset_and_bounded() {
+    let mut disk = gpt_disk();
+    let info = scan_image(&mut disk).unwrap().remove(0);
+    let mut partition = Partition::new(MemoryDevice::new(&mut disk, BLOCK), &info);
+    assert_eq!(partition.block_count(), info.block_count);
+
+    partition.write_blocks(1, &[0xab; BLOCK]).unwrap();
+    let mut buf = [0; BLOCK];
+    partition.read_blocks(1, &mut buf).unwrap();
+    assert_eq!(buf, [0xab; BLOCK]);
+    assert_eq!(
+        partition.read_blocks(info.block_count, &mut buf),
+        Err(BlockError::OutOfRange)
+    );
+    assert_eq!(
+        partition.read_blocks(0, &mut buf[..100]),
+        Err(BlockError::Unaligned)
+    );
+    let disk = partition.into_inner();
+    let at = (FIRST_USABLE as usize + 1) * BLOCK;
+    assert_eq!(&disk.as_slice()[at..at + BLOCK], &[0xab; BLOCK]);
+}
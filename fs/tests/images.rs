@@ -0,0 +1,152 @@
+//! Tests against images made by another FAT implementation
+//!
+//! `images/make.sh` describes the tree each image holds and regenerates
+//! them with `mkfs.fat` and mtools.
+
+use std::{fs::File, io::Read};
+
+use flate2::read::GzDecoder;
+use rust_mikan_os_fs::{
+    block::MemoryDevice,
+    fat::{FatError, FatType, FileSystem},
+};
+
+const SECTOR: usize = 512;
+
+const IMAGES: [(&str, FatType); 3] = [
+    ("fat12", FatType::Fat12),
+    ("fat16", FatType::Fat16),
+    ("fat32", FatType::Fat32),
+];
+
+fn image(name: &str) -> Vec<u8> {
+    let path = format!("{}/tests/images/{name}.img.gz", env!("CARGO_MANIFEST_DIR"));
+    let mut image = Vec::new();
+    GzDecoder::new(File::open(path).unwrap())
+        .read_to_end(&mut image)
+        .unwrap();
+    image
+}
+
+fn mount(image: &mut [u8]) -> FileSystem<MemoryDevice<'_>> {
+    FileSystem::mount(MemoryDevice::new(image, SECTOR)).unwrap()
+}
+
+fn names(fs: &mut FileSystem<MemoryDevice<'_>>, path: &str) -> Vec<String> {
+    let mut names: Vec<String> = fs
+        .read_dir(path)
+        .unwrap()
+        .into_iter()
+        .map(|e| e.name)
+        .filter(|name| name != "." && name != "..")
+        .collect();
+    names.sort();
+    names
+}
+
+/// Output of `seq 1 2000`
+fn seq() -> Vec<u8> {
+    (1..=2000)
+        .flat_map(|i| format!("{i}\n").into_bytes())
+        .collect()
+}
+
+#[test]
+fn reads_the_tree() {
+    for (name, fat_type) in IMAGES {
+        let mut image = image(name);
+        let mut fs = mount(&mut image);
+        assert_eq!(fs.fat_type(), fat_type, "{name}");
+        assert_eq!(
+            names(&mut fs, "/"),
+            ["A long file name.txt", "DOCS", "HELLO.TXT"],
+            "{name}"
+        );
+        assert_eq!(names(&mut fs, "/DOCS"), ["EMPTY", "Nested long name.log"]);
+        assert!(fs.lookup("/DOCS").unwrap().is_dir());
+        assert_eq!(fs.read_file("/HELLO.TXT").unwrap(), b"Hello, FAT!\n");
+        assert_eq!(fs.read_file("/DOCS/EMPTY").unwrap(), b"");
+
+        // 複数クラスタにまたがるファイル
+        let seq = seq();
+        assert!(seq.len() > 4 * fs.cluster_size());
+        assert_eq!(fs.read_file("/DOCS/Nested long name.log").unwrap(), seq);
+        let file = fs.lookup("/DOCS/Nested long name.log").unwrap();
+        let mut buf = [0; 10];
+        assert_eq!(fs.read(&file, 2000, &mut buf).unwrap(), 10);
+        assert_eq!(buf, seq[2000..2010]);
+    }
+}
+
+#[test]
+fn long_names_match_case_insensitively() {
+    for (name, _) in IMAGES {
+        let mut image = image(name);
+        let mut fs = mount(&mut image);
+        assert_eq!(
+            fs.read_file("/a LONG file NAME.TXT").unwrap(),
+            b"Written by another driver.\n"
+        );
+        let entry = fs.lookup("/docs/nested LONG name.LOG").unwrap();
+        assert_eq!(entry.name, "Nested long name.log");
+        // 短い名前でも引ける
+        let short = String::from_utf8(entry.short_name[..8].to_vec()).unwrap();
+        let short = format!("/DOCS/{}.LOG", short.trim_end());
+        assert_eq!(fs.lookup(&short).unwrap().name, entry.name);
+        assert_eq!(
+            fs.lookup("/A long file name").unwrap_err(),
+            FatError::NotFound
+        );
+    }
+}
+
+#[test]
+fn writes_to_the_tree_survive_a_remount() {
+    for (name, _) in IMAGES {
+        let mut image = image(name);
+        {
+            let mut fs = mount(&mut image);
+            fs.append("/DOCS/Nested long name.log", b"2001\n").unwrap();
+            let mut file = fs.create_file("/DOCS/Another long name.txt").unwrap();
+            fs.write(&mut file, 0, b"from this driver").unwrap();
+            fs.remove("/HELLO.TXT").unwrap();
+            fs.flush().unwrap();
+        }
+        let mut fs = mount(&mut image);
+        let mut seq = seq();
+        seq.extend_from_slice(b"2001\n");
+        assert_eq!(fs.read_file("/DOCS/Nested long name.log").unwrap(), seq);
+        assert_eq!(
+            fs.read_file("/docs/another long name.TXT").unwrap(),
+            b"from this driver"
+        );
+        assert_eq!(names(&mut fs, "/"), ["A long file name.txt", "DOCS"]);
+    }
+}
+
+#[test]
+fn fs_info_free_count_matches_the_fat() {
+    let mut image = image("fat32");
+    let info = mount(&mut image).bpb().fs_info_sector as usize * SECTOR;
+    let free_count =
+        |image: &[u8]| u32::from_le_bytes(image[info + 488..info + 492].try_into().unwrap());
+    let recorded = free_count(&image);
+
+    // ヒントを捨てて FAT を数え直した値と一致する
+    image[info + 488..info + 492].fill(0xff);
+    assert_eq!(mount(&mut image).free_clusters().unwrap(), recorded);
+    image[info + 488..info + 492].copy_from_slice(&recorded.to_le_bytes());
+
+    {
+        let mut fs = mount(&mut image);
+        fs.remove("/DOCS/Nested long name.log").unwrap();
+        fs.flush().unwrap();
+    }
+    let clusters = seq().len().div_ceil(SECTOR) as u32;
+    assert_eq!(free_count(&image), recorded + clusters);
+    image[info + 488..info + 492].fill(0xff);
+    assert_eq!(
+        mount(&mut image).free_clusters().unwrap(),
+        recorded + clusters
+    );
+}
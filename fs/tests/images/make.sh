@@ -0,0 +1,35 @@
+#!/bin/sh
+# Regenerates the FAT images used by ../images.rs with dosfstools and mtools
+#
+# Every image holds the same tree:
+#   /HELLO.TXT                  "Hello, FAT!\n"
+#   /A long file name.txt       "Written by another driver.\n"
+#   /DOCS/Nested long name.log  output of `seq 1 2000`
+#   /DOCS/EMPTY                 empty
+set -eu
+
+cd "$(dirname "$0")"
+work=$(mktemp -d)
+trap 'rm -rf "$work"' EXIT
+
+printf 'Hello, FAT!\n' > "$work/hello"
+printf 'Written by another driver.\n' > "$work/long"
+seq 1 2000 > "$work/seq"
+: > "$work/empty"
+
+make_image() {
+    name=$1
+    shift
+    rm -f "$name.img" "$name.img.gz"
+    mkfs.fat "$@" -C "$name.img" > /dev/null
+    mcopy -i "$name.img" "$work/hello" ::HELLO.TXT
+    mcopy -i "$name.img" "$work/long" "::A long file name.txt"
+    mmd -i "$name.img" ::DOCS
+    mcopy -i "$name.img" "$work/seq" "::DOCS/Nested long name.log"
+    mcopy -i "$name.img" "$work/empty" ::DOCS/EMPTY
+    gzip -9n "$name.img"
+}
+
+make_image fat12 -F 12 -i 12340012 1440
+make_image fat16 -F 16 -s 2 -r 512 -i 12340016 8192
+make_image fat32 -F 32 -s 1 -i 12340032 33792
@@ -0,0 +1,44 @@
+//! ACPI tables of the running machine
+//!
+//! The parsers live in the `acpi_tables` crate; this module reads the
+//! tables out of firmware memory and keeps what the kernel needs later.
+pub use acpi_tables::{AcpiError, AcpiTables, fadt, madt, mcfg, sdt};
+use sdt::{AddressSpace, GenericAddress};
+use spin::Once;
+
+use crate::x86;
+
+/// The FADT reset register and value, kept for [`reset`]
+static RESET_REGISTER: Once<(GenericAddress, u8)> = Once::new();
+
+/// Parse the ACPI tables of the running machine
+///
+/// # Safety
+///
+/// - `rsdp_addr` は UEFI から渡された RSDP の物理アドレスか `0` である必要があります。
+/// - ACPI テーブルの領域がアイデンティティマップされている前提です。
+pub unsafe fn init(rsdp_addr: u64) -> Result<AcpiTables<'static>, AcpiError> {
+    let tables = AcpiTables::parse(rsdp_addr, |addr, len| unsafe {
+        core::slice::from_raw_parts(addr as *const u8, len)
+    })?;
+    if let Some(reset) = tables.fadt.and_then(|fadt| fadt.reset) {
+        RESET_REGISTER.call_once(|| reset);
+    }
+    Ok(tables)
+}
+
+/// Write the FADT reset value; returns if there is no usable reset register
+/// or the write had no effect
+pub fn reset() {
+    let Some(&(register, value)) = RESET_REGISTER.get() else {
+        return;
+    };
+    match register.address_space {
+        AddressSpace::SystemIo => x86::io_out8(register.address as u16, value),
+        AddressSpace::SystemMemory => unsafe {
+            (register.address as *mut u8).write_volatile(value)
+        },
+        // PCI 構成空間のリセットレジスタには対応しない
+        _ => {}
+    }
+}
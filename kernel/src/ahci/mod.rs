@@ -0,0 +1,176 @@
+//! AHCI host bus adapter driver for SATA disks
+//!
+//! Every port with an ATA disk becomes a block device (`sd<n>`). Commands
+//! are issued one at a time through command slot 0; the caller sleeps until
+//! the MSI interrupt, or polls when the HBA has no MSI.
+pub mod port;
+
+use core::sync::atomic::{AtomicU8, AtomicU64, Ordering};
+
+use crate::{
+    apic,
+    block::{self, completion::Completion},
+    clock::wait_until,
+    interrupt::{self, InterruptFrame},
+    pci,
+};
+use port::AhciPort;
+
+/// Mass storage / SATA / AHCI 1.0
+const CLASS_MASS_STORAGE: u8 = 0x01;
+const SUBCLASS_SATA: u8 = 0x06;
+const INTERFACE_AHCI: u8 = 0x01;
+const ABAR_INDEX: usize = 5;
+
+// Generic Host Control レジスタ
+const HBA_CAP: u64 = 0x00;
+const HBA_GHC: u64 = 0x04;
+const HBA_IS: u64 = 0x08;
+const HBA_PI: u64 = 0x0c;
+const HBA_CAP2: u64 = 0x24;
+const HBA_BOHC: u64 = 0x28;
+const PORT_REGISTERS: u64 = 0x100;
+const PORT_REGISTERS_SIZE: u64 = 0x80;
+
+const CAP_SSS: u32 = 1 << 27;
+const GHC_HR: u32 = 1 << 0;
+const GHC_IE: u32 = 1 << 1;
+const GHC_AE: u32 = 1 << 31;
+const CAP2_BOH: u32 = 1 << 0;
+const BOHC_BOS: u32 = 1 << 0;
+const BOHC_OOS: u32 = 1 << 1;
+
+const MAX_CONTROLLERS: usize = 4;
+const MAX_PORTS: usize = 32;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AhciError {
+    NoAbar,
+    ResetTimeout,
+    NoDevice,
+    DeviceBusy,
+    CommandFailed,
+    OutOfMemory,
+}
+
+/// ABAR of each controller slot; `0` when unused
+static CONTROLLERS: [AtomicU64; MAX_CONTROLLERS] = [const { AtomicU64::new(0) }; MAX_CONTROLLERS];
+static VECTORS: [AtomicU8; MAX_CONTROLLERS] = [const { AtomicU8::new(0) }; MAX_CONTROLLERS];
+static COMPLETIONS: [[Completion; MAX_PORTS]; MAX_CONTROLLERS] =
+    [const { [const { Completion::new() }; MAX_PORTS] }; MAX_CONTROLLERS];
+
+/// Memory-mapped HBA registers
+#[derive(Clone, Copy, Debug)]
+pub struct Hba {
+    base: u64,
+}
+
+impl Hba {
+    fn read(&self, offset: u64) -> u32 {
+        unsafe { ((self.base + offset) as *const u32).read_volatile() }
+    }
+
+    fn write(&self, offset: u64, value: u32) {
+        unsafe { ((self.base + offset) as *mut u32).write_volatile(value) }
+    }
+
+    fn port_base(&self, port: usize) -> u64 {
+        self.base + PORT_REGISTERS + port as u64 * PORT_REGISTERS_SIZE
+    }
+
+    /// Take the HBA from the firmware and reset it into AHCI mode
+    fn reset(&self) -> Result<(), AhciError> {
+        if self.read(HBA_CAP2) & CAP2_BOH != 0 {
+            self.write(HBA_BOHC, self.read(HBA_BOHC) | BOHC_OOS);
+            // BIOS が処理中の要求を終えるまで最大 2 秒待つ
+            wait_until(2000, || self.read(HBA_BOHC) & BOHC_BOS == 0);
+        }
+
+        self.write(HBA_GHC, self.read(HBA_GHC) | GHC_AE);
+        self.write(HBA_GHC, self.read(HBA_GHC) | GHC_HR);
+        if !wait_until(1000, || self.read(HBA_GHC) & GHC_HR == 0) {
+            return Err(AhciError::ResetTimeout);
+        }
+        // リセットで AE が落ちる実装があるので設定し直す
+        self.write(HBA_GHC, self.read(HBA_GHC) | GHC_AE);
+        Ok(())
+    }
+}
+
+fn find_controllers() -> impl Iterator<Item = &'static pci::Device> {
+    pci::find_by_class_interface(CLASS_MASS_STORAGE, SUBCLASS_SATA, INTERFACE_AHCI)
+}
+
+/// Reset every AHCI controller and register its disks; returns the number of
+/// disks found
+pub fn init() -> usize {
+    find_controllers()
+        .take(MAX_CONTROLLERS)
+        .enumerate()
+        .filter_map(|(index, device)| init_controller(device, index).ok())
+        .sum()
+}
+
+fn init_controller(device: &pci::Device, index: usize) -> Result<usize, AhciError> {
+    let abar = device
+        .bar(ABAR_INDEX)
+        .ok()
+        .and_then(|bar| bar.memory_base())
+        .ok_or(AhciError::NoAbar)?;
+    device.enable_bus_master();
+    let hba = Hba { base: abar };
+    hba.reset()?;
+
+    let cap = hba.read(HBA_CAP);
+    let interrupt = setup_interrupt(device, hba, index);
+
+    let implemented = hba.read(HBA_PI);
+    let mut count = 0;
+    for port in (0..MAX_PORTS).filter(|&p| implemented & 1 << p != 0) {
+        let completion = interrupt.then_some(&COMPLETIONS[index][port]);
+        if let Ok(disk) = AhciPort::new(hba.port_base(port), cap & CAP_SSS != 0, completion) {
+            block::register("sd", disk);
+            count += 1;
+        }
+    }
+    if interrupt {
+        hba.write(HBA_IS, u32::MAX);
+        hba.write(HBA_GHC, hba.read(HBA_GHC) | GHC_IE);
+    }
+    Ok(count)
+}
+
+/// Route the HBA interrupt to a single MSI vector; returns whether
+/// interrupts are in use
+fn setup_interrupt(device: &pci::Device, hba: Hba, index: usize) -> bool {
+    let Some(vector) = interrupt::allocate_vector() else {
+        return false;
+    };
+    CONTROLLERS[index].store(hba.base, Ordering::Release);
+    VECTORS[index].store(vector, Ordering::Release);
+    interrupt::register_handler(vector, on_interrupt);
+    if device.configure_msi(apic::local::id(), vector, 1).is_err() {
+        VECTORS[index].store(0, Ordering::Release);
+        interrupt::free_vector(vector);
+        return false;
+    }
+    true
+}
+
+fn on_interrupt(frame: &mut InterruptFrame) {
+    for (index, vector) in VECTORS.iter().enumerate() {
+        if vector.load(Ordering::Acquire) != frame.vector as u8 {
+            continue;
+        }
+        let hba = Hba {
+            base: CONTROLLERS[index].load(Ordering::Acquire),
+        };
+        let pending = hba.read(HBA_IS);
+        for port in (0..MAX_PORTS).filter(|&p| pending & 1 << p != 0) {
+            // ポートの IS を先に落としてから HBA の IS を落とす
+            port::clear_interrupt(hba.port_base(port));
+            COMPLETIONS[index][port].notify();
+        }
+        hba.write(HBA_IS, pending);
+    }
+}
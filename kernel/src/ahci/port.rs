@@ -0,0 +1,412 @@
+//! One SATA port: command list, received FIS area and ATA commands
+
+use crate::{
+    block::{BlockDevice, BlockError, completion::Completion},
+    clock::wait_until,
+    dma,
+};
+
+use super::AhciError;
+
+// ポートレジスタ
+const PORT_CLB: u64 = 0x00;
+const PORT_CLBU: u64 = 0x04;
+const PORT_FB: u64 = 0x08;
+const PORT_FBU: u64 = 0x0c;
+const PORT_IS: u64 = 0x10;
+const PORT_IE: u64 = 0x14;
+const PORT_CMD: u64 = 0x18;
+const PORT_TFD: u64 = 0x20;
+const PORT_SIG: u64 = 0x24;
+const PORT_SSTS: u64 = 0x28;
+const PORT_SERR: u64 = 0x30;
+const PORT_CI: u64 = 0x38;
+
+const CMD_ST: u32 = 1 << 0;
+const CMD_SUD: u32 = 1 << 1;
+const CMD_POD: u32 = 1 << 2;
+const CMD_FRE: u32 = 1 << 4;
+const CMD_FR: u32 = 1 << 14;
+const CMD_CR: u32 = 1 << 15;
+
+const TFD_ERR: u32 = 1 << 0;
+const TFD_DRQ: u32 = 1 << 3;
+const TFD_BSY: u32 = 1 << 7;
+
+const SSTS_DET_MASK: u32 = 0x0f;
+const SSTS_DET_PRESENT: u32 = 3;
+const SIG_ATA: u32 = 0x0000_0101;
+
+const IS_DHRS: u32 = 1 << 0;
+const IS_PSS: u32 = 1 << 1;
+const IS_DSS: u32 = 1 << 2;
+const IS_SDBS: u32 = 1 << 3;
+const IS_IFS: u32 = 1 << 27;
+const IS_HBDS: u32 = 1 << 28;
+const IS_HBFS: u32 = 1 << 29;
+const IS_TFES: u32 = 1 << 30;
+/// Completion and error interrupts the driver waits for
+const IE_MASK: u32 = IS_DHRS | IS_PSS | IS_DSS | IS_SDBS | IS_IFS | IS_HBDS | IS_HBFS | IS_TFES;
+
+const FIS_TYPE_REG_H2D: u8 = 0x27;
+/// Register H2D FIS length in dwords
+const FIS_REG_H2D_LENGTH: u16 = 5;
+const HEADER_WRITE: u16 = 1 << 6;
+const PRD_INTERRUPT: u32 = 1 << 31;
+
+const ATA_IDENTIFY_DEVICE: u8 = 0xec;
+const ATA_READ_DMA: u8 = 0xc8;
+const ATA_READ_DMA_EXT: u8 = 0x25;
+const ATA_WRITE_DMA: u8 = 0xca;
+const ATA_WRITE_DMA_EXT: u8 = 0x35;
+const ATA_FLUSH_CACHE: u8 = 0xe7;
+const ATA_FLUSH_CACHE_EXT: u8 = 0xea;
+/// Device register bit selecting LBA addressing
+const DEVICE_LBA: u8 = 1 << 6;
+
+/// Largest data buffer in a single command
+const MAX_TRANSFER: usize = 64 * 1024;
+/// Used for IDENTIFY and for caller buffers the HBA cannot address (odd
+/// addresses)
+const BOUNCE_SIZE: usize = 4096;
+
+const COMMAND_LIST_SIZE: usize = 1024;
+const RECEIVED_FIS_SIZE: usize = 256;
+
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct CommandHeader {
+    /// CFL, ATAPI, write, prefetchable, ...
+    flags: u16,
+    prdt_length: u16,
+    prd_byte_count: u32,
+    table: u64,
+    reserved: [u32; 4],
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct PrdEntry {
+    address: u64,
+    reserved: u32,
+    /// Byte count minus one, interrupt on completion in bit 31
+    count: u32,
+}
+
+#[repr(C, align(128))]
+struct CommandTable {
+    fis: [u8; 64],
+    atapi_command: [u8; 16],
+    reserved: [u8; 48],
+    prdt: [PrdEntry; 1],
+}
+
+/// Direction and buffer of a data command
+#[derive(Clone, Copy)]
+enum Data {
+    None,
+    Read(u64, usize),
+    Write(u64, usize),
+}
+
+fn read(base: u64, offset: u64) -> u32 {
+    unsafe { ((base + offset) as *const u32).read_volatile() }
+}
+
+fn write(base: u64, offset: u64, value: u32) {
+    unsafe { ((base + offset) as *mut u32).write_volatile(value) }
+}
+
+/// Acknowledge all pending interrupts of the port at `base`
+pub fn clear_interrupt(base: u64) {
+    write(base, PORT_IS, read(base, PORT_IS));
+}
+
+/// Stop command processing and FIS reception
+fn stop(base: u64) -> Result<(), AhciError> {
+    write(base, PORT_CMD, read(base, PORT_CMD) & !CMD_ST);
+    if !wait_until(500, || read(base, PORT_CMD) & CMD_CR == 0) {
+        return Err(AhciError::DeviceBusy);
+    }
+    write(base, PORT_CMD, read(base, PORT_CMD) & !CMD_FRE);
+    if !wait_until(500, || read(base, PORT_CMD) & CMD_FR == 0) {
+        return Err(AhciError::DeviceBusy);
+    }
+    Ok(())
+}
+
+fn start(base: u64) {
+    wait_until(500, || read(base, PORT_CMD) & CMD_CR == 0);
+    write(base, PORT_CMD, read(base, PORT_CMD) | CMD_FRE | CMD_ST);
+}
+
+pub struct AhciPort {
+    base: u64,
+    header: *mut CommandHeader,
+    table: *mut CommandTable,
+    bounce: *mut u8,
+    sector_size: usize,
+    sector_count: u64,
+    lba48: bool,
+    completion: Option<&'static Completion>,
+}
+
+// DMA 用の領域はこのポートだけが使う
+unsafe impl Send for AhciPort {}
+
+impl AhciPort {
+    /// Bring up the port at `base` and IDENTIFY its disk
+    ///
+    /// With `completion`, the port's interrupts are enabled and commands
+    /// sleep until [`Completion::notify`].
+    pub fn new(
+        base: u64,
+        staggered_spin_up: bool,
+        completion: Option<&'static Completion>,
+    ) -> Result<Self, AhciError> {
+        stop(base)?;
+        if staggered_spin_up {
+            write(base, PORT_CMD, read(base, PORT_CMD) | CMD_SUD | CMD_POD);
+        }
+        // リンクが確立していないポートには領域を割り当てない
+        if !wait_until(50, || {
+            read(base, PORT_SSTS) & SSTS_DET_MASK == SSTS_DET_PRESENT
+        }) {
+            return Err(AhciError::NoDevice);
+        }
+
+        let command_list =
+            dma::allocate(COMMAND_LIST_SIZE, 1024, 0).ok_or(AhciError::OutOfMemory)?;
+        let received_fis =
+            dma::allocate(RECEIVED_FIS_SIZE, 256, 0).ok_or(AhciError::OutOfMemory)?;
+        let table = dma::allocate(size_of::<CommandTable>(), 128, 0)
+            .ok_or(AhciError::OutOfMemory)? as *mut CommandTable;
+        let bounce = dma::allocate(BOUNCE_SIZE, 4096, 0).ok_or(AhciError::OutOfMemory)?;
+
+        let header = command_list as *mut CommandHeader;
+        unsafe {
+            header.write_volatile(CommandHeader {
+                table: table as u64,
+                ..Default::default()
+            })
+        };
+        write(base, PORT_CLB, command_list as u64 as u32);
+        write(base, PORT_CLBU, (command_list as u64 >> 32) as u32);
+        write(base, PORT_FB, received_fis as u64 as u32);
+        write(base, PORT_FBU, (received_fis as u64 >> 32) as u32);
+        write(base, PORT_SERR, u32::MAX);
+        write(base, PORT_IS, u32::MAX);
+        write(base, PORT_CMD, read(base, PORT_CMD) | CMD_FRE);
+
+        // デバイスが最初の D2H FIS を送るまで BSY が立っている
+        if !wait_until(1000, || read(base, PORT_TFD) & (TFD_BSY | TFD_DRQ) == 0) {
+            return Err(AhciError::DeviceBusy);
+        }
+        if read(base, PORT_SIG) != SIG_ATA {
+            stop(base)?;
+            return Err(AhciError::NoDevice);
+        }
+        write(
+            base,
+            PORT_IE,
+            if completion.is_some() { IE_MASK } else { 0 },
+        );
+        start(base);
+
+        let mut port = Self {
+            base,
+            header,
+            table,
+            bounce,
+            sector_size: 512,
+            sector_count: 0,
+            lba48: false,
+            completion,
+        };
+        port.identify()?;
+        Ok(port)
+    }
+
+    fn identify(&mut self) -> Result<(), AhciError> {
+        self.command(
+            ATA_IDENTIFY_DEVICE,
+            0,
+            0,
+            Data::Read(self.bounce as u64, 512),
+        )
+        .map_err(|_| AhciError::CommandFailed)?;
+        let words = unsafe { core::slice::from_raw_parts(self.bounce as *const u16, 256) };
+        let dword = |i: usize| words[i] as u32 | (words[i + 1] as u32) << 16;
+
+        self.lba48 = words[83] & (1 << 10) != 0;
+        self.sector_count = if self.lba48 {
+            dword(100) as u64 | (dword(102) as u64) << 32
+        } else {
+            dword(60) as u64
+        };
+        // word 106 が有効で、論理セクタが 256 ワードより大きい場合
+        if words[106] & 0xc000 == 0x4000 && words[106] & (1 << 12) != 0 {
+            self.sector_size = dword(117) as usize * 2;
+        }
+        if self.sector_size > BOUNCE_SIZE || self.sector_count == 0 {
+            return Err(AhciError::NoDevice);
+        }
+        Ok(())
+    }
+
+    /// Issue one ATA command through slot 0 and wait for it
+    fn command(&mut self, command: u8, lba: u64, count: u32, data: Data) -> Result<(), BlockError> {
+        let base = self.base;
+        if !wait_until(1000, || read(base, PORT_TFD) & (TFD_BSY | TFD_DRQ) == 0) {
+            self.recover();
+            return Err(BlockError::Io);
+        }
+
+        let mut fis = [0u8; 64];
+        fis[0] = FIS_TYPE_REG_H2D;
+        // C ビット: コマンドレジスタの更新
+        fis[1] = 1 << 7;
+        fis[2] = command;
+        fis[4..7].copy_from_slice(&lba.to_le_bytes()[..3]);
+        if self.lba48 {
+            fis[7] = DEVICE_LBA;
+            fis[8..11].copy_from_slice(&lba.to_le_bytes()[3..6]);
+            fis[12..14].copy_from_slice(&(count as u16).to_le_bytes());
+        } else {
+            fis[7] = DEVICE_LBA | (lba >> 24) as u8 & 0x0f;
+            fis[12] = count as u8;
+        }
+
+        let (buffer, is_write) = match data {
+            Data::None => (None, false),
+            Data::Read(address, len) => (Some((address, len)), false),
+            Data::Write(address, len) => (Some((address, len)), true),
+        };
+        unsafe {
+            (&raw mut (*self.table).fis).write_volatile(fis);
+            if let Some((address, len)) = buffer {
+                (&raw mut (*self.table).prdt[0]).write_volatile(PrdEntry {
+                    address,
+                    reserved: 0,
+                    count: (len as u32 - 1) | PRD_INTERRUPT,
+                });
+            }
+            self.header.write_volatile(CommandHeader {
+                flags: FIS_REG_H2D_LENGTH | if is_write { HEADER_WRITE } else { 0 },
+                prdt_length: buffer.is_some() as u16,
+                prd_byte_count: 0,
+                table: self.table as u64,
+                reserved: [0; 4],
+            });
+        }
+
+        write(base, PORT_IS, u32::MAX);
+        core::sync::atomic::fence(core::sync::atomic::Ordering::SeqCst);
+        write(base, PORT_CI, 1);
+
+        // エラー時は CI が落ちないので TFD の ERR も見る
+        let done = || read(base, PORT_CI) & 1 == 0 || read(base, PORT_TFD) & TFD_ERR != 0;
+        match self.completion {
+            Some(completion) => completion.wait_until(true, done),
+            None => {
+                while !done() {
+                    core::hint::spin_loop();
+                }
+            }
+        }
+        if read(base, PORT_TFD) & TFD_ERR != 0 || read(base, PORT_IS) & IS_TFES != 0 {
+            self.recover();
+            return Err(BlockError::Io);
+        }
+        Ok(())
+    }
+
+    /// Restart the port after a task file error
+    fn recover(&mut self) {
+        let _ = stop(self.base);
+        write(self.base, PORT_SERR, u32::MAX);
+        write(self.base, PORT_IS, u32::MAX);
+        start(self.base);
+    }
+
+    fn max_sectors(&self) -> usize {
+        let limit = if self.lba48 { 65536 } else { 256 };
+        (MAX_TRANSFER / self.sector_size).min(limit)
+    }
+
+    /// Transfer whole sectors, through the bounce buffer if `address` is odd
+    fn transfer(
+        &mut self,
+        lba: u64,
+        address: u64,
+        len: usize,
+        is_write: bool,
+    ) -> Result<(), BlockError> {
+        let command = match (is_write, self.lba48) {
+            (false, true) => ATA_READ_DMA_EXT,
+            (false, false) => ATA_READ_DMA,
+            (true, true) => ATA_WRITE_DMA_EXT,
+            (true, false) => ATA_WRITE_DMA,
+        };
+        // PRD のアドレスはワード境界でなければならない
+        let direct = address & 1 == 0;
+        let chunk_size = if direct {
+            self.max_sectors() * self.sector_size
+        } else {
+            BOUNCE_SIZE / self.sector_size * self.sector_size
+        };
+
+        let mut done = 0;
+        while done < len {
+            let size = chunk_size.min(len - done);
+            let sector = lba + (done / self.sector_size) as u64;
+            let count = (size / self.sector_size) as u32;
+            let source = address + done as u64;
+            let target = if direct { source } else { self.bounce as u64 };
+            if !direct && is_write {
+                unsafe { core::ptr::copy_nonoverlapping(source as *const u8, self.bounce, size) };
+            }
+            let data = if is_write {
+                Data::Write(target, size)
+            } else {
+                Data::Read(target, size)
+            };
+            self.command(command, sector, count, data)?;
+            if !direct && !is_write {
+                unsafe { core::ptr::copy_nonoverlapping(self.bounce, source as *mut u8, size) };
+            }
+            done += size;
+        }
+        Ok(())
+    }
+}
+
+impl BlockDevice for AhciPort {
+    fn block_size(&self) -> usize {
+        self.sector_size
+    }
+
+    fn block_count(&self) -> u64 {
+        self.sector_count
+    }
+
+    fn read_blocks(&mut self, lba: u64, buf: &mut [u8]) -> Result<(), BlockError> {
+        self.check_range(lba, buf.len())?;
+        // カーネルは恒等マップで動いているので、バッファのアドレスをそのまま渡せる
+        self.transfer(lba, buf.as_mut_ptr() as u64, buf.len(), false)
+    }
+
+    fn write_blocks(&mut self, lba: u64, buf: &[u8]) -> Result<(), BlockError> {
+        self.check_range(lba, buf.len())?;
+        self.transfer(lba, buf.as_ptr() as u64, buf.len(), true)
+    }
+
+    fn flush(&mut self) -> Result<(), BlockError> {
+        let command = if self.lba48 {
+            ATA_FLUSH_CACHE_EXT
+        } else {
+            ATA_FLUSH_CACHE
+        };
+        self.command(command, 0, 0, Data::None)
+    }
+}
@@ -0,0 +1,84 @@
+//! Kernel heap: the shared first-fit free list over a static region
+
+use core::{
+    alloc::{GlobalAlloc, Layout},
+    cell::UnsafeCell,
+    ptr,
+};
+
+use common::free_list::{FreeList, block_align, block_size};
+use spin::Mutex;
+
+use crate::x86;
+
+pub const HEAP_SIZE: usize = 32 * 1024 * 1024;
+
+#[repr(C, align(4096))]
+struct HeapRegion(UnsafeCell<[u8; HEAP_SIZE]>);
+
+// 領域へのアクセスはすべて KernelHeap のロックを通す
+unsafe impl Sync for HeapRegion {}
+
+static HEAP: HeapRegion = HeapRegion(UnsafeCell::new([0; HEAP_SIZE]));
+
+struct KernelHeap {
+    free_list: FreeList,
+    initialized: bool,
+}
+
+impl KernelHeap {
+    fn allocate(&mut self, size: usize, align: usize) -> *mut u8 {
+        if !self.initialized {
+            // 静的領域はほかから使われておらず、ページ境界に揃っている
+            unsafe { self.free_list.insert(HEAP.0.get() as usize, HEAP_SIZE) };
+            self.initialized = true;
+        }
+        self.free_list.find(size, align)
+    }
+}
+
+pub struct KernelAllocator {
+    heap: Mutex<KernelHeap>,
+}
+
+unsafe impl GlobalAlloc for KernelAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let size = block_size(&layout);
+        let align = block_align(&layout);
+        x86::without_interrupts(|| self.heap.lock().allocate(size, align))
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let size = block_size(&layout);
+        x86::without_interrupts(|| unsafe {
+            self.heap.lock().free_list.insert(ptr as usize, size)
+        });
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        let ptr = unsafe { self.alloc(layout) };
+        if !ptr.is_null() {
+            unsafe { ptr::write_bytes(ptr, 0, layout.size()) };
+        }
+        ptr
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: KernelAllocator = KernelAllocator {
+    heap: Mutex::new(KernelHeap {
+        free_list: FreeList::new(),
+        initialized: false,
+    }),
+};
+
+/// Bytes currently free in the kernel heap
+pub fn free_bytes() -> usize {
+    x86::without_interrupts(|| {
+        let heap = ALLOCATOR.heap.lock();
+        if !heap.initialized {
+            return HEAP_SIZE;
+        }
+        heap.free_list.free_bytes()
+    })
+}
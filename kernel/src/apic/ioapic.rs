@@ -0,0 +1,157 @@
+//! I/O APIC redirection table programming
+
+use spin::Mutex;
+
+use crate::acpi::madt::{Madt, MadtEntry, MpsIntiFlags, Polarity, TriggerMode};
+
+use super::ApicError;
+
+const MAX_IO_APICS: usize = 8;
+const ISA_IRQS: usize = 16;
+
+const IOREGSEL: u64 = 0x00;
+const IOWIN: u64 = 0x10;
+const REG_VERSION: u32 = 0x01;
+const REG_REDIRECTION_BASE: u32 = 0x10;
+
+const REDIRECTION_ACTIVE_LOW: u64 = 1 << 13;
+const REDIRECTION_LEVEL: u64 = 1 << 15;
+const REDIRECTION_MASKED: u64 = 1 << 16;
+
+#[derive(Clone, Copy, Debug)]
+struct IoApic {
+    base: u64,
+    gsi_base: u32,
+    entries: u32,
+}
+
+impl IoApic {
+    fn read(&self, reg: u32) -> u32 {
+        unsafe {
+            ((self.base + IOREGSEL) as *mut u32).write_volatile(reg);
+            ((self.base + IOWIN) as *const u32).read_volatile()
+        }
+    }
+
+    fn write(&self, reg: u32, value: u32) {
+        unsafe {
+            ((self.base + IOREGSEL) as *mut u32).write_volatile(reg);
+            ((self.base + IOWIN) as *mut u32).write_volatile(value);
+        }
+    }
+
+    fn handles(&self, gsi: u32) -> bool {
+        self.gsi_base <= gsi && gsi < self.gsi_base + self.entries
+    }
+
+    fn write_redirection(&self, gsi: u32, entry: u64) {
+        let reg = REG_REDIRECTION_BASE + (gsi - self.gsi_base) * 2;
+        // 書き換え途中で割り込みが飛ばないよう、マスクした状態で下位を先に書く
+        self.write(reg, (entry as u32) | REDIRECTION_MASKED as u32);
+        self.write(reg + 1, (entry >> 32) as u32);
+        self.write(reg, entry as u32);
+    }
+}
+
+/// How an ISA IRQ reaches the I/O APIC after interrupt source overrides
+#[derive(Clone, Copy, Debug)]
+struct IsaRoute {
+    gsi: u32,
+    active_low: bool,
+    level: bool,
+}
+
+struct IoApics {
+    apics: [Option<IoApic>; MAX_IO_APICS],
+    isa_routes: [IsaRoute; ISA_IRQS],
+}
+
+static IO_APICS: Mutex<IoApics> = Mutex::new(IoApics {
+    apics: [None; MAX_IO_APICS],
+    isa_routes: [IsaRoute {
+        gsi: 0,
+        active_low: false,
+        level: false,
+    }; ISA_IRQS],
+});
+
+/// Register every I/O APIC in the MADT and mask all of their inputs
+pub fn init(madt: &Madt) -> Result<(), ApicError> {
+    let mut io_apics = IO_APICS.lock();
+    let mut count = 0;
+    for entry in madt.entries() {
+        let MadtEntry::IoApic {
+            address, gsi_base, ..
+        } = entry
+        else {
+            continue;
+        };
+        if count == MAX_IO_APICS {
+            break;
+        }
+        let mut apic = IoApic {
+            base: address as u64,
+            gsi_base,
+            entries: 0,
+        };
+        apic.entries = ((apic.read(REG_VERSION) >> 16) & 0xff) + 1;
+        for gsi in gsi_base..gsi_base + apic.entries {
+            apic.write_redirection(gsi, REDIRECTION_MASKED);
+        }
+        io_apics.apics[count] = Some(apic);
+        count += 1;
+    }
+    if count == 0 {
+        return Err(ApicError::NoIoApic);
+    }
+
+    for (irq, route) in io_apics.isa_routes.iter_mut().enumerate() {
+        let (gsi, flags) = madt
+            .isa_override(irq as u8)
+            .unwrap_or((irq as u32, MpsIntiFlags(0)));
+        *route = IsaRoute {
+            gsi,
+            // ISA のデフォルトはアクティブハイ・エッジトリガ
+            active_low: flags.polarity() == Polarity::ActiveLow,
+            level: flags.trigger_mode() == TriggerMode::Level,
+        };
+    }
+    Ok(())
+}
+
+/// Route `gsi` to `vector` on the local APIC `apic_id`
+pub fn route_gsi(
+    gsi: u32,
+    vector: u8,
+    apic_id: u8,
+    active_low: bool,
+    level: bool,
+) -> Result<(), ApicError> {
+    let io_apics = IO_APICS.lock();
+    let apic = io_apics
+        .apics
+        .iter()
+        .flatten()
+        .find(|apic| apic.handles(gsi))
+        .ok_or(ApicError::GsiNotFound(gsi))?;
+
+    let mut entry = vector as u64 | (apic_id as u64) << 56;
+    if active_low {
+        entry |= REDIRECTION_ACTIVE_LOW;
+    }
+    if level {
+        entry |= REDIRECTION_LEVEL;
+    }
+    apic.write_redirection(gsi, entry);
+    Ok(())
+}
+
+/// Route ISA `irq` to `vector`, honouring interrupt source overrides
+pub fn route_isa_irq(irq: u8, vector: u8, apic_id: u8) -> Result<(), ApicError> {
+    let route = *IO_APICS
+        .lock()
+        .isa_routes
+        .get(irq as usize)
+        .ok_or(ApicError::GsiNotFound(irq as u32))?;
+    route_gsi(route.gsi, vector, apic_id, route.active_low, route.level)
+}
@@ -0,0 +1,94 @@
+//! Local APIC and its timer
+
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use crate::{clock::ReferenceClock, interrupt::SPURIOUS, x86};
+
+const IA32_APIC_BASE: u32 = 0x1b;
+const APIC_GLOBAL_ENABLE: u64 = 1 << 11;
+
+const REG_ID: u64 = 0x020;
+const REG_TPR: u64 = 0x080;
+const REG_EOI: u64 = 0x0b0;
+const REG_SVR: u64 = 0x0f0;
+const REG_LVT_TIMER: u64 = 0x320;
+const REG_LVT_LINT0: u64 = 0x350;
+const REG_LVT_LINT1: u64 = 0x360;
+const REG_LVT_ERROR: u64 = 0x370;
+const REG_TIMER_INITIAL: u64 = 0x380;
+const REG_TIMER_CURRENT: u64 = 0x390;
+const REG_TIMER_DIVIDE: u64 = 0x3e0;
+
+const SVR_APIC_ENABLE: u32 = 1 << 8;
+const LVT_MASKED: u32 = 1 << 16;
+const LVT_TIMER_PERIODIC: u32 = 1 << 17;
+const TIMER_DIVIDE_BY_1: u32 = 0b1011;
+
+const CALIBRATION_US: u64 = 10_000;
+
+static BASE: AtomicU64 = AtomicU64::new(0xfee0_0000);
+static TIMER_FREQUENCY: AtomicU64 = AtomicU64::new(0);
+
+fn read(reg: u64) -> u32 {
+    unsafe { ((BASE.load(Ordering::Relaxed) + reg) as *const u32).read_volatile() }
+}
+
+fn write(reg: u64, value: u32) {
+    unsafe { ((BASE.load(Ordering::Relaxed) + reg) as *mut u32).write_volatile(value) }
+}
+
+/// Software-enable the local APIC at `base` and route spurious interrupts
+pub fn init(base: u64) {
+    BASE.store(base, Ordering::Relaxed);
+    unsafe {
+        let msr = x86::read_msr(IA32_APIC_BASE);
+        x86::write_msr(IA32_APIC_BASE, msr | APIC_GLOBAL_ENABLE);
+    }
+    write(REG_TPR, 0);
+    write(REG_LVT_ERROR, LVT_MASKED);
+    write(REG_LVT_TIMER, LVT_MASKED);
+    write(REG_SVR, SVR_APIC_ENABLE | SPURIOUS as u32);
+}
+
+pub fn id() -> u8 {
+    (read(REG_ID) >> 24) as u8
+}
+
+pub fn end_of_interrupt() {
+    write(REG_EOI, 0);
+}
+
+/// Program LINT0/LINT1, e.g. as NMI inputs from the MADT
+pub fn set_lint(lint: u8, lvt: u32) {
+    match lint {
+        0 => write(REG_LVT_LINT0, lvt),
+        1 => write(REG_LVT_LINT1, lvt),
+        _ => {}
+    }
+}
+
+/// Measure the timer frequency (ticks per second with divide-by-1)
+pub fn calibrate_timer(reference: &ReferenceClock) -> u64 {
+    write(REG_TIMER_DIVIDE, TIMER_DIVIDE_BY_1);
+    write(REG_LVT_TIMER, LVT_MASKED);
+    write(REG_TIMER_INITIAL, u32::MAX);
+    reference.wait_us(CALIBRATION_US);
+    let elapsed = u32::MAX - read(REG_TIMER_CURRENT);
+    write(REG_TIMER_INITIAL, 0);
+
+    let frequency = elapsed as u64 * 1_000_000 / CALIBRATION_US;
+    TIMER_FREQUENCY.store(frequency, Ordering::Relaxed);
+    frequency
+}
+
+pub fn timer_frequency() -> u64 {
+    TIMER_FREQUENCY.load(Ordering::Relaxed)
+}
+
+/// Fire `vector` `hz` times per second
+pub fn start_periodic_timer(vector: u8, hz: u64) {
+    let count = (timer_frequency() / hz).clamp(1, u32::MAX as u64) as u32;
+    write(REG_TIMER_DIVIDE, TIMER_DIVIDE_BY_1);
+    write(REG_LVT_TIMER, LVT_TIMER_PERIODIC | vector as u32);
+    write(REG_TIMER_INITIAL, count);
+}
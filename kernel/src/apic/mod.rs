@@ -0,0 +1,80 @@
+//! Local APIC / I/O APIC interrupt controller setup
+pub mod ioapic;
+pub mod local;
+pub mod pic;
+pub mod timer;
+
+use crate::{
+    acpi::{
+        AcpiTables,
+        madt::{MadtEntry, Polarity, TriggerMode},
+    },
+    clock::{self, ReferenceClock},
+    interrupt::{self, IRQ_BASE, InterruptHandler},
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ApicError {
+    NoMadt,
+    NoIoApic,
+    NoReferenceClock,
+    GsiNotFound(u32),
+}
+
+const LVT_NMI: u32 = 0b100 << 8;
+const LVT_ACTIVE_LOW: u32 = 1 << 13;
+const LVT_LEVEL: u32 = 1 << 15;
+
+/// Switch from the 8259 to the APICs and start the periodic tick
+///
+/// Interrupts stay disabled; the caller enables them once handlers are ready.
+pub fn init(acpi: &AcpiTables) -> Result<(), ApicError> {
+    let madt = acpi.madt.ok_or(ApicError::NoMadt)?;
+
+    pic::disable();
+    local::init(madt.local_apic_base());
+    let apic_id = local::id();
+    for entry in madt.entries() {
+        let MadtEntry::LocalApicNmi {
+            processor_uid,
+            flags,
+            lint,
+        } = entry
+        else {
+            continue;
+        };
+        // 0xff は全プロセッサを表す
+        if processor_uid != 0xff && !is_current_processor(&madt, processor_uid, apic_id) {
+            continue;
+        }
+        let mut lvt = LVT_NMI;
+        if flags.polarity() == Polarity::ActiveLow {
+            lvt |= LVT_ACTIVE_LOW;
+        }
+        if flags.trigger_mode() == TriggerMode::Level {
+            lvt |= LVT_LEVEL;
+        }
+        local::set_lint(lint, lvt);
+    }
+    ioapic::init(&madt)?;
+
+    let reference = ReferenceClock::from_acpi(acpi).ok_or(ApicError::NoReferenceClock)?;
+    local::calibrate_timer(&reference);
+    clock::calibrate_tsc(&reference);
+    timer::start();
+    Ok(())
+}
+
+fn is_current_processor(madt: &crate::acpi::madt::Madt, uid: u8, apic_id: u8) -> bool {
+    madt.entries().any(|entry| {
+        matches!(entry, MadtEntry::LocalApic { processor_uid, apic_id: id, .. }
+            if processor_uid == uid && id == apic_id)
+    })
+}
+
+/// Install `handler` for ISA `irq` and unmask it on the I/O APIC
+pub fn register_isa_irq(irq: u8, handler: InterruptHandler) -> Result<(), ApicError> {
+    let vector = IRQ_BASE + irq;
+    interrupt::register_handler(vector, handler);
+    ioapic::route_isa_irq(irq, vector, local::id())
+}
@@ -0,0 +1,24 @@
+//! Legacy 8259 PIC
+
+use crate::{interrupt::IRQ_BASE, x86};
+
+const MASTER_COMMAND: u16 = 0x20;
+const MASTER_DATA: u16 = 0x21;
+const SLAVE_COMMAND: u16 = 0xa0;
+const SLAVE_DATA: u16 = 0xa1;
+
+/// Remap the PICs away from the exception vectors and mask every line
+pub fn disable() {
+    // ICW1〜ICW4: 初期化して IRQ_BASE 以降に再配置する
+    x86::io_out8(MASTER_COMMAND, 0x11);
+    x86::io_out8(SLAVE_COMMAND, 0x11);
+    x86::io_out8(MASTER_DATA, IRQ_BASE);
+    x86::io_out8(SLAVE_DATA, IRQ_BASE + 8);
+    x86::io_out8(MASTER_DATA, 1 << 2);
+    x86::io_out8(SLAVE_DATA, 2);
+    x86::io_out8(MASTER_DATA, 0x01);
+    x86::io_out8(SLAVE_DATA, 0x01);
+
+    x86::io_out8(MASTER_DATA, 0xff);
+    x86::io_out8(SLAVE_DATA, 0xff);
+}
@@ -0,0 +1,36 @@
+//! Periodic tick driven by the local APIC timer
+
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use crate::{
+    interrupt::{self, InterruptFrame, LAPIC_TIMER},
+    task, timer,
+};
+
+use super::local;
+
+/// Tick frequency of the kernel
+pub const TICK_HZ: u64 = 100;
+
+static TICKS: AtomicU64 = AtomicU64::new(0);
+
+pub fn start() {
+    interrupt::register_handler(LAPIC_TIMER, on_tick);
+    local::start_periodic_timer(LAPIC_TIMER, TICK_HZ);
+}
+
+/// Ticks elapsed since [`start`]
+pub fn ticks() -> u64 {
+    TICKS.load(Ordering::Relaxed)
+}
+
+/// Milliseconds elapsed since [`start`], at tick resolution
+pub fn uptime_ms() -> u64 {
+    ticks() * 1000 / TICK_HZ
+}
+
+fn on_tick(_frame: &mut InterruptFrame) {
+    let now = TICKS.fetch_add(1, Ordering::Relaxed) + 1;
+    timer::on_tick(now);
+    task::on_tick(now);
+}
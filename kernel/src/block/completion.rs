@@ -0,0 +1,51 @@
+//! Waiting for a storage request from task context
+
+use spin::Mutex;
+
+use crate::{
+    task::{self, TaskId},
+    x86,
+};
+
+/// Lets the task that submitted a request sleep until the device interrupt
+pub struct Completion {
+    waiter: Mutex<Option<TaskId>>,
+}
+
+impl Completion {
+    pub const fn new() -> Self {
+        Self {
+            waiter: Mutex::new(None),
+        }
+    }
+
+    /// Return once `done` holds
+    ///
+    /// With `interrupt` the task blocks between checks and [`notify`] wakes
+    /// it; otherwise, or while interrupts are disabled, this busy-waits.
+    ///
+    /// [`notify`]: Completion::notify
+    pub fn wait_until(&self, interrupt: bool, mut done: impl FnMut() -> bool) {
+        // 割り込み禁止中はハンドラが走らないので待っても起こされない
+        let blocking = interrupt && x86::interrupts_enabled();
+        while !done() {
+            if !blocking {
+                core::hint::spin_loop();
+                continue;
+            }
+            x86::without_interrupts(|| *self.waiter.lock() = Some(task::current_id()));
+            // 登録前に完了していた場合に備えてもう一度確かめる
+            if !done() {
+                task::block();
+            }
+            x86::without_interrupts(|| *self.waiter.lock() = None);
+        }
+    }
+
+    /// Wake the waiting task; safe to call from interrupt handlers
+    pub fn notify(&self) {
+        if let Some(id) = *self.waiter.lock() {
+            task::wake(id);
+        }
+    }
+}
@@ -0,0 +1,103 @@
+//! Block device registry
+//!
+//! Storage drivers register their devices by name; filesystems get a
+//! [`Device`] handle that implements [`BlockDevice`] by locking the driver.
+//! Partitions found on a disk are registered as devices of their own.
+pub mod completion;
+pub mod partition;
+pub mod ramdisk;
+
+use alloc::{string::String, sync::Arc, vec::Vec};
+
+use spin::Mutex;
+
+pub use fs::{
+    block::{BlockDevice, BlockError},
+    partition::{Guid, PartitionInfo},
+};
+
+/// Shared handle to a registered device
+#[derive(Clone)]
+pub struct Device {
+    name: Arc<str>,
+    inner: Arc<Mutex<dyn BlockDevice + Send>>,
+    /// Set when this device is a partition of another one
+    partition: Option<Arc<PartitionInfo>>,
+}
+
+impl Device {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn partition(&self) -> Option<&PartitionInfo> {
+        self.partition.as_deref()
+    }
+}
+
+impl BlockDevice for Device {
+    fn block_size(&self) -> usize {
+        self.inner.lock().block_size()
+    }
+
+    fn block_count(&self) -> u64 {
+        self.inner.lock().block_count()
+    }
+
+    fn read_blocks(&mut self, lba: u64, buf: &mut [u8]) -> Result<(), BlockError> {
+        self.inner.lock().read_blocks(lba, buf)
+    }
+
+    fn write_blocks(&mut self, lba: u64, buf: &[u8]) -> Result<(), BlockError> {
+        self.inner.lock().write_blocks(lba, buf)
+    }
+
+    fn flush(&mut self) -> Result<(), BlockError> {
+        self.inner.lock().flush()
+    }
+}
+
+static DEVICES: Mutex<Vec<Device>> = Mutex::new(Vec::new());
+
+/// Register `device` as `<prefix><n>` with the first unused `n`
+pub fn register<D: BlockDevice + Send + 'static>(prefix: &str, device: D) -> Device {
+    let mut devices = DEVICES.lock();
+    let name = (0..)
+        .map(|n| alloc::format!("{prefix}{n}"))
+        .find(|name| devices.iter().all(|d| *d.name != **name))
+        .unwrap();
+    let device = Device {
+        name: name.into(),
+        inner: Arc::new(Mutex::new(device)),
+        partition: None,
+    };
+    devices.push(device.clone());
+    device
+}
+
+/// Register a partition under an exact name; `None` if the name is taken
+fn register_partition<D: BlockDevice + Send + 'static>(
+    name: String,
+    device: D,
+    info: PartitionInfo,
+) -> Option<Device> {
+    let mut devices = DEVICES.lock();
+    if devices.iter().any(|d| *d.name == *name) {
+        return None;
+    }
+    let device = Device {
+        name: name.into(),
+        inner: Arc::new(Mutex::new(device)),
+        partition: Some(Arc::new(info)),
+    };
+    devices.push(device.clone());
+    Some(device)
+}
+
+pub fn find(name: &str) -> Option<Device> {
+    DEVICES.lock().iter().find(|d| *d.name == *name).cloned()
+}
+
+pub fn devices() -> Vec<Device> {
+    DEVICES.lock().clone()
+}
@@ -0,0 +1,58 @@
+//! Partition discovery on registered disks
+
+use alloc::{format, vec::Vec};
+
+use common::boot_info::BootInfo;
+use fs::partition::{self, Partition, PartitionError};
+use spin::Once;
+
+use super::{Device, Guid};
+
+/// Unique GUID of the partition the bootloader was loaded from
+static BOOT_PARTITION: Once<Guid> = Once::new();
+
+/// Register the partitions of `disk` as `<disk>p<number>`
+///
+/// Partitions that are already registered are skipped, so scanning a disk
+/// again is harmless.
+pub fn scan(disk: &Device) -> Result<Vec<Device>, PartitionError> {
+    let mut device = disk.clone();
+    let partitions = partition::scan(&mut device)?;
+    Ok(partitions
+        .into_iter()
+        .filter_map(|info| {
+            let name = format!("{}p{}", disk.name(), info.number);
+            let device = Partition::new(disk.clone(), &info);
+            super::register_partition(name, device, info)
+        })
+        .collect())
+}
+
+/// Scan every registered disk; returns the number of partitions found
+pub fn init(boot_info: &BootInfo) -> usize {
+    BOOT_PARTITION.call_once(|| Guid(boot_info.boot_partition_guid));
+    super::devices()
+        .iter()
+        .filter(|device| device.partition().is_none())
+        .filter_map(|disk| scan(disk).ok())
+        .map(|partitions| partitions.len())
+        .sum()
+}
+
+/// The partition the system booted from
+///
+/// This is the partition whose unique GUID the bootloader reported, or the
+/// first EFI System Partition when the boot device path had none.
+pub fn boot_partition() -> Option<Device> {
+    let devices = super::devices();
+    if let Some(&guid) = BOOT_PARTITION.get().filter(|guid| !guid.is_null())
+        && let Some(device) = devices
+            .iter()
+            .find(|d| d.partition().and_then(|p| p.unique_guid()) == Some(guid))
+    {
+        return Some(device.clone());
+    }
+    devices
+        .into_iter()
+        .find(|device| device.partition().is_some_and(|p| p.is_efi_system()))
+}
@@ -0,0 +1,26 @@
+//! RAM disk over the volume image loaded by the bootloader
+
+use common::boot_info::BootInfo;
+use fs::block::MemoryDevice;
+
+use super::Device;
+
+pub const BLOCK_SIZE: usize = 512;
+
+/// Register the volume image from `boot_info` as `ram<n>`
+///
+/// # Safety
+///
+/// `boot_info` のボリュームイメージの範囲は恒等マップされていて、カーネルの他の用途と重なっていない必要があります。
+pub unsafe fn init(boot_info: &BootInfo) -> Option<Device> {
+    if boot_info.volume_image_base == 0 || boot_info.volume_image_size == 0 {
+        return None;
+    }
+    let data = unsafe {
+        core::slice::from_raw_parts_mut(
+            boot_info.volume_image_base as *mut u8,
+            boot_info.volume_image_size as usize,
+        )
+    };
+    Some(super::register("ram", MemoryDevice::new(data, BLOCK_SIZE)))
+}
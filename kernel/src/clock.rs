@@ -0,0 +1,175 @@
+//! Fixed-frequency reference clocks used to calibrate other timers, and the
+//! TSC-based high-resolution clock calibrated against them
+
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use crate::{
+    acpi::{
+        AcpiTables,
+        fadt::PM_TIMER_FREQUENCY,
+        sdt::{AddressSpace, GenericAddress},
+    },
+    x86,
+};
+
+const HPET_CAPABILITIES: u64 = 0x00;
+const HPET_CONFIG: u64 = 0x10;
+const HPET_MAIN_COUNTER: u64 = 0xf0;
+const HPET_ENABLE: u64 = 1 << 0;
+const HPET_COUNT_SIZE_64: u64 = 1 << 13;
+
+const TSC_CALIBRATION_US: u64 = 10_000;
+
+static TSC_FREQUENCY: AtomicU64 = AtomicU64::new(0);
+static TSC_BASE: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Clone, Copy, Debug)]
+pub enum ReferenceClock {
+    /// ACPI PM timer (3.579545 MHz, 24 or 32 bits)
+    PmTimer { register: GenericAddress, mask: u64 },
+    /// HPET main counter
+    Hpet {
+        base: u64,
+        frequency: u64,
+        mask: u64,
+    },
+}
+
+impl ReferenceClock {
+    /// Pick the ACPI PM timer if present, otherwise the HPET
+    pub fn from_acpi(acpi: &AcpiTables) -> Option<Self> {
+        if let Some(fadt) = acpi.fadt
+            && let Some(register) = fadt.pm_timer
+            && matches!(
+                register.address_space,
+                AddressSpace::SystemIo | AddressSpace::SystemMemory
+            )
+        {
+            let mask = if fadt.pm_timer_is_32bit() {
+                0xffff_ffff
+            } else {
+                0x00ff_ffff
+            };
+            return Some(Self::PmTimer { register, mask });
+        }
+
+        let hpet = acpi.hpet?;
+        if hpet.base_address.address_space != AddressSpace::SystemMemory {
+            return None;
+        }
+        let base = hpet.base_address.address;
+        let capabilities = unsafe { read_mmio64(base + HPET_CAPABILITIES) };
+        let period_fs = capabilities >> 32;
+        if period_fs == 0 {
+            return None;
+        }
+        unsafe {
+            let config = read_mmio64(base + HPET_CONFIG);
+            write_mmio64(base + HPET_CONFIG, config | HPET_ENABLE);
+        }
+        let mask = if capabilities & HPET_COUNT_SIZE_64 != 0 {
+            u64::MAX
+        } else {
+            0xffff_ffff
+        };
+        Some(Self::Hpet {
+            base,
+            frequency: 1_000_000_000_000_000 / period_fs,
+            mask,
+        })
+    }
+
+    pub fn frequency(&self) -> u64 {
+        match self {
+            Self::PmTimer { .. } => PM_TIMER_FREQUENCY,
+            Self::Hpet { frequency, .. } => *frequency,
+        }
+    }
+
+    pub fn counter(&self) -> u64 {
+        match self {
+            Self::PmTimer { register, mask } => {
+                let value = match register.address_space {
+                    AddressSpace::SystemIo => x86::io_in32(register.address as u16),
+                    _ => unsafe { (register.address as *const u32).read_volatile() },
+                };
+                value as u64 & mask
+            }
+            Self::Hpet { base, mask, .. } => unsafe {
+                read_mmio64(base + HPET_MAIN_COUNTER) & mask
+            },
+        }
+    }
+
+    fn mask(&self) -> u64 {
+        match self {
+            Self::PmTimer { mask, .. } | Self::Hpet { mask, .. } => *mask,
+        }
+    }
+
+    /// Busy-wait for `us` microseconds
+    pub fn wait_us(&self, us: u64) {
+        let target = self.frequency() * us / 1_000_000;
+        let start = self.counter();
+        while self.counter().wrapping_sub(start) & self.mask() < target {
+            core::hint::spin_loop();
+        }
+    }
+}
+
+unsafe fn read_mmio64(addr: u64) -> u64 {
+    unsafe { (addr as *const u64).read_volatile() }
+}
+
+unsafe fn write_mmio64(addr: u64, value: u64) {
+    unsafe { (addr as *mut u64).write_volatile(value) }
+}
+
+/// Measure the TSC frequency; the high-resolution clock counts from here
+pub fn calibrate_tsc(reference: &ReferenceClock) -> u64 {
+    let start = x86::rdtsc();
+    reference.wait_us(TSC_CALIBRATION_US);
+    let end = x86::rdtsc();
+    let frequency = (end - start) * 1_000_000 / TSC_CALIBRATION_US;
+    TSC_BASE.store(start, Ordering::Relaxed);
+    TSC_FREQUENCY.store(frequency, Ordering::Relaxed);
+    frequency
+}
+
+/// TSC ticks per second, `0` before [`calibrate_tsc`]
+pub fn tsc_frequency() -> u64 {
+    TSC_FREQUENCY.load(Ordering::Relaxed)
+}
+
+/// Nanoseconds since TSC calibration, `None` before it
+pub fn uptime_ns() -> Option<u64> {
+    let frequency = tsc_frequency();
+    if frequency == 0 {
+        return None;
+    }
+    let elapsed = x86::rdtsc().wrapping_sub(TSC_BASE.load(Ordering::Relaxed));
+    Some((elapsed as u128 * 1_000_000_000 / frequency as u128) as u64)
+}
+
+/// Poll `condition` for up to `timeout_ms`; returns whether it became true
+///
+/// Before the TSC is calibrated the timeout is approximated by an iteration
+/// count.
+pub fn wait_until(timeout_ms: u64, mut condition: impl FnMut() -> bool) -> bool {
+    let start = uptime_ns();
+    let mut spins = 0u64;
+    loop {
+        if condition() {
+            return true;
+        }
+        let expired = match (start, uptime_ns()) {
+            (Some(start), Some(now)) => now - start >= timeout_ms * 1_000_000,
+            _ => spins >= timeout_ms * 100_000,
+        };
+        if expired {
+            return false;
+        }
+        spins += 1;
+        core::hint::spin_loop();
+    }
+}
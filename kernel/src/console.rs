@@ -0,0 +1,46 @@
+//! Kernel text console
+//!
+//! Input is the ASCII of key presses, fed in by the kernel event loop.
+//! Output goes to the serial port.
+
+use spin::Mutex;
+
+use crate::{block::completion::Completion, queue::ArrayQueue, serial, x86};
+
+const INPUT_SIZE: usize = 256;
+
+static INPUT: Mutex<ArrayQueue<u8, INPUT_SIZE>> = Mutex::new(ArrayQueue::new());
+static INPUT_READY: Completion = Completion::new();
+
+/// Queue a typed byte for readers; dropped when the queue is full
+pub fn push_input(byte: u8) {
+    let pushed = x86::without_interrupts(|| INPUT.lock().push(byte).is_ok());
+    if pushed {
+        INPUT_READY.notify();
+    }
+}
+
+/// Wait for input and read what is available, at most `buf.len()` bytes
+pub fn read(buf: &mut [u8]) -> usize {
+    if buf.is_empty() {
+        return 0;
+    }
+    INPUT_READY.wait_until(true, || {
+        x86::without_interrupts(|| !INPUT.lock().is_empty())
+    });
+    x86::without_interrupts(|| {
+        let mut input = INPUT.lock();
+        let mut len = 0;
+        while len < buf.len()
+            && let Some(byte) = input.pop()
+        {
+            buf[len] = byte;
+            len += 1;
+        }
+        len
+    })
+}
+
+pub fn write(bytes: &[u8]) {
+    serial::write(bytes);
+}
@@ -0,0 +1,230 @@
+//! Desktop: background, windows and the mouse cursor on a [`LayerManager`]
+//!
+//! The desktop is global so that system calls can draw into the windows of
+//! user tasks; the kernel event loop still does all input handling and
+//! composing.
+
+use alloc::{string::String, vec::Vec};
+
+use spin::Mutex;
+
+use crate::{
+    graphics::{PixelColor, PixelWriter, ShadowBuffer},
+    input::{MouseEvent, button},
+    layer::{LayerId, LayerManager},
+    message::{Message, WindowEvent},
+    mouse::{self, CURSOR_HEIGHT, CURSOR_TRANSPARENT, CURSOR_WIDTH},
+    task::{self, TaskId},
+    window,
+};
+
+const BACKGROUND: PixelColor = PixelColor::new(45, 118, 237);
+
+static DESKTOP: Mutex<Option<Desktop>> = Mutex::new(None);
+
+/// Create the desktop on `screen`
+pub fn init(screen: ShadowBuffer) {
+    let desktop = Desktop::new(screen);
+    *DESKTOP.lock() = Some(desktop);
+}
+
+/// Run `f` on the desktop; `None` when there is no screen
+pub fn with<R>(f: impl FnOnce(&mut Desktop) -> R) -> Option<R> {
+    DESKTOP.lock().as_mut().map(f)
+}
+
+/// Ask the kernel event loop to compose changes made from another task
+pub fn request_redraw() {
+    // メールボックスが一杯なら、その処理の後で描画される
+    let _ = task::send_message(task::main_id(), Message::Redraw);
+}
+
+struct Window {
+    layer: LayerId,
+    title: String,
+    /// Task that receives this window's [`WindowEvent`]s
+    owner: TaskId,
+}
+
+pub struct Desktop {
+    layers: LayerManager<ShadowBuffer>,
+    background: LayerId,
+    windows: Vec<Window>,
+    active: Option<LayerId>,
+    cursor: LayerId,
+    cursor_position: (usize, usize),
+    buttons: u8,
+    /// Window being dragged with the left button
+    dragging: Option<LayerId>,
+}
+
+impl Desktop {
+    pub fn new(screen: ShadowBuffer) -> Self {
+        let mut layers = LayerManager::new(screen);
+        let (width, height) = layers.screen_size();
+
+        let background = layers.new_layer(width, height);
+        if let Some(layer) = layers.layer_mut(background) {
+            layer.fill_rectangle(0, 0, width, height, BACKGROUND);
+        }
+        layers.show(background);
+
+        let cursor = layers.new_layer(CURSOR_WIDTH, CURSOR_HEIGHT);
+        if let Some(layer) = layers.layer_mut(cursor) {
+            layer.set_always_on_top(true);
+            layer.fill_rectangle(0, 0, CURSOR_WIDTH, CURSOR_HEIGHT, CURSOR_TRANSPARENT);
+            mouse::draw_cursor(layer, 0, 0);
+            layer.set_transparent(Some(CURSOR_TRANSPARENT));
+        }
+        let cursor_position = (200, 100);
+        layers.move_to(cursor, cursor_position.0 as i32, cursor_position.1 as i32);
+        layers.show(cursor);
+
+        let mut desktop = Self {
+            layers,
+            background,
+            windows: Vec::new(),
+            active: None,
+            cursor,
+            cursor_position,
+            buttons: 0,
+            dragging: None,
+        };
+        desktop.layers.compose_all();
+        desktop
+    }
+
+    /// Create a decorated window owned by `owner`, shown and activated
+    pub fn new_window(
+        &mut self,
+        width: usize,
+        height: usize,
+        title: &str,
+        owner: TaskId,
+    ) -> LayerId {
+        let id = self.layers.new_layer(width, height);
+        if let Some(layer) = self.layers.layer_mut(id) {
+            window::draw_window(layer, title, false);
+        }
+        self.windows.push(Window {
+            layer: id,
+            title: title.into(),
+            owner,
+        });
+        self.layers.show(id);
+        self.activate(Some(id));
+        id
+    }
+
+    /// The task that receives `id`'s events; `None` if it is not a window
+    pub fn window_owner(&self, id: LayerId) -> Option<TaskId> {
+        self.windows.iter().find(|w| w.layer == id).map(|w| w.owner)
+    }
+
+    pub fn active_window(&self) -> Option<LayerId> {
+        self.active
+    }
+
+    /// Close every window of `owner`; returns whether there were any
+    pub fn close_windows_of(&mut self, owner: TaskId) -> bool {
+        let ids: Vec<LayerId> = self
+            .windows
+            .iter()
+            .filter(|w| w.owner == owner)
+            .map(|w| w.layer)
+            .collect();
+        for &id in &ids {
+            self.close_window(id);
+        }
+        !ids.is_empty()
+    }
+
+    pub fn close_window(&mut self, id: LayerId) {
+        self.windows.retain(|w| w.layer != id);
+        self.layers.remove(id);
+        if self.active == Some(id) {
+            self.active = None;
+        }
+        if self.dragging == Some(id) {
+            self.dragging = None;
+        }
+    }
+
+    /// Raise `id` and redraw the title bars whose state changed
+    fn activate(&mut self, id: Option<LayerId>) {
+        if self.active == id {
+            return;
+        }
+        let previous = core::mem::replace(&mut self.active, id);
+        for (layer, active) in [(previous, false), (id, true)] {
+            let Some(window) = layer.and_then(|l| self.windows.iter().find(|w| w.layer == l))
+            else {
+                continue;
+            };
+            let (layer, owner) = (window.layer, window.owner);
+            if let Some(writer) = self.layers.layer_mut(layer) {
+                window::draw_title_bar(writer, &window.title, active);
+            }
+            let event = if active {
+                WindowEvent::Activated(layer)
+            } else {
+                WindowEvent::Deactivated(layer)
+            };
+            let _ = task::send_message(owner, Message::Window(event));
+        }
+        if let Some(id) = id {
+            self.layers.raise(id);
+        }
+    }
+
+    pub fn layers(&mut self) -> &mut LayerManager<ShadowBuffer> {
+        &mut self.layers
+    }
+
+    pub fn on_mouse(&mut self, event: MouseEvent) {
+        let (x, y) = mouse::move_clamped(
+            self.cursor_position,
+            event.dx,
+            event.dy,
+            self.layers.screen_size(),
+        );
+        let (dx, dy) = (
+            x as i32 - self.cursor_position.0 as i32,
+            y as i32 - self.cursor_position.1 as i32,
+        );
+        self.cursor_position = (x, y);
+        self.layers.move_to(self.cursor, x as i32, y as i32);
+
+        let pressed = event.buttons & !self.buttons;
+        let released = self.buttons & !event.buttons;
+        self.buttons = event.buttons;
+        if pressed & button::LEFT != 0 {
+            let clicked = self
+                .layers
+                .layer_at(x as i32, y as i32, Some(self.cursor))
+                .filter(|&id| id != self.background);
+            self.activate(clicked);
+            self.dragging = clicked;
+            if let Some(window) = clicked.and_then(|id| self.windows.iter().find(|w| w.layer == id))
+                && let Some(layer) = self.layers.layer(window.layer)
+            {
+                let rect = layer.rect();
+                let close = window::close_button_rect(rect.width as usize).offset(rect.x, rect.y);
+                if close.contains(x as i32, y as i32) {
+                    self.dragging = None;
+                    let event = WindowEvent::Close(window.layer);
+                    let _ = task::send_message(window.owner, Message::Window(event));
+                }
+            }
+        } else if released & button::LEFT != 0 {
+            self.dragging = None;
+        } else if let Some(id) = self.dragging {
+            self.layers.move_relative(id, dx, dy);
+        }
+    }
+
+    /// Push pending changes to the screen
+    pub fn compose(&mut self) {
+        self.layers.compose();
+    }
+}
@@ -0,0 +1,47 @@
+//! Physically contiguous memory for DMA (controller rings, queues, buffers)
+//!
+//! Allocations come from a static pool and are never freed. The kernel runs
+//! on the identity mapping set up by UEFI, so these addresses can be handed
+//! to the controller as-is.
+
+use core::{
+    cell::UnsafeCell,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+const POOL_SIZE: usize = 2 * 1024 * 1024;
+
+#[repr(C, align(4096))]
+struct Pool(UnsafeCell<[u8; POOL_SIZE]>);
+
+// 各領域は一度しか払い出さないので、同時に同じバイトを触ることはない
+unsafe impl Sync for Pool {}
+
+static POOL: Pool = Pool(UnsafeCell::new([0; POOL_SIZE]));
+static NEXT: AtomicUsize = AtomicUsize::new(0);
+
+/// Allocate `size` zeroed bytes aligned to `align` that do not cross a
+/// multiple of `boundary` (`0` for no restriction)
+pub fn allocate(size: usize, align: usize, boundary: usize) -> Option<*mut u8> {
+    let base = POOL.0.get() as usize;
+    let mut result = None;
+    NEXT.fetch_update(Ordering::AcqRel, Ordering::Acquire, |next| {
+        let mut start = (base + next).next_multiple_of(align);
+        if boundary != 0 && start / boundary != (start + size - 1) / boundary {
+            start = start.next_multiple_of(boundary);
+        }
+        let end = start + size - base;
+        if end > POOL_SIZE {
+            return None;
+        }
+        result = Some(start as *mut u8);
+        Some(end)
+    })
+    .ok()?;
+    result
+}
+
+/// Allocate an array of `count` zeroed `T`s
+pub fn allocate_array<T>(count: usize, align: usize, boundary: usize) -> Option<*mut T> {
+    allocate(size_of::<T>() * count, align.max(align_of::<T>()), boundary).map(|p| p as *mut T)
+}
@@ -0,0 +1,64 @@
+//! 8x16 ASCII bitmap font
+
+use crate::graphics::{PixelColor, PixelWriter};
+
+pub const FONT_WIDTH: usize = 8;
+pub const FONT_HEIGHT: usize = 16;
+
+/// Glyph rows indexed by character code; bit 7 is the leftmost pixel
+static FONT: [[u8; FONT_HEIGHT]; 256] = parse(include_bytes!("font.txt"));
+
+/// Parse `font.txt` at compile time
+const fn parse(src: &[u8]) -> [[u8; FONT_HEIGHT]; 256] {
+    let mut font = [[0u8; FONT_HEIGHT]; 256];
+    let mut i = 0;
+    while i + 4 <= src.len() {
+        if src[i] == b'0' && src[i + 1] == b'x' {
+            let code = hex_digit(src[i + 2]) * 16 + hex_digit(src[i + 3]);
+            i = next_line(src, i);
+            let mut row = 0;
+            while row < FONT_HEIGHT {
+                let mut bits = 0u8;
+                let mut col = 0;
+                while col < FONT_WIDTH {
+                    if src[i + col] == b'*' {
+                        bits |= 0x80 >> col;
+                    }
+                    col += 1;
+                }
+                font[code][row] = bits;
+                i = next_line(src, i);
+                row += 1;
+            }
+        } else {
+            i = next_line(src, i);
+        }
+    }
+    font
+}
+
+const fn hex_digit(c: u8) -> usize {
+    match c {
+        b'0'..=b'9' => (c - b'0') as usize,
+        b'a'..=b'f' => (c - b'a' + 10) as usize,
+        b'A'..=b'F' => (c - b'A' + 10) as usize,
+        _ => panic!("invalid hex digit in font.txt"),
+    }
+}
+
+const fn next_line(src: &[u8], mut i: usize) -> usize {
+    while i < src.len() && src[i] != b'\n' {
+        i += 1;
+    }
+    i + 1
+}
+
+pub fn write_ascii(writer: &mut impl PixelWriter, x: usize, y: usize, c: u8, color: PixelColor) {
+    for (dy, &bits) in FONT[c as usize].iter().enumerate() {
+        for dx in 0..FONT_WIDTH {
+            if bits & (0x80 >> dx) != 0 {
+                writer.write(x + dx, y + dy, color);
+            }
+        }
+    }
+}
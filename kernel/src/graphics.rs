@@ -0,0 +1,258 @@
+//! Frame buffer drawing primitives
+
+use alloc::{vec, vec::Vec};
+
+use common::boot_info::{BootInfo, PixelFormat};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PixelColor {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+impl PixelColor {
+    pub const fn new(r: u8, g: u8, b: u8) -> Self {
+        Self { r, g, b }
+    }
+
+    pub const BLACK: Self = Self::new(0, 0, 0);
+    pub const WHITE: Self = Self::new(255, 255, 255);
+}
+
+/// Anything pixels can be drawn to; coordinates outside the surface are ignored
+pub trait PixelWriter {
+    fn width(&self) -> usize;
+    fn height(&self) -> usize;
+    fn write(&mut self, x: usize, y: usize, color: PixelColor);
+
+    fn fill_rectangle(&mut self, x: usize, y: usize, width: usize, height: usize, c: PixelColor) {
+        for dy in 0..height {
+            for dx in 0..width {
+                self.write(x + dx, y + dy, c);
+            }
+        }
+    }
+
+    /// Push buffered pixels to the device; a no-op for unbuffered writers
+    fn flush(&mut self) {}
+}
+
+/// Writes straight into the GOP frame buffer
+pub struct FrameBufferWriter {
+    base: *mut u32,
+    width: usize,
+    height: usize,
+    stride: usize,
+    format: PixelFormat,
+}
+
+// フレームバッファを指すのはこの 1 つだけなので、タスク間で移しても競合しない
+unsafe impl Send for FrameBufferWriter {}
+
+impl FrameBufferWriter {
+    /// Returns `None` for pixel formats the kernel cannot draw
+    ///
+    /// # Safety
+    ///
+    /// `boot_info` のフレームバッファ情報が正しく、その領域が他から書き換えられない必要があります。
+    pub unsafe fn new(boot_info: &BootInfo) -> Option<Self> {
+        if boot_info.pixel_format == PixelFormat::Unsupported || boot_info.frame_buffer_base == 0 {
+            return None;
+        }
+        Some(Self {
+            base: boot_info.frame_buffer_base as *mut u32,
+            width: boot_info.horizontal_resolution as usize,
+            height: boot_info.vertical_resolution as usize,
+            stride: boot_info.pixels_per_scan_line as usize,
+            format: boot_info.pixel_format,
+        })
+    }
+
+    /// Copy already encoded pixels to row `y` starting at column `x`
+    pub fn write_raw(&mut self, x: usize, y: usize, pixels: &[u32]) {
+        if y >= self.height || x >= self.width {
+            return;
+        }
+        let count = pixels.len().min(self.width - x);
+        unsafe {
+            core::ptr::copy_nonoverlapping(
+                pixels.as_ptr(),
+                self.base.add(y * self.stride + x),
+                count,
+            )
+        };
+    }
+
+    pub fn encode(&self, c: PixelColor) -> u32 {
+        match self.format {
+            PixelFormat::Rgb => u32::from_le_bytes([c.r, c.g, c.b, 0]),
+            _ => u32::from_le_bytes([c.b, c.g, c.r, 0]),
+        }
+    }
+}
+
+impl PixelWriter for FrameBufferWriter {
+    fn width(&self) -> usize {
+        self.width
+    }
+
+    fn height(&self) -> usize {
+        self.height
+    }
+
+    fn write(&mut self, x: usize, y: usize, color: PixelColor) {
+        if x >= self.width || y >= self.height {
+            return;
+        }
+        let value = self.encode(color);
+        unsafe { self.base.add(y * self.stride + x).write_volatile(value) };
+    }
+}
+
+/// Off-screen copy of the frame buffer
+///
+/// Drawing goes to ordinary cached RAM; [`flush`](PixelWriter::flush)
+/// copies only the changed span of each row to the (uncached or
+/// write-combining) frame buffer, which also avoids showing half-drawn frames.
+pub struct ShadowBuffer {
+    frame_buffer: FrameBufferWriter,
+    pixels: Vec<u32>,
+    /// Changed columns `start..end` of each row; empty when the row is clean
+    dirty: Vec<(u32, u32)>,
+}
+
+impl ShadowBuffer {
+    /// The buffer starts out black; only pixels drawn afterwards reach the screen
+    pub fn new(frame_buffer: FrameBufferWriter) -> Self {
+        let (width, height) = (frame_buffer.width(), frame_buffer.height());
+        Self {
+            frame_buffer,
+            pixels: vec![0; width * height],
+            dirty: vec![(0, 0); height],
+        }
+    }
+
+    fn mark_dirty(&mut self, y: usize, start: usize, end: usize) {
+        let (s, e) = &mut self.dirty[y];
+        if *s >= *e {
+            (*s, *e) = (start as u32, end as u32);
+        } else {
+            *s = (*s).min(start as u32);
+            *e = (*e).max(end as u32);
+        }
+    }
+}
+
+impl PixelWriter for ShadowBuffer {
+    fn width(&self) -> usize {
+        self.frame_buffer.width()
+    }
+
+    fn height(&self) -> usize {
+        self.frame_buffer.height()
+    }
+
+    fn write(&mut self, x: usize, y: usize, color: PixelColor) {
+        if x >= self.width() || y >= self.height() {
+            return;
+        }
+        let width = self.width();
+        self.pixels[y * width + x] = self.frame_buffer.encode(color);
+        self.mark_dirty(y, x, x + 1);
+    }
+
+    fn fill_rectangle(&mut self, x: usize, y: usize, width: usize, height: usize, c: PixelColor) {
+        let right = (x + width).min(self.width());
+        let bottom = (y + height).min(self.height());
+        if x >= right || y >= bottom {
+            return;
+        }
+        let value = self.frame_buffer.encode(c);
+        let stride = self.width();
+        for row in y..bottom {
+            self.pixels[row * stride + x..row * stride + right].fill(value);
+            self.mark_dirty(row, x, right);
+        }
+    }
+
+    fn flush(&mut self) {
+        let width = self.width();
+        for (y, span) in self.dirty.iter_mut().enumerate() {
+            let (start, end) = (span.0 as usize, span.1 as usize);
+            if start >= end {
+                continue;
+            }
+            let row = &self.pixels[y * width..(y + 1) * width];
+            self.frame_buffer.write_raw(start, y, &row[start..end]);
+            *span = (0, 0);
+        }
+    }
+}
+
+/// Axis-aligned rectangle; `x`/`y` may be negative for things partly off screen
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Rect {
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32,
+}
+
+impl Rect {
+    pub const fn new(x: i32, y: i32, width: i32, height: i32) -> Self {
+        Self {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.width <= 0 || self.height <= 0
+    }
+
+    pub fn right(&self) -> i32 {
+        self.x + self.width
+    }
+
+    pub fn bottom(&self) -> i32 {
+        self.y + self.height
+    }
+
+    pub fn contains(&self, x: i32, y: i32) -> bool {
+        (self.x..self.right()).contains(&x) && (self.y..self.bottom()).contains(&y)
+    }
+
+    /// Overlapping part of both rectangles (empty if they do not overlap)
+    pub fn intersection(&self, other: &Rect) -> Rect {
+        let x = self.x.max(other.x);
+        let y = self.y.max(other.y);
+        let right = self.right().min(other.right());
+        let bottom = self.bottom().min(other.bottom());
+        Rect::new(x, y, (right - x).max(0), (bottom - y).max(0))
+    }
+
+    /// Smallest rectangle covering both
+    pub fn union(&self, other: &Rect) -> Rect {
+        if self.is_empty() {
+            return *other;
+        }
+        if other.is_empty() {
+            return *self;
+        }
+        let x = self.x.min(other.x);
+        let y = self.y.min(other.y);
+        Rect::new(
+            x,
+            y,
+            self.right().max(other.right()) - x,
+            self.bottom().max(other.bottom()) - y,
+        )
+    }
+
+    pub fn offset(&self, dx: i32, dy: i32) -> Rect {
+        Rect::new(self.x + dx, self.y + dy, self.width, self.height)
+    }
+}
@@ -0,0 +1,120 @@
+//! HID usage ID to character translation
+
+use core::sync::atomic::{AtomicU8, Ordering};
+
+use super::modifier;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Layout {
+    Us,
+    /// JIS 106/109 key layout
+    Jp,
+}
+
+const TABLE_SIZE: usize = 0x8c;
+
+static LAYOUT: AtomicU8 = AtomicU8::new(Layout::Us as u8);
+
+/// `(normal, shifted)` characters indexed by HID usage ID (US layout)
+const US_LAYOUT: [(u8, u8); TABLE_SIZE] = {
+    let mut table = [(0u8, 0u8); TABLE_SIZE];
+    let letters = b"abcdefghijklmnopqrstuvwxyz";
+    let mut i = 0;
+    while i < letters.len() {
+        table[0x04 + i] = (letters[i], letters[i] - b'a' + b'A');
+        i += 1;
+    }
+    let digits = b"1234567890";
+    let shifted_digits = b"!@#$%^&*()";
+    let mut i = 0;
+    while i < digits.len() {
+        table[0x1e + i] = (digits[i], shifted_digits[i]);
+        i += 1;
+    }
+    table[0x28] = (b'\n', b'\n');
+    table[0x29] = (0x1b, 0x1b);
+    table[0x2a] = (0x08, 0x08);
+    table[0x2b] = (b'\t', b'\t');
+    table[0x2c] = (b' ', b' ');
+    table[0x2d] = (b'-', b'_');
+    table[0x2e] = (b'=', b'+');
+    table[0x2f] = (b'[', b'{');
+    table[0x30] = (b']', b'}');
+    table[0x31] = (b'\\', b'|');
+    table[0x32] = (b'#', b'~');
+    table[0x33] = (b';', b':');
+    table[0x34] = (b'\'', b'"');
+    table[0x35] = (b'`', b'~');
+    table[0x36] = (b',', b'<');
+    table[0x37] = (b'.', b'>');
+    table[0x38] = (b'/', b'?');
+    // テンキー
+    table[0x54] = (b'/', b'/');
+    table[0x55] = (b'*', b'*');
+    table[0x56] = (b'-', b'-');
+    table[0x57] = (b'+', b'+');
+    table[0x58] = (b'\n', b'\n');
+    let keypad = b"1234567890";
+    let mut i = 0;
+    while i < keypad.len() {
+        table[0x59 + i] = (keypad[i], keypad[i]);
+        i += 1;
+    }
+    table[0x63] = (b'.', b'.');
+    table[0x64] = (b'\\', b'|');
+    table
+};
+
+/// JIS layout: the US table with the symbol keys moved around
+const JP_LAYOUT: [(u8, u8); TABLE_SIZE] = {
+    let mut table = US_LAYOUT;
+    let shifted_digits = b"!\"#$%&'()";
+    let mut i = 0;
+    while i < shifted_digits.len() {
+        table[0x1e + i].1 = shifted_digits[i];
+        i += 1;
+    }
+    table[0x27] = (b'0', 0);
+    table[0x2d] = (b'-', b'=');
+    table[0x2e] = (b'^', b'~');
+    table[0x2f] = (b'@', b'`');
+    table[0x30] = (b'[', b'{');
+    table[0x31] = (b']', b'}');
+    table[0x32] = (b']', b'}');
+    table[0x33] = (b';', b'+');
+    table[0x34] = (b':', b'*');
+    // 半角/全角
+    table[0x35] = (0, 0);
+    table[0x87] = (b'\\', b'_');
+    table[0x89] = (b'\\', b'|');
+    table
+};
+
+#[allow(dead_code)] // レイアウトを切り替える設定やコマンドはまだない
+pub fn set_layout(layout: Layout) {
+    LAYOUT.store(layout as u8, Ordering::Relaxed);
+}
+
+pub fn layout() -> Layout {
+    if LAYOUT.load(Ordering::Relaxed) == Layout::Jp as u8 {
+        Layout::Jp
+    } else {
+        Layout::Us
+    }
+}
+
+pub fn to_ascii(keycode: u8, modifiers: u8) -> u8 {
+    let table = match layout() {
+        Layout::Us => &US_LAYOUT,
+        Layout::Jp => &JP_LAYOUT,
+    };
+    let Some(&(normal, shifted)) = table.get(keycode as usize) else {
+        return 0;
+    };
+    if modifiers & modifier::SHIFT != 0 {
+        shifted
+    } else {
+        normal
+    }
+}
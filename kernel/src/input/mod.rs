@@ -0,0 +1,77 @@
+//! Input events shared by all keyboard and mouse drivers
+pub mod keymap;
+
+use crate::{message::Message, task};
+
+/// HID modifier bits (same layout as byte 0 of a boot keyboard report)
+#[allow(dead_code)] // ビット配置は仕様どおりすべて定義しておく
+pub mod modifier {
+    pub const LEFT_CONTROL: u8 = 1 << 0;
+    pub const LEFT_SHIFT: u8 = 1 << 1;
+    pub const LEFT_ALT: u8 = 1 << 2;
+    pub const LEFT_GUI: u8 = 1 << 3;
+    pub const RIGHT_CONTROL: u8 = 1 << 4;
+    pub const RIGHT_SHIFT: u8 = 1 << 5;
+    pub const RIGHT_ALT: u8 = 1 << 6;
+    pub const RIGHT_GUI: u8 = 1 << 7;
+
+    pub const SHIFT: u8 = LEFT_SHIFT | RIGHT_SHIFT;
+    pub const CONTROL: u8 = LEFT_CONTROL | RIGHT_CONTROL;
+    pub const ALT: u8 = LEFT_ALT | RIGHT_ALT;
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct KeyEvent {
+    /// HID keyboard usage ID; PS/2 scancodes are translated to these
+    pub keycode: u8,
+    pub modifiers: u8,
+    pub pressed: bool,
+    /// ASCII character for the active layout, `0` if the key has none
+    pub ascii: u8,
+}
+
+/// Mouse button bits
+#[allow(dead_code)] // ビット配置は仕様どおりすべて定義しておく
+pub mod button {
+    pub const LEFT: u8 = 1 << 0;
+    pub const RIGHT: u8 = 1 << 1;
+    pub const MIDDLE: u8 = 1 << 2;
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MouseEvent {
+    pub dx: i16,
+    pub dy: i16,
+    pub wheel: i8,
+    pub buttons: u8,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InputEvent {
+    Key(KeyEvent),
+    Mouse(MouseEvent),
+}
+
+impl From<InputEvent> for Message {
+    fn from(event: InputEvent) -> Self {
+        match event {
+            InputEvent::Key(key) => Message::Key(key),
+            InputEvent::Mouse(mouse) => Message::Mouse(mouse),
+        }
+    }
+}
+
+/// Deliver an event to the kernel event loop. Drops the event when its mailbox is full.
+pub fn push(event: InputEvent) -> bool {
+    task::send_message(task::main_id(), event.into()).is_ok()
+}
+
+/// Build a [`KeyEvent`] for `keycode`, resolving its character with the current layout
+pub fn key_event(keycode: u8, modifiers: u8, pressed: bool) -> KeyEvent {
+    KeyEvent {
+        keycode,
+        modifiers,
+        pressed,
+        ascii: keymap::to_ascii(keycode, modifiers),
+    }
+}
@@ -0,0 +1,250 @@
+//! IDT setup and interrupt dispatch
+//!
+//! Every vector gets a small assembly stub that saves the general purpose
+//! registers and calls [`interrupt_dispatch`] with an [`InterruptFrame`], so
+//! handlers are plain Rust functions registered at run time.
+
+use core::{
+    arch::{asm, global_asm},
+    sync::atomic::{AtomicU64, AtomicUsize, Ordering},
+};
+
+use spin::Once;
+
+use crate::{apic, task, user, x86};
+
+/// ISA IRQ `n` is delivered on vector `IRQ_BASE + n`
+pub const IRQ_BASE: u8 = 0x20;
+pub const LAPIC_TIMER: u8 = 0x40;
+/// Vectors handed out by [`allocate_vectors`] (MSI/MSI-X)
+pub const DYNAMIC_VECTOR_START: u8 = 0x50;
+pub const DYNAMIC_VECTOR_END: u8 = 0xf0;
+pub const SPURIOUS: u8 = 0xff;
+
+/// Registers saved by the interrupt stubs, lowest address first
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct InterruptFrame {
+    pub rax: u64,
+    pub rbx: u64,
+    pub rcx: u64,
+    pub rdx: u64,
+    pub rsi: u64,
+    pub rdi: u64,
+    pub rbp: u64,
+    pub r8: u64,
+    pub r9: u64,
+    pub r10: u64,
+    pub r11: u64,
+    pub r12: u64,
+    pub r13: u64,
+    pub r14: u64,
+    pub r15: u64,
+    pub vector: u64,
+    /// CPU がエラーコードを積まない例外・割り込みでは 0
+    pub error_code: u64,
+    pub rip: u64,
+    pub cs: u64,
+    pub rflags: u64,
+    pub rsp: u64,
+    pub ss: u64,
+}
+
+pub type InterruptHandler = fn(&mut InterruptFrame);
+
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct IdtEntry {
+    offset_low: u16,
+    selector: u16,
+    attributes: u16,
+    offset_middle: u16,
+    offset_high: u32,
+    reserved: u32,
+}
+
+/// Present, DPL 0, 64-bit interrupt gate
+const INTERRUPT_GATE: u16 = 0x8e00;
+
+impl IdtEntry {
+    fn new(handler: u64, selector: u16) -> Self {
+        Self {
+            offset_low: handler as u16,
+            selector,
+            attributes: INTERRUPT_GATE,
+            offset_middle: (handler >> 16) as u16,
+            offset_high: (handler >> 32) as u32,
+            reserved: 0,
+        }
+    }
+}
+
+#[repr(C, packed)]
+struct IdtPointer {
+    limit: u16,
+    base: u64,
+}
+
+const STUB_SIZE: u64 = 16;
+
+static IDT: Once<[IdtEntry; 256]> = Once::new();
+static HANDLERS: [AtomicUsize; 256] = [const { AtomicUsize::new(0) }; 256];
+static ALLOCATED_VECTORS: [AtomicU64; 4] = [const { AtomicU64::new(0) }; 4];
+
+unsafe extern "C" {
+    static interrupt_stub_table: u8;
+}
+
+// 16 バイトごとに並べたスタブ。エラーコードを積まないベクタではダミーの 0 を積んで
+// フレームの形を揃える。
+global_asm!(
+    r#"
+    .section .text
+    .global interrupt_stub_table
+    .balign 16
+interrupt_stub_table:
+    .set vector, 0
+    .rept 256
+    .balign 16
+    .if !(vector == 8 || (vector >= 10 && vector <= 14) || vector == 17 || vector == 21 || vector == 29 || vector == 30)
+    pushq $0
+    .endif
+    pushq $vector
+    jmp interrupt_common
+    .set vector, vector + 1
+    .endr
+
+interrupt_common:
+    pushq %r15
+    pushq %r14
+    pushq %r13
+    pushq %r12
+    pushq %r11
+    pushq %r10
+    pushq %r9
+    pushq %r8
+    pushq %rbp
+    pushq %rdi
+    pushq %rsi
+    pushq %rdx
+    pushq %rcx
+    pushq %rbx
+    pushq %rax
+    movq %rsp, %rdi
+    cld
+    call {dispatch}
+    popq %rax
+    popq %rbx
+    popq %rcx
+    popq %rdx
+    popq %rsi
+    popq %rdi
+    popq %rbp
+    popq %r8
+    popq %r9
+    popq %r10
+    popq %r11
+    popq %r12
+    popq %r13
+    popq %r14
+    popq %r15
+    addq $16, %rsp
+    iretq
+"#,
+    dispatch = sym interrupt_dispatch,
+    options(att_syntax)
+);
+
+/// Build the IDT with the stubs of all 256 vectors and load it
+pub fn init() {
+    let idt = IDT.call_once(|| {
+        let selector = x86::read_cs();
+        let table = &raw const interrupt_stub_table as u64;
+        core::array::from_fn(|vector| IdtEntry::new(table + vector as u64 * STUB_SIZE, selector))
+    });
+
+    let pointer = IdtPointer {
+        limit: (size_of::<[IdtEntry; 256]>() - 1) as u16,
+        base: idt.as_ptr() as u64,
+    };
+    unsafe { asm!("lidt [{}]", in(reg) &pointer, options(readonly, nostack)) };
+}
+
+pub fn register_handler(vector: u8, handler: InterruptHandler) {
+    HANDLERS[vector as usize].store(handler as usize, Ordering::Release);
+}
+
+pub fn unregister_handler(vector: u8) {
+    HANDLERS[vector as usize].store(0, Ordering::Release);
+}
+
+/// Reserve `count` consecutive dynamic vectors aligned to `count`
+///
+/// `count` must be a power of two, as multi-message MSI requires.
+pub fn allocate_vectors(count: u8) -> Option<u8> {
+    if !count.is_power_of_two() || count > 32 {
+        return None;
+    }
+    let mut start = DYNAMIC_VECTOR_START.next_multiple_of(count);
+    while start as u16 + count as u16 <= DYNAMIC_VECTOR_END as u16 {
+        if try_reserve_range(start, count) {
+            return Some(start);
+        }
+        start += count;
+    }
+    None
+}
+
+fn try_reserve_range(start: u8, count: u8) -> bool {
+    for vector in start..start + count {
+        if !try_reserve_vector(vector) {
+            // 途中まで確保したものは戻しておく
+            (start..vector).for_each(free_vector);
+            return false;
+        }
+    }
+    true
+}
+
+fn try_reserve_vector(vector: u8) -> bool {
+    let bit = 1 << (vector % 64);
+    ALLOCATED_VECTORS[vector as usize / 64].fetch_or(bit, Ordering::AcqRel) & bit == 0
+}
+
+pub fn allocate_vector() -> Option<u8> {
+    allocate_vectors(1)
+}
+
+pub fn free_vector(vector: u8) {
+    unregister_handler(vector);
+    let bit = 1 << (vector % 64);
+    ALLOCATED_VECTORS[vector as usize / 64].fetch_and(!bit, Ordering::AcqRel);
+}
+
+extern "sysv64" fn interrupt_dispatch(frame: &mut InterruptFrame) {
+    let vector = frame.vector as u8;
+    if vector < IRQ_BASE && frame.cs & 3 == 3 {
+        // ユーザータスクの例外はそのタスクだけを終わらせる
+        user::on_exception(frame);
+    }
+    let handler = HANDLERS[vector as usize].load(Ordering::Acquire);
+    if handler != 0 {
+        let handler = unsafe { core::mem::transmute::<usize, InterruptHandler>(handler) };
+        handler(frame);
+    } else if vector < IRQ_BASE {
+        panic!(
+            "CPU exception {} (error code {:#x}) at {:#x}",
+            vector, frame.error_code, frame.rip
+        );
+    }
+
+    // スプリアス割り込みには EOI を送ってはいけない
+    if vector >= IRQ_BASE && vector != SPURIOUS {
+        apic::local::end_of_interrupt();
+        // EOI の後でないと切り替え先のタスクに割り込みが届かない
+        task::preempt_if_needed();
+        if frame.cs & 3 == 3 {
+            user::exit_if_killed();
+        }
+    }
+}
@@ -0,0 +1,315 @@
+//! Layers composed onto the screen in z-order
+//!
+//! Every layer draws into its own back buffer. [`LayerManager::compose`]
+//! then redraws only the parts of the screen that changed.
+
+use alloc::{vec, vec::Vec};
+
+use crate::graphics::{PixelColor, PixelWriter, Rect};
+
+pub type LayerId = u32;
+
+/// Dirty rectangles kept before they are merged into one bounding box
+const MAX_DIRTY_RECTS: usize = 32;
+
+pub struct Layer {
+    id: LayerId,
+    x: i32,
+    y: i32,
+    width: usize,
+    height: usize,
+    buffer: Vec<PixelColor>,
+    /// Pixels of this colour let the layers below show through
+    transparent: Option<PixelColor>,
+    visible: bool,
+    always_on_top: bool,
+    /// Area changed since the last compose, in layer coordinates
+    dirty: Rect,
+}
+
+impl Layer {
+    fn new(id: LayerId, width: usize, height: usize) -> Self {
+        Self {
+            id,
+            x: 0,
+            y: 0,
+            width,
+            height,
+            buffer: vec![PixelColor::BLACK; width * height],
+            transparent: None,
+            visible: false,
+            always_on_top: false,
+            dirty: Rect::default(),
+        }
+    }
+
+    pub fn position(&self) -> (i32, i32) {
+        (self.x, self.y)
+    }
+
+    /// Area covered on the screen
+    pub fn rect(&self) -> Rect {
+        Rect::new(self.x, self.y, self.width as i32, self.height as i32)
+    }
+
+    pub fn set_transparent(&mut self, color: Option<PixelColor>) {
+        self.transparent = color;
+        self.invalidate();
+    }
+
+    /// Keep this layer above everything [`LayerManager::raise`] brings up
+    pub fn set_always_on_top(&mut self, on_top: bool) {
+        self.always_on_top = on_top;
+    }
+
+    /// Mark the whole layer as changed
+    pub fn invalidate(&mut self) {
+        self.dirty = Rect::new(0, 0, self.width as i32, self.height as i32);
+    }
+
+    fn mark_dirty(&mut self, area: Rect) {
+        self.dirty = self.dirty.union(&area);
+    }
+
+    /// Take the changed area, converted to screen coordinates
+    fn take_dirty(&mut self) -> Rect {
+        let dirty = core::mem::take(&mut self.dirty);
+        dirty.offset(self.x, self.y)
+    }
+}
+
+impl PixelWriter for Layer {
+    fn width(&self) -> usize {
+        self.width
+    }
+
+    fn height(&self) -> usize {
+        self.height
+    }
+
+    fn write(&mut self, x: usize, y: usize, color: PixelColor) {
+        if x >= self.width || y >= self.height {
+            return;
+        }
+        self.buffer[y * self.width + x] = color;
+        self.mark_dirty(Rect::new(x as i32, y as i32, 1, 1));
+    }
+
+    fn fill_rectangle(&mut self, x: usize, y: usize, width: usize, height: usize, c: PixelColor) {
+        let right = (x + width).min(self.width);
+        let bottom = (y + height).min(self.height);
+        if x >= right || y >= bottom {
+            return;
+        }
+        for row in y..bottom {
+            self.buffer[row * self.width + x..row * self.width + right].fill(c);
+        }
+        self.mark_dirty(Rect::new(
+            x as i32,
+            y as i32,
+            (right - x) as i32,
+            (bottom - y) as i32,
+        ));
+    }
+}
+
+pub struct LayerManager<W: PixelWriter> {
+    screen: W,
+    layers: Vec<Layer>,
+    /// Layer ids from bottom to top; hidden layers keep their place
+    order: Vec<LayerId>,
+    next_id: LayerId,
+    /// Screen areas to redraw regardless of layer contents (moves, hides)
+    dirty: Vec<Rect>,
+}
+
+impl<W: PixelWriter> LayerManager<W> {
+    pub fn new(screen: W) -> Self {
+        Self {
+            screen,
+            layers: Vec::new(),
+            order: Vec::new(),
+            next_id: 1,
+            dirty: Vec::new(),
+        }
+    }
+
+    pub fn screen_size(&self) -> (usize, usize) {
+        (self.screen.width(), self.screen.height())
+    }
+
+    fn screen_rect(&self) -> Rect {
+        Rect::new(
+            0,
+            0,
+            self.screen.width() as i32,
+            self.screen.height() as i32,
+        )
+    }
+
+    /// Create a hidden layer at `(0, 0)` on top of the others
+    pub fn new_layer(&mut self, width: usize, height: usize) -> LayerId {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.layers.push(Layer::new(id, width, height));
+        self.order.push(id);
+        self.raise(id);
+        id
+    }
+
+    pub fn remove(&mut self, id: LayerId) {
+        let Some(index) = self.layers.iter().position(|l| l.id == id) else {
+            return;
+        };
+        let layer = self.layers.swap_remove(index);
+        if layer.visible {
+            self.invalidate(layer.rect());
+        }
+        self.order.retain(|&other| other != id);
+    }
+
+    pub fn layer(&self, id: LayerId) -> Option<&Layer> {
+        self.layers.iter().find(|l| l.id == id)
+    }
+
+    /// Draw into a layer; changes appear on the next [`compose`](Self::compose)
+    pub fn layer_mut(&mut self, id: LayerId) -> Option<&mut Layer> {
+        self.layers.iter_mut().find(|l| l.id == id)
+    }
+
+    pub fn move_to(&mut self, id: LayerId, x: i32, y: i32) {
+        let Some(layer) = self.layer_mut(id) else {
+            return;
+        };
+        let old = layer.rect();
+        let visible = layer.visible;
+        layer.x = x;
+        layer.y = y;
+        let new = layer.rect();
+        if visible {
+            self.invalidate(old);
+            self.invalidate(new);
+        }
+    }
+
+    pub fn move_relative(&mut self, id: LayerId, dx: i32, dy: i32) {
+        if let Some((x, y)) = self.layer(id).map(Layer::position) {
+            self.move_to(id, x + dx, y + dy);
+        }
+    }
+
+    /// Bring a layer to the front, below any always-on-top layers
+    pub fn raise(&mut self, id: LayerId) {
+        let Some(on_top) = self.layer(id).map(|l| l.always_on_top) else {
+            return;
+        };
+        self.order.retain(|&other| other != id);
+        let index = if on_top {
+            self.order.len()
+        } else {
+            self.order
+                .iter()
+                .position(|&other| self.layer(other).is_some_and(|l| l.always_on_top))
+                .unwrap_or(self.order.len())
+        };
+        self.order.insert(index, id);
+        if let Some(rect) = self.layer(id).filter(|l| l.visible).map(Layer::rect) {
+            self.invalidate(rect);
+        }
+    }
+
+    pub fn show(&mut self, id: LayerId) {
+        self.set_visible(id, true);
+    }
+
+    #[allow(dead_code)] // 隠す操作を使うウィンドウはまだない
+    pub fn hide(&mut self, id: LayerId) {
+        self.set_visible(id, false);
+    }
+
+    fn set_visible(&mut self, id: LayerId, visible: bool) {
+        let Some(layer) = self.layer_mut(id) else {
+            return;
+        };
+        if layer.visible == visible {
+            return;
+        }
+        layer.visible = visible;
+        let rect = layer.rect();
+        self.invalidate(rect);
+    }
+
+    /// Topmost visible layer under `(x, y)`, skipping `exclude` (e.g. the cursor)
+    pub fn layer_at(&self, x: i32, y: i32, exclude: Option<LayerId>) -> Option<LayerId> {
+        self.order.iter().rev().copied().find(|&id| {
+            Some(id) != exclude
+                && self
+                    .layer(id)
+                    .is_some_and(|l| l.visible && l.rect().contains(x, y))
+        })
+    }
+
+    /// Schedule a screen area for redraw
+    pub fn invalidate(&mut self, rect: Rect) {
+        let mut rect = rect.intersection(&self.screen_rect());
+        if rect.is_empty() {
+            return;
+        }
+        // 重なる矩形はまとめて、同じ画素を何度も描かないようにする
+        while let Some(index) = self
+            .dirty
+            .iter()
+            .position(|d| !d.intersection(&rect).is_empty())
+        {
+            rect = rect.union(&self.dirty.swap_remove(index));
+        }
+        self.dirty.push(rect);
+        if self.dirty.len() > MAX_DIRTY_RECTS {
+            let bounds = self.dirty.iter().fold(Rect::default(), |a, r| a.union(r));
+            self.dirty.clear();
+            self.dirty.push(bounds);
+        }
+    }
+
+    /// Redraw everything that changed since the last call
+    pub fn compose(&mut self) {
+        for index in 0..self.layers.len() {
+            let layer = &mut self.layers[index];
+            let dirty = layer.take_dirty();
+            if layer.visible && !dirty.is_empty() {
+                self.invalidate(dirty);
+            }
+        }
+        for area in core::mem::take(&mut self.dirty) {
+            self.redraw(area);
+        }
+        self.screen.flush();
+    }
+
+    /// Redraw the whole screen
+    pub fn compose_all(&mut self) {
+        self.invalidate(self.screen_rect());
+        self.compose();
+    }
+
+    fn redraw(&mut self, area: Rect) {
+        for &id in &self.order {
+            let Some(layer) = self.layers.iter().find(|l| l.id == id) else {
+                continue;
+            };
+            if !layer.visible {
+                continue;
+            }
+            let overlap = area.intersection(&layer.rect());
+            for y in overlap.y..overlap.bottom() {
+                let row = (y - layer.y) as usize * layer.width;
+                for x in overlap.x..overlap.right() {
+                    let color = layer.buffer[row + (x - layer.x) as usize];
+                    if Some(color) != layer.transparent {
+                        self.screen.write(x as usize, y as usize, color);
+                    }
+                }
+            }
+        }
+    }
+}
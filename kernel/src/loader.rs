@@ -0,0 +1,183 @@
+//! Loading ELF applications into user tasks
+//!
+//! The image is read, checked and copied into a fresh [`AddressSpace`]
+//! before the task is created, so every failure is reported to the caller.
+//! The new task only runs in the kernel long enough to drop to ring 3.
+//!
+//! At entry `rsp` points to `argc`, followed by the `argv` pointers, a null,
+//! the `envp` pointers, a null and an empty auxiliary vector, as in the
+//! System V ABI. `rdi` and `rsi` also hold `argc` and `argv`.
+
+use alloc::{boxed::Box, sync::Arc, vec, vec::Vec};
+
+use common::elf::{Elf, ElfError, PF_W, PF_X};
+
+use crate::{
+    paging::{ALLOCATE_START, AddressSpace, PagingError, Protection, USER_END, USER_START},
+    task::{self, TaskId},
+    user,
+    vfs::{self, NodeKind, VfsError, fd::FileTable, open_flag},
+};
+
+/// Larger files are refused rather than read into the kernel heap
+const MAX_IMAGE_SIZE: u64 = 16 * 1024 * 1024;
+const STACK_SIZE: u64 = 64 * 1024;
+/// Room for the argument and environment strings and their pointers
+const MAX_ARGUMENTS_SIZE: usize = 16 * 1024;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LoadError {
+    Vfs(VfsError),
+    Elf(ElfError),
+    /// A segment or the entry point lies outside the program area
+    BadLayout,
+    Paging(PagingError),
+    /// The arguments and environment do not fit on the stack
+    ArgumentsTooLarge,
+    /// The scheduler refused the task
+    Spawn,
+}
+
+impl From<VfsError> for LoadError {
+    fn from(error: VfsError) -> Self {
+        Self::Vfs(error)
+    }
+}
+
+impl From<ElfError> for LoadError {
+    fn from(error: ElfError) -> Self {
+        Self::Elf(error)
+    }
+}
+
+impl From<PagingError> for LoadError {
+    fn from(error: PagingError) -> Self {
+        Self::Paging(error)
+    }
+}
+
+/// Where the new task drops to ring 3
+struct Start {
+    entry: u64,
+    rsp: u64,
+    argc: u64,
+    argv: u64,
+}
+
+/// Run the executable at `path` as a new user task with `files` as its
+/// descriptor table
+pub fn spawn_with_files(
+    path: &str,
+    args: &[&str],
+    env: &[&str],
+    files: FileTable,
+) -> Result<TaskId, LoadError> {
+    let image = read_file(path)?;
+    let elf = Elf::parse(&image)?;
+    let space = Arc::new(AddressSpace::new()?);
+    load_segments(&space, &elf)?;
+
+    space.map(USER_END - STACK_SIZE, STACK_SIZE, Protection::READ_WRITE)?;
+    let (rsp, argv) = build_stack(&space, USER_END, args, env)?;
+    let start = Box::new(Start {
+        entry: elf.entry(),
+        rsp,
+        argc: args.len() as u64,
+        argv,
+    });
+    let arg = Box::into_raw(start) as u64;
+    task::spawn_user(enter, arg, task::PRIORITY_NORMAL, space, files).ok_or_else(|| {
+        drop(unsafe { Box::from_raw(arg as *mut Start) });
+        LoadError::Spawn
+    })
+}
+
+extern "sysv64" fn enter(arg: u64) {
+    // ring 3 からは戻らないので、Box はここで解放しておく
+    let start = *unsafe { Box::from_raw(arg as *mut Start) };
+    user::enter(start.entry, start.rsp, start.argc, start.argv)
+}
+
+fn read_file(path: &str) -> Result<Vec<u8>, LoadError> {
+    let file = vfs::open(path, open_flag::READ)?;
+    let metadata = file.metadata()?;
+    if metadata.kind != NodeKind::File {
+        return Err(VfsError::IsADirectory.into());
+    }
+    if metadata.size > MAX_IMAGE_SIZE {
+        return Err(VfsError::FileTooLarge.into());
+    }
+    let mut image = vec![0; metadata.size as usize];
+    let mut len = 0;
+    while len < image.len() {
+        match file.read(&mut image[len..])? {
+            0 => break,
+            read => len += read,
+        }
+    }
+    image.truncate(len);
+    Ok(image)
+}
+
+fn load_segments(space: &AddressSpace, elf: &Elf) -> Result<(), LoadError> {
+    let mut entry_mapped = false;
+    for phdr in elf.load_segments() {
+        if phdr.p_memsz == 0 {
+            continue;
+        }
+        let end = phdr.p_vaddr + phdr.p_memsz;
+        if phdr.p_vaddr < USER_START || end > ALLOCATE_START {
+            return Err(LoadError::BadLayout);
+        }
+        let protection = Protection {
+            writable: phdr.p_flags & PF_W != 0,
+            executable: phdr.p_flags & PF_X != 0,
+        };
+        space.map(phdr.p_vaddr, phdr.p_memsz, protection)?;
+        // 新しいページは 0 で埋まっているので、.bss はそのままでよい
+        space.write(phdr.p_vaddr, elf.segment_data(&phdr)?)?;
+        entry_mapped |= protection.executable && (phdr.p_vaddr..end).contains(&elf.entry());
+    }
+    if !entry_mapped {
+        return Err(LoadError::BadLayout);
+    }
+    Ok(())
+}
+
+/// Put the strings and the pointer arrays below `top`; returns the initial
+/// stack pointer and `argv`
+fn build_stack(
+    space: &AddressSpace,
+    top: u64,
+    args: &[&str],
+    env: &[&str],
+) -> Result<(u64, u64), LoadError> {
+    let strings: Vec<&str> = args.iter().chain(env).copied().collect();
+    let strings_size: usize = strings.iter().map(|s| s.len() + 1).sum();
+    // argc, 2 つの null, 空の補助ベクタ (AT_NULL の 2 ワード)
+    let words = strings.len() + 5;
+    if strings_size + words * size_of::<u64>() + 32 > MAX_ARGUMENTS_SIZE {
+        return Err(LoadError::ArgumentsTooLarge);
+    }
+
+    let strings_start = (top - strings_size as u64) & !0xf;
+    let mut block = Vec::with_capacity(strings_size);
+    let mut addresses = Vec::with_capacity(strings.len());
+    for s in &strings {
+        addresses.push(strings_start + block.len() as u64);
+        block.extend_from_slice(s.as_bytes());
+        block.push(0);
+    }
+    space.write(strings_start, &block)?;
+
+    let mut table = Vec::with_capacity(words);
+    table.push(args.len() as u64);
+    table.extend_from_slice(&addresses[..args.len()]);
+    table.push(0);
+    table.extend_from_slice(&addresses[args.len()..]);
+    table.extend_from_slice(&[0, 0, 0]);
+    let table: Vec<u8> = table.iter().flat_map(|word| word.to_le_bytes()).collect();
+    let rsp = (strings_start - table.len() as u64) & !0xf;
+    space.write(rsp, &table)?;
+    Ok((rsp, rsp + size_of::<u64>() as u64))
+}
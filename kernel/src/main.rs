@@ -1,7 +1,48 @@
 #![no_std]
 #![no_main]
 
-use core::{arch::asm, panic::PanicInfo};
+extern crate alloc;
+
+mod acpi;
+mod ahci;
+mod allocator;
+mod apic;
+mod block;
+mod clock;
+mod console;
+mod desktop;
+mod dma;
+mod font;
+mod graphics;
+mod input;
+mod interrupt;
+mod layer;
+mod loader;
+mod message;
+mod mouse;
+mod nvme;
+mod paging;
+mod pci;
+mod power;
+mod ps2;
+mod queue;
+mod segment;
+mod serial;
+mod syscall;
+mod task;
+mod terminal;
+mod timer;
+mod usb;
+mod user;
+mod vfs;
+mod virtio;
+mod window;
+mod x86;
+
+use common::boot_info::BootInfo;
+use core::panic::PanicInfo;
+use graphics::{FrameBufferWriter, ShadowBuffer};
+use message::{Message, WindowEvent};
 
 #[panic_handler]
 fn panic(_panic: &PanicInfo<'_>) -> ! {
@@ -11,19 +52,72 @@ fn panic(_panic: &PanicInfo<'_>) -> ! {
 
 /// # Safety
 ///
-/// - `frame_buffer_base` は `frame_buffer_size * 8` バイト分の有効なメモリ領域を指している必要があります。
+/// - `boot_info` のフレームバッファ情報 (ベースアドレス・解像度・ストライド) が正しい必要があります。
 /// - この関数は UEFI ブートローダから正しく初期化された状態で呼び出される前提です。
 #[unsafe(no_mangle)]
 #[allow(unreachable_code)]
-pub unsafe extern "C" fn kernel_main(frame_buffer_base: *mut u64, frame_buffer_size: u64) -> ! {
-    for i in 0..frame_buffer_size {
-        unsafe {
-            *frame_buffer_base.add(i as usize) = i % 256;
-        }
+pub unsafe extern "C" fn kernel_main(boot_info: &BootInfo) -> ! {
+    serial::init();
+    segment::init();
+    let acpi = unsafe { acpi::init(boot_info.acpi_rsdp) }.unwrap_or_default();
+    interrupt::init();
+    user::init();
+    syscall::init();
+    task::init();
+    let _ = pci::init(&acpi);
+    let _ = unsafe { block::ramdisk::init(boot_info) };
+
+    let _ = paging::set_write_combining(boot_info.frame_buffer_base, boot_info.frame_buffer_size);
+    if let Some(screen) = unsafe { FrameBufferWriter::new(boot_info) } {
+        desktop::init(ShadowBuffer::new(screen));
     }
+    desktop::with(|desktop| {
+        let window = desktop.new_window(160, 68, "Hello Window", task::main_id());
+        desktop.layers().move_to(window, 300, 100);
+        desktop.compose();
+    });
+    if apic::init(&acpi).is_ok() {
+        x86::enable_interrupts();
+    }
+    let _ = usb::xhci::init();
+    let _ = virtio::blk::init();
+    let _ = ahci::init();
+    let _ = nvme::init(nvme::Mode::Interrupt);
+    let _ = block::partition::init(boot_info);
+    vfs::init();
+    let _ = ps2::init(&acpi);
+    let _ = terminal::spawn();
+
+    // メッセージがなければ block し、アイドルタスクが hlt する
     loop {
-        unsafe {
-            asm!("hlt");
+        match task::wait_message() {
+            Message::XhciInterrupt => usb::xhci::poll(),
+            // メインタスク宛てのタイマーは xHCI のポートのデバウンスだけ
+            Message::TimerTick { value, .. } => usb::xhci::on_debounce_timer(value),
+            Message::Ps2Keyboard(byte) => ps2::keyboard::on_byte(byte),
+            Message::Ps2Mouse(byte) => ps2::mouse::on_byte(byte),
+            Message::Key(key) => {
+                // アクティブなウィンドウを持つタスクがいればそちらに渡す
+                let owner = desktop::with(|d| d.active_window().and_then(|w| d.window_owner(w)));
+                match owner.flatten() {
+                    Some(owner) if owner != task::main_id() => {
+                        let _ = task::send_message(owner, Message::Key(key));
+                    }
+                    _ if key.pressed && key.ascii != 0 => console::push_input(key.ascii),
+                    _ => {}
+                }
+            }
+            Message::Mouse(event) => {
+                desktop::with(|d| d.on_mouse(event));
+            }
+            Message::Window(WindowEvent::Close(layer)) => {
+                desktop::with(|d| d.close_window(layer));
+            }
+            _ => {}
+        }
+        // まとめて届いたメッセージを処理し終えてから描画する
+        if task::mailbox_is_empty() {
+            desktop::with(|d| d.compose());
         }
     }
 }
@@ -0,0 +1,44 @@
+//! Messages delivered to task mailboxes
+//!
+//! Interrupt handlers only read what the hardware hands them and post a
+//! message; the receiving task does the actual work.
+
+use crate::{
+    input::{KeyEvent, MouseEvent},
+    layer::LayerId,
+    task::TaskId,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WindowEvent {
+    /// The close button was clicked
+    Close(LayerId),
+    /// The window became the active (topmost) one
+    Activated(LayerId),
+    Deactivated(LayerId),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Message {
+    /// A timer expired; `value` is whatever the timer was registered with
+    TimerTick {
+        tick: u64,
+        value: u64,
+    },
+    Key(KeyEvent),
+    Mouse(MouseEvent),
+    Window(WindowEvent),
+    /// The xHC raised an interrupt; its event ring needs processing
+    XhciInterrupt,
+    /// Raw byte from the PS/2 keyboard port
+    Ps2Keyboard(u8),
+    /// Raw byte from the PS/2 mouse port
+    Ps2Mouse(u8),
+    /// Another task drew into a layer; the screen needs composing
+    Redraw,
+    /// A task spawned by the receiver ended with `code`
+    TaskExit {
+        task: TaskId,
+        code: i32,
+    },
+}
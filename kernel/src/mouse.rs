@@ -0,0 +1,68 @@
+//! Software mouse cursor
+
+use crate::graphics::{PixelColor, PixelWriter};
+
+pub const CURSOR_WIDTH: usize = 15;
+pub const CURSOR_HEIGHT: usize = 24;
+
+/// Colour key for the transparent part of a cursor layer
+pub const CURSOR_TRANSPARENT: PixelColor = PixelColor::new(0, 0, 1);
+
+/// `@` is the outline, `.` the fill, and space is transparent
+const CURSOR_SHAPE: [&[u8; CURSOR_WIDTH]; CURSOR_HEIGHT] = [
+    b"@              ",
+    b"@@             ",
+    b"@.@            ",
+    b"@..@           ",
+    b"@...@          ",
+    b"@....@         ",
+    b"@.....@        ",
+    b"@......@       ",
+    b"@.......@      ",
+    b"@........@     ",
+    b"@.........@    ",
+    b"@..........@   ",
+    b"@...........@  ",
+    b"@............@ ",
+    b"@......@@@@@@@@",
+    b"@......@       ",
+    b"@....@@.@      ",
+    b"@...@ @.@      ",
+    b"@..@   @.@     ",
+    b"@.@    @.@     ",
+    b"@@      @.@    ",
+    b"@       @.@    ",
+    b"         @.@   ",
+    b"         @@@   ",
+];
+
+/// Draw the cursor sprite with its top-left corner at `(x, y)`
+pub fn draw_cursor(writer: &mut impl PixelWriter, x: usize, y: usize) {
+    for (dy, row) in CURSOR_SHAPE.iter().enumerate() {
+        for (dx, &pixel) in row.iter().enumerate() {
+            match pixel {
+                b'@' => writer.write(x + dx, y + dy, PixelColor::BLACK),
+                b'.' => writer.write(x + dx, y + dy, PixelColor::WHITE),
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Apply a mouse delta to `position`, keeping it inside `(width, height)`
+pub fn move_clamped(
+    position: (usize, usize),
+    dx: i16,
+    dy: i16,
+    (width, height): (usize, usize),
+) -> (usize, usize) {
+    (
+        clamp_add(position.0, dx, width),
+        clamp_add(position.1, dy, height),
+    )
+}
+
+fn clamp_add(value: usize, delta: i16, limit: usize) -> usize {
+    let moved = value as isize + delta as isize;
+    moved.clamp(0, limit.saturating_sub(1) as isize) as usize
+}
@@ -0,0 +1,450 @@
+//! NVMe controller driver
+//!
+//! Each controller gets one admin queue pair and one I/O queue pair, and every
+//! active namespace becomes a block device (`nvme<n>`). Commands are issued
+//! one at a time; I/O completions are polled, or waited for through MSI-X
+//! entry 0 in [`Mode::Interrupt`]. A controller whose MSI-X cannot be set up
+//! falls back to [`Mode::Polling`].
+pub mod namespace;
+pub mod queue;
+
+use alloc::{sync::Arc, vec::Vec};
+use core::sync::atomic::{AtomicU8, Ordering};
+
+use spin::Mutex;
+
+use crate::{
+    apic,
+    block::{self, BlockError, completion::Completion},
+    clock::wait_until,
+    dma,
+    interrupt::{self, InterruptFrame},
+    pci,
+};
+use namespace::Namespace;
+use queue::{Command, CompletionEntry, PAGE_SIZE, QueuePair};
+
+/// Mass storage / NVM / NVM Express
+const CLASS_MASS_STORAGE: u8 = 0x01;
+const SUBCLASS_NVM: u8 = 0x08;
+const INTERFACE_NVME: u8 = 0x02;
+
+// コントローラレジスタ
+const REG_CAP: u64 = 0x00;
+const REG_VS: u64 = 0x08;
+const REG_CC: u64 = 0x14;
+const REG_CSTS: u64 = 0x1c;
+const REG_AQA: u64 = 0x24;
+const REG_ASQ: u64 = 0x28;
+const REG_ACQ: u64 = 0x30;
+const DOORBELLS: u64 = 0x1000;
+
+const CC_EN: u32 = 1 << 0;
+/// 64-byte submission and 16-byte completion entries
+const CC_QUEUE_ENTRY_SIZES: u32 = 6 << 16 | 4 << 20;
+const CSTS_RDY: u32 = 1 << 0;
+const CSTS_CFS: u32 = 1 << 1;
+
+const ADMIN_DELETE_CQ: u8 = 0x04;
+const ADMIN_CREATE_SQ: u8 = 0x01;
+const ADMIN_CREATE_CQ: u8 = 0x05;
+const ADMIN_IDENTIFY: u8 = 0x06;
+const ADMIN_SET_FEATURES: u8 = 0x09;
+const IO_FLUSH: u8 = 0x00;
+const IO_WRITE: u8 = 0x01;
+const IO_READ: u8 = 0x02;
+
+const CNS_NAMESPACE: u32 = 0x00;
+const CNS_CONTROLLER: u32 = 0x01;
+const CNS_ACTIVE_NAMESPACES: u32 = 0x02;
+const FEATURE_NUMBER_OF_QUEUES: u32 = 0x07;
+const QUEUE_PHYSICALLY_CONTIGUOUS: u32 = 1 << 0;
+const QUEUE_INTERRUPTS_ENABLED: u32 = 1 << 1;
+
+/// Active namespace list requires NVMe 1.1
+const VERSION_1_1: u32 = 0x0001_0100;
+
+const ADMIN_QUEUE_SIZE: u16 = 16;
+const IO_QUEUE_SIZE: u16 = 64;
+/// Largest data buffer in a single command
+const MAX_TRANSFER: usize = 64 * 1024;
+const ADMIN_TIMEOUT_MS: u64 = 5000;
+const IO_TIMEOUT_MS: u64 = 30_000;
+
+const MAX_CONTROLLERS: usize = 8;
+
+/// MSI-X vector of each controller slot; `0` when unused
+static VECTORS: [AtomicU8; MAX_CONTROLLERS] = [const { AtomicU8::new(0) }; MAX_CONTROLLERS];
+static COMPLETIONS: [Completion; MAX_CONTROLLERS] = [const { Completion::new() }; MAX_CONTROLLERS];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NvmeError {
+    NoBar,
+    UnsupportedPageSize,
+    Timeout,
+    ControllerFatal,
+    /// Status code type and status code of a failed command
+    CommandFailed(u16),
+    OutOfMemory,
+}
+
+/// How I/O completions are waited for
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Mode {
+    Polling,
+    /// MSI-X when the controller has it, polling otherwise
+    Interrupt,
+}
+
+/// Format of an active namespace
+#[derive(Clone, Copy, Debug)]
+pub struct NamespaceInfo {
+    pub id: u32,
+    pub block_size: usize,
+    pub block_count: u64,
+}
+
+pub struct Controller {
+    version: u32,
+    admin: QueuePair,
+    io: QueuePair,
+    /// PRP list for transfers spanning more than two pages
+    prp_list: *mut u64,
+    /// Identify data, and caller buffers that are not dword aligned
+    bounce: *mut u8,
+    max_transfer: usize,
+    namespace_count: u32,
+    /// Index in [`COMPLETIONS`] and [`VECTORS`]
+    slot: usize,
+    /// [`Mode::Polling`] also when MSI-X could not be set up
+    mode: Mode,
+}
+
+// DMA 用の領域はこのコントローラだけが使う
+unsafe impl Send for Controller {}
+
+impl Controller {
+    pub fn new(device: &pci::Device, slot: usize, mode: Mode) -> Result<Self, NvmeError> {
+        let base = device
+            .bar(0)
+            .ok()
+            .and_then(|bar| bar.memory_base())
+            .ok_or(NvmeError::NoBar)?;
+        device.enable_bus_master();
+
+        let read32 = |offset| unsafe { ((base + offset) as *const u32).read_volatile() };
+        let write32 =
+            |offset, value| unsafe { ((base + offset) as *mut u32).write_volatile(value) };
+        let write64 = |offset, value: u64| {
+            write32(offset, value as u32);
+            write32(offset + 4, (value >> 32) as u32);
+        };
+
+        let cap = read32(REG_CAP) as u64 | (read32(REG_CAP + 4) as u64) << 32;
+        // MPSMIN: 4 KiB ページを使えないコントローラは扱わない
+        if (cap >> 48) & 0xf != 0 {
+            return Err(NvmeError::UnsupportedPageSize);
+        }
+        let max_entries = (cap & 0xffff) as u16 + 1;
+        let stride = 4 << ((cap >> 32) & 0xf);
+        let ready_timeout = ((cap >> 24) & 0xff).max(1) * 500;
+
+        if read32(REG_CC) & CC_EN != 0 {
+            write32(REG_CC, read32(REG_CC) & !CC_EN);
+        }
+        if !wait_until(ready_timeout, || read32(REG_CSTS) & CSTS_RDY == 0) {
+            return Err(NvmeError::Timeout);
+        }
+
+        let doorbells = base + DOORBELLS;
+        let admin_size = ADMIN_QUEUE_SIZE.min(max_entries);
+        let admin =
+            QueuePair::new(0, admin_size, doorbells, stride).ok_or(NvmeError::OutOfMemory)?;
+        write32(
+            REG_AQA,
+            (admin_size as u32 - 1) << 16 | (admin_size as u32 - 1),
+        );
+        write64(REG_ASQ, admin.submission_address());
+        write64(REG_ACQ, admin.completion_address());
+        write32(REG_CC, CC_QUEUE_ENTRY_SIZES | CC_EN);
+        if !wait_until(ready_timeout, || {
+            read32(REG_CSTS) & (CSTS_RDY | CSTS_CFS) != 0
+        }) {
+            return Err(NvmeError::Timeout);
+        }
+        if read32(REG_CSTS) & CSTS_CFS != 0 {
+            return Err(NvmeError::ControllerFatal);
+        }
+
+        let io = QueuePair::new(1, IO_QUEUE_SIZE.min(max_entries), doorbells, stride)
+            .ok_or(NvmeError::OutOfMemory)?;
+        let prp_list = dma::allocate_array::<u64>(PAGE_SIZE / 8, PAGE_SIZE, 0)
+            .ok_or(NvmeError::OutOfMemory)?;
+        let bounce = dma::allocate(PAGE_SIZE, PAGE_SIZE, 0).ok_or(NvmeError::OutOfMemory)?;
+        let mut controller = Self {
+            version: read32(REG_VS),
+            admin,
+            io,
+            prp_list,
+            bounce,
+            max_transfer: MAX_TRANSFER,
+            namespace_count: 0,
+            slot,
+            mode: match mode {
+                Mode::Interrupt if setup_interrupt(device, slot) => Mode::Interrupt,
+                _ => Mode::Polling,
+            },
+        };
+        controller.identify_controller()?;
+        controller.create_io_queues()?;
+        Ok(controller)
+    }
+
+    fn identify_controller(&mut self) -> Result<(), NvmeError> {
+        self.identify(CNS_CONTROLLER, 0)?;
+        let data = unsafe { core::slice::from_raw_parts(self.bounce, PAGE_SIZE) };
+        // MDTS: 最小ページサイズの 2 の累乗倍、0 は無制限
+        let mdts = data[77] as u32;
+        if mdts != 0 && mdts < 16 {
+            self.max_transfer = self.max_transfer.min(PAGE_SIZE << mdts);
+        }
+        self.namespace_count = u32::from_le_bytes(data[516..520].try_into().unwrap());
+        Ok(())
+    }
+
+    fn create_io_queues(&mut self) -> Result<(), NvmeError> {
+        // I/O キューは 1 組だけ使う (値は 0 始まり)
+        let mut command = Command::new(ADMIN_SET_FEATURES);
+        command.cdw10 = FEATURE_NUMBER_OF_QUEUES;
+        command.cdw11 = 0;
+        self.execute_admin(command)?;
+
+        let id = self.io.id() as u32;
+        let size = self.io.size() as u32;
+        let mut command = Command::new(ADMIN_CREATE_CQ);
+        command.prp1 = self.io.completion_address();
+        command.cdw10 = (size - 1) << 16 | id;
+        // MSI-X エントリ 0 を使う
+        command.cdw11 = QUEUE_PHYSICALLY_CONTIGUOUS
+            | if self.mode == Mode::Interrupt {
+                QUEUE_INTERRUPTS_ENABLED
+            } else {
+                0
+            };
+        self.execute_admin(command)?;
+
+        let mut command = Command::new(ADMIN_CREATE_SQ);
+        command.prp1 = self.io.submission_address();
+        command.cdw10 = (size - 1) << 16 | id;
+        command.cdw11 = id << 16 | QUEUE_PHYSICALLY_CONTIGUOUS;
+        if let Err(error) = self.execute_admin(command) {
+            // 作成済みの完了キューを片付ける
+            let mut command = Command::new(ADMIN_DELETE_CQ);
+            command.cdw10 = id;
+            let _ = self.execute_admin(command);
+            return Err(error);
+        }
+        Ok(())
+    }
+
+    /// Run Identify into the bounce buffer
+    fn identify(&mut self, cns: u32, namespace: u32) -> Result<(), NvmeError> {
+        let mut command = Command::new(ADMIN_IDENTIFY);
+        command.namespace = namespace;
+        command.prp1 = self.bounce as u64;
+        command.cdw10 = cns;
+        self.execute_admin(command).map(|_| ())
+    }
+
+    /// IDs of the active namespaces
+    fn active_namespaces(&mut self) -> Vec<u32> {
+        if self.version >= VERSION_1_1 && self.identify(CNS_ACTIVE_NAMESPACES, 0).is_ok() {
+            let list = unsafe { core::slice::from_raw_parts(self.bounce as *const u32, 1024) };
+            return list.iter().copied().take_while(|&id| id != 0).collect();
+        }
+        (1..=self.namespace_count).collect()
+    }
+
+    /// Formats of the active namespaces, skipping those with an LBA size this
+    /// driver cannot transfer
+    pub fn namespaces(&mut self) -> Vec<NamespaceInfo> {
+        let mut namespaces = Vec::new();
+        for id in self.active_namespaces() {
+            if self.identify(CNS_NAMESPACE, id).is_err() {
+                continue;
+            }
+            let data = unsafe { core::slice::from_raw_parts(self.bounce, PAGE_SIZE) };
+            let block_count = u64::from_le_bytes(data[0..8].try_into().unwrap());
+            // FLBAS が指す LBA フォーマットの LBADS
+            let format = (data[26] & 0xf) as usize;
+            let lba_shift = data[128 + format * 4 + 2] as u32;
+            if block_count == 0 || !(9..=12).contains(&lba_shift) {
+                continue;
+            }
+            namespaces.push(NamespaceInfo {
+                id,
+                block_size: 1 << lba_shift,
+                block_count,
+            });
+        }
+        namespaces
+    }
+
+    fn execute_admin(&mut self, command: Command) -> Result<CompletionEntry, NvmeError> {
+        let id = self.admin.submit(command);
+        let admin = &self.admin;
+        if !wait_until(ADMIN_TIMEOUT_MS, || admin.has_completion()) {
+            return Err(NvmeError::Timeout);
+        }
+        complete(&mut self.admin, id)
+    }
+
+    fn execute_io(&mut self, command: Command) -> Result<CompletionEntry, NvmeError> {
+        let id = self.io.submit(command);
+        let io = &self.io;
+        match self.mode {
+            Mode::Interrupt => COMPLETIONS[self.slot].wait_until(true, || io.has_completion()),
+            Mode::Polling => {
+                if !wait_until(IO_TIMEOUT_MS, || io.has_completion()) {
+                    return Err(NvmeError::Timeout);
+                }
+            }
+        }
+        complete(&mut self.io, id)
+    }
+
+    /// Point the command at `len` bytes from `address`
+    fn set_prps(&mut self, command: &mut Command, address: u64, len: usize) {
+        let page = PAGE_SIZE as u64;
+        let second = (address & !(page - 1)) + page;
+        let end = address + len as u64;
+        command.prp1 = address;
+        command.prp2 = if end <= second {
+            0
+        } else if end <= second + page {
+            second
+        } else {
+            for (i, page) in (second..end).step_by(PAGE_SIZE).enumerate() {
+                unsafe { self.prp_list.add(i).write_volatile(page) };
+            }
+            self.prp_list as u64
+        };
+    }
+
+    /// Read or write whole blocks of `namespace`, through the bounce buffer if
+    /// `address` is not dword aligned
+    pub fn transfer(
+        &mut self,
+        namespace: &NamespaceInfo,
+        lba: u64,
+        address: u64,
+        len: usize,
+        write: bool,
+    ) -> Result<(), BlockError> {
+        let block_size = namespace.block_size;
+        let direct = address & 3 == 0;
+        let limit = if direct { self.max_transfer } else { PAGE_SIZE };
+        let chunk_size = limit / block_size * block_size;
+
+        let mut done = 0;
+        while done < len {
+            let size = chunk_size.min(len - done);
+            let source = address + done as u64;
+            let target = if direct { source } else { self.bounce as u64 };
+            if !direct && write {
+                unsafe { core::ptr::copy_nonoverlapping(source as *const u8, self.bounce, size) };
+            }
+
+            let start = lba + (done / block_size) as u64;
+            let mut command = Command::new(if write { IO_WRITE } else { IO_READ });
+            command.namespace = namespace.id;
+            command.cdw10 = start as u32;
+            command.cdw11 = (start >> 32) as u32;
+            command.cdw12 = (size / block_size - 1) as u32;
+            self.set_prps(&mut command, target, size);
+            self.execute_io(command).map_err(|_| BlockError::Io)?;
+
+            if !direct && !write {
+                unsafe { core::ptr::copy_nonoverlapping(self.bounce, source as *mut u8, size) };
+            }
+            done += size;
+        }
+        Ok(())
+    }
+
+    pub fn flush(&mut self, namespace: u32) -> Result<(), BlockError> {
+        let mut command = Command::new(IO_FLUSH);
+        command.namespace = namespace;
+        self.execute_io(command)
+            .map(|_| ())
+            .map_err(|_| BlockError::Io)
+    }
+}
+
+/// Pop completions up to the one for command `id` and check its status
+fn complete(queue: &mut QueuePair, id: u16) -> Result<CompletionEntry, NvmeError> {
+    // 同時に出すコマンドは 1 つだけなので、通常は先頭がこのコマンド
+    while let Some(entry) = queue.pop() {
+        if entry.command_id != id {
+            continue;
+        }
+        return match entry.status_code() {
+            0 => Ok(entry),
+            status => Err(NvmeError::CommandFailed(status)),
+        };
+    }
+    Err(NvmeError::Timeout)
+}
+
+/// Route MSI-X entry 0 to a fresh vector; `false` if the controller has to
+/// be polled instead
+fn setup_interrupt(device: &pci::Device, slot: usize) -> bool {
+    if slot >= MAX_CONTROLLERS {
+        return false;
+    }
+    let Ok(table) = device.msix_table() else {
+        return false;
+    };
+    let Some(vector) = interrupt::allocate_vector() else {
+        return false;
+    };
+    VECTORS[slot].store(vector, Ordering::Release);
+    interrupt::register_handler(vector, on_interrupt);
+    table.set_vector(0, apic::local::id(), vector);
+    if device.enable_msix().is_err() {
+        VECTORS[slot].store(0, Ordering::Release);
+        interrupt::free_vector(vector);
+        return false;
+    }
+    true
+}
+
+fn on_interrupt(frame: &mut InterruptFrame) {
+    for (vector, completion) in VECTORS.iter().zip(&COMPLETIONS) {
+        if vector.load(Ordering::Acquire) == frame.vector as u8 {
+            completion.notify();
+        }
+    }
+}
+
+fn find_controllers() -> impl Iterator<Item = &'static pci::Device> {
+    pci::find_by_class_interface(CLASS_MASS_STORAGE, SUBCLASS_NVM, INTERFACE_NVME)
+}
+
+/// Initialize every NVMe controller and register each active namespace as
+/// `nvme<n>`; returns the number of namespaces
+pub fn init(mode: Mode) -> usize {
+    let mut count = 0;
+    for (slot, device) in find_controllers().enumerate() {
+        let Ok(mut controller) = Controller::new(device, slot, mode) else {
+            continue;
+        };
+        let namespaces = controller.namespaces();
+        let controller = Arc::new(Mutex::new(controller));
+        for info in namespaces {
+            block::register("nvme", Namespace::new(controller.clone(), info));
+            count += 1;
+        }
+    }
+    count
+}
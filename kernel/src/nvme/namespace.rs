@@ -0,0 +1,50 @@
+//! NVMe namespace as a block device
+
+use alloc::sync::Arc;
+
+use spin::Mutex;
+
+use crate::block::{BlockDevice, BlockError};
+
+use super::{Controller, NamespaceInfo};
+
+/// One namespace; namespaces of a controller share its I/O queue
+pub struct Namespace {
+    controller: Arc<Mutex<Controller>>,
+    info: NamespaceInfo,
+}
+
+impl Namespace {
+    pub fn new(controller: Arc<Mutex<Controller>>, info: NamespaceInfo) -> Self {
+        Self { controller, info }
+    }
+}
+
+impl BlockDevice for Namespace {
+    fn block_size(&self) -> usize {
+        self.info.block_size
+    }
+
+    fn block_count(&self) -> u64 {
+        self.info.block_count
+    }
+
+    fn read_blocks(&mut self, lba: u64, buf: &mut [u8]) -> Result<(), BlockError> {
+        self.check_range(lba, buf.len())?;
+        // カーネルは恒等マップで動いているので、バッファのアドレスをそのまま渡せる
+        self.controller
+            .lock()
+            .transfer(&self.info, lba, buf.as_mut_ptr() as u64, buf.len(), false)
+    }
+
+    fn write_blocks(&mut self, lba: u64, buf: &[u8]) -> Result<(), BlockError> {
+        self.check_range(lba, buf.len())?;
+        self.controller
+            .lock()
+            .transfer(&self.info, lba, buf.as_ptr() as u64, buf.len(), true)
+    }
+
+    fn flush(&mut self) -> Result<(), BlockError> {
+        self.controller.lock().flush(self.info.id)
+    }
+}
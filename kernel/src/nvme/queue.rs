@@ -0,0 +1,144 @@
+//! Submission/completion queue pairs
+
+use crate::dma;
+
+/// Size of one queue page; the controller is run with a 4 KiB memory page
+pub const PAGE_SIZE: usize = 4096;
+
+/// 64-byte submission queue entry
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Command {
+    pub opcode: u8,
+    pub flags: u8,
+    pub id: u16,
+    pub namespace: u32,
+    pub reserved: [u32; 2],
+    pub metadata: u64,
+    pub prp1: u64,
+    pub prp2: u64,
+    pub cdw10: u32,
+    pub cdw11: u32,
+    pub cdw12: u32,
+    pub cdw13: u32,
+    pub cdw14: u32,
+    pub cdw15: u32,
+}
+
+impl Command {
+    pub fn new(opcode: u8) -> Self {
+        Self {
+            opcode,
+            ..Default::default()
+        }
+    }
+}
+
+/// 16-byte completion queue entry
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CompletionEntry {
+    pub result: u32,
+    pub reserved: u32,
+    pub sq_head: u16,
+    pub sq_id: u16,
+    pub command_id: u16,
+    /// Phase tag in bit 0, status field above it
+    pub status: u16,
+}
+
+impl CompletionEntry {
+    fn phase(&self) -> bool {
+        self.status & 1 != 0
+    }
+
+    /// Status code type and status code; `0` on success
+    pub fn status_code(&self) -> u16 {
+        (self.status >> 1) & 0x7ff
+    }
+}
+
+pub struct QueuePair {
+    id: u16,
+    size: u16,
+    submission: *mut Command,
+    completion: *mut CompletionEntry,
+    tail: u16,
+    head: u16,
+    /// Phase tag expected in the next new completion entry
+    phase: bool,
+    sq_doorbell: *mut u32,
+    cq_doorbell: *mut u32,
+}
+
+impl QueuePair {
+    /// Allocate queue `id` with `size` entries; `doorbells` and `stride` come
+    /// from the controller registers
+    pub fn new(id: u16, size: u16, doorbells: u64, stride: u64) -> Option<Self> {
+        let submission = dma::allocate_array::<Command>(size as usize, PAGE_SIZE, 0)?;
+        let completion = dma::allocate_array::<CompletionEntry>(size as usize, PAGE_SIZE, 0)?;
+        Some(Self {
+            id,
+            size,
+            submission,
+            completion,
+            tail: 0,
+            head: 0,
+            phase: true,
+            sq_doorbell: (doorbells + (2 * id as u64) * stride) as *mut u32,
+            cq_doorbell: (doorbells + (2 * id as u64 + 1) * stride) as *mut u32,
+        })
+    }
+
+    pub fn id(&self) -> u16 {
+        self.id
+    }
+
+    pub fn size(&self) -> u16 {
+        self.size
+    }
+
+    pub fn submission_address(&self) -> u64 {
+        self.submission as u64
+    }
+
+    pub fn completion_address(&self) -> u64 {
+        self.completion as u64
+    }
+
+    /// Put `command` at the tail and ring the doorbell; returns its command ID
+    pub fn submit(&mut self, mut command: Command) -> u16 {
+        command.id = self.tail;
+        unsafe {
+            self.submission
+                .add(self.tail as usize)
+                .write_volatile(command)
+        };
+        self.tail = (self.tail + 1) % self.size;
+        core::sync::atomic::fence(core::sync::atomic::Ordering::SeqCst);
+        unsafe { self.sq_doorbell.write_volatile(self.tail as u32) };
+        command.id
+    }
+
+    /// Whether a new completion entry has been posted
+    pub fn has_completion(&self) -> bool {
+        let entry = unsafe { self.completion.add(self.head as usize).read_volatile() };
+        entry.phase() == self.phase
+    }
+
+    /// Take the next completion entry and release its slot to the controller
+    pub fn pop(&mut self) -> Option<CompletionEntry> {
+        if !self.has_completion() {
+            return None;
+        }
+        let entry = unsafe { self.completion.add(self.head as usize).read_volatile() };
+        self.head += 1;
+        if self.head == self.size {
+            // 一周するごとに期待する Phase Tag が反転する
+            self.head = 0;
+            self.phase = !self.phase;
+        }
+        unsafe { self.cq_doorbell.write_volatile(self.head as u32) };
+        Some(entry)
+    }
+}
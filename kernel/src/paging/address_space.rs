@@ -0,0 +1,237 @@
+//! Per-task user page tables
+//!
+//! The lower half stays the kernel's: its top-level entries are copied from
+//! the kernel's table, minus the user bit, and share the tables below. The
+//! upper half belongs to the task and is backed by 4 KiB pages from the
+//! kernel heap, which are freed together with the address space.
+
+use alloc::alloc::dealloc;
+
+use spin::Mutex;
+
+use super::{
+    ADDRESS_MASK, NO_EXECUTE, PAGE_SIZE_4K, PRESENT, PageTable, PagingError, USER, WRITABLE,
+    allocate_table, table_at, table_layout,
+};
+use crate::x86;
+
+pub const USER_START: u64 = 0xffff_8000_0000_0000;
+/// The last top-level entry is left out so that user ranges never wrap
+pub const USER_END: u64 = 0xffff_ff80_0000_0000;
+/// Programs are loaded below this; [`AddressSpace::allocate`] hands out
+/// memory from here up to [`STACK_START`]
+pub const ALLOCATE_START: u64 = 0xffff_c000_0000_0000;
+/// The last 4 GiB are kept for stacks
+pub const STACK_START: u64 = USER_END - 0x1_0000_0000;
+const FIRST_USER_ENTRY: usize = 256;
+
+const IA32_EFER: u32 = 0xc000_0080;
+const EFER_NXE: u64 = 1 << 11;
+
+/// Access allowed to user pages; they are always readable
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Protection {
+    pub writable: bool,
+    pub executable: bool,
+}
+
+impl Protection {
+    pub const READ_WRITE: Self = Self {
+        writable: true,
+        executable: false,
+    };
+}
+
+struct Tables {
+    pml4: *mut PageTable,
+    /// Next address for [`AddressSpace::allocate`]
+    allocate_next: u64,
+}
+
+// テーブルへのアクセスは AddressSpace のロックを通す
+unsafe impl Send for Tables {}
+
+pub struct AddressSpace {
+    /// Physical address of the top-level table, for CR3
+    cr3: u64,
+    tables: Mutex<Tables>,
+}
+
+fn is_user_range(start: u64, len: u64) -> bool {
+    start >= USER_START && start.checked_add(len).is_some_and(|end| end <= USER_END)
+}
+
+fn nx_enabled() -> bool {
+    x86::read_msr(IA32_EFER) & EFER_NXE != 0
+}
+
+/// The leaf entry for `addr`, creating the tables above it
+///
+/// # Safety
+///
+/// `pml4` はこのアドレス空間のトップレベルテーブルで、ロックを取った状態で呼ぶ必要があります。
+unsafe fn leaf_entry(pml4: *mut PageTable, addr: u64) -> Result<*mut u64, PagingError> {
+    let mut table = pml4;
+    for level in (2..=4).rev() {
+        let index = ((addr >> (12 + 9 * (level - 1))) & 0x1ff) as usize;
+        let entry = unsafe { &raw mut (*table)[index] };
+        if unsafe { *entry } & PRESENT == 0 {
+            // 権限は末端のエントリで絞る
+            let child = allocate_table()?;
+            unsafe { *entry = child as u64 | PRESENT | WRITABLE | USER };
+        }
+        table = table_at(unsafe { *entry });
+    }
+    Ok(unsafe { &raw mut (*table)[((addr >> 12) & 0x1ff) as usize] })
+}
+
+/// The leaf entry mapping `addr`, if there is one
+///
+/// # Safety
+///
+/// `pml4` はこのアドレス空間のトップレベルテーブルで、ロックを取った状態で呼ぶ必要があります。
+unsafe fn find_leaf(pml4: *mut PageTable, addr: u64) -> Option<u64> {
+    let mut table = pml4;
+    for level in (1..=4).rev() {
+        let index = ((addr >> (12 + 9 * (level - 1))) & 0x1ff) as usize;
+        let entry = unsafe { (*table)[index] };
+        if entry & PRESENT == 0 {
+            return None;
+        }
+        if level == 1 {
+            return Some(entry);
+        }
+        table = table_at(entry);
+    }
+    None
+}
+
+/// Free `table` and everything below it; `level` 1 holds the pages
+///
+/// # Safety
+///
+/// `table` とその下のテーブル・ページは、このアドレス空間だけが所有している必要があります。
+unsafe fn free_table(table: *mut PageTable, level: u32) {
+    for &entry in unsafe { (*table).iter() } {
+        if entry & PRESENT == 0 {
+            continue;
+        }
+        if level > 1 {
+            unsafe { free_table(table_at(entry), level - 1) };
+        } else {
+            unsafe { dealloc((entry & ADDRESS_MASK) as *mut u8, table_layout()) };
+        }
+    }
+    unsafe { dealloc(table as *mut u8, table_layout()) };
+}
+
+impl AddressSpace {
+    /// An address space with the kernel mapped and no user pages
+    pub fn new() -> Result<Self, PagingError> {
+        let pml4 = allocate_table()?;
+        let kernel = table_at(x86::read_cr3());
+        for i in 0..FIRST_USER_ENTRY {
+            unsafe { (*pml4)[i] = (*kernel)[i] & !USER };
+        }
+        Ok(Self {
+            cr3: pml4 as u64,
+            tables: Mutex::new(Tables {
+                pml4,
+                allocate_next: ALLOCATE_START,
+            }),
+        })
+    }
+
+    pub fn cr3(&self) -> u64 {
+        self.cr3
+    }
+
+    /// Back `start..start + len` with zeroed pages
+    ///
+    /// Pages that are already mapped keep their contents and gain the
+    /// access in `protection`.
+    pub fn map(&self, start: u64, len: u64, protection: Protection) -> Result<(), PagingError> {
+        if !is_user_range(start, len) {
+            return Err(PagingError::InvalidRange);
+        }
+        let mut flags = PRESENT | USER;
+        if protection.writable {
+            flags |= WRITABLE;
+        }
+        let no_execute = !protection.executable && nx_enabled();
+
+        let tables = self.tables.lock();
+        let first = start & !(PAGE_SIZE_4K - 1);
+        let end = start + len;
+        for page in (first..end).step_by(PAGE_SIZE_4K as usize) {
+            let entry = unsafe { leaf_entry(tables.pml4, page)? };
+            let old = unsafe { *entry };
+            let value = if old & PRESENT == 0 {
+                let frame = allocate_table()? as u64;
+                frame | flags | if no_execute { NO_EXECUTE } else { 0 }
+            } else {
+                // 同じページに載る別のセグメントの権限を足し合わせる
+                let nx = old & NO_EXECUTE != 0 && no_execute;
+                (old & !NO_EXECUTE) | flags | if nx { NO_EXECUTE } else { 0 }
+            };
+            unsafe { *entry = value };
+            if old & PRESENT != 0 {
+                x86::invlpg(page);
+            }
+        }
+        Ok(())
+    }
+
+    /// Copy `data` to `addr` through the kernel's identity mapping, so that
+    /// pages the user cannot write can be filled too
+    pub fn write(&self, addr: u64, data: &[u8]) -> Result<(), PagingError> {
+        if !is_user_range(addr, data.len() as u64) {
+            return Err(PagingError::InvalidRange);
+        }
+        let tables = self.tables.lock();
+        let mut done = 0;
+        while done < data.len() {
+            let target = addr + done as u64;
+            let entry =
+                unsafe { find_leaf(tables.pml4, target) }.ok_or(PagingError::NotMapped(target))?;
+            let offset = target & (PAGE_SIZE_4K - 1);
+            let len = (data.len() - done).min((PAGE_SIZE_4K - offset) as usize);
+            let frame = (entry & ADDRESS_MASK) + offset;
+            unsafe { core::ptr::copy_nonoverlapping(data[done..].as_ptr(), frame as *mut u8, len) };
+            done += len;
+        }
+        Ok(())
+    }
+
+    /// Map `len` bytes of fresh read-write memory at an unused address
+    pub fn allocate(&self, len: u64) -> Result<u64, PagingError> {
+        let len = len
+            .checked_next_multiple_of(PAGE_SIZE_4K)
+            .filter(|&len| len > 0)
+            .ok_or(PagingError::InvalidRange)?;
+        let start = {
+            let mut tables = self.tables.lock();
+            let start = tables.allocate_next;
+            if start.checked_add(len).is_none_or(|end| end > STACK_START) {
+                return Err(PagingError::OutOfMemory);
+            }
+            tables.allocate_next += len;
+            start
+        };
+        self.map(start, len, Protection::READ_WRITE)?;
+        Ok(start)
+    }
+}
+
+impl Drop for AddressSpace {
+    fn drop(&mut self) {
+        let pml4 = self.tables.get_mut().pml4;
+        for i in FIRST_USER_ENTRY..512 {
+            let entry = unsafe { (*pml4)[i] };
+            if entry & PRESENT != 0 {
+                unsafe { free_table(table_at(entry), 3) };
+            }
+        }
+        unsafe { dealloc(pml4 as *mut u8, table_layout()) };
+    }
+}
@@ -0,0 +1,179 @@
+//! Tweaks to the page tables inherited from UEFI, and per-task user tables
+//!
+//! The kernel keeps running on the firmware's identity mapping, so table
+//! entries can be dereferenced with their physical addresses.
+mod address_space;
+
+use alloc::alloc::{Layout, alloc_zeroed};
+
+use crate::x86;
+pub use address_space::{ALLOCATE_START, AddressSpace, Protection, USER_END, USER_START};
+
+const IA32_PAT: u32 = 0x277;
+/// PAT layout with entry 1 (PWT=1, PCD=0, PAT=0) switched from WT to WC
+const PAT_VALUE: u64 = 0x0007_0406_0007_0106;
+
+const CPUID_PAT: u32 = 1 << 16;
+const CR0_WRITE_PROTECT: u64 = 1 << 16;
+const CR0_PAGING: u64 = 1 << 31;
+const CR4_PGE: u64 = 1 << 7;
+const CR4_LA57: u64 = 1 << 12;
+
+const PRESENT: u64 = 1 << 0;
+const WRITABLE: u64 = 1 << 1;
+const USER: u64 = 1 << 2;
+const PWT: u64 = 1 << 3;
+const PCD: u64 = 1 << 4;
+const HUGE_PAGE: u64 = 1 << 7;
+/// PAT bit of a 4 KiB entry; in 2 MiB / 1 GiB entries it moves to bit 12
+const PAT_4K: u64 = 1 << 7;
+const PAT_LARGE: u64 = 1 << 12;
+const NO_EXECUTE: u64 = 1 << 63;
+const ADDRESS_MASK: u64 = 0x000f_ffff_ffff_f000;
+
+const PAGE_SIZE_4K: u64 = 0x1000;
+const PAGE_SIZE_1G: u64 = 0x4000_0000;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PagingError {
+    /// Paging is off or the CPU has no PAT
+    Unsupported,
+    /// 5-level paging is not handled
+    La57,
+    NotMapped(u64),
+    /// The range is not inside user space
+    InvalidRange,
+    OutOfMemory,
+}
+
+type PageTable = [u64; 512];
+
+fn table_at(entry: u64) -> *mut PageTable {
+    (entry & ADDRESS_MASK) as *mut PageTable
+}
+
+fn table_layout() -> Layout {
+    Layout::from_size_align(size_of::<PageTable>(), PAGE_SIZE_4K as usize).unwrap()
+}
+
+/// A zeroed page from the kernel heap, for a table or a user page
+fn allocate_table() -> Result<*mut PageTable, PagingError> {
+    let table = unsafe { alloc_zeroed(table_layout()) } as *mut PageTable;
+    if table.is_null() {
+        return Err(PagingError::OutOfMemory);
+    }
+    Ok(table)
+}
+
+fn is_supported() -> bool {
+    x86::read_cr0() & CR0_PAGING != 0 && x86::cpuid(1, 0).3 & CPUID_PAT != 0
+}
+
+/// Split a 1 GiB or 2 MiB page into 512 pages of the next size down
+///
+/// # Safety
+///
+/// `entry` は大きなページを指す有効なエントリである必要があります。
+unsafe fn split(entry: *mut u64, page_size: u64) -> Result<(), PagingError> {
+    let old = unsafe { entry.read_volatile() };
+    let table = allocate_table()?;
+
+    let base = old & ADDRESS_MASK & !(page_size - 1);
+    let child_size = page_size / 512;
+    let mut flags = old & !ADDRESS_MASK & !PAT_LARGE;
+    if child_size == PAGE_SIZE_4K {
+        flags &= !HUGE_PAGE;
+        if old & PAT_LARGE != 0 {
+            flags |= PAT_4K;
+        }
+    } else {
+        flags |= old & PAT_LARGE;
+    }
+    for (i, child) in unsafe { (*table).iter_mut() }.enumerate() {
+        *child = (base + i as u64 * child_size) | flags;
+    }
+
+    let parent_flags = PRESENT | WRITABLE | (old & (USER | NO_EXECUTE));
+    unsafe { entry.write_volatile(table as u64 | parent_flags) };
+    Ok(())
+}
+
+/// Set the memory type of the leaf entry mapping `addr` to WC, splitting
+/// large pages that stick out of `range`; returns the next address to visit
+///
+/// # Safety
+///
+/// CR0.WP を落とした状態で、割り込み禁止のまま呼ぶ必要があります。
+unsafe fn set_wc_page(addr: u64, range: core::ops::Range<u64>) -> Result<u64, PagingError> {
+    let mut table = table_at(x86::read_cr3());
+    let mut page_size = PAGE_SIZE_1G * 512 * 512;
+    for level in (1..=4).rev() {
+        page_size /= 512;
+        let index = ((addr >> (12 + 9 * (level - 1))) & 0x1ff) as usize;
+        let entry = unsafe { &raw mut (*table)[index] };
+        let value = unsafe { entry.read_volatile() };
+        if value & PRESENT == 0 {
+            return Err(PagingError::NotMapped(addr));
+        }
+
+        let is_leaf = level == 1 || (level <= 3 && value & HUGE_PAGE != 0);
+        if !is_leaf {
+            table = table_at(value);
+            continue;
+        }
+        let start = addr & !(page_size - 1);
+        if start < range.start || start + page_size > range.end {
+            // 範囲外の MMIO まで WC にしないよう分割する
+            unsafe { split(entry, page_size)? };
+            return Ok(addr);
+        }
+        let pat = if level == 1 { PAT_4K } else { PAT_LARGE };
+        unsafe { entry.write_volatile((value & !(PCD | pat)) | PWT) };
+        return Ok(start + page_size);
+    }
+    unreachable!()
+}
+
+/// Map `base..base + size` write-combining; used for the frame buffer
+pub fn set_write_combining(base: u64, size: u64) -> Result<(), PagingError> {
+    if !is_supported() {
+        return Err(PagingError::Unsupported);
+    }
+    if x86::read_cr4() & CR4_LA57 != 0 {
+        return Err(PagingError::La57);
+    }
+    let start = base & !(PAGE_SIZE_4K - 1);
+    let end = (base + size).next_multiple_of(PAGE_SIZE_4K);
+
+    x86::without_interrupts(|| {
+        let cr0 = x86::read_cr0();
+        let cr4 = x86::read_cr4();
+        unsafe {
+            x86::write_msr(IA32_PAT, PAT_VALUE);
+            // UEFI がページテーブルを読み取り専用にしていることがある
+            x86::write_cr0(cr0 & !CR0_WRITE_PROTECT);
+        }
+
+        let mut addr = start;
+        let mut result = Ok(());
+        while addr < end {
+            match unsafe { set_wc_page(addr, start..end) } {
+                Ok(next) => addr = next,
+                Err(e) => {
+                    result = Err(e);
+                    break;
+                }
+            }
+        }
+
+        x86::wbinvd();
+        unsafe {
+            x86::write_cr0(cr0);
+            // PGE を切り替えてグローバルページを含む TLB を全て破棄する
+            x86::write_cr4(cr4 & !CR4_PGE);
+            x86::write_cr4(cr4);
+            x86::write_cr3(x86::read_cr3());
+        }
+        result
+    })
+}
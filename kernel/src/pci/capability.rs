@@ -0,0 +1,106 @@
+//! Capability list walking and MSI/MSI-X capability decoding
+
+use super::config::{Address, ConfigAccess};
+
+pub const CAP_MSI: u8 = 0x05;
+pub const CAP_VENDOR_SPECIFIC: u8 = 0x09;
+pub const CAP_MSIX: u8 = 0x11;
+
+const CAPABILITIES_POINTER: u16 = 0x34;
+/// ループしたリストで止まらないための上限 (256 バイト / 最小 4 バイト)
+const MAX_CAPABILITIES: usize = 48;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Capability {
+    pub id: u8,
+    pub offset: u8,
+}
+
+pub struct Capabilities<'a> {
+    access: &'a ConfigAccess,
+    addr: Address,
+    next: u8,
+    remaining: usize,
+}
+
+impl<'a> Capabilities<'a> {
+    /// `has_list` is the "capabilities list" bit of the status register
+    pub fn new(access: &'a ConfigAccess, addr: Address, has_list: bool) -> Self {
+        let next = if has_list {
+            access.read8(addr, CAPABILITIES_POINTER) & !0b11
+        } else {
+            0
+        };
+        Self {
+            access,
+            addr,
+            next,
+            remaining: MAX_CAPABILITIES,
+        }
+    }
+}
+
+impl Iterator for Capabilities<'_> {
+    type Item = Capability;
+
+    fn next(&mut self) -> Option<Capability> {
+        if self.next < 0x40 || self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+        let offset = self.next;
+        let header = self.access.read16(self.addr, offset as u16);
+        self.next = (header >> 8) as u8 & !0b11;
+        Some(Capability {
+            id: header as u8,
+            offset,
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MsiCapability {
+    pub offset: u8,
+    pub is_64bit: bool,
+    pub per_vector_masking: bool,
+    /// log2 of the number of vectors the function can request
+    pub multiple_message_capable: u8,
+}
+
+impl MsiCapability {
+    pub fn read(access: &ConfigAccess, addr: Address, offset: u8) -> Self {
+        let control = access.read16(addr, offset as u16 + 2);
+        Self {
+            offset,
+            is_64bit: control & (1 << 7) != 0,
+            per_vector_masking: control & (1 << 8) != 0,
+            multiple_message_capable: ((control >> 1) & 0b111) as u8,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MsixCapability {
+    pub offset: u8,
+    pub table_size: u16,
+    pub table_bar: u8,
+    pub table_offset: u32,
+    pub pba_bar: u8,
+    pub pba_offset: u32,
+}
+
+impl MsixCapability {
+    pub fn read(access: &ConfigAccess, addr: Address, offset: u8) -> Self {
+        let control = access.read16(addr, offset as u16 + 2);
+        let table = access.read32(addr, offset as u16 + 4);
+        let pba = access.read32(addr, offset as u16 + 8);
+        Self {
+            offset,
+            table_size: (control & 0x7ff) + 1,
+            table_bar: (table & 0b111) as u8,
+            table_offset: table & !0b111,
+            pba_bar: (pba & 0b111) as u8,
+            pba_offset: pba & !0b111,
+        }
+    }
+}
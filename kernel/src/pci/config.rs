@@ -0,0 +1,130 @@
+//! Configuration space access through I/O ports or ECAM
+
+use crate::{acpi::mcfg::Mcfg, x86};
+
+const CONFIG_ADDRESS: u16 = 0x0cf8;
+const CONFIG_DATA: u16 = 0x0cfc;
+
+/// Location of a function on segment group 0
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Address {
+    pub bus: u8,
+    pub device: u8,
+    pub function: u8,
+}
+
+impl Address {
+    pub const fn new(bus: u8, device: u8, function: u8) -> Self {
+        Self {
+            bus,
+            device,
+            function,
+        }
+    }
+}
+
+impl core::fmt::Display for Address {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{:02x}:{:02x}.{}", self.bus, self.device, self.function)
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum ConfigAccess {
+    /// Configuration mechanism #1 (ports 0xCF8/0xCFC), 256 bytes per function
+    Legacy,
+    /// Memory mapped configuration space, 4 KiB per function
+    Ecam(Mcfg<'static>),
+}
+
+impl ConfigAccess {
+    /// Buses this mechanism can reach
+    pub fn bus_range(&self) -> (u8, u8) {
+        match self {
+            Self::Legacy => (0, 255),
+            Self::Ecam(mcfg) => mcfg
+                .regions()
+                .filter(|r| r.segment_group == 0)
+                .fold((255, 0), |(start, end), r| {
+                    (start.min(r.start_bus), end.max(r.end_bus))
+                }),
+        }
+    }
+
+    fn ecam_address(&self, addr: Address, offset: u16) -> Option<u64> {
+        let Self::Ecam(mcfg) = self else {
+            return None;
+        };
+        let region = mcfg.region_for_bus(addr.bus)?;
+        Some(region.config_address(addr.bus, addr.device, addr.function)? + offset as u64)
+    }
+
+    pub fn read32(&self, addr: Address, offset: u16) -> u32 {
+        let offset = offset & !0b11;
+        match self {
+            Self::Legacy => {
+                if offset >= 0x100 {
+                    return u32::MAX;
+                }
+                x86::io_out32(CONFIG_ADDRESS, legacy_address(addr, offset));
+                x86::io_in32(CONFIG_DATA)
+            }
+            Self::Ecam(_) => match self.ecam_address(addr, offset) {
+                Some(mmio) => unsafe { (mmio as *const u32).read_volatile() },
+                None => u32::MAX,
+            },
+        }
+    }
+
+    pub fn write32(&self, addr: Address, offset: u16, value: u32) {
+        let offset = offset & !0b11;
+        match self {
+            Self::Legacy => {
+                if offset >= 0x100 {
+                    return;
+                }
+                x86::io_out32(CONFIG_ADDRESS, legacy_address(addr, offset));
+                x86::io_out32(CONFIG_DATA, value);
+            }
+            Self::Ecam(_) => {
+                if let Some(mmio) = self.ecam_address(addr, offset) {
+                    unsafe { (mmio as *mut u32).write_volatile(value) };
+                }
+            }
+        }
+    }
+
+    pub fn read16(&self, addr: Address, offset: u16) -> u16 {
+        (self.read32(addr, offset) >> ((offset & 0b10) * 8)) as u16
+    }
+
+    pub fn read8(&self, addr: Address, offset: u16) -> u8 {
+        (self.read32(addr, offset) >> ((offset & 0b11) * 8)) as u8
+    }
+
+    pub fn write16(&self, addr: Address, offset: u16, value: u16) {
+        let offset = offset & !0b1;
+        match self {
+            Self::Legacy => {
+                if offset >= 0x100 {
+                    return;
+                }
+                x86::io_out32(CONFIG_ADDRESS, legacy_address(addr, offset & !0b11));
+                x86::io_out16(CONFIG_DATA + (offset & 0b10), value);
+            }
+            Self::Ecam(_) => {
+                if let Some(mmio) = self.ecam_address(addr, offset) {
+                    unsafe { (mmio as *mut u16).write_volatile(value) };
+                }
+            }
+        }
+    }
+}
+
+fn legacy_address(addr: Address, offset: u16) -> u32 {
+    1 << 31
+        | (addr.bus as u32) << 16
+        | (addr.device as u32) << 11
+        | (addr.function as u32) << 8
+        | offset as u32
+}
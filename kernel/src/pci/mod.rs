@@ -0,0 +1,361 @@
+//! PCI device enumeration
+//!
+//! All buses are scanned once at boot and the result is kept in a fixed-size
+//! device table that drivers query by vendor/device ID or by class code.
+pub mod capability;
+pub mod config;
+pub mod msi;
+
+use spin::Once;
+
+use crate::acpi::AcpiTables;
+use capability::{CAP_MSI, CAP_MSIX, Capabilities, MsiCapability, MsixCapability};
+use config::{Address, ConfigAccess};
+
+pub const MAX_DEVICES: usize = 64;
+
+const REG_VENDOR_ID: u16 = 0x00;
+const REG_DEVICE_ID: u16 = 0x02;
+const REG_COMMAND: u16 = 0x04;
+const REG_STATUS: u16 = 0x06;
+const REG_CLASS: u16 = 0x08;
+const REG_HEADER_TYPE: u16 = 0x0e;
+const REG_BAR0: u16 = 0x10;
+
+pub const COMMAND_IO_SPACE: u16 = 1 << 0;
+pub const COMMAND_MEMORY_SPACE: u16 = 1 << 1;
+pub const COMMAND_BUS_MASTER: u16 = 1 << 2;
+pub const COMMAND_INTX_DISABLE: u16 = 1 << 10;
+
+const STATUS_CAPABILITIES_LIST: u16 = 1 << 4;
+
+const INVALID_VENDOR_ID: u16 = 0xffff;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PciError {
+    TableFull,
+    NoSuchBar,
+    NoCapability,
+    InvalidVector,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ClassCode {
+    pub base: u8,
+    pub sub: u8,
+    pub interface: u8,
+}
+
+impl ClassCode {
+    pub fn matches(&self, base: u8, sub: u8) -> bool {
+        self.base == base && self.sub == sub
+    }
+
+    pub fn matches_interface(&self, base: u8, sub: u8, interface: u8) -> bool {
+        self.matches(base, sub) && self.interface == interface
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Bar {
+    None,
+    Io {
+        port: u16,
+        size: u32,
+    },
+    Memory32 {
+        base: u32,
+        size: u32,
+        prefetchable: bool,
+    },
+    Memory64 {
+        base: u64,
+        size: u64,
+        prefetchable: bool,
+    },
+}
+
+impl Bar {
+    /// MMIO base address of a memory BAR
+    pub fn memory_base(&self) -> Option<u64> {
+        match *self {
+            Bar::Memory32 { base, .. } => Some(base as u64),
+            Bar::Memory64 { base, .. } => Some(base),
+            _ => None,
+        }
+    }
+
+    #[allow(dead_code)] // サイズ判定の結果。今のドライバはベースアドレスしか見ない
+    pub fn size(&self) -> u64 {
+        match *self {
+            Bar::None => 0,
+            Bar::Io { size, .. } | Bar::Memory32 { size, .. } => size as u64,
+            Bar::Memory64 { size, .. } => size,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HeaderType {
+    Standard,
+    PciToPciBridge,
+    CardBusBridge,
+    Unknown(u8),
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Device {
+    pub address: Address,
+    pub vendor_id: u16,
+    pub device_id: u16,
+    pub class: ClassCode,
+    #[allow(dead_code)] // ブリッジを区別したいドライバ向けに残す
+    pub header_type: HeaderType,
+    /// BAR の 64 ビット上位側のスロットは [`Bar::None`] になる
+    pub bars: [Bar; 6],
+    pub msi: Option<MsiCapability>,
+    pub msix: Option<MsixCapability>,
+}
+
+static ACCESS: Once<ConfigAccess> = Once::new();
+static DEVICES: Once<DeviceTable> = Once::new();
+
+struct DeviceTable {
+    devices: [Option<Device>; MAX_DEVICES],
+    count: usize,
+}
+
+pub(crate) fn access() -> &'static ConfigAccess {
+    ACCESS.call_once(|| ConfigAccess::Legacy)
+}
+
+impl Device {
+    pub fn read_config32(&self, offset: u16) -> u32 {
+        access().read32(self.address, offset)
+    }
+
+    pub fn write_config32(&self, offset: u16, value: u32) {
+        access().write32(self.address, offset, value)
+    }
+
+    pub fn read_config16(&self, offset: u16) -> u16 {
+        access().read16(self.address, offset)
+    }
+
+    pub fn write_config16(&self, offset: u16, value: u16) {
+        access().write16(self.address, offset, value)
+    }
+
+    pub fn read_config8(&self, offset: u16) -> u8 {
+        access().read8(self.address, offset)
+    }
+
+    pub fn command(&self) -> u16 {
+        self.read_config16(REG_COMMAND)
+    }
+
+    pub fn set_command(&self, command: u16) {
+        self.write_config16(REG_COMMAND, command)
+    }
+
+    /// Enable MMIO decoding and DMA, as most drivers need both
+    pub fn enable_bus_master(&self) {
+        self.set_command(self.command() | COMMAND_MEMORY_SPACE | COMMAND_BUS_MASTER);
+    }
+
+    pub fn capabilities(&self) -> Capabilities<'static> {
+        let status = self.read_config16(REG_STATUS);
+        Capabilities::new(
+            access(),
+            self.address,
+            status & STATUS_CAPABILITIES_LIST != 0,
+        )
+    }
+
+    pub fn bar(&self, index: usize) -> Result<Bar, PciError> {
+        match self.bars.get(index) {
+            Some(Bar::None) | None => Err(PciError::NoSuchBar),
+            Some(bar) => Ok(*bar),
+        }
+    }
+}
+
+/// Choose ECAM when the MCFG is available and scan every bus
+pub fn init(acpi: &AcpiTables<'static>) -> Result<usize, PciError> {
+    let access = *ACCESS.call_once(|| match acpi.mcfg {
+        Some(mcfg) if mcfg.regions().any(|r| r.segment_group == 0) => ConfigAccess::Ecam(mcfg),
+        _ => ConfigAccess::Legacy,
+    });
+
+    let mut result = Ok(());
+    let table = DEVICES.call_once(|| {
+        let mut table = DeviceTable {
+            devices: [None; MAX_DEVICES],
+            count: 0,
+        };
+        result = scan_all_buses(&access, &mut table);
+        table
+    });
+    result.map(|()| table.count)
+}
+
+fn scan_all_buses(access: &ConfigAccess, table: &mut DeviceTable) -> Result<(), PciError> {
+    let (start_bus, end_bus) = access.bus_range();
+    for bus in start_bus..=end_bus {
+        for device in 0..32 {
+            let addr = Address::new(bus, device, 0);
+            if access.read16(addr, REG_VENDOR_ID) == INVALID_VENDOR_ID {
+                continue;
+            }
+            let multi_function = access.read8(addr, REG_HEADER_TYPE) & 0x80 != 0;
+            let functions = if multi_function { 8 } else { 1 };
+            for function in 0..functions {
+                let addr = Address::new(bus, device, function);
+                if access.read16(addr, REG_VENDOR_ID) == INVALID_VENDOR_ID {
+                    continue;
+                }
+                if table.count == MAX_DEVICES {
+                    return Err(PciError::TableFull);
+                }
+                table.devices[table.count] = Some(read_device(access, addr));
+                table.count += 1;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn read_device(access: &ConfigAccess, addr: Address) -> Device {
+    let class = access.read32(addr, REG_CLASS);
+    let header = access.read8(addr, REG_HEADER_TYPE);
+    let header_type = match header & 0x7f {
+        0 => HeaderType::Standard,
+        1 => HeaderType::PciToPciBridge,
+        2 => HeaderType::CardBusBridge,
+        other => HeaderType::Unknown(other),
+    };
+
+    let mut device = Device {
+        address: addr,
+        vendor_id: access.read16(addr, REG_VENDOR_ID),
+        device_id: access.read16(addr, REG_DEVICE_ID),
+        class: ClassCode {
+            base: (class >> 24) as u8,
+            sub: (class >> 16) as u8,
+            interface: (class >> 8) as u8,
+        },
+        header_type,
+        bars: [Bar::None; 6],
+        msi: None,
+        msix: None,
+    };
+
+    let bar_count = match header_type {
+        HeaderType::Standard => 6,
+        HeaderType::PciToPciBridge => 2,
+        _ => 0,
+    };
+    let mut index = 0;
+    while index < bar_count {
+        let (bar, used) = probe_bar(access, addr, index);
+        device.bars[index] = bar;
+        index += used;
+    }
+
+    for cap in device.capabilities() {
+        match cap.id {
+            CAP_MSI => device.msi = Some(MsiCapability::read(access, addr, cap.offset)),
+            CAP_MSIX => device.msix = Some(MsixCapability::read(access, addr, cap.offset)),
+            _ => {}
+        }
+    }
+    device
+}
+
+/// Decode and size BAR `index`, returning how many BAR slots it occupies
+fn probe_bar(access: &ConfigAccess, addr: Address, index: usize) -> (Bar, usize) {
+    let offset = REG_BAR0 + index as u16 * 4;
+    let original = access.read32(addr, offset);
+
+    // サイズを測る間はデコードを止めておく
+    let command = access.read16(addr, REG_COMMAND);
+    access.write16(
+        addr,
+        REG_COMMAND,
+        command & !(COMMAND_IO_SPACE | COMMAND_MEMORY_SPACE),
+    );
+
+    let result = if original & 1 != 0 {
+        access.write32(addr, offset, u32::MAX);
+        let mask = access.read32(addr, offset) & !0b11 & 0xffff;
+        access.write32(addr, offset, original);
+        let bar = match mask {
+            0 => Bar::None,
+            _ => Bar::Io {
+                port: (original & !0b11) as u16,
+                size: (!mask & 0xffff) + 1,
+            },
+        };
+        (bar, 1)
+    } else if (original >> 1) & 0b11 == 0b10 && index < 5 {
+        let original_high = access.read32(addr, offset + 4);
+        access.write32(addr, offset, u32::MAX);
+        access.write32(addr, offset + 4, u32::MAX);
+        let mask = (access.read32(addr, offset) as u64
+            | (access.read32(addr, offset + 4) as u64) << 32)
+            & !0xf;
+        access.write32(addr, offset, original);
+        access.write32(addr, offset + 4, original_high);
+        let bar = match mask {
+            0 => Bar::None,
+            _ => Bar::Memory64 {
+                base: (original as u64 | (original_high as u64) << 32) & !0xf,
+                size: (!mask).wrapping_add(1),
+                prefetchable: original & (1 << 3) != 0,
+            },
+        };
+        (bar, 2)
+    } else {
+        access.write32(addr, offset, u32::MAX);
+        let mask = access.read32(addr, offset) & !0xf;
+        access.write32(addr, offset, original);
+        let bar = match mask {
+            0 => Bar::None,
+            _ => Bar::Memory32 {
+                base: original & !0xf,
+                size: (!mask).wrapping_add(1),
+                prefetchable: original & (1 << 3) != 0,
+            },
+        };
+        (bar, 1)
+    };
+
+    access.write16(addr, REG_COMMAND, command);
+    result
+}
+
+/// Every function found during [`init`]
+pub fn devices() -> impl Iterator<Item = &'static Device> {
+    DEVICES
+        .get()
+        .into_iter()
+        .flat_map(|table| table.devices[..table.count].iter().flatten())
+}
+
+#[allow(dead_code)] // ベンダ/デバイス ID で探すドライバはまだない
+pub fn find_by_id(vendor_id: u16, device_id: u16) -> impl Iterator<Item = &'static Device> {
+    devices().filter(move |d| d.vendor_id == vendor_id && d.device_id == device_id)
+}
+
+#[allow(dead_code)] // 今のドライバはプログラミングインターフェースまで指定して探す
+pub fn find_by_class(base: u8, sub: u8) -> impl Iterator<Item = &'static Device> {
+    devices().filter(move |d| d.class.matches(base, sub))
+}
+
+pub fn find_by_class_interface(
+    base: u8,
+    sub: u8,
+    interface: u8,
+) -> impl Iterator<Item = &'static Device> {
+    devices().filter(move |d| d.class.matches_interface(base, sub, interface))
+}
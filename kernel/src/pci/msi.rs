@@ -0,0 +1,157 @@
+//! Message signalled interrupts (MSI / MSI-X)
+
+use super::{COMMAND_INTX_DISABLE, Device, PciError, capability::MsixCapability};
+
+const MSI_ADDRESS_BASE: u32 = 0xfee0_0000;
+
+const MSI_CONTROL_ENABLE: u16 = 1 << 0;
+const MSI_CONTROL_MME_SHIFT: u16 = 4;
+const MSI_CONTROL_MME_MASK: u16 = 0b111 << MSI_CONTROL_MME_SHIFT;
+
+const MSIX_CONTROL_FUNCTION_MASK: u16 = 1 << 14;
+const MSIX_CONTROL_ENABLE: u16 = 1 << 15;
+
+const MSIX_ENTRY_SIZE: u64 = 16;
+const MSIX_VECTOR_CONTROL_MASKED: u32 = 1 << 0;
+
+/// Message address/data pair that delivers `vector` to the local APIC `apic_id`
+///
+/// Fixed delivery mode, physical destination, edge triggered.
+pub fn message(apic_id: u8, vector: u8) -> (u64, u32) {
+    (
+        (MSI_ADDRESS_BASE | (apic_id as u32) << 12) as u64,
+        vector as u32,
+    )
+}
+
+impl Device {
+    /// Route MSI to `vector` .. `vector + count` on `apic_id` and enable it
+    ///
+    /// `count` must be a power of two the function supports, and `vector`
+    /// must be aligned to it (see [`crate::interrupt::allocate_vectors`]).
+    pub fn configure_msi(&self, apic_id: u8, vector: u8, count: u8) -> Result<(), PciError> {
+        let msi = self.msi.ok_or(PciError::NoCapability)?;
+        if !count.is_power_of_two()
+            || count.trailing_zeros() > msi.multiple_message_capable as u32
+            || !vector.is_multiple_of(count)
+        {
+            return Err(PciError::InvalidVector);
+        }
+
+        let cap = msi.offset as u16;
+        let (address, data) = message(apic_id, vector);
+        let mut control = self.read_config16(cap + 2);
+        self.write_config16(cap + 2, control & !MSI_CONTROL_ENABLE);
+
+        self.write_config32(cap + 4, address as u32);
+        let data_offset = if msi.is_64bit {
+            self.write_config32(cap + 8, (address >> 32) as u32);
+            cap + 0x0c
+        } else {
+            cap + 0x08
+        };
+        self.write_config16(data_offset, data as u16);
+        if msi.per_vector_masking {
+            // Mask Bits はデータレジスタの 4 バイト後ろ
+            self.write_config32(data_offset + 4, 0);
+        }
+
+        control &= !MSI_CONTROL_MME_MASK;
+        control |= (count.trailing_zeros() as u16) << MSI_CONTROL_MME_SHIFT;
+        self.write_config16(cap + 2, control | MSI_CONTROL_ENABLE);
+        self.set_command(self.command() | COMMAND_INTX_DISABLE);
+        Ok(())
+    }
+
+    pub fn disable_msi(&self) {
+        if let Some(msi) = self.msi {
+            let cap = msi.offset as u16;
+            let control = self.read_config16(cap + 2);
+            self.write_config16(cap + 2, control & !MSI_CONTROL_ENABLE);
+        }
+    }
+
+    /// Locate the MSI-X table in its BAR
+    ///
+    /// All vectors start masked; program them with [`MsixTable::set_vector`]
+    /// and then call [`Device::enable_msix`].
+    pub fn msix_table(&self) -> Result<MsixTable, PciError> {
+        let msix = self.msix.ok_or(PciError::NoCapability)?;
+        let table_bar = self.bar(msix.table_bar as usize)?;
+        let table_base = table_bar.memory_base().ok_or(PciError::NoSuchBar)?;
+
+        // 全エントリをマスクした状態でファンクションごと有効化しておく
+        let cap = msix.offset as u16;
+        let control = self.read_config16(cap + 2);
+        self.write_config16(cap + 2, control | MSIX_CONTROL_FUNCTION_MASK);
+        self.set_command(self.command() | super::COMMAND_MEMORY_SPACE);
+
+        let table = MsixTable {
+            capability: msix,
+            table: table_base + msix.table_offset as u64,
+        };
+        for index in 0..msix.table_size {
+            table.mask(index);
+        }
+        Ok(table)
+    }
+
+    /// Turn MSI-X on (and MSI/INTx off); vectors keep their own mask bits
+    pub fn enable_msix(&self) -> Result<(), PciError> {
+        let msix = self.msix.ok_or(PciError::NoCapability)?;
+        self.disable_msi();
+        let cap = msix.offset as u16;
+        let control = self.read_config16(cap + 2);
+        self.write_config16(
+            cap + 2,
+            (control | MSIX_CONTROL_ENABLE) & !MSIX_CONTROL_FUNCTION_MASK,
+        );
+        self.set_command(self.command() | COMMAND_INTX_DISABLE);
+        Ok(())
+    }
+}
+
+/// MSI-X table of one function, in MMIO
+#[derive(Clone, Copy, Debug)]
+pub struct MsixTable {
+    capability: MsixCapability,
+    table: u64,
+}
+
+impl MsixTable {
+    pub fn len(&self) -> u16 {
+        self.capability.table_size
+    }
+
+    fn entry(&self, index: u16) -> *mut u32 {
+        assert!(index < self.len(), "MSI-X index out of range");
+        (self.table + index as u64 * MSIX_ENTRY_SIZE) as *mut u32
+    }
+
+    /// Point entry `index` at `vector` on `apic_id` and unmask it
+    pub fn set_vector(&self, index: u16, apic_id: u8, vector: u8) {
+        let (address, data) = message(apic_id, vector);
+        let entry = self.entry(index);
+        self.mask(index);
+        unsafe {
+            entry.write_volatile(address as u32);
+            entry.add(1).write_volatile((address >> 32) as u32);
+            entry.add(2).write_volatile(data);
+        }
+        self.unmask(index);
+    }
+
+    pub fn mask(&self, index: u16) {
+        unsafe {
+            let control = self.entry(index).add(3);
+            control.write_volatile(control.read_volatile() | MSIX_VECTOR_CONTROL_MASKED);
+        }
+    }
+
+    pub fn unmask(&self, index: u16) {
+        unsafe {
+            let control = self.entry(index).add(3);
+            control.write_volatile(control.read_volatile() & !MSIX_VECTOR_CONTROL_MASKED);
+        }
+    }
+}
@@ -0,0 +1,26 @@
+//! Restarting the machine
+
+use core::arch::asm;
+
+use crate::{acpi, ps2, x86};
+
+/// Busy-wait iterations given to each reset method before trying the next
+const RESET_WAIT: usize = 10_000_000;
+
+fn settle() {
+    for _ in 0..RESET_WAIT {
+        core::hint::spin_loop();
+    }
+}
+
+/// Reset through ACPI, then the 8042, then a triple fault
+pub fn reboot() -> ! {
+    x86::disable_interrupts();
+    acpi::reset();
+    settle();
+    let _ = ps2::pulse_reset();
+    settle();
+    // 空の IDT で例外を起こせば二重・三重フォルトになり CPU がリセットされる
+    let idtr = [0u16; 5];
+    unsafe { asm!("lidt [{}]", "int3", in(reg) &idtr, options(noreturn)) }
+}
@@ -0,0 +1,64 @@
+//! PS/2 keyboard: scancodes to [`KeyEvent`](crate::input::KeyEvent)s
+
+use spin::Mutex;
+
+use super::{
+    DATA_PORT, DEVICE_ENABLE_SCANNING, Port, Ps2Error, device_command, reset_device,
+    scancode::{Decoder, ScancodeSet},
+};
+use crate::{
+    input::{self, InputEvent},
+    interrupt::InterruptFrame,
+    message::Message,
+    task, x86,
+};
+
+/// HID usage IDs of the modifier keys (LeftControl..RightGUI)
+const MODIFIER_USAGES: core::ops::RangeInclusive<u8> = 0xe0..=0xe7;
+
+struct Keyboard {
+    decoder: Decoder,
+    modifiers: u8,
+}
+
+static KEYBOARD: Mutex<Keyboard> = Mutex::new(Keyboard {
+    decoder: Decoder::new(ScancodeSet::Set1),
+    modifiers: 0,
+});
+
+/// Reset the keyboard and start scanning
+///
+/// With controller translation on, the keyboard's set 2 arrives as set 1.
+pub(super) fn init(translated: bool) -> Result<(), Ps2Error> {
+    reset_device(Port::First)?;
+    device_command(Port::First, DEVICE_ENABLE_SCANNING)?;
+    let set = if translated {
+        ScancodeSet::Set1
+    } else {
+        ScancodeSet::Set2
+    };
+    KEYBOARD.lock().decoder = Decoder::new(set);
+    Ok(())
+}
+
+pub(super) fn on_interrupt(_frame: &mut InterruptFrame) {
+    let byte = x86::io_in8(DATA_PORT);
+    let _ = task::send_message(task::main_id(), Message::Ps2Keyboard(byte));
+}
+
+/// Decode a byte posted by [`on_interrupt`]; called from the kernel event loop
+pub fn on_byte(byte: u8) {
+    let mut keyboard = KEYBOARD.lock();
+    for key in keyboard.decoder.feed(byte).into_iter().flatten() {
+        if MODIFIER_USAGES.contains(&key.usage) {
+            let bit = 1 << (key.usage - 0xe0);
+            if key.pressed {
+                keyboard.modifiers |= bit;
+            } else {
+                keyboard.modifiers &= !bit;
+            }
+        }
+        let event = input::key_event(key.usage, keyboard.modifiers, key.pressed);
+        input::push(InputEvent::Key(event));
+    }
+}
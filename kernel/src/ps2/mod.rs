@@ -0,0 +1,227 @@
+//! i8042 PS/2 controller, used when no xHCI is available
+pub mod keyboard;
+pub mod mouse;
+pub mod scancode;
+
+use crate::{acpi::AcpiTables, apic, x86};
+
+const DATA_PORT: u16 = 0x60;
+const STATUS_PORT: u16 = 0x64;
+const COMMAND_PORT: u16 = 0x64;
+
+const STATUS_OUTPUT_FULL: u8 = 1 << 0;
+const STATUS_INPUT_FULL: u8 = 1 << 1;
+
+const COMMAND_READ_CONFIG: u8 = 0x20;
+const COMMAND_WRITE_CONFIG: u8 = 0x60;
+const COMMAND_DISABLE_SECOND: u8 = 0xa7;
+const COMMAND_ENABLE_SECOND: u8 = 0xa8;
+const COMMAND_TEST_SECOND: u8 = 0xa9;
+const COMMAND_SELF_TEST: u8 = 0xaa;
+const COMMAND_TEST_FIRST: u8 = 0xab;
+const COMMAND_DISABLE_FIRST: u8 = 0xad;
+const COMMAND_ENABLE_FIRST: u8 = 0xae;
+const COMMAND_WRITE_SECOND: u8 = 0xd4;
+const COMMAND_PULSE_RESET: u8 = 0xfe;
+
+const CONFIG_FIRST_IRQ: u8 = 1 << 0;
+const CONFIG_SECOND_IRQ: u8 = 1 << 1;
+const CONFIG_SECOND_CLOCK_DISABLED: u8 = 1 << 5;
+const CONFIG_TRANSLATION: u8 = 1 << 6;
+
+const SELF_TEST_OK: u8 = 0x55;
+const PORT_TEST_OK: u8 = 0x00;
+
+/// Device commands and responses shared by keyboards and mice
+pub(crate) const DEVICE_RESET: u8 = 0xff;
+pub(crate) const DEVICE_ENABLE_SCANNING: u8 = 0xf4;
+pub(crate) const DEVICE_ACK: u8 = 0xfa;
+pub(crate) const DEVICE_RESEND: u8 = 0xfe;
+pub(crate) const DEVICE_SELF_TEST_PASSED: u8 = 0xaa;
+
+pub const KEYBOARD_IRQ: u8 = 1;
+pub const MOUSE_IRQ: u8 = 12;
+
+/// Polling iterations before a controller access gives up
+const TIMEOUT: usize = 100_000;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Ps2Error {
+    /// The FADT says there is no 8042
+    NotPresent,
+    Timeout,
+    SelfTestFailed,
+    /// Neither port passed its interface test or answered a reset
+    NoDevice,
+    Apic(apic::ApicError),
+}
+
+impl From<apic::ApicError> for Ps2Error {
+    fn from(e: apic::ApicError) -> Self {
+        Ps2Error::Apic(e)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Port {
+    First,
+    Second,
+}
+
+fn wait_input_empty() -> Result<(), Ps2Error> {
+    for _ in 0..TIMEOUT {
+        if x86::io_in8(STATUS_PORT) & STATUS_INPUT_FULL == 0 {
+            return Ok(());
+        }
+        core::hint::spin_loop();
+    }
+    Err(Ps2Error::Timeout)
+}
+
+fn wait_output_full() -> Result<(), Ps2Error> {
+    for _ in 0..TIMEOUT {
+        if x86::io_in8(STATUS_PORT) & STATUS_OUTPUT_FULL != 0 {
+            return Ok(());
+        }
+        core::hint::spin_loop();
+    }
+    Err(Ps2Error::Timeout)
+}
+
+fn command(command: u8) -> Result<(), Ps2Error> {
+    wait_input_empty()?;
+    x86::io_out8(COMMAND_PORT, command);
+    Ok(())
+}
+
+/// Pulse the CPU reset line; the machine restarts shortly after
+pub fn pulse_reset() -> Result<(), Ps2Error> {
+    command(COMMAND_PULSE_RESET)
+}
+
+fn command_with_response(cmd: u8) -> Result<u8, Ps2Error> {
+    command(cmd)?;
+    read_data()
+}
+
+pub(crate) fn read_data() -> Result<u8, Ps2Error> {
+    wait_output_full()?;
+    Ok(x86::io_in8(DATA_PORT))
+}
+
+fn write_data(value: u8) -> Result<(), Ps2Error> {
+    wait_input_empty()?;
+    x86::io_out8(DATA_PORT, value);
+    Ok(())
+}
+
+fn flush_output() {
+    while x86::io_in8(STATUS_PORT) & STATUS_OUTPUT_FULL != 0 {
+        x86::io_in8(DATA_PORT);
+    }
+}
+
+/// Send a byte to the device on `port`
+fn write_device(port: Port, value: u8) -> Result<(), Ps2Error> {
+    if port == Port::Second {
+        command(COMMAND_WRITE_SECOND)?;
+    }
+    write_data(value)
+}
+
+/// Send a device command and wait for its ACK, resending a few times on request
+pub(crate) fn device_command(port: Port, value: u8) -> Result<(), Ps2Error> {
+    for _ in 0..3 {
+        write_device(port, value)?;
+        match read_data()? {
+            DEVICE_ACK => return Ok(()),
+            DEVICE_RESEND => continue,
+            _ => return Err(Ps2Error::NoDevice),
+        }
+    }
+    Err(Ps2Error::NoDevice)
+}
+
+/// Reset the device on `port` and wait for its self-test result
+pub(crate) fn reset_device(port: Port) -> Result<(), Ps2Error> {
+    device_command(port, DEVICE_RESET)?;
+    // BAT はリセットに数百ミリ秒かかることがある
+    for _ in 0..10 {
+        match read_data() {
+            Ok(DEVICE_SELF_TEST_PASSED) => return Ok(()),
+            Ok(_) => return Err(Ps2Error::NoDevice),
+            Err(_) => continue,
+        }
+    }
+    Err(Ps2Error::Timeout)
+}
+
+/// Which devices [`init`] brought up
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Devices {
+    pub keyboard: bool,
+    pub mouse: bool,
+}
+
+/// Bring up the i8042 and route IRQ1/IRQ12 through the I/O APIC
+///
+/// Call after [`apic::init`], and after the xHCI driver has taken the
+/// controller away from firmware's legacy USB emulation.
+pub fn init(acpi: &AcpiTables) -> Result<Devices, Ps2Error> {
+    if acpi.fadt.is_some_and(|fadt| !fadt.has_8042()) {
+        return Err(Ps2Error::NotPresent);
+    }
+
+    command(COMMAND_DISABLE_FIRST)?;
+    command(COMMAND_DISABLE_SECOND)?;
+    flush_output();
+
+    let mut config = command_with_response(COMMAND_READ_CONFIG)?;
+    let translated = config & CONFIG_TRANSLATION != 0;
+    config &= !(CONFIG_FIRST_IRQ | CONFIG_SECOND_IRQ);
+    command(COMMAND_WRITE_CONFIG)?;
+    write_data(config)?;
+
+    if command_with_response(COMMAND_SELF_TEST)? != SELF_TEST_OK {
+        return Err(Ps2Error::SelfTestFailed);
+    }
+    // セルフテストで設定がリセットされるコントローラがある
+    command(COMMAND_WRITE_CONFIG)?;
+    write_data(config)?;
+
+    // 第 2 ポートを有効にしてクロックが動けばマウスポートがある
+    command(COMMAND_ENABLE_SECOND)?;
+    let dual_channel =
+        command_with_response(COMMAND_READ_CONFIG)? & CONFIG_SECOND_CLOCK_DISABLED == 0;
+    command(COMMAND_DISABLE_SECOND)?;
+
+    let first_ok = command_with_response(COMMAND_TEST_FIRST)? == PORT_TEST_OK;
+    let second_ok = dual_channel && command_with_response(COMMAND_TEST_SECOND)? == PORT_TEST_OK;
+
+    let mut devices = Devices::default();
+    if first_ok {
+        command(COMMAND_ENABLE_FIRST)?;
+        devices.keyboard = keyboard::init(translated).is_ok();
+    }
+    if second_ok {
+        command(COMMAND_ENABLE_SECOND)?;
+        devices.mouse = mouse::init().is_ok();
+    }
+    if !devices.keyboard && !devices.mouse {
+        return Err(Ps2Error::NoDevice);
+    }
+    flush_output();
+
+    if devices.keyboard {
+        config |= CONFIG_FIRST_IRQ;
+        apic::register_isa_irq(KEYBOARD_IRQ, keyboard::on_interrupt)?;
+    }
+    if devices.mouse {
+        config |= CONFIG_SECOND_IRQ;
+        config &= !CONFIG_SECOND_CLOCK_DISABLED;
+        apic::register_isa_irq(MOUSE_IRQ, mouse::on_interrupt)?;
+    }
+    command(COMMAND_WRITE_CONFIG)?;
+    write_data(config)?;
+    Ok(devices)
+}
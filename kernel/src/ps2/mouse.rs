@@ -0,0 +1,107 @@
+//! PS/2 mouse packet decoder (with IntelliMouse wheel when available)
+
+use spin::Mutex;
+
+use super::{
+    DATA_PORT, DEVICE_ENABLE_SCANNING, Port, Ps2Error, device_command, read_data, reset_device,
+};
+use crate::{
+    input::{self, InputEvent, MouseEvent},
+    interrupt::InterruptFrame,
+    message::Message,
+    task, x86,
+};
+
+const SET_SAMPLE_RATE: u8 = 0xf3;
+const GET_DEVICE_ID: u8 = 0xf2;
+const ID_INTELLIMOUSE: u8 = 3;
+
+const PACKET_BUTTONS: u8 = 0b111;
+const PACKET_ALWAYS_ONE: u8 = 1 << 3;
+const PACKET_X_SIGN: u8 = 1 << 4;
+const PACKET_Y_SIGN: u8 = 1 << 5;
+const PACKET_OVERFLOW: u8 = 0b11 << 6;
+
+struct Mouse {
+    packet: [u8; 4],
+    received: usize,
+    packet_size: usize,
+}
+
+static MOUSE: Mutex<Mouse> = Mutex::new(Mouse {
+    packet: [0; 4],
+    received: 0,
+    packet_size: 3,
+});
+
+pub(super) fn init() -> Result<(), Ps2Error> {
+    reset_device(Port::Second)?;
+    // リセット後にデバイス ID (0x00) が続く
+    let _ = read_data();
+
+    // 200, 100, 80 の順にサンプルレートを設定するとホイールが有効になる
+    for rate in [200, 100, 80] {
+        device_command(Port::Second, SET_SAMPLE_RATE)?;
+        device_command(Port::Second, rate)?;
+    }
+    device_command(Port::Second, GET_DEVICE_ID)?;
+    let packet_size = if read_data()? == ID_INTELLIMOUSE {
+        4
+    } else {
+        3
+    };
+    device_command(Port::Second, DEVICE_ENABLE_SCANNING)?;
+
+    let mut mouse = MOUSE.lock();
+    mouse.packet_size = packet_size;
+    mouse.received = 0;
+    Ok(())
+}
+
+/// Decode a complete packet; PS/2 Y grows upwards, events use screen coordinates
+fn decode(packet: &[u8]) -> Option<MouseEvent> {
+    let flags = packet[0];
+    if flags & PACKET_OVERFLOW != 0 {
+        return None;
+    }
+    let mut dx = packet[1] as i16;
+    let mut dy = packet[2] as i16;
+    if flags & PACKET_X_SIGN != 0 {
+        dx -= 0x100;
+    }
+    if flags & PACKET_Y_SIGN != 0 {
+        dy -= 0x100;
+    }
+    Some(MouseEvent {
+        dx,
+        dy: -dy,
+        wheel: packet.get(3).map_or(0, |&z| (z as i8).saturating_neg()),
+        buttons: flags & PACKET_BUTTONS,
+    })
+}
+
+pub(super) fn on_interrupt(_frame: &mut InterruptFrame) {
+    let byte = x86::io_in8(DATA_PORT);
+    let _ = task::send_message(task::main_id(), Message::Ps2Mouse(byte));
+}
+
+/// Assemble packets from bytes posted by [`on_interrupt`]; called from the
+/// kernel event loop
+pub fn on_byte(byte: u8) {
+    let mut mouse = MOUSE.lock();
+    // 先頭バイトの bit 3 は常に 1 なので、ずれたらそこで再同期する
+    if mouse.received == 0 && byte & PACKET_ALWAYS_ONE == 0 {
+        return;
+    }
+    let index = mouse.received;
+    mouse.packet[index] = byte;
+    mouse.received += 1;
+    if mouse.received < mouse.packet_size {
+        return;
+    }
+    mouse.received = 0;
+    let size = mouse.packet_size;
+    if let Some(event) = decode(&mouse.packet[..size]) {
+        input::push(InputEvent::Mouse(event));
+    }
+}
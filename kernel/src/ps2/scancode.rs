@@ -0,0 +1,329 @@
+//! PS/2 scancode set 1 and set 2 to HID usage ID translation
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ScancodeSet {
+    Set1,
+    Set2,
+}
+
+const fn build_table(pairs: &[(u8, u8)]) -> [u8; 0x90] {
+    let mut table = [0u8; 0x90];
+    let mut i = 0;
+    while i < pairs.len() {
+        table[pairs[i].0 as usize] = pairs[i].1;
+        i += 1;
+    }
+    table
+}
+
+/// `(scancode, usage)` for keys without a prefix
+const SET1: [u8; 0x90] = build_table(&[
+    (0x01, 0x29),
+    (0x02, 0x1e),
+    (0x03, 0x1f),
+    (0x04, 0x20),
+    (0x05, 0x21),
+    (0x06, 0x22),
+    (0x07, 0x23),
+    (0x08, 0x24),
+    (0x09, 0x25),
+    (0x0a, 0x26),
+    (0x0b, 0x27),
+    (0x0c, 0x2d),
+    (0x0d, 0x2e),
+    (0x0e, 0x2a),
+    (0x0f, 0x2b),
+    (0x10, 0x14),
+    (0x11, 0x1a),
+    (0x12, 0x08),
+    (0x13, 0x15),
+    (0x14, 0x17),
+    (0x15, 0x1c),
+    (0x16, 0x18),
+    (0x17, 0x0c),
+    (0x18, 0x12),
+    (0x19, 0x13),
+    (0x1a, 0x2f),
+    (0x1b, 0x30),
+    (0x1c, 0x28),
+    (0x1d, 0xe0),
+    (0x1e, 0x04),
+    (0x1f, 0x16),
+    (0x20, 0x07),
+    (0x21, 0x09),
+    (0x22, 0x0a),
+    (0x23, 0x0b),
+    (0x24, 0x0d),
+    (0x25, 0x0e),
+    (0x26, 0x0f),
+    (0x27, 0x33),
+    (0x28, 0x34),
+    (0x29, 0x35),
+    (0x2a, 0xe1),
+    (0x2b, 0x31),
+    (0x2c, 0x1d),
+    (0x2d, 0x1b),
+    (0x2e, 0x06),
+    (0x2f, 0x19),
+    (0x30, 0x05),
+    (0x31, 0x11),
+    (0x32, 0x10),
+    (0x33, 0x36),
+    (0x34, 0x37),
+    (0x35, 0x38),
+    (0x36, 0xe5),
+    (0x37, 0x55),
+    (0x38, 0xe2),
+    (0x39, 0x2c),
+    (0x3a, 0x39),
+    (0x3b, 0x3a),
+    (0x3c, 0x3b),
+    (0x3d, 0x3c),
+    (0x3e, 0x3d),
+    (0x3f, 0x3e),
+    (0x40, 0x3f),
+    (0x41, 0x40),
+    (0x42, 0x41),
+    (0x43, 0x42),
+    (0x44, 0x43),
+    (0x45, 0x53),
+    (0x46, 0x47),
+    (0x47, 0x5f),
+    (0x48, 0x60),
+    (0x49, 0x61),
+    (0x4a, 0x56),
+    (0x4b, 0x5c),
+    (0x4c, 0x5d),
+    (0x4d, 0x5e),
+    (0x4e, 0x57),
+    (0x4f, 0x59),
+    (0x50, 0x5a),
+    (0x51, 0x5b),
+    (0x52, 0x62),
+    (0x53, 0x63),
+    (0x56, 0x64),
+    (0x57, 0x44),
+    (0x58, 0x45),
+    // JIS 固有キー
+    (0x70, 0x88),
+    (0x73, 0x87),
+    (0x79, 0x8a),
+    (0x7b, 0x8b),
+    (0x7d, 0x89),
+]);
+
+const SET2: [u8; 0x90] = build_table(&[
+    (0x76, 0x29),
+    (0x16, 0x1e),
+    (0x1e, 0x1f),
+    (0x26, 0x20),
+    (0x25, 0x21),
+    (0x2e, 0x22),
+    (0x36, 0x23),
+    (0x3d, 0x24),
+    (0x3e, 0x25),
+    (0x46, 0x26),
+    (0x45, 0x27),
+    (0x4e, 0x2d),
+    (0x55, 0x2e),
+    (0x66, 0x2a),
+    (0x0d, 0x2b),
+    (0x15, 0x14),
+    (0x1d, 0x1a),
+    (0x24, 0x08),
+    (0x2d, 0x15),
+    (0x2c, 0x17),
+    (0x35, 0x1c),
+    (0x3c, 0x18),
+    (0x43, 0x0c),
+    (0x44, 0x12),
+    (0x4d, 0x13),
+    (0x54, 0x2f),
+    (0x5b, 0x30),
+    (0x5a, 0x28),
+    (0x14, 0xe0),
+    (0x1c, 0x04),
+    (0x1b, 0x16),
+    (0x23, 0x07),
+    (0x2b, 0x09),
+    (0x34, 0x0a),
+    (0x33, 0x0b),
+    (0x3b, 0x0d),
+    (0x42, 0x0e),
+    (0x4b, 0x0f),
+    (0x4c, 0x33),
+    (0x52, 0x34),
+    (0x0e, 0x35),
+    (0x12, 0xe1),
+    (0x5d, 0x31),
+    (0x1a, 0x1d),
+    (0x22, 0x1b),
+    (0x21, 0x06),
+    (0x2a, 0x19),
+    (0x32, 0x05),
+    (0x31, 0x11),
+    (0x3a, 0x10),
+    (0x41, 0x36),
+    (0x49, 0x37),
+    (0x4a, 0x38),
+    (0x59, 0xe5),
+    (0x7c, 0x55),
+    (0x11, 0xe2),
+    (0x29, 0x2c),
+    (0x58, 0x39),
+    (0x05, 0x3a),
+    (0x06, 0x3b),
+    (0x04, 0x3c),
+    (0x0c, 0x3d),
+    (0x03, 0x3e),
+    (0x0b, 0x3f),
+    (0x83, 0x40),
+    (0x0a, 0x41),
+    (0x01, 0x42),
+    (0x09, 0x43),
+    (0x77, 0x53),
+    (0x7e, 0x47),
+    (0x6c, 0x5f),
+    (0x75, 0x60),
+    (0x7d, 0x61),
+    (0x7b, 0x56),
+    (0x6b, 0x5c),
+    (0x73, 0x5d),
+    (0x74, 0x5e),
+    (0x79, 0x57),
+    (0x69, 0x59),
+    (0x72, 0x5a),
+    (0x7a, 0x5b),
+    (0x70, 0x62),
+    (0x71, 0x63),
+    (0x61, 0x64),
+    (0x78, 0x44),
+    (0x07, 0x45),
+    (0x13, 0x88),
+    (0x51, 0x87),
+    (0x64, 0x8a),
+    (0x67, 0x8b),
+    (0x6a, 0x89),
+]);
+
+/// Keys behind the `E0` prefix
+fn extended(set: ScancodeSet, code: u8) -> u8 {
+    match (set, code) {
+        (ScancodeSet::Set1, 0x1c) | (ScancodeSet::Set2, 0x5a) => 0x58,
+        (ScancodeSet::Set1, 0x1d) | (ScancodeSet::Set2, 0x14) => 0xe4,
+        (ScancodeSet::Set1, 0x35) | (ScancodeSet::Set2, 0x4a) => 0x54,
+        (ScancodeSet::Set1, 0x38) | (ScancodeSet::Set2, 0x11) => 0xe6,
+        (ScancodeSet::Set1, 0x37) | (ScancodeSet::Set2, 0x7c) => 0x46,
+        (ScancodeSet::Set1, 0x47) | (ScancodeSet::Set2, 0x6c) => 0x4a,
+        (ScancodeSet::Set1, 0x48) | (ScancodeSet::Set2, 0x75) => 0x52,
+        (ScancodeSet::Set1, 0x49) | (ScancodeSet::Set2, 0x7d) => 0x4b,
+        (ScancodeSet::Set1, 0x4b) | (ScancodeSet::Set2, 0x6b) => 0x50,
+        (ScancodeSet::Set1, 0x4d) | (ScancodeSet::Set2, 0x74) => 0x4f,
+        (ScancodeSet::Set1, 0x4f) | (ScancodeSet::Set2, 0x69) => 0x4d,
+        (ScancodeSet::Set1, 0x50) | (ScancodeSet::Set2, 0x72) => 0x51,
+        (ScancodeSet::Set1, 0x51) | (ScancodeSet::Set2, 0x7a) => 0x4e,
+        (ScancodeSet::Set1, 0x52) | (ScancodeSet::Set2, 0x70) => 0x49,
+        (ScancodeSet::Set1, 0x53) | (ScancodeSet::Set2, 0x71) => 0x4c,
+        (ScancodeSet::Set1, 0x5b) | (ScancodeSet::Set2, 0x1f) => 0xe3,
+        (ScancodeSet::Set1, 0x5c) | (ScancodeSet::Set2, 0x27) => 0xe7,
+        (ScancodeSet::Set1, 0x5d) | (ScancodeSet::Set2, 0x2f) => 0x65,
+        // E0 2A / E0 12 などの疑似 Shift は 0 (無視)
+        _ => 0,
+    }
+}
+
+/// HID usage for the Pause key
+const USAGE_PAUSE: u8 = 0x48;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Key {
+    pub usage: u8,
+    pub pressed: bool,
+}
+
+/// Byte-at-a-time decoder that tracks the `E0`, `E1` and `F0` prefixes
+#[derive(Clone, Copy, Debug)]
+pub struct Decoder {
+    set: ScancodeSet,
+    extended: bool,
+    release: bool,
+    /// Remaining bytes of a Pause sequence
+    pause_remaining: u8,
+}
+
+impl Decoder {
+    pub const fn new(set: ScancodeSet) -> Self {
+        Self {
+            set,
+            extended: false,
+            release: false,
+            pause_remaining: 0,
+        }
+    }
+
+    /// Feed one byte; at most two keys come out (Pause is press and release at once)
+    pub fn feed(&mut self, byte: u8) -> [Option<Key>; 2] {
+        if self.pause_remaining > 0 {
+            self.pause_remaining -= 1;
+            if self.pause_remaining == 0 {
+                return [
+                    Some(Key {
+                        usage: USAGE_PAUSE,
+                        pressed: true,
+                    }),
+                    Some(Key {
+                        usage: USAGE_PAUSE,
+                        pressed: false,
+                    }),
+                ];
+            }
+            return [None, None];
+        }
+
+        match byte {
+            0xe0 => {
+                self.extended = true;
+                return [None, None];
+            }
+            // Pause には break コードがなく、make だけで set 1 は 6 バイト、set 2 は 8 バイト
+            0xe1 => {
+                self.pause_remaining = match self.set {
+                    ScancodeSet::Set1 => 5,
+                    ScancodeSet::Set2 => 7,
+                };
+                return [None, None];
+            }
+            0xf0 if self.set == ScancodeSet::Set2 => {
+                self.release = true;
+                return [None, None];
+            }
+            // ACK や BAT 完了などのコントローラ応答
+            0x00 | 0xaa if self.set == ScancodeSet::Set2 && !self.extended && !self.release => {
+                return [None, None];
+            }
+            0xfa | 0xfe | 0xff => return [None, None],
+            _ => {}
+        }
+
+        let (code, pressed) = match self.set {
+            ScancodeSet::Set1 => (byte & 0x7f, byte & 0x80 == 0),
+            ScancodeSet::Set2 => (byte, !self.release),
+        };
+        let usage = if self.extended {
+            extended(self.set, code)
+        } else {
+            let table = match self.set {
+                ScancodeSet::Set1 => &SET1,
+                ScancodeSet::Set2 => &SET2,
+            };
+            table.get(code as usize).copied().unwrap_or(0)
+        };
+        self.extended = false;
+        self.release = false;
+
+        if usage == 0 {
+            return [None, None];
+        }
+        [Some(Key { usage, pressed }), None]
+    }
+}
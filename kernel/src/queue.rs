@@ -0,0 +1,47 @@
+//! Fixed-capacity FIFO that does not need a heap
+
+pub struct ArrayQueue<T: Copy, const N: usize> {
+    buf: [Option<T>; N],
+    read: usize,
+    len: usize,
+}
+
+impl<T: Copy, const N: usize> ArrayQueue<T, N> {
+    pub const fn new() -> Self {
+        Self {
+            buf: [None; N],
+            read: 0,
+            len: 0,
+        }
+    }
+
+    /// Append `value`, handing it back if the queue is full
+    pub fn push(&mut self, value: T) -> Result<(), T> {
+        if self.len == N {
+            return Err(value);
+        }
+        self.buf[(self.read + self.len) % N] = Some(value);
+        self.len += 1;
+        Ok(())
+    }
+
+    pub fn pop(&mut self) -> Option<T> {
+        if self.len == 0 {
+            return None;
+        }
+        let value = self.buf[self.read].take();
+        self.read = (self.read + 1) % N;
+        self.len -= 1;
+        value
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+impl<T: Copy, const N: usize> Default for ArrayQueue<T, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
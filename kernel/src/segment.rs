@@ -0,0 +1,128 @@
+//! GDT and TSS owned by the kernel
+//!
+//! The GDT inherited from UEFI has no user segments, so the kernel loads its
+//! own. The order of the entries is fixed by `SYSCALL`/`SYSRET`: kernel code
+//! followed by kernel data, then user data followed by user code.
+
+use core::{
+    arch::asm,
+    cell::UnsafeCell,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use spin::Once;
+
+pub const KERNEL_CS: u16 = 0x08;
+pub const KERNEL_SS: u16 = 0x10;
+/// Base of the user selectors in `IA32_STAR`; `SYSRET` adds 8 for SS and
+/// 16 for CS
+pub const USER_BASE: u16 = 0x10 | 3;
+pub const USER_SS: u16 = 0x18 | 3;
+pub const USER_CS: u16 = 0x20 | 3;
+const TSS_SELECTOR: u16 = 0x28;
+
+const KERNEL_CODE: u64 = 0x00af_9a00_0000_ffff;
+const KERNEL_DATA: u64 = 0x00cf_9200_0000_ffff;
+const USER_DATA: u64 = 0x00cf_f200_0000_ffff;
+const USER_CODE: u64 = 0x00af_fa00_0000_ffff;
+/// Present, available 64-bit TSS
+const TSS_TYPE: u64 = 0x89;
+
+#[repr(C, packed)]
+struct TaskStateSegment {
+    reserved0: u32,
+    /// Stacks loaded when entering ring 0-2 from a less privileged ring
+    rsp: [u64; 3],
+    reserved1: u64,
+    ist: [u64; 7],
+    reserved2: u64,
+    reserved3: u16,
+    io_map_base: u16,
+}
+
+struct Tss(UnsafeCell<TaskStateSegment>);
+
+// CPU は 1 つだけで、書き換えは割り込み禁止中のタスク切り替えでのみ行う
+unsafe impl Sync for Tss {}
+
+static TSS: Tss = Tss(UnsafeCell::new(TaskStateSegment {
+    reserved0: 0,
+    rsp: [0; 3],
+    reserved1: 0,
+    ist: [0; 7],
+    reserved2: 0,
+    reserved3: 0,
+    // I/O 許可ビットマップなし
+    io_map_base: size_of::<TaskStateSegment>() as u16,
+}));
+
+static GDT: Once<[u64; 7]> = Once::new();
+
+/// Kernel stack of the running task, read by the `SYSCALL` entry
+pub static KERNEL_STACK_TOP: AtomicU64 = AtomicU64::new(0);
+
+#[repr(C, packed)]
+struct GdtPointer {
+    limit: u16,
+    base: u64,
+}
+
+fn tss_descriptor(base: u64) -> [u64; 2] {
+    let limit = size_of::<TaskStateSegment>() as u64 - 1;
+    let low = limit | (base & 0xff_ffff) << 16 | TSS_TYPE << 40 | ((base >> 24) & 0xff) << 56;
+    [low, base >> 32]
+}
+
+/// Load the GDT and TSS and reload every segment register
+///
+/// Must run before [`interrupt::init`](crate::interrupt::init), which takes
+/// the code selector from CS.
+pub fn init() {
+    let gdt = GDT.call_once(|| {
+        let [tss_low, tss_high] = tss_descriptor(TSS.0.get() as u64);
+        [
+            0,
+            KERNEL_CODE,
+            KERNEL_DATA,
+            USER_DATA,
+            USER_CODE,
+            tss_low,
+            tss_high,
+        ]
+    });
+    let pointer = GdtPointer {
+        limit: (size_of::<[u64; 7]>() - 1) as u16,
+        base: gdt.as_ptr() as u64,
+    };
+    unsafe {
+        asm!("lgdt [{}]", in(reg) &pointer, options(readonly, nostack));
+        asm!(
+            "mov ds, {ss:x}",
+            "mov es, {ss:x}",
+            "mov ss, {ss:x}",
+            "mov fs, {null:x}",
+            "mov gs, {null:x}",
+            // CS は far return でしか読み込み直せない
+            "push {cs}",
+            "lea {tmp}, [rip + 2f]",
+            "push {tmp}",
+            "retfq",
+            "2:",
+            ss = in(reg) KERNEL_SS as u64,
+            null = in(reg) 0u64,
+            cs = in(reg) KERNEL_CS as u64,
+            tmp = lateout(reg) _,
+        );
+        asm!("ltr {0:x}", in(reg) TSS_SELECTOR, options(nomem, nostack));
+    }
+}
+
+/// Use `top` as the stack for interrupts and system calls from ring 3
+///
+/// Called with interrupts disabled whenever a task with its own kernel stack
+/// is switched in.
+pub fn set_kernel_stack(top: u64) {
+    let rsp0 = unsafe { &raw mut (*TSS.0.get()).rsp[0] };
+    unsafe { rsp0.write_unaligned(top) };
+    KERNEL_STACK_TOP.store(top, Ordering::Relaxed);
+}
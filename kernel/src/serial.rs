@@ -0,0 +1,101 @@
+//! 16550 UART on COM1, polled
+
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use spin::Mutex;
+
+use crate::x86;
+
+const COM1: u16 = 0x3f8;
+
+// レジスタ (DLAB=0 / DLAB=1)
+const DATA: u16 = 0;
+const INTERRUPT_ENABLE: u16 = 1;
+const DIVISOR_LOW: u16 = 0;
+const DIVISOR_HIGH: u16 = 1;
+const FIFO_CONTROL: u16 = 2;
+const LINE_CONTROL: u16 = 3;
+const MODEM_CONTROL: u16 = 4;
+const LINE_STATUS: u16 = 5;
+const SCRATCH: u16 = 7;
+
+const LINE_8N1: u8 = 0x03;
+const LINE_DLAB: u8 = 1 << 7;
+/// Enable and clear both FIFOs, 14-byte receive threshold
+const FIFO_ENABLE: u8 = 0xc7;
+/// DTR, RTS and OUT2
+const MODEM_READY: u8 = 0x0b;
+const MODEM_LOOPBACK: u8 = 1 << 4;
+const STATUS_DATA_READY: u8 = 1 << 0;
+const STATUS_TRANSMIT_EMPTY: u8 = 1 << 5;
+
+/// 115200 / `DIVISOR` baud
+const DIVISOR: u16 = 1;
+/// Polling iterations before a byte is dropped
+const TIMEOUT: usize = 100_000;
+
+static PRESENT: AtomicBool = AtomicBool::new(false);
+/// Keeps bytes of concurrent writers together
+static WRITE_LOCK: Mutex<()> = Mutex::new(());
+
+/// Program COM1 for 115200 8N1; returns whether a UART answered
+pub fn init() -> bool {
+    let port = |offset| COM1 + offset;
+    // スクラッチレジスタが読み書きできなければ UART はない
+    x86::io_out8(port(SCRATCH), 0x5a);
+    if x86::io_in8(port(SCRATCH)) != 0x5a {
+        return false;
+    }
+
+    x86::io_out8(port(INTERRUPT_ENABLE), 0);
+    x86::io_out8(port(LINE_CONTROL), LINE_DLAB);
+    x86::io_out8(port(DIVISOR_LOW), DIVISOR as u8);
+    x86::io_out8(port(DIVISOR_HIGH), (DIVISOR >> 8) as u8);
+    x86::io_out8(port(LINE_CONTROL), LINE_8N1);
+    x86::io_out8(port(FIFO_CONTROL), FIFO_ENABLE);
+
+    // ループバックで送ったバイトが戻ってくるか確かめる
+    x86::io_out8(port(MODEM_CONTROL), MODEM_READY | MODEM_LOOPBACK);
+    x86::io_out8(port(DATA), 0xae);
+    let echoed = x86::io_in8(port(DATA)) == 0xae;
+    x86::io_out8(port(MODEM_CONTROL), MODEM_READY);
+
+    PRESENT.store(echoed, Ordering::Relaxed);
+    echoed
+}
+
+pub fn is_present() -> bool {
+    PRESENT.load(Ordering::Relaxed)
+}
+
+fn write_byte(byte: u8) {
+    for _ in 0..TIMEOUT {
+        if x86::io_in8(COM1 + LINE_STATUS) & STATUS_TRANSMIT_EMPTY != 0 {
+            x86::io_out8(COM1 + DATA, byte);
+            return;
+        }
+        core::hint::spin_loop();
+    }
+}
+
+/// Send `bytes`, turning `\n` into `\r\n`
+pub fn write(bytes: &[u8]) {
+    if !is_present() {
+        return;
+    }
+    let _guard = WRITE_LOCK.lock();
+    for &byte in bytes {
+        if byte == b'\n' {
+            write_byte(b'\r');
+        }
+        write_byte(byte);
+    }
+}
+
+/// The next received byte, if any
+pub fn read_byte() -> Option<u8> {
+    if !is_present() || x86::io_in8(COM1 + LINE_STATUS) & STATUS_DATA_READY == 0 {
+        return None;
+    }
+    Some(x86::io_in8(COM1 + DATA))
+}
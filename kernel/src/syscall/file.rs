@@ -0,0 +1,116 @@
+//! File descriptor system calls
+
+use alloc::vec;
+
+use common::syscall::{DirEntry, Error, SeekFrom, open_flag};
+
+use super::{Result, to_usize};
+use crate::{
+    user,
+    vfs::{self, NodeKind, VfsError, fd, path::MAX_PATH},
+};
+
+/// Bytes moved per `read`/`write`; larger requests return short counts
+const IO_CHUNK: usize = 64 * 1024;
+
+impl From<VfsError> for Error {
+    fn from(error: VfsError) -> Self {
+        match error {
+            VfsError::NotFound => Self::NotFound,
+            VfsError::NotADirectory => Self::NotADirectory,
+            VfsError::IsADirectory => Self::IsADirectory,
+            VfsError::AlreadyExists => Self::AlreadyExists,
+            VfsError::DirectoryNotEmpty => Self::DirectoryNotEmpty,
+            VfsError::InvalidPath => Self::InvalidPath,
+            VfsError::ReadOnly => Self::ReadOnly,
+            VfsError::NoSpace => Self::NoSpace,
+            VfsError::FileTooLarge => Self::FileTooLarge,
+            VfsError::Busy => Self::Busy,
+            VfsError::BadDescriptor => Self::BadDescriptor,
+            VfsError::TooManyOpenFiles => Self::TooManyOpenFiles,
+            VfsError::Unsupported => Self::Unsupported,
+            VfsError::PermissionDenied => Self::PermissionDenied,
+            VfsError::InvalidArgument => Self::InvalidArgument,
+            VfsError::Io => Self::Io,
+            VfsError::BrokenPipe => Self::BrokenPipe,
+            VfsError::Interrupted => Self::Interrupted,
+        }
+    }
+}
+
+fn descriptor(arg: u64) -> Result<fd::Fd> {
+    to_usize(arg, fd::MAX_FILES - 1).map_err(|_| Error::BadDescriptor)
+}
+
+pub fn read([fd, buf, len, ..]: [u64; 6]) -> Result<u64> {
+    let fd = descriptor(fd)?;
+    let len = usize::try_from(len).unwrap_or(usize::MAX).min(IO_CHUNK);
+    // 読んだ後で捨てることにならないよう、先に範囲を確かめる
+    if !user::is_user_range(buf, len) {
+        return Err(Error::BadAddress);
+    }
+    let mut data = vec![0; len];
+    let read = fd::read(fd, &mut data)?;
+    user::copy_to_user(buf, &data[..read])?;
+    Ok(read as u64)
+}
+
+pub fn write([fd, buf, len, ..]: [u64; 6]) -> Result<u64> {
+    let fd = descriptor(fd)?;
+    let len = usize::try_from(len).unwrap_or(usize::MAX).min(IO_CHUNK);
+    let mut data = vec![0; len];
+    user::copy_from_user(&mut data, buf)?;
+    Ok(fd::write(fd, &data)? as u64)
+}
+
+pub fn open([path, path_len, flags, ..]: [u64; 6]) -> Result<u64> {
+    let flags = u32::try_from(flags)
+        .ok()
+        .filter(|flags| flags & !open_flag::ALL == 0)
+        .ok_or(Error::InvalidArgument)?;
+    let path = user::read_string(path, to_usize(path_len, MAX_PATH)?, MAX_PATH)?;
+    Ok(fd::open(&path, flags)? as u64)
+}
+
+pub fn close([fd, ..]: [u64; 6]) -> Result<u64> {
+    fd::close(descriptor(fd)?)?;
+    Ok(0)
+}
+
+pub fn seek([fd, offset, whence, ..]: [u64; 6]) -> Result<u64> {
+    let fd = descriptor(fd)?;
+    let target = SeekFrom::from_raw(offset, whence).ok_or(Error::InvalidArgument)?;
+    Ok(fd::seek(fd, target)?)
+}
+
+pub fn unlink([path, path_len, ..]: [u64; 6]) -> Result<u64> {
+    let path = user::read_string(path, to_usize(path_len, MAX_PATH)?, MAX_PATH)?;
+    vfs::remove(&path)?;
+    Ok(0)
+}
+
+pub fn read_dir([fd, entry, ..]: [u64; 6]) -> Result<u64> {
+    let fd = descriptor(fd)?;
+    if !user::is_user_range(entry, size_of::<DirEntry>()) {
+        return Err(Error::BadAddress);
+    }
+    let Some(next) = fd::read_dir(fd)? else {
+        return Ok(0);
+    };
+    let mut name_len = next.name.len().min(DirEntry::MAX_NAME);
+    while !next.name.is_char_boundary(name_len) {
+        name_len -= 1;
+    }
+    let mut result = DirEntry {
+        kind: match next.kind {
+            NodeKind::File => DirEntry::FILE,
+            NodeKind::Directory => DirEntry::DIRECTORY,
+            NodeKind::CharDevice => DirEntry::CHAR_DEVICE,
+        },
+        name_len: name_len as u32,
+        ..DirEntry::EMPTY
+    };
+    result.name[..name_len].copy_from_slice(&next.name.as_bytes()[..name_len]);
+    user::write_value(entry, &result)?;
+    Ok(1)
+}
@@ -0,0 +1,189 @@
+//! `SYSCALL` entry and the system call table
+//!
+//! The entry stub switches to the task's kernel stack, saves the argument
+//! registers as a [`SyscallFrame`] and calls [`syscall_dispatch`] with
+//! interrupts enabled, so a system call can block like any kernel code. The
+//! ABI itself lives in [`common::syscall`].
+mod file;
+mod window;
+
+use core::{arch::global_asm, sync::atomic::AtomicU64};
+
+use common::syscall::{self as abi, Error, number};
+
+use crate::{apic::timer, paging::PagingError, segment, task, user, x86};
+
+type Result<T> = core::result::Result<T, Error>;
+type Handler = fn([u64; 6]) -> Result<u64>;
+
+const IA32_EFER: u32 = 0xc000_0080;
+const IA32_STAR: u32 = 0xc000_0081;
+const IA32_LSTAR: u32 = 0xc000_0082;
+const IA32_FMASK: u32 = 0xc000_0084;
+const EFER_SCE: u64 = 1 << 0;
+
+const RFLAGS_TF: u64 = 1 << 8;
+const RFLAGS_IF: u64 = 1 << 9;
+const RFLAGS_DF: u64 = 1 << 10;
+const RFLAGS_IOPL: u64 = 3 << 12;
+const RFLAGS_AC: u64 = 1 << 18;
+
+/// Registers saved by the entry stub, lowest address first
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+struct SyscallFrame {
+    /// System call number on entry, result on return
+    rax: u64,
+    rdi: u64,
+    rsi: u64,
+    rdx: u64,
+    r10: u64,
+    r8: u64,
+    r9: u64,
+    /// User RFLAGS, saved by `SYSCALL`
+    r11: u64,
+    /// User RIP, saved by `SYSCALL`
+    rcx: u64,
+    rsp: u64,
+}
+
+/// User RSP between `SYSCALL` and the push onto the kernel stack
+// CPU は 1 つで、積み終えるまで割り込みは FMASK で止まっている
+static USER_RSP: AtomicU64 = AtomicU64::new(0);
+
+unsafe extern "C" {
+    fn syscall_entry();
+}
+
+global_asm!(
+    r#"
+    .section .text
+    .global syscall_entry
+syscall_entry:
+    movq %rsp, {user_rsp}(%rip)
+    movq {kernel_stack}(%rip), %rsp
+    pushq {user_rsp}(%rip)
+    pushq %rcx
+    pushq %r11
+    pushq %r9
+    pushq %r8
+    pushq %r10
+    pushq %rdx
+    pushq %rsi
+    pushq %rdi
+    pushq %rax
+    movq %rsp, %rdi
+    sti
+    call {dispatch}
+    cli
+    popq %rax
+    popq %rdi
+    popq %rsi
+    popq %rdx
+    popq %r10
+    popq %r8
+    popq %r9
+    popq %r11
+    popq %rcx
+    popq %rsp
+    sysretq
+"#,
+    user_rsp = sym USER_RSP,
+    kernel_stack = sym segment::KERNEL_STACK_TOP,
+    dispatch = sym syscall_dispatch,
+    options(att_syntax)
+);
+
+/// Enable `SYSCALL`/`SYSRET` and point them at the entry stub
+pub fn init() {
+    let star = (segment::USER_BASE as u64) << 48 | (segment::KERNEL_CS as u64) << 32;
+    unsafe {
+        x86::write_msr(IA32_STAR, star);
+        x86::write_msr(IA32_LSTAR, syscall_entry as *const () as u64);
+        x86::write_msr(IA32_FMASK, RFLAGS_TF | RFLAGS_IF | RFLAGS_DF | RFLAGS_AC);
+        x86::write_msr(IA32_EFER, x86::read_msr(IA32_EFER) | EFER_SCE);
+    }
+}
+
+/// Longer sleeps would overflow the tick count
+const MAX_SLEEP_MS: u64 = u32::MAX as u64;
+
+const TABLE_SIZE: usize = number::READ_DIR as usize + 1;
+
+/// Handlers indexed by system call number
+static TABLE: [Handler; TABLE_SIZE] = {
+    let mut table = [no_syscall as Handler; TABLE_SIZE];
+    table[number::EXIT as usize] = exit;
+    table[number::READ as usize] = file::read;
+    table[number::WRITE as usize] = file::write;
+    table[number::OPEN as usize] = file::open;
+    table[number::CLOSE as usize] = file::close;
+    table[number::MMAP as usize] = mmap;
+    table[number::SLEEP as usize] = sleep;
+    table[number::GET_TIME as usize] = get_time;
+    table[number::OPEN_WINDOW as usize] = window::open;
+    table[number::CLOSE_WINDOW as usize] = window::close;
+    table[number::WINDOW_FILL_RECT as usize] = window::fill_rect;
+    table[number::WINDOW_WRITE_STRING as usize] = window::write_string;
+    table[number::WINDOW_READ_EVENT as usize] = window::read_event;
+    table[number::SEEK as usize] = file::seek;
+    table[number::UNLINK as usize] = file::unlink;
+    table[number::READ_DIR as usize] = file::read_dir;
+    table
+};
+
+extern "sysv64" fn syscall_dispatch(frame: &mut SyscallFrame) {
+    // SYSRET は非カノニカルな RIP で ring 0 のまま #GP を起こす
+    if !user::is_user_range(frame.rcx, 0) {
+        user::exit(user::EXIT_EXCEPTION);
+    }
+    let args = [
+        frame.rdi, frame.rsi, frame.rdx, frame.r10, frame.r8, frame.r9,
+    ];
+    let result = TABLE
+        .get(frame.rax as usize)
+        .map_or(Err(Error::NoSyscall), |handler| handler(args));
+    frame.rax = abi::encode(result);
+    user::exit_if_killed();
+    frame.r11 = (frame.r11 & !RFLAGS_IOPL) | RFLAGS_IF;
+}
+
+fn no_syscall(_: [u64; 6]) -> Result<u64> {
+    Err(Error::NoSyscall)
+}
+
+impl From<PagingError> for Error {
+    fn from(error: PagingError) -> Self {
+        match error {
+            PagingError::InvalidRange => Self::InvalidArgument,
+            PagingError::OutOfMemory => Self::OutOfMemory,
+            _ => Self::Unsupported,
+        }
+    }
+}
+
+/// Convert an argument to `usize`, rejecting values above `max`
+fn to_usize(arg: u64, max: usize) -> Result<usize> {
+    usize::try_from(arg)
+        .ok()
+        .filter(|&value| value <= max)
+        .ok_or(Error::InvalidArgument)
+}
+
+fn exit([code, ..]: [u64; 6]) -> Result<u64> {
+    user::exit(code as i32)
+}
+
+fn mmap([len, ..]: [u64; 6]) -> Result<u64> {
+    let space = task::address_space().ok_or(Error::Unsupported)?;
+    Ok(space.allocate(len)?)
+}
+
+fn sleep([ms, ..]: [u64; 6]) -> Result<u64> {
+    task::sleep_ms(ms.min(MAX_SLEEP_MS));
+    Ok(0)
+}
+
+fn get_time(_: [u64; 6]) -> Result<u64> {
+    Ok(timer::uptime_ms())
+}
@@ -0,0 +1,164 @@
+//! Window system calls
+//!
+//! Coordinates are relative to the client area, and drawing is clipped to
+//! it. A task may only touch the windows it opened.
+
+use common::syscall::{Error, Event, event_flag};
+
+use super::{Result, to_usize};
+use crate::{
+    desktop::{self, Desktop},
+    font::{self, FONT_HEIGHT, FONT_WIDTH},
+    graphics::{PixelColor, PixelWriter, Rect},
+    layer::{Layer, LayerId},
+    message::{Message, WindowEvent},
+    task, user, window,
+};
+
+const MAX_TITLE: usize = 64;
+const MAX_TEXT: usize = 1024;
+
+fn color(rgb: u64) -> PixelColor {
+    PixelColor::new((rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8)
+}
+
+fn coordinate(arg: u64) -> Result<i32> {
+    i32::try_from(arg as i64).map_err(|_| Error::InvalidArgument)
+}
+
+/// The layer of `id` if the current task owns it, with its client area
+fn owned_layer(desktop: &mut Desktop, id: u64) -> Result<(&mut Layer, Rect)> {
+    let id = LayerId::try_from(id).map_err(|_| Error::BadDescriptor)?;
+    if desktop.window_owner(id) != Some(task::current_id()) {
+        return Err(Error::BadDescriptor);
+    }
+    let layer = desktop.layers().layer_mut(id).ok_or(Error::BadDescriptor)?;
+    let area = window::client_area(layer.width(), layer.height());
+    Ok((layer, area))
+}
+
+/// Run `f` on a window of the current task, then have the screen redrawn
+fn draw(id: u64, f: impl FnOnce(&mut Layer, Rect) -> Result<()>) -> Result<u64> {
+    desktop::with(|desktop| {
+        let (layer, area) = owned_layer(desktop, id)?;
+        f(layer, area)
+    })
+    .ok_or(Error::Unsupported)??;
+    desktop::request_redraw();
+    Ok(0)
+}
+
+pub fn open([width, height, x, y, title, title_len]: [u64; 6]) -> Result<u64> {
+    let title = user::read_string(title, to_usize(title_len, MAX_TITLE)?, MAX_TITLE)?;
+    let (x, y) = (coordinate(x)?, coordinate(y)?);
+    let owner = task::current_id();
+    let id = desktop::with(|desktop| {
+        let (screen_width, screen_height) = desktop.layers().screen_size();
+        let width = to_usize(width, screen_width)? + 2 * window::BORDER;
+        let height = to_usize(height, screen_height)? + window::TITLE_BAR_HEIGHT + window::BORDER;
+        let id = desktop.new_window(width, height, &title, owner);
+        desktop.layers().move_to(id, x, y);
+        Ok::<_, Error>(id)
+    })
+    .ok_or(Error::Unsupported)??;
+    desktop::request_redraw();
+    Ok(id as u64)
+}
+
+pub fn close([id, ..]: [u64; 6]) -> Result<u64> {
+    desktop::with(|desktop| {
+        let id = LayerId::try_from(id).map_err(|_| Error::BadDescriptor)?;
+        if desktop.window_owner(id) != Some(task::current_id()) {
+            return Err(Error::BadDescriptor);
+        }
+        desktop.close_window(id);
+        Ok(())
+    })
+    .ok_or(Error::Unsupported)??;
+    desktop::request_redraw();
+    Ok(0)
+}
+
+pub fn fill_rect([id, x, y, width, height, rgb]: [u64; 6]) -> Result<u64> {
+    let rect = Rect::new(
+        coordinate(x)?,
+        coordinate(y)?,
+        coordinate(width)?,
+        coordinate(height)?,
+    );
+    draw(id, |layer, area| {
+        let rect = rect.offset(area.x, area.y).intersection(&area);
+        if !rect.is_empty() {
+            layer.fill_rectangle(
+                rect.x as usize,
+                rect.y as usize,
+                rect.width as usize,
+                rect.height as usize,
+                color(rgb),
+            );
+        }
+        Ok(())
+    })
+}
+
+pub fn write_string([id, x, y, rgb, text, text_len]: [u64; 6]) -> Result<u64> {
+    let text = user::read_string(text, to_usize(text_len, MAX_TEXT)?, MAX_TEXT)?;
+    let (x, y) = (coordinate(x)?, coordinate(y)?);
+    draw(id, |layer, area| {
+        for (i, c) in text.bytes().enumerate() {
+            let glyph = Rect::new(
+                area.x + x + (i * FONT_WIDTH) as i32,
+                area.y + y,
+                FONT_WIDTH as i32,
+                FONT_HEIGHT as i32,
+            );
+            // 一部でもはみ出す文字は描かない
+            if glyph.intersection(&area) == glyph {
+                font::write_ascii(layer, glyph.x as usize, glyph.y as usize, c, color(rgb));
+            }
+        }
+        Ok(())
+    })
+}
+
+fn to_event(message: Message) -> Option<Event> {
+    let (kind, window, value) = match message {
+        Message::Window(WindowEvent::Close(id)) => (Event::CLOSE, id, 0),
+        Message::Window(WindowEvent::Activated(id)) => (Event::ACTIVATED, id, 0),
+        Message::Window(WindowEvent::Deactivated(id)) => (Event::DEACTIVATED, id, 0),
+        Message::Key(key) if key.pressed && key.ascii != 0 => {
+            // キーはアクティブなウィンドウの持ち主にだけ届く
+            let id = desktop::with(|d| d.active_window()).flatten()?;
+            (Event::KEY, id, key.ascii as u64)
+        }
+        _ => return None,
+    };
+    Some(Event {
+        kind,
+        window,
+        value,
+    })
+}
+
+pub fn read_event([event, flags, ..]: [u64; 6]) -> Result<u64> {
+    if flags & !event_flag::NONBLOCK != 0 {
+        return Err(Error::InvalidArgument);
+    }
+    if !user::is_user_range(event, size_of::<Event>()) {
+        return Err(Error::BadAddress);
+    }
+    loop {
+        let message = if flags & event_flag::NONBLOCK != 0 {
+            match task::receive_message() {
+                Some(message) => message,
+                None => return Ok(0),
+            }
+        } else {
+            task::wait_message()
+        };
+        if let Some(e) = to_event(message) {
+            user::write_value(event, &e)?;
+            return Ok(1);
+        }
+    }
+}
@@ -0,0 +1,75 @@
+//! Kernel stack switching
+//!
+//! A suspended task's stack holds its callee-saved registers and RFLAGS,
+//! topped by the address to resume at. Tasks preempted by the timer also
+//! keep their full [`InterruptFrame`](crate::interrupt::InterruptFrame)
+//! further up the same stack, which the interrupt epilogue restores once the
+//! task is switched back in.
+
+use core::arch::global_asm;
+
+unsafe extern "sysv64" {
+    /// Save the current context, storing its stack pointer in `*current_rsp`,
+    /// and resume the one suspended at `next_rsp`
+    pub fn switch_context(current_rsp: *mut u64, next_rsp: u64);
+    fn task_trampoline();
+}
+
+global_asm!(
+    r#"
+    .section .text
+    .global switch_context
+switch_context:
+    pushq %rbp
+    pushq %rbx
+    pushq %r12
+    pushq %r13
+    pushq %r14
+    pushq %r15
+    pushfq
+    movq %rsp, (%rdi)
+    movq %rsi, %rsp
+    popfq
+    popq %r15
+    popq %r14
+    popq %r13
+    popq %r12
+    popq %rbx
+    popq %rbp
+    ret
+
+    .global task_trampoline
+task_trampoline:
+    movq %r13, %rdi
+    sti
+    call *%r12
+    call {exit}
+    ud2
+"#,
+    exit = sym super::exit_current,
+    options(att_syntax)
+);
+
+/// Interrupts disabled; `task_trampoline` enables them
+const INITIAL_RFLAGS: u64 = 0x2;
+
+/// Lay out a fresh stack so that switching to it calls `entry(arg)`
+///
+/// Returns the stack pointer to pass to [`switch_context`].
+pub fn prepare_stack(stack: &mut [u64], entry: extern "sysv64" fn(u64), arg: u64) -> u64 {
+    let top = stack.as_mut_ptr_range().end as u64 & !0xf;
+    let frame = [
+        INITIAL_RFLAGS,
+        0,                         // r15
+        0,                         // r14
+        arg,                       // r13
+        entry as *const () as u64, // r12
+        0,                         // rbx
+        0,                         // rbp
+        task_trampoline as *const () as u64,
+    ];
+    // ret 後の rsp が 16 バイト境界になるように積む
+    let rsp = top - (frame.len() * size_of::<u64>()) as u64;
+    unsafe { core::ptr::copy_nonoverlapping(frame.as_ptr(), rsp as *mut u64, frame.len()) };
+    rsp
+}
@@ -0,0 +1,562 @@
+//! Kernel tasks with priority round-robin scheduling
+//!
+//! Tasks switch cooperatively through [`yield_now`], [`sleep`] and
+//! [`block`], and the timer tick preempts a task whose time slice ran out.
+//! Preemption happens at the end of interrupt dispatch, after the EOI, so
+//! the interrupted task's full register frame stays on its own stack.
+mod context;
+
+use alloc::{collections::VecDeque, sync::Arc, vec, vec::Vec};
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+use spin::Mutex;
+
+use crate::{
+    apic::timer,
+    message::Message,
+    paging::AddressSpace,
+    queue::ArrayQueue,
+    segment,
+    vfs::fd::{FileTable, SharedFileTable},
+    x86,
+};
+
+pub type TaskId = u64;
+
+/// Number of priority levels; a higher level always runs first
+pub const PRIORITY_LEVELS: usize = 4;
+/// Reserved for the idle task
+pub const PRIORITY_IDLE: u8 = 0;
+pub const PRIORITY_NORMAL: u8 = 1;
+
+/// Ticks a task runs before others of the same priority get the CPU
+const TIME_SLICE: u32 = 2;
+const STACK_SIZE: usize = 64 * 1024;
+const MAILBOX_SIZE: usize = 128;
+/// Status of a task ended by [`kill`], as for `SIGKILL` on Unix
+pub const EXIT_KILLED: i32 = 137;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TaskState {
+    Ready,
+    Running,
+    /// Waiting for a tick count; [`wake`] also ends the sleep early
+    Sleeping,
+    /// Waiting for [`wake`]
+    Blocked,
+    Finished,
+}
+
+struct Task {
+    id: TaskId,
+    priority: u8,
+    state: TaskState,
+    /// Saved stack pointer while the task is not running
+    rsp: u64,
+    /// `None` for the boot task, which runs on the stack UEFI handed over
+    stack: Option<Vec<u64>>,
+    wake_at: u64,
+    /// A [`wake`] arrived while the task was still running
+    wake_pending: bool,
+    mailbox: ArrayQueue<Message, MAILBOX_SIZE>,
+    files: SharedFileTable,
+    /// User page tables; `None` for kernel-only tasks
+    address_space: Option<Arc<AddressSpace>>,
+    /// Status passed to [`exit`]
+    exit_code: i32,
+    /// Task that spawned this one; it is sent [`Message::TaskExit`]
+    parent: Option<TaskId>,
+    /// [`kill`] was called; the task ends at its next safe point
+    killed: bool,
+}
+
+impl Task {
+    fn kernel_stack_top(&self) -> Option<u64> {
+        let stack = self.stack.as_ref()?;
+        Some(stack.as_ptr_range().end as u64 & !0xf)
+    }
+}
+
+/// What [`schedule`] needs to resume the next task
+struct SwitchTarget {
+    current_rsp: *mut u64,
+    next_rsp: u64,
+    kernel_stack_top: Option<u64>,
+    cr3: u64,
+}
+
+struct Scheduler {
+    tasks: Vec<Task>,
+    ready: [VecDeque<TaskId>; PRIORITY_LEVELS],
+    current: TaskId,
+    next_id: TaskId,
+    slice_remaining: u32,
+    /// Page tables of tasks without an address space
+    kernel_cr3: u64,
+}
+
+static SCHEDULER: Mutex<Option<Scheduler>> = Mutex::new(None);
+/// The task that was running when [`init`] was called (the kernel event loop)
+static MAIN_TASK: AtomicU64 = AtomicU64::new(0);
+/// Set from interrupt context when the running task should give up the CPU
+static NEED_RESCHED: AtomicBool = AtomicBool::new(false);
+
+impl Scheduler {
+    fn task(&self, id: TaskId) -> Option<&Task> {
+        self.tasks.iter().find(|t| t.id == id)
+    }
+
+    fn task_mut(&mut self, id: TaskId) -> Option<&mut Task> {
+        self.tasks.iter_mut().find(|t| t.id == id)
+    }
+
+    fn add(
+        &mut self,
+        priority: u8,
+        stack: Option<Vec<u64>>,
+        rsp: u64,
+        files: SharedFileTable,
+    ) -> TaskId {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.tasks.push(Task {
+            id,
+            priority,
+            state: TaskState::Ready,
+            rsp,
+            stack,
+            wake_at: 0,
+            wake_pending: false,
+            mailbox: ArrayQueue::new(),
+            files,
+            address_space: None,
+            exit_code: 0,
+            parent: None,
+            killed: false,
+        });
+        id
+    }
+
+    fn make_ready(&mut self, id: TaskId) {
+        let current_priority = self.task(self.current).map_or(0, |t| t.priority);
+        let Some(task) = self.task_mut(id) else {
+            return;
+        };
+        task.state = TaskState::Ready;
+        let priority = task.priority;
+        self.ready[priority as usize].push_back(id);
+        if priority > current_priority {
+            NEED_RESCHED.store(true, Ordering::Relaxed);
+        }
+    }
+
+    fn wake(&mut self, id: TaskId) {
+        let Some(task) = self.task_mut(id) else {
+            return;
+        };
+        match task.state {
+            TaskState::Blocked | TaskState::Sleeping => self.make_ready(id),
+            TaskState::Running | TaskState::Ready => task.wake_pending = true,
+            TaskState::Finished => {}
+        }
+    }
+
+    fn pop_ready(&mut self) -> Option<TaskId> {
+        self.ready
+            .iter_mut()
+            .rev()
+            .find_map(|queue| queue.pop_front())
+    }
+
+    fn highest_ready_priority(&self) -> Option<usize> {
+        self.ready.iter().rposition(|queue| !queue.is_empty())
+    }
+
+    /// Pick the next task and return what is needed to switch to it
+    fn switch_targets(&mut self, requeue_current: bool) -> Option<SwitchTarget> {
+        // 自分のスタック上にいるタスクは解放できないので、ここで回収する
+        let current = self.current;
+        self.tasks
+            .retain(|t| t.state != TaskState::Finished || t.id == current);
+
+        if requeue_current {
+            let current_task = self.task(current)?;
+            // 優先度の高いタスクがなければ同じタスクを続ける
+            if current_task.state == TaskState::Running
+                && self
+                    .highest_ready_priority()
+                    .is_none_or(|p| p < current_task.priority as usize)
+            {
+                self.slice_remaining = TIME_SLICE;
+                return None;
+            }
+            self.make_ready(current);
+        }
+        let next = self.pop_ready()?;
+        self.slice_remaining = TIME_SLICE;
+        if next == current {
+            if let Some(task) = self.task_mut(current) {
+                task.state = TaskState::Running;
+            }
+            return None;
+        }
+
+        let current_rsp = &raw mut self.task_mut(current)?.rsp;
+        let kernel_cr3 = self.kernel_cr3;
+        let next_task = self.task_mut(next)?;
+        next_task.state = TaskState::Running;
+        let target = SwitchTarget {
+            current_rsp,
+            next_rsp: next_task.rsp,
+            kernel_stack_top: next_task.kernel_stack_top(),
+            cr3: next_task
+                .address_space
+                .as_ref()
+                .map_or(kernel_cr3, |space| space.cr3()),
+        };
+        self.current = next;
+        Some(target)
+    }
+}
+
+/// Switch away from the current task; interrupts must be disabled
+fn schedule(requeue_current: bool) {
+    let targets = SCHEDULER
+        .lock()
+        .as_mut()
+        .and_then(|s| s.switch_targets(requeue_current));
+    // ロックを持ったまま切り替えると次のタスクが取れなくなる
+    if let Some(target) = targets {
+        if let Some(top) = target.kernel_stack_top {
+            segment::set_kernel_stack(top);
+        }
+        if x86::read_cr3() != target.cr3 {
+            unsafe { x86::write_cr3(target.cr3) };
+        }
+        unsafe { context::switch_context(target.current_rsp, target.next_rsp) };
+    }
+}
+
+extern "sysv64" fn idle(_: u64) {
+    loop {
+        x86::hlt();
+    }
+}
+
+/// Turn the running kernel flow into the first task and create the idle task
+pub fn init() {
+    x86::without_interrupts(|| {
+        let mut scheduler = Scheduler {
+            tasks: Vec::new(),
+            ready: core::array::from_fn(|_| VecDeque::new()),
+            current: 0,
+            next_id: 1,
+            slice_remaining: TIME_SLICE,
+            kernel_cr3: x86::read_cr3(),
+        };
+        let main = scheduler.add(PRIORITY_NORMAL, None, 0, SharedFileTable::default());
+        MAIN_TASK.store(main, Ordering::Relaxed);
+        scheduler.current = main;
+        if let Some(task) = scheduler.task_mut(main) {
+            task.state = TaskState::Running;
+        }
+
+        let mut stack = vec![0u64; STACK_SIZE / size_of::<u64>()];
+        let rsp = context::prepare_stack(&mut stack, idle, 0);
+        let idle = scheduler.add(PRIORITY_IDLE, Some(stack), rsp, SharedFileTable::default());
+        scheduler.make_ready(idle);
+        *SCHEDULER.lock() = Some(scheduler);
+    });
+}
+
+/// Start `entry(arg)` as a new task with a copy of the current task's
+/// descriptor table
+///
+/// The current task becomes its parent and gets a [`Message::TaskExit`]
+/// when it ends.
+pub fn spawn(entry: extern "sysv64" fn(u64), arg: u64, priority: u8) -> Option<TaskId> {
+    let files = files().lock().clone();
+    spawn_in(entry, arg, priority, None, files)
+}
+
+/// Like [`spawn`], but the task starts with `files` as its descriptor table
+pub fn spawn_with_files(
+    entry: extern "sysv64" fn(u64),
+    arg: u64,
+    priority: u8,
+    files: FileTable,
+) -> Option<TaskId> {
+    spawn_in(entry, arg, priority, None, files)
+}
+
+/// Like [`spawn_with_files`], but the task runs in `space`, from its very
+/// first instruction
+pub fn spawn_user(
+    entry: extern "sysv64" fn(u64),
+    arg: u64,
+    priority: u8,
+    space: Arc<AddressSpace>,
+    files: FileTable,
+) -> Option<TaskId> {
+    spawn_in(entry, arg, priority, Some(space), files)
+}
+
+fn spawn_in(
+    entry: extern "sysv64" fn(u64),
+    arg: u64,
+    priority: u8,
+    space: Option<Arc<AddressSpace>>,
+    files: FileTable,
+) -> Option<TaskId> {
+    let priority = priority.clamp(PRIORITY_NORMAL, PRIORITY_LEVELS as u8 - 1);
+    let mut stack = vec![0u64; STACK_SIZE / size_of::<u64>()];
+    let rsp = context::prepare_stack(&mut stack, entry, arg);
+    x86::without_interrupts(|| {
+        let mut guard = SCHEDULER.lock();
+        let scheduler = guard.as_mut()?;
+        let parent = scheduler.current;
+        let id = scheduler.add(priority, Some(stack), rsp, Arc::new(Mutex::new(files)));
+        if let Some(task) = scheduler.task_mut(id) {
+            task.address_space = space;
+            task.parent = Some(parent);
+        }
+        scheduler.make_ready(id);
+        Some(id)
+    })
+}
+
+/// The kernel event loop task; drivers post their messages here
+pub fn main_id() -> TaskId {
+    MAIN_TASK.load(Ordering::Relaxed)
+}
+
+pub fn current_id() -> TaskId {
+    x86::without_interrupts(|| SCHEDULER.lock().as_ref().map_or(0, |s| s.current))
+}
+
+/// Descriptor table of the current task
+pub fn files() -> SharedFileTable {
+    x86::without_interrupts(|| {
+        SCHEDULER
+            .lock()
+            .as_ref()
+            .and_then(|s| s.task(s.current))
+            .map(|t| t.files.clone())
+    })
+    .unwrap_or_default()
+}
+
+/// User address space of the current task
+pub fn address_space() -> Option<Arc<AddressSpace>> {
+    x86::without_interrupts(|| {
+        let guard = SCHEDULER.lock();
+        let scheduler = guard.as_ref()?;
+        scheduler.task(scheduler.current)?.address_space.clone()
+    })
+}
+
+/// Let other ready tasks of the same or higher priority run
+pub fn yield_now() {
+    x86::without_interrupts(|| schedule(true));
+}
+
+/// Put the current task to sleep for `ticks` timer ticks
+pub fn sleep(ticks: u64) {
+    x86::without_interrupts(|| {
+        {
+            let mut guard = SCHEDULER.lock();
+            let Some(scheduler) = guard.as_mut() else {
+                return;
+            };
+            let current = scheduler.current;
+            let Some(task) = scheduler.task_mut(current) else {
+                return;
+            };
+            task.state = TaskState::Sleeping;
+            task.wake_at = timer::ticks() + ticks.max(1);
+        }
+        schedule(false);
+    });
+}
+
+pub fn sleep_ms(ms: u64) {
+    sleep((ms * timer::TICK_HZ).div_ceil(1000));
+}
+
+/// Wait until another task or an interrupt handler calls [`wake`]
+///
+/// Returns at once if a wake-up arrived since the last `block`.
+pub fn block() {
+    x86::without_interrupts(|| {
+        {
+            let mut guard = SCHEDULER.lock();
+            let Some(scheduler) = guard.as_mut() else {
+                return;
+            };
+            let current = scheduler.current;
+            let Some(task) = scheduler.task_mut(current) else {
+                return;
+            };
+            if core::mem::take(&mut task.wake_pending) {
+                return;
+            }
+            task.state = TaskState::Blocked;
+        }
+        schedule(false);
+    });
+}
+
+/// Make a blocked or sleeping task ready; safe to call from interrupt handlers
+pub fn wake(id: TaskId) {
+    x86::without_interrupts(|| {
+        if let Some(scheduler) = SCHEDULER.lock().as_mut() {
+            scheduler.wake(id);
+        }
+    });
+}
+
+/// Post `message` to a task's mailbox and wake it; safe to call from
+/// interrupt handlers. Hands the message back if the mailbox is full.
+pub fn send_message(id: TaskId, message: Message) -> Result<(), Message> {
+    x86::without_interrupts(|| {
+        let mut guard = SCHEDULER.lock();
+        let Some(scheduler) = guard.as_mut() else {
+            return Err(message);
+        };
+        let Some(task) = scheduler.task_mut(id) else {
+            return Err(message);
+        };
+        task.mailbox.push(message)?;
+        scheduler.wake(id);
+        Ok(())
+    })
+}
+
+/// Take the oldest message of the current task without waiting
+pub fn receive_message() -> Option<Message> {
+    x86::without_interrupts(|| {
+        let mut guard = SCHEDULER.lock();
+        let scheduler = guard.as_mut()?;
+        let current = scheduler.current;
+        scheduler.task_mut(current)?.mailbox.pop()
+    })
+}
+
+pub fn mailbox_is_empty() -> bool {
+    x86::without_interrupts(|| {
+        let guard = SCHEDULER.lock();
+        guard
+            .as_ref()
+            .and_then(|s| s.task(s.current))
+            .is_none_or(|t| t.mailbox.is_empty())
+    })
+}
+
+/// Block until a message arrives for the current task
+pub fn wait_message() -> Message {
+    loop {
+        if let Some(message) = receive_message() {
+            return message;
+        }
+        // 受信確認と block の間に届いたメッセージは wake_pending で拾われる
+        block();
+    }
+}
+
+/// Ask a task to end
+///
+/// The task is woken and ends the next time it reaches a safe point: a
+/// user task when it returns to ring 3, a kernel task when it checks
+/// [`is_killed`]. Waits that can last forever check it too and fail with
+/// `Interrupted`. Returns `false` if there is no such task.
+pub fn kill(id: TaskId) -> bool {
+    x86::without_interrupts(|| {
+        let mut guard = SCHEDULER.lock();
+        let Some(scheduler) = guard.as_mut() else {
+            return false;
+        };
+        match scheduler.task_mut(id) {
+            Some(task) if task.state != TaskState::Finished => {
+                task.killed = true;
+                scheduler.wake(id);
+                true
+            }
+            _ => false,
+        }
+    })
+}
+
+/// Whether [`kill`] was called for the current task
+pub fn is_killed() -> bool {
+    x86::without_interrupts(|| {
+        let guard = SCHEDULER.lock();
+        guard
+            .as_ref()
+            .and_then(|s| s.task(s.current))
+            .is_some_and(|t| t.killed)
+    })
+}
+
+/// End the current task with status `0`
+pub extern "sysv64" fn exit_current() -> ! {
+    exit(0)
+}
+
+/// End the current task; its stack and address space are freed by a later
+/// switch
+pub fn exit(code: i32) -> ! {
+    // ファイルを閉じるのはスケジューラのロックの外で
+    files().lock().clear();
+    x86::disable_interrupts();
+    if let Some(scheduler) = SCHEDULER.lock().as_mut() {
+        let current = scheduler.current;
+        let mut parent = None;
+        if let Some(task) = scheduler.task_mut(current) {
+            task.state = TaskState::Finished;
+            task.exit_code = code;
+            parent = task.parent;
+        }
+        // 親のメールボックスが一杯なら通知は失われる
+        if let Some(parent) = parent
+            && let Some(task) = scheduler.task_mut(parent)
+            && task
+                .mailbox
+                .push(Message::TaskExit {
+                    task: current,
+                    code,
+                })
+                .is_ok()
+        {
+            scheduler.wake(parent);
+        }
+    }
+    schedule(false);
+    unreachable!("finished task was scheduled again");
+}
+
+/// Timer tick hook: wake sleepers and charge the running task's time slice
+pub fn on_tick(now: u64) {
+    let mut guard = SCHEDULER.lock();
+    let Some(scheduler) = guard.as_mut() else {
+        return;
+    };
+    for i in 0..scheduler.tasks.len() {
+        let task = &scheduler.tasks[i];
+        if task.state == TaskState::Sleeping && task.wake_at <= now {
+            let id = task.id;
+            scheduler.make_ready(id);
+        }
+    }
+
+    scheduler.slice_remaining = scheduler.slice_remaining.saturating_sub(1);
+    if scheduler.slice_remaining == 0 {
+        NEED_RESCHED.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Called at the end of interrupt dispatch with interrupts disabled
+pub fn preempt_if_needed() {
+    if NEED_RESCHED.swap(false, Ordering::Relaxed) {
+        schedule(true);
+    }
+}
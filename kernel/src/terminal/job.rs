@@ -0,0 +1,115 @@
+//! Jobs: the tasks started for one command line
+
+use alloc::{format, string::String, vec::Vec};
+
+use crate::{
+    task::{self, TaskId},
+    user,
+};
+
+pub struct Job {
+    /// Shown as `[number]`; the lowest one not in use
+    pub number: usize,
+    pub line: String,
+    /// Tasks that have not exited yet
+    pub tasks: Vec<TaskId>,
+    /// Task of the last command, whose status is the job's
+    pub last: Option<TaskId>,
+    pub status: i32,
+}
+
+impl Job {
+    pub fn is_done(&self) -> bool {
+        self.tasks.is_empty()
+    }
+
+    pub fn kill(&self) {
+        for &task in &self.tasks {
+            task::kill(task);
+        }
+    }
+}
+
+pub struct Jobs {
+    jobs: Vec<Job>,
+}
+
+impl Jobs {
+    pub const fn new() -> Self {
+        Self { jobs: Vec::new() }
+    }
+
+    /// Track the tasks of `line`; `status` stands until `last` exits
+    pub fn add(
+        &mut self,
+        line: &str,
+        tasks: Vec<TaskId>,
+        last: Option<TaskId>,
+        status: i32,
+    ) -> usize {
+        let number = (1..)
+            .find(|&n| self.jobs.iter().all(|job| job.number != n))
+            .unwrap_or(1);
+        self.jobs.push(Job {
+            number,
+            line: String::from(line),
+            tasks,
+            last,
+            status,
+        });
+        number
+    }
+
+    pub fn get(&self, number: usize) -> Option<&Job> {
+        self.jobs.iter().find(|job| job.number == number)
+    }
+
+    pub fn remove(&mut self, number: usize) -> Option<Job> {
+        let index = self.jobs.iter().position(|job| job.number == number)?;
+        Some(self.jobs.remove(index))
+    }
+
+    /// The most recently started job
+    pub fn latest(&self) -> Option<usize> {
+        self.jobs.last().map(|job| job.number)
+    }
+
+    /// The job `task` belongs to
+    pub fn find_task(&self, task: TaskId) -> Option<usize> {
+        self.jobs
+            .iter()
+            .find(|job| job.tasks.contains(&task))
+            .map(|job| job.number)
+    }
+
+    /// Record that `task` exited; returns its job's number
+    pub fn on_exit(&mut self, task: TaskId, code: i32) -> Option<usize> {
+        let job = self.jobs.iter_mut().find(|job| job.tasks.contains(&task))?;
+        job.tasks.retain(|&t| t != task);
+        if job.last == Some(task) {
+            job.status = code;
+        }
+        Some(job.number)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Job> {
+        self.jobs.iter()
+    }
+}
+
+/// Whether `status` means the task was ended by a CPU exception
+pub fn is_crash(status: i32) -> bool {
+    // CPU 例外のベクタは 32 未満
+    status != task::EXIT_KILLED
+        && (user::EXIT_EXCEPTION..user::EXIT_EXCEPTION + 32).contains(&status)
+}
+
+/// How a job with exit status `status` ended, as shown in reports
+pub fn describe(status: i32) -> String {
+    match status {
+        0 => String::from("Done"),
+        task::EXIT_KILLED => String::from("Killed"),
+        status if is_crash(status) => format!("Exception {}", status - user::EXIT_EXCEPTION),
+        status => format!("Exit {}", status),
+    }
+}
@@ -0,0 +1,206 @@
+//! Editing the command line: cursor movement, history and key bindings
+
+use alloc::{collections::VecDeque, string::String};
+
+use crate::input::{KeyEvent, modifier};
+
+/// Lines kept for recall with the up and down keys
+const HISTORY_SIZE: usize = 32;
+/// Longest line accepted; the terminal can show it on a few rows
+pub const MAX_LINE: usize = 1024;
+
+// HID キーボードの usage ID (ASCII を持たないキー)
+const KEY_HOME: u8 = 0x4a;
+const KEY_DELETE: u8 = 0x4c;
+const KEY_END: u8 = 0x4d;
+const KEY_RIGHT: u8 = 0x4f;
+const KEY_LEFT: u8 = 0x50;
+const KEY_DOWN: u8 = 0x51;
+const KEY_UP: u8 = 0x52;
+
+/// What a key press did to the line
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Edit {
+    /// Nothing to redraw
+    None,
+    /// The text or the cursor changed
+    Changed,
+    /// Enter was pressed; the line has been cleared
+    Submit(String),
+    /// Tab was pressed
+    Complete,
+    /// Ctrl-D on an empty line
+    EndOfInput,
+    /// Ctrl-C; the line has been cleared
+    Interrupt,
+}
+
+pub struct LineEditor {
+    /// Printable ASCII only
+    line: String,
+    /// Byte index into `line`
+    cursor: usize,
+    history: VecDeque<String>,
+    /// Entry shown by the up and down keys; `None` for the line being typed
+    history_index: Option<usize>,
+    /// The line being typed while browsing the history
+    draft: String,
+}
+
+impl LineEditor {
+    pub const fn new() -> Self {
+        Self {
+            line: String::new(),
+            cursor: 0,
+            history: VecDeque::new(),
+            history_index: None,
+            draft: String::new(),
+        }
+    }
+
+    pub fn line(&self) -> &str {
+        &self.line
+    }
+
+    pub fn cursor(&self) -> usize {
+        self.cursor
+    }
+
+    /// Drop the line being typed
+    pub fn clear(&mut self) {
+        self.line.clear();
+        self.cursor = 0;
+        self.history_index = None;
+    }
+
+    pub fn on_key(&mut self, key: KeyEvent) -> Edit {
+        if !key.pressed {
+            return Edit::None;
+        }
+        if key.modifiers & modifier::CONTROL != 0 {
+            return self.on_control(key.ascii);
+        }
+        match (key.ascii, key.keycode) {
+            (b'\n', _) => {
+                self.history_index = None;
+                self.cursor = 0;
+                Edit::Submit(core::mem::take(&mut self.line))
+            }
+            (b'\t', _) => Edit::Complete,
+            (0x08, _) if self.cursor > 0 => {
+                self.cursor -= 1;
+                self.line.remove(self.cursor);
+                Edit::Changed
+            }
+            (0, KEY_DELETE) if self.cursor < self.line.len() => {
+                self.line.remove(self.cursor);
+                Edit::Changed
+            }
+            (0, KEY_LEFT) if self.cursor > 0 => self.move_to(self.cursor - 1),
+            (0, KEY_RIGHT) if self.cursor < self.line.len() => self.move_to(self.cursor + 1),
+            (0, KEY_HOME) => self.move_to(0),
+            (0, KEY_END) => self.move_to(self.line.len()),
+            (0, KEY_UP) => self.recall_older(),
+            (0, KEY_DOWN) => self.recall_newer(),
+            (c @ b' '..=b'~', _) => {
+                self.insert(&[c]);
+                Edit::Changed
+            }
+            _ => Edit::None,
+        }
+    }
+
+    fn on_control(&mut self, ascii: u8) -> Edit {
+        match ascii.to_ascii_lowercase() {
+            b'a' => self.move_to(0),
+            b'e' => self.move_to(self.line.len()),
+            b'b' if self.cursor > 0 => self.move_to(self.cursor - 1),
+            b'f' if self.cursor < self.line.len() => self.move_to(self.cursor + 1),
+            b'c' => {
+                self.line.clear();
+                self.cursor = 0;
+                self.history_index = None;
+                Edit::Interrupt
+            }
+            b'd' if self.line.is_empty() => Edit::EndOfInput,
+            b'd' if self.cursor < self.line.len() => {
+                self.line.remove(self.cursor);
+                Edit::Changed
+            }
+            // カーソルより前を消す
+            b'u' => {
+                self.line.drain(..self.cursor);
+                self.cursor = 0;
+                Edit::Changed
+            }
+            b'k' => {
+                self.line.truncate(self.cursor);
+                Edit::Changed
+            }
+            _ => Edit::None,
+        }
+    }
+
+    fn move_to(&mut self, cursor: usize) -> Edit {
+        if cursor == self.cursor {
+            return Edit::None;
+        }
+        self.cursor = cursor;
+        Edit::Changed
+    }
+
+    /// Insert printable ASCII at the cursor, as far as it fits
+    pub fn insert(&mut self, text: &[u8]) {
+        for &c in text {
+            if self.line.len() >= MAX_LINE {
+                break;
+            }
+            if c == b' ' || c.is_ascii_graphic() {
+                self.line.insert(self.cursor, c as char);
+                self.cursor += 1;
+            }
+        }
+    }
+
+    /// Remember a submitted line; blank lines and repeats are skipped
+    pub fn add_history(&mut self, line: &str) {
+        if line.trim().is_empty() || self.history.back().is_some_and(|last| last == line) {
+            return;
+        }
+        if self.history.len() == HISTORY_SIZE {
+            self.history.pop_front();
+        }
+        self.history.push_back(line.into());
+    }
+
+    fn recall_older(&mut self) -> Edit {
+        let index = match self.history_index {
+            None if self.history.is_empty() => return Edit::None,
+            None => {
+                self.draft = core::mem::take(&mut self.line);
+                self.history.len() - 1
+            }
+            Some(0) => return Edit::None,
+            Some(index) => index - 1,
+        };
+        self.history_index = Some(index);
+        self.line = self.history[index].clone();
+        self.cursor = self.line.len();
+        Edit::Changed
+    }
+
+    fn recall_newer(&mut self) -> Edit {
+        let Some(index) = self.history_index else {
+            return Edit::None;
+        };
+        if index + 1 < self.history.len() {
+            self.history_index = Some(index + 1);
+            self.line = self.history[index + 1].clone();
+        } else {
+            self.history_index = None;
+            self.line = core::mem::take(&mut self.draft);
+        }
+        self.cursor = self.line.len();
+        Edit::Changed
+    }
+}
@@ -0,0 +1,495 @@
+//! Terminal window running the shell
+//!
+//! The terminal is a kernel task that owns its window and receives its key
+//! presses. Its standard descriptors write to the window and read the lines
+//! typed while a foreground job runs; commands get them unless a pipe or a
+//! redirection replaces them, so their output shows up in the window.
+//!
+//! Each command line becomes a job. The terminal waits for a foreground
+//! job before it prompts again, and reports background jobs when they end.
+mod job;
+mod line_editor;
+mod screen;
+mod shell;
+
+use alloc::{collections::VecDeque, format, string::String, sync::Arc, vec::Vec};
+use core::fmt::Write;
+
+use spin::Mutex;
+
+use crate::{
+    block::completion::Completion,
+    desktop,
+    input::KeyEvent,
+    layer::LayerId,
+    message::{Message, WindowEvent},
+    task::{self, TaskId},
+    vfs::{File, Metadata, NodeKind, Result, VfsError, fd},
+    window,
+};
+use job::{Job, Jobs};
+use line_editor::{Edit, LineEditor};
+use screen::{COLUMNS, Screen};
+use shell::{Command, Output};
+
+const PROMPT: &str = "> ";
+const TITLE: &str = "Terminal";
+/// Initial window position on the screen
+const POSITION: (i32, i32) = (40, 40);
+
+/// Lines typed for the running application
+struct Input {
+    bytes: VecDeque<u8>,
+    /// Ctrl-D was pressed; the next read that finds no bytes returns `0`
+    end: bool,
+}
+
+/// State shared with the tasks that use the terminal through a descriptor
+struct Shared {
+    screen: Mutex<Screen>,
+    input: Mutex<Input>,
+    input_ready: Completion,
+}
+
+/// The terminal's standard streams
+struct TerminalFile(Arc<Shared>);
+
+impl File for TerminalFile {
+    fn read(&self, buf: &mut [u8]) -> Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let shared = &self.0;
+        loop {
+            shared.input_ready.wait_until(true, || {
+                task::is_killed() || {
+                    let input = shared.input.lock();
+                    !input.bytes.is_empty() || input.end
+                }
+            });
+            let mut input = shared.input.lock();
+            if !input.bytes.is_empty() {
+                let len = buf.len().min(input.bytes.len());
+                for (dst, src) in buf.iter_mut().zip(input.bytes.drain(..len)) {
+                    *dst = src;
+                }
+                return Ok(len);
+            }
+            if input.end {
+                input.end = false;
+                return Ok(0);
+            }
+            drop(input);
+            if task::is_killed() {
+                return Err(VfsError::Interrupted);
+            }
+            // 同じ端末を読む他のタスクに先に取られたので、そちらに譲ってからまた待つ
+            task::yield_now();
+        }
+    }
+
+    fn write(&self, buf: &[u8]) -> Result<usize> {
+        self.0.screen.lock().write(buf);
+        Ok(buf.len())
+    }
+
+    fn metadata(&self) -> Result<Metadata> {
+        Ok(Metadata {
+            kind: NodeKind::CharDevice,
+            size: 0,
+        })
+    }
+}
+
+struct Terminal {
+    layer: LayerId,
+    shared: Arc<Shared>,
+    /// The [`TerminalFile`] given to commands
+    file: Arc<dyn File>,
+    editor: LineEditor,
+    /// Screen position where the edited line starts, after the prompt
+    line_start: (usize, usize),
+    /// Scroll count and cursor position right after the line was drawn;
+    /// anything else means another task wrote in the meantime
+    drawn: (u64, (usize, usize)),
+    jobs: Jobs,
+    /// Number of the job the terminal waits for
+    foreground: Option<usize>,
+    /// Status of the last foreground job, for `$?`
+    status: i32,
+}
+
+/// Open a terminal window in a new task
+pub fn spawn() -> Option<TaskId> {
+    task::spawn(run, 0, task::PRIORITY_NORMAL)
+}
+
+extern "sysv64" fn run(_: u64) {
+    let Some(mut terminal) = Terminal::open() else {
+        return;
+    };
+    terminal.prompt();
+    loop {
+        match task::wait_message() {
+            Message::Key(key) => terminal.on_key(key),
+            Message::Window(WindowEvent::Activated(_)) => {
+                terminal.shared.screen.lock().set_cursor_visible(true);
+            }
+            Message::Window(WindowEvent::Deactivated(_)) => {
+                terminal.shared.screen.lock().set_cursor_visible(false);
+            }
+            Message::Window(WindowEvent::Close(_)) => {
+                terminal.close();
+                return;
+            }
+            Message::TaskExit { task, code } => terminal.on_exit(task, code),
+            _ => {}
+        }
+    }
+}
+
+impl Terminal {
+    fn open() -> Option<Self> {
+        let owner = task::current_id();
+        let (width, height) = screen::client_size();
+        let layer = desktop::with(|desktop| {
+            let id = desktop.new_window(
+                width + 2 * window::BORDER,
+                height + window::TITLE_BAR_HEIGHT + window::BORDER,
+                TITLE,
+                owner,
+            );
+            desktop.layers().move_to(id, POSITION.0, POSITION.1);
+            id
+        })?;
+        let shared = Arc::new(Shared {
+            screen: Mutex::new(Screen::new(layer)),
+            input: Mutex::new(Input {
+                bytes: VecDeque::new(),
+                end: false,
+            }),
+            input_ready: Completion::new(),
+        });
+        let file: Arc<dyn File> = Arc::new(TerminalFile(shared.clone()));
+        fd::init_stdio(file.clone());
+        Some(Self {
+            layer,
+            shared,
+            file,
+            editor: LineEditor::new(),
+            line_start: (0, 0),
+            drawn: (0, (0, 0)),
+            jobs: Jobs::new(),
+            foreground: None,
+            status: 0,
+        })
+    }
+
+    fn prompt(&mut self) {
+        {
+            let mut screen = self.shared.screen.lock();
+            if screen.position().0 != 0 {
+                screen.write(b"\n");
+            }
+            screen.write(PROMPT.as_bytes());
+        }
+        self.begin_line();
+    }
+
+    /// Start editing at the cursor
+    fn begin_line(&mut self) {
+        let screen = self.shared.screen.lock();
+        self.line_start = screen.position();
+        self.drawn = (screen.scrolled(), screen.position());
+    }
+
+    fn on_key(&mut self, key: KeyEvent) {
+        match self.editor.on_key(key) {
+            Edit::None => {}
+            Edit::Changed => self.draw_line(),
+            Edit::Complete if self.foreground.is_none() => self.complete(),
+            Edit::Complete => {}
+            Edit::Submit(line) => {
+                self.draw_text(&line, line.len());
+                self.shared.screen.lock().write(b"\n");
+                if self.foreground.is_some() {
+                    self.send_input(line.as_bytes(), false);
+                    self.begin_line();
+                } else {
+                    self.editor.add_history(&line);
+                    self.execute(&line);
+                }
+            }
+            Edit::EndOfInput if self.foreground.is_some() => self.send_input(&[], true),
+            Edit::EndOfInput => {}
+            Edit::Interrupt => {
+                self.shared.screen.lock().write(b"^C\n");
+                match self.foreground.and_then(|number| self.jobs.get(number)) {
+                    // 終わったときに on_exit がプロンプトを出す
+                    Some(job) => {
+                        job.kill();
+                        self.begin_line();
+                    }
+                    None => self.prompt(),
+                }
+            }
+        }
+    }
+
+    fn execute(&mut self, line: &str) {
+        let pipeline = match shell::tokenize(line, self.status).and_then(shell::parse) {
+            Ok(pipeline) => pipeline,
+            Err(message) => {
+                let _ = writeln!(Output(self.file.clone()), "shell: {}", message);
+                self.status = shell::STATUS_USAGE;
+                self.prompt();
+                return;
+            }
+        };
+        let Some(first) = pipeline.commands.first() else {
+            self.prompt();
+            return;
+        };
+        let name = first.args[0].as_str();
+        if pipeline.commands.len() == 1
+            && !pipeline.background
+            && shell::JOB_BUILTINS.contains(&name)
+        {
+            self.status = self.run_job_builtin(first);
+            if self.foreground.is_none() {
+                self.prompt();
+            }
+            return;
+        }
+
+        let started = shell::start(&pipeline, &self.file);
+        let Some(&task) = started.tasks.last() else {
+            self.status = started.status;
+            self.prompt();
+            return;
+        };
+        let number = self
+            .jobs
+            .add(line.trim(), started.tasks, started.last, started.status);
+        if pipeline.background {
+            let _ = writeln!(Output(self.file.clone()), "[{}] {}", number, task);
+            self.status = 0;
+            self.prompt();
+        } else {
+            self.set_foreground(number);
+        }
+    }
+
+    /// Wait for job `number`, giving it the lines typed from now on
+    fn set_foreground(&mut self, number: usize) {
+        let mut input = self.shared.input.lock();
+        input.bytes.clear();
+        input.end = false;
+        drop(input);
+        self.foreground = Some(number);
+        self.begin_line();
+    }
+
+    /// Run `jobs`, `fg` or `kill`; returns the status
+    fn run_job_builtin(&mut self, command: &Command) -> i32 {
+        let mut err = Output(self.file.clone());
+        let files = shell::redirect(command, self.file.clone(), self.file.clone(), &mut err);
+        let Some((_, output)) = files else {
+            return 1;
+        };
+        let mut out = Output(output);
+        let args: Vec<&str> = command.args.iter().map(String::as_str).collect();
+        match args[..] {
+            ["jobs"] => {
+                for job in self.jobs.iter() {
+                    let _ = writeln!(out, "[{}] {:<12}{}", job.number, "Running", job.line);
+                }
+                0
+            }
+            ["fg"] | ["fg", _] => {
+                let number = match args.get(1) {
+                    Some(arg) => arg.trim_start_matches('%').parse().ok(),
+                    None => self.jobs.latest(),
+                };
+                let Some(job) = number.and_then(|number| self.jobs.get(number)) else {
+                    let _ = writeln!(err, "fg: no such job");
+                    return 1;
+                };
+                let (number, line) = (job.number, job.line.clone());
+                let _ = writeln!(out, "{}", line);
+                self.set_foreground(number);
+                0
+            }
+            ["kill", ref targets @ ..] if !targets.is_empty() => {
+                let mut status = 0;
+                for &target in targets {
+                    let killed = match target.strip_prefix('%') {
+                        Some(number) => number
+                            .parse()
+                            .ok()
+                            .and_then(|number| self.jobs.get(number))
+                            .map(Job::kill)
+                            .is_some(),
+                        // ジョブに属さないタスク (カーネルのタスクなど) は殺させない
+                        None => target
+                            .parse()
+                            .ok()
+                            .filter(|&task| self.jobs.find_task(task).is_some())
+                            .is_some_and(task::kill),
+                    };
+                    if !killed {
+                        let _ = writeln!(err, "kill: {}: no such job or task", target);
+                        status = 1;
+                    }
+                }
+                status
+            }
+            [name, ..] => {
+                let _ = writeln!(err, "usage: {}", job_builtin_usage(name));
+                shell::STATUS_USAGE
+            }
+            [] => 0,
+        }
+    }
+
+    fn on_exit(&mut self, task: TaskId, code: i32) {
+        let Some(number) = self.jobs.on_exit(task, code) else {
+            return;
+        };
+        if !self.jobs.get(number).is_some_and(Job::is_done) {
+            return;
+        }
+        let Some(job) = self.jobs.remove(number) else {
+            return;
+        };
+        if self.foreground == Some(number) {
+            self.foreground = None;
+            self.status = job.status;
+            if job::is_crash(job.status) {
+                let _ = writeln!(Output(self.file.clone()), "{}", job::describe(job.status));
+            }
+            // ジョブ向けに打ちかけていた行は捨てる
+            self.editor.clear();
+            self.prompt();
+        } else {
+            self.report(&job);
+        }
+    }
+
+    /// Tell that a background job ended, keeping the line being typed
+    fn report(&mut self, job: &Job) {
+        let message = format!(
+            "[{}] {:<12}{}\n",
+            job.number,
+            job::describe(job.status),
+            job.line
+        );
+        {
+            let mut screen = self.shared.screen.lock();
+            if screen.position().0 != 0 {
+                screen.write(b"\n");
+            }
+            screen.write(message.as_bytes());
+        }
+        if self.foreground.is_none() {
+            self.prompt();
+            self.draw_line();
+        } else {
+            self.begin_line();
+        }
+    }
+
+    /// Queue a typed line, or the end of input, for the application
+    fn send_input(&mut self, line: &[u8], end: bool) {
+        let mut input = self.shared.input.lock();
+        if end {
+            input.end = true;
+        } else {
+            input.bytes.extend(line);
+            input.bytes.push_back(b'\n');
+        }
+        drop(input);
+        self.shared.input_ready.notify();
+    }
+
+    fn draw_line(&mut self) {
+        let line = String::from(self.editor.line());
+        self.draw_text(&line, self.editor.cursor());
+    }
+
+    /// Draw `text` as the edited line with the cursor at `cursor`
+    fn draw_text(&mut self, text: &str, cursor: usize) {
+        let mut screen = self.shared.screen.lock();
+        if (screen.scrolled(), screen.position()) != self.drawn {
+            // 他のタスクが書いた後ろから描き直す
+            self.line_start = screen.position();
+        }
+        let scrolled = screen.scrolled();
+        screen.move_to(self.line_start.0, self.line_start.1);
+        screen.write(text.as_bytes());
+        screen.clear_to_end();
+        let lines = (screen.scrolled() - scrolled) as usize;
+        self.line_start.1 = self.line_start.1.saturating_sub(lines);
+
+        let offset = self.line_start.0 + cursor;
+        let scrolled = screen.scrolled();
+        screen.move_to(offset % COLUMNS, self.line_start.1 + offset / COLUMNS);
+        let lines = (screen.scrolled() - scrolled) as usize;
+        self.line_start.1 = self.line_start.1.saturating_sub(lines);
+        self.drawn = (screen.scrolled(), screen.position());
+    }
+
+    /// Complete the word before the cursor, or list the choices
+    fn complete(&mut self) {
+        let cursor = self.editor.cursor();
+        let (start, candidates) = shell::complete(&self.editor.line()[..cursor]);
+        let typed = cursor - start;
+        let Some(first) = candidates.first() else {
+            return;
+        };
+        let common = candidates.iter().fold(first.len(), |len, candidate| {
+            let same = first.bytes().zip(candidate.bytes());
+            len.min(same.take_while(|(a, b)| a == b).count())
+        });
+        if candidates.len() == 1 {
+            self.editor.insert(&first.as_bytes()[typed..]);
+            if !first.ends_with('/') {
+                self.editor.insert(b" ");
+            }
+        } else if common > typed {
+            self.editor.insert(&first.as_bytes()[typed..common]);
+        } else {
+            // 候補を並べてから、入力中の行を出し直す
+            let line = String::from(self.editor.line());
+            self.draw_text(&line, line.len());
+            {
+                let mut screen = self.shared.screen.lock();
+                screen.write(b"\n");
+                for candidate in &candidates {
+                    screen.write(candidate.as_bytes());
+                    screen.write(b"  ");
+                }
+            }
+            self.prompt();
+        }
+        self.draw_line();
+    }
+
+    fn close(&mut self) {
+        // 窓がなくなるので、残っているジョブは終わらせる
+        for job in self.jobs.iter() {
+            job.kill();
+        }
+        self.send_input(&[], true);
+        desktop::with(|desktop| desktop.close_window(self.layer));
+        desktop::request_redraw();
+    }
+}
+
+fn job_builtin_usage(name: &str) -> &'static str {
+    match name {
+        "fg" => "fg [%JOB]",
+        "kill" => "kill %JOB|TASK...",
+        _ => "jobs",
+    }
+}
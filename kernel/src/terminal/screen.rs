@@ -0,0 +1,218 @@
+//! Character grid of a terminal window
+
+use alloc::{vec, vec::Vec};
+
+use crate::{
+    desktop,
+    font::{self, FONT_HEIGHT, FONT_WIDTH},
+    graphics::{PixelColor, PixelWriter},
+    layer::{Layer, LayerId},
+    window,
+};
+
+pub const COLUMNS: usize = 80;
+pub const ROWS: usize = 25;
+/// Space between the client area's edge and the text
+const MARGIN: usize = 4;
+const TAB_WIDTH: usize = 8;
+
+const FOREGROUND: PixelColor = PixelColor::WHITE;
+const BACKGROUND: PixelColor = PixelColor::BLACK;
+
+/// Client area size that fits the grid
+pub const fn client_size() -> (usize, usize) {
+    (
+        COLUMNS * FONT_WIDTH + 2 * MARGIN,
+        ROWS * FONT_HEIGHT + 2 * MARGIN,
+    )
+}
+
+/// Text drawn into a window layer
+///
+/// Every change is drawn right away; the kernel event loop is asked to
+/// compose the screen afterwards.
+pub struct Screen {
+    layer: LayerId,
+    cells: Vec<u8>,
+    column: usize,
+    row: usize,
+    cursor_visible: bool,
+    /// Lines scrolled off the top so far, so that callers can follow text
+    /// that moved up
+    scrolled: u64,
+}
+
+impl Screen {
+    pub fn new(layer: LayerId) -> Self {
+        let mut screen = Self {
+            layer,
+            cells: vec![b' '; COLUMNS * ROWS],
+            column: 0,
+            row: 0,
+            cursor_visible: false,
+            scrolled: 0,
+        };
+        screen.draw(|screen, layer| screen.redraw_all(layer));
+        screen
+    }
+
+    pub fn position(&self) -> (usize, usize) {
+        (self.column, self.row)
+    }
+
+    pub fn scrolled(&self) -> u64 {
+        self.scrolled
+    }
+
+    /// Write `bytes`, wrapping at the right edge and scrolling at the bottom
+    ///
+    /// `\n` starts a new line, `\r` returns to its start, `\t` moves to the
+    /// next tab stop and `\x0c` (form feed) clears the screen.
+    pub fn write(&mut self, bytes: &[u8]) {
+        self.draw(|screen, layer| {
+            for &byte in bytes {
+                screen.put(layer, byte);
+            }
+        });
+    }
+
+    /// Move the cursor, scrolling if `row` is below the last line
+    pub fn move_to(&mut self, column: usize, row: usize) {
+        self.draw(|screen, layer| {
+            let (column, mut row) = (column.min(COLUMNS - 1), row);
+            while row >= ROWS {
+                screen.scroll(layer);
+                row -= 1;
+            }
+            screen.column = column;
+            screen.row = row;
+        });
+    }
+
+    /// Blank everything from the cursor to the end of the screen
+    pub fn clear_to_end(&mut self) {
+        self.draw(|screen, layer| {
+            let start = screen.row * COLUMNS + screen.column;
+            for i in start..screen.cells.len() {
+                if screen.cells[i] != b' ' {
+                    screen.cells[i] = b' ';
+                    screen.draw_cell(layer, i % COLUMNS, i / COLUMNS);
+                }
+            }
+        });
+    }
+
+    pub fn set_cursor_visible(&mut self, visible: bool) {
+        self.draw(|screen, _| screen.cursor_visible = visible);
+    }
+
+    /// Run `f` with the layer, keeping the cursor drawn at its new place
+    fn draw(&mut self, f: impl FnOnce(&mut Self, &mut Layer)) {
+        let id = self.layer;
+        let drawn = desktop::with(|desktop| {
+            let Some(layer) = desktop.layers().layer_mut(id) else {
+                return false;
+            };
+            let (column, row) = (self.column, self.row);
+            let visible = core::mem::replace(&mut self.cursor_visible, false);
+            self.draw_cell(layer, column, row);
+            self.cursor_visible = visible;
+            f(self, layer);
+            self.draw_cell(layer, self.column, self.row);
+            true
+        });
+        if drawn == Some(true) {
+            desktop::request_redraw();
+        }
+    }
+
+    fn put(&mut self, layer: &mut Layer, byte: u8) {
+        match byte {
+            b'\n' => self.new_line(layer),
+            b'\r' => self.column = 0,
+            b'\t' => {
+                let next = (self.column / TAB_WIDTH + 1) * TAB_WIDTH;
+                while self.column < next.min(COLUMNS) {
+                    self.put(layer, b' ');
+                }
+            }
+            0x0c => self.clear_all(layer),
+            byte => {
+                if self.column >= COLUMNS {
+                    self.new_line(layer);
+                }
+                // 表示できない文字は ? にする
+                let byte = if byte.is_ascii_graphic() || byte == b' ' {
+                    byte
+                } else {
+                    b'?'
+                };
+                self.cells[self.row * COLUMNS + self.column] = byte;
+                self.draw_cell(layer, self.column, self.row);
+                self.column += 1;
+                if self.column == COLUMNS {
+                    self.new_line(layer);
+                }
+            }
+        }
+    }
+
+    fn new_line(&mut self, layer: &mut Layer) {
+        self.column = 0;
+        if self.row + 1 < ROWS {
+            self.row += 1;
+        } else {
+            self.scroll(layer);
+        }
+    }
+
+    fn scroll(&mut self, layer: &mut Layer) {
+        self.cells.copy_within(COLUMNS.., 0);
+        let last = (ROWS - 1) * COLUMNS;
+        self.cells[last..].fill(b' ');
+        self.scrolled += 1;
+        self.redraw_all(layer);
+    }
+
+    fn clear_all(&mut self, layer: &mut Layer) {
+        self.cells.fill(b' ');
+        self.column = 0;
+        self.row = 0;
+        self.redraw_all(layer);
+    }
+
+    fn redraw_all(&self, layer: &mut Layer) {
+        let area = window::client_area(layer.width(), layer.height());
+        layer.fill_rectangle(
+            area.x as usize,
+            area.y as usize,
+            area.width as usize,
+            area.height as usize,
+            BACKGROUND,
+        );
+        for row in 0..ROWS {
+            for column in 0..COLUMNS {
+                if self.cells[row * COLUMNS + column] != b' ' {
+                    self.draw_cell(layer, column, row);
+                }
+            }
+        }
+    }
+
+    fn draw_cell(&self, layer: &mut Layer, column: usize, row: usize) {
+        if column >= COLUMNS || row >= ROWS {
+            return;
+        }
+        let area = window::client_area(layer.width(), layer.height());
+        let x = area.x as usize + MARGIN + column * FONT_WIDTH;
+        let y = area.y as usize + MARGIN + row * FONT_HEIGHT;
+        let cursor = self.cursor_visible && (column, row) == (self.column, self.row);
+        let (foreground, background) = if cursor {
+            (BACKGROUND, FOREGROUND)
+        } else {
+            (FOREGROUND, BACKGROUND)
+        };
+        layer.fill_rectangle(x, y, FONT_WIDTH, FONT_HEIGHT, background);
+        font::write_ascii(layer, x, y, self.cells[row * COLUMNS + column], foreground);
+    }
+}
@@ -0,0 +1,554 @@
+//! Command interpreter: parsing command lines and starting their commands
+//!
+//! A line is a pipeline of commands separated by `|`, each with optional
+//! `<`, `>` and `>>` redirections, and may end with `&` to run in the
+//! background. Every command runs as its own task: built-ins as kernel
+//! tasks, anything else as an application looked up in [`APPS_DIR`] unless
+//! it contains a `/`. The job control built-ins ([`JOB_BUILTINS`]) change
+//! the terminal's own state, so the terminal runs those itself.
+
+use alloc::{
+    boxed::Box,
+    format,
+    string::{String, ToString},
+    sync::Arc,
+    vec::Vec,
+};
+use core::fmt::{self, Write};
+
+use crate::{
+    allocator,
+    apic::timer,
+    loader::{self, LoadError},
+    pci, power,
+    task::{self, TaskId},
+    usb::xhci,
+    vfs::{
+        self, File, NodeKind, VfsError,
+        fd::{FileTable, STDERR, STDIN, STDOUT},
+        open_flag, pipe,
+    },
+};
+
+/// Where commands without a `/` are looked up
+pub const APPS_DIR: &str = "/boot/apps";
+/// Environment given to applications
+const ENVIRONMENT: &[&str] = &["PATH=/boot/apps"];
+/// Standard input of background jobs, which must not take the terminal's
+const NULL_DEVICE: &str = "/dev/null";
+
+const BUILTINS: &[&str] = &[
+    "cat", "clear", "echo", "ls", "lspci", "lsusb", "memstat", "reboot", "uptime",
+];
+/// Built-ins run by the terminal itself
+pub const JOB_BUILTINS: &[&str] = &["fg", "jobs", "kill"];
+
+/// Status for a command that could not be found or started
+const STATUS_NOT_FOUND: i32 = 127;
+const STATUS_CANNOT_RUN: i32 = 126;
+pub const STATUS_USAGE: i32 = 2;
+
+/// [`fmt::Write`] for an open file
+pub struct Output(pub Arc<dyn File>);
+
+impl Output {
+    /// Standard descriptor `fd` of the current task
+    fn stdio(fd: usize) -> Option<Self> {
+        vfs::fd::get(fd).ok().map(Self)
+    }
+
+    fn write_all(&self, mut bytes: &[u8]) -> vfs::Result<()> {
+        while !bytes.is_empty() {
+            match self.0.write(bytes)? {
+                0 => return Err(VfsError::Io),
+                written => bytes = &bytes[written..],
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Write for Output {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.write_all(s.as_bytes()).map_err(|_| fmt::Error)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Token {
+    Word(String),
+    /// `|`
+    Pipe,
+    /// `<`
+    Input,
+    /// `>`
+    Output,
+    /// `>>`
+    Append,
+    /// `&`
+    Background,
+}
+
+/// Split `line` into words and operators
+///
+/// Single and double quotes keep spaces and operators in a word, and a
+/// backslash takes the next character literally. `$?` outside single
+/// quotes becomes `status`, the status of the last foreground job.
+pub fn tokenize(line: &str, status: i32) -> Result<Vec<Token>, &'static str> {
+    let mut tokens = Vec::new();
+    let mut word = String::new();
+    let mut in_word = false;
+    let mut quote = None;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some('"'), '\\') | (None, '\\') => {
+                word.push(chars.next().ok_or("trailing backslash")?);
+                in_word = true;
+            }
+            (Some('"'), '$') | (None, '$') if chars.next_if_eq(&'?').is_some() => {
+                word.push_str(&status.to_string());
+                in_word = true;
+            }
+            (Some(_), c) => word.push(c),
+            (None, '\'' | '"') => {
+                quote = Some(c);
+                in_word = true;
+            }
+            (None, ' ' | '\t' | '|' | '<' | '>' | '&') => {
+                if in_word {
+                    tokens.push(Token::Word(core::mem::take(&mut word)));
+                    in_word = false;
+                }
+                tokens.push(match c {
+                    '|' => Token::Pipe,
+                    '<' => Token::Input,
+                    '>' if chars.next_if_eq(&'>').is_some() => Token::Append,
+                    '>' => Token::Output,
+                    '&' => Token::Background,
+                    _ => continue,
+                });
+            }
+            (None, c) => {
+                word.push(c);
+                in_word = true;
+            }
+        }
+    }
+    if quote.is_some() {
+        return Err("unterminated quote");
+    }
+    if in_word {
+        tokens.push(Token::Word(word));
+    }
+    Ok(tokens)
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Command {
+    pub args: Vec<String>,
+    /// File given with `<`
+    pub input: Option<String>,
+    /// File given with `>`, or with `>>` to append
+    pub output: Option<(String, bool)>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Pipeline {
+    /// Empty for a blank line
+    pub commands: Vec<Command>,
+    pub background: bool,
+}
+
+/// Group `tokens` into the commands of a pipeline
+pub fn parse(tokens: Vec<Token>) -> Result<Pipeline, &'static str> {
+    let mut pipeline = Pipeline::default();
+    let mut command = Command::default();
+    let mut tokens = tokens.into_iter();
+    while let Some(token) = tokens.next() {
+        if pipeline.background {
+            return Err("`&` must end the line");
+        }
+        match token {
+            Token::Word(word) => command.args.push(word),
+            Token::Input | Token::Output | Token::Append => {
+                let Some(Token::Word(path)) = tokens.next() else {
+                    return Err("missing file name after redirection");
+                };
+                match token {
+                    Token::Input => command.input = Some(path),
+                    _ => command.output = Some((path, token == Token::Append)),
+                }
+            }
+            Token::Pipe => {
+                if command.args.is_empty() {
+                    return Err("missing command");
+                }
+                pipeline.commands.push(core::mem::take(&mut command));
+            }
+            Token::Background => pipeline.background = true,
+        }
+    }
+    if command.args.is_empty() {
+        if command != Command::default() || !pipeline.commands.is_empty() || pipeline.background {
+            return Err("missing command");
+        }
+    } else {
+        pipeline.commands.push(command);
+    }
+    Ok(pipeline)
+}
+
+/// Tasks started for a pipeline
+pub struct Started {
+    pub tasks: Vec<TaskId>,
+    /// Task of the last command; `None` if it could not start
+    pub last: Option<TaskId>,
+    /// Status of the last command if it could not start
+    pub status: i32,
+}
+
+/// Start every command of `pipeline`, connected by pipes
+///
+/// `terminal` is the standard input, output and error of the commands
+/// unless a pipe or a redirection replaces them; background jobs read
+/// [`NULL_DEVICE`] instead of the terminal. A command that cannot start is
+/// reported and left out, so its neighbours see the end of their input or
+/// a broken pipe.
+pub fn start(pipeline: &Pipeline, terminal: &Arc<dyn File>) -> Started {
+    let mut err = Output(terminal.clone());
+    let mut started = Started {
+        tasks: Vec::new(),
+        last: None,
+        status: 0,
+    };
+    let mut piped_input = None;
+    for (i, command) in pipeline.commands.iter().enumerate() {
+        let is_last = i + 1 == pipeline.commands.len();
+        let input = piped_input.take().unwrap_or_else(|| {
+            if pipeline.background {
+                vfs::open(NULL_DEVICE, open_flag::READ).unwrap_or_else(|_| terminal.clone())
+            } else {
+                terminal.clone()
+            }
+        });
+        let output = if is_last {
+            terminal.clone()
+        } else {
+            let (reader, writer) = pipe::new();
+            piped_input = Some(reader);
+            writer
+        };
+        let result = redirect(command, input, output, &mut err)
+            .ok_or(1)
+            .and_then(|(input, output)| {
+                // 標準エラー出力はパイプでつながず、いつも端末に出す
+                let mut files = FileTable::new();
+                for (fd, file) in [(STDIN, input), (STDOUT, output), (STDERR, terminal.clone())] {
+                    let _ = files.insert_at(fd, file);
+                }
+                spawn(&command.args, files, &mut err)
+            });
+        match result {
+            Ok(task) => {
+                started.tasks.push(task);
+                if is_last {
+                    started.last = Some(task);
+                }
+            }
+            Err(status) if is_last => started.status = status,
+            Err(_) => {}
+        }
+    }
+    started
+}
+
+/// Apply the redirections of `command` over its piped or inherited files
+///
+/// A file that cannot be opened is reported to `err`.
+pub fn redirect(
+    command: &Command,
+    input: Arc<dyn File>,
+    output: Arc<dyn File>,
+    err: &mut Output,
+) -> Option<(Arc<dyn File>, Arc<dyn File>)> {
+    let mut open = |path: &String, flags| {
+        vfs::open(path, flags)
+            .inspect_err(|error| {
+                let _ = writeln!(err, "{}: {:?}", path, error);
+            })
+            .ok()
+    };
+    let input = match &command.input {
+        Some(path) => open(path, open_flag::READ)?,
+        None => input,
+    };
+    let output = match &command.output {
+        Some((path, append)) => {
+            let mode = if *append {
+                open_flag::APPEND
+            } else {
+                open_flag::TRUNCATE
+            };
+            open(path, open_flag::WRITE | open_flag::CREATE | mode)?
+        }
+        None => output,
+    };
+    Some((input, output))
+}
+
+/// Start `args` as a task with `files` as its descriptors
+fn spawn(args: &[String], files: FileTable, err: &mut Output) -> Result<TaskId, i32> {
+    let name = args[0].as_str();
+    if BUILTINS.contains(&name) {
+        let arg = Box::into_raw(Box::new(args.to_vec())) as u64;
+        return task::spawn_with_files(run_builtin_task, arg, task::PRIORITY_NORMAL, files)
+            .ok_or_else(|| {
+                drop(unsafe { Box::from_raw(arg as *mut Vec<String>) });
+                let _ = writeln!(err, "{}: cannot start task", name);
+                STATUS_CANNOT_RUN
+            });
+    }
+    if JOB_BUILTINS.contains(&name) {
+        let _ = writeln!(err, "{}: only runs alone in the foreground", name);
+        return Err(STATUS_USAGE);
+    }
+    let path = if name.contains('/') {
+        String::from(name)
+    } else {
+        vfs::path::join(APPS_DIR, name)
+    };
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    match loader::spawn_with_files(&path, &args, ENVIRONMENT, files) {
+        Ok(task) => Ok(task),
+        Err(LoadError::Vfs(VfsError::NotFound)) => {
+            let _ = writeln!(err, "{}: command not found", name);
+            Err(STATUS_NOT_FOUND)
+        }
+        Err(error) => {
+            let _ = writeln!(err, "{}: {:?}", name, error);
+            Err(STATUS_CANNOT_RUN)
+        }
+    }
+}
+
+extern "sysv64" fn run_builtin_task(arg: u64) {
+    let status = {
+        let words = unsafe { Box::from_raw(arg as *mut Vec<String>) };
+        let args: Vec<&str> = words.iter().map(String::as_str).collect();
+        run_builtin(&args)
+    };
+    // 途中で kill されたなら、書き込みの失敗などより kill を伝える
+    let status = if task::is_killed() {
+        task::EXIT_KILLED
+    } else {
+        status
+    };
+    task::exit(status)
+}
+
+/// Run the built-in named by `args[0]` with the current task's standard
+/// descriptors; returns its status
+fn run_builtin(args: &[&str]) -> i32 {
+    let (Some(mut out), Some(mut err)) = (Output::stdio(STDOUT), Output::stdio(STDERR)) else {
+        return STATUS_CANNOT_RUN;
+    };
+    match args[0] {
+        "echo" => {
+            let _ = writeln!(out, "{}", args[1..].join(" "));
+            0
+        }
+        // 画面を消す制御文字を出力する (書き出し先がファイルならそのまま残る)
+        "clear" => {
+            let _ = out.write_str("\x0c");
+            0
+        }
+        "ls" => ls(&mut out, &mut err, &args[1..]),
+        "cat" => cat(&out, &mut err, &args[1..]),
+        "memstat" => {
+            let free = allocator::free_bytes();
+            let total = allocator::HEAP_SIZE;
+            let _ = writeln!(
+                out,
+                "heap: {} KiB used, {} KiB free, {} KiB total",
+                (total - free) / 1024,
+                free / 1024,
+                total / 1024
+            );
+            0
+        }
+        "lspci" => {
+            for device in pci::devices() {
+                let pci::config::Address {
+                    bus,
+                    device: number,
+                    function,
+                } = device.address;
+                let _ = writeln!(
+                    out,
+                    "{:02x}:{:02x}.{} {:04x}:{:04x} class {:02x}.{:02x}.{:02x}",
+                    bus,
+                    number,
+                    function,
+                    device.vendor_id,
+                    device.device_id,
+                    device.class.base,
+                    device.class.sub,
+                    device.class.interface
+                );
+            }
+            0
+        }
+        "lsusb" => lsusb(&mut out, &mut err),
+        "uptime" => {
+            let ms = timer::uptime_ms();
+            let seconds = ms / 1000;
+            let _ = writeln!(
+                out,
+                "up {}:{:02}:{:02}.{:03}",
+                seconds / 3600,
+                seconds / 60 % 60,
+                seconds % 60,
+                ms % 1000
+            );
+            0
+        }
+        "reboot" => {
+            // 書き込まれていないデータを落とさないように
+            let _ = vfs::sync();
+            power::reboot()
+        }
+        _ => STATUS_NOT_FOUND,
+    }
+}
+
+fn ls(out: &mut Output, err: &mut Output, paths: &[&str]) -> i32 {
+    let paths = if paths.is_empty() { &["/"][..] } else { paths };
+    let mut status = 0;
+    for (i, path) in paths.iter().enumerate() {
+        if paths.len() > 1 {
+            let separator = if i > 0 { "\n" } else { "" };
+            let _ = writeln!(out, "{}{}:", separator, path);
+        }
+        match vfs::read_dir(path) {
+            Ok(entries) => {
+                for entry in entries {
+                    let suffix = if entry.kind == NodeKind::Directory {
+                        "/"
+                    } else {
+                        ""
+                    };
+                    let _ = writeln!(out, "{}{}", entry.name, suffix);
+                }
+            }
+            Err(error) => {
+                let _ = writeln!(err, "ls: {}: {:?}", path, error);
+                status = 1;
+            }
+        }
+    }
+    status
+}
+
+/// Copy files, or the standard input without any, to `out`
+fn cat(out: &Output, err: &mut Output, paths: &[&str]) -> i32 {
+    let paths = if paths.is_empty() { &["-"][..] } else { paths };
+    let mut status = 0;
+    let mut buf = [0; 512];
+    for path in paths {
+        let file = match *path {
+            "-" => vfs::fd::get(STDIN),
+            path => vfs::open(path, open_flag::READ),
+        };
+        let result = file.and_then(|file| {
+            loop {
+                match file.read(&mut buf)? {
+                    0 => return Ok(()),
+                    len => out.write_all(&buf[..len])?,
+                }
+            }
+        });
+        match result {
+            Ok(()) => {}
+            // 読み手がいなくなったか kill されたので、黙ってやめる
+            Err(VfsError::BrokenPipe | VfsError::Interrupted) => return 1,
+            Err(error) => {
+                let _ = writeln!(err, "cat: {}: {:?}", path, error);
+                status = 1;
+            }
+        }
+    }
+    status
+}
+
+fn lsusb(out: &mut Output, err: &mut Output) -> i32 {
+    let lines = xhci::with_controller(|controller| {
+        controller
+            .devices()
+            .map(|device| {
+                let ids = device.descriptor.map_or(String::from("????:????"), |d| {
+                    format!(
+                        "{:04x}:{:04x} class {:02x}",
+                        d.vendor_id, d.product_id, d.class
+                    )
+                });
+                format!(
+                    "slot {} port {} {:?} {} {:?}",
+                    device.slot_id, device.port, device.speed, ids, device.state
+                )
+            })
+            .collect::<Vec<_>>()
+    });
+    let Some(lines) = lines else {
+        let _ = writeln!(err, "lsusb: no USB controller");
+        return 1;
+    };
+    for line in lines {
+        let _ = writeln!(out, "{}", line);
+    }
+    0
+}
+
+/// Completions for the word that ends `before`, the text left of the cursor
+///
+/// Returns where the word starts and the words that could replace it;
+/// directories end with `/`.
+pub fn complete(before: &str) -> (usize, Vec<String>) {
+    let start = before.rfind(' ').map_or(0, |i| i + 1);
+    let word = &before[start..];
+    let mut candidates = Vec::new();
+    // 行頭と | の直後はコマンド名
+    let previous = before[..start].trim_end();
+    if (previous.is_empty() || previous.ends_with('|')) && !word.contains('/') {
+        let builtins = BUILTINS.iter().chain(JOB_BUILTINS);
+        candidates.extend(builtins.map(|&name| String::from(name)));
+        if let Ok(entries) = vfs::read_dir(APPS_DIR) {
+            let apps = entries.into_iter().filter(|e| e.kind == NodeKind::File);
+            candidates.extend(apps.map(|e| e.name));
+        }
+        candidates.retain(|name| name.starts_with(word));
+    } else {
+        let (directory, prefix) = match word.rfind('/') {
+            Some(i) => word.split_at(i + 1),
+            None => ("", word),
+        };
+        let listed = if directory.is_empty() { "/" } else { directory };
+        if let Ok(entries) = vfs::read_dir(listed) {
+            for entry in entries {
+                if !entry.name.starts_with(prefix) {
+                    continue;
+                }
+                let suffix = if entry.kind == NodeKind::Directory {
+                    "/"
+                } else {
+                    ""
+                };
+                candidates.push(format!("{}{}{}", directory, entry.name, suffix));
+            }
+        }
+    }
+    candidates.sort();
+    candidates.dedup();
+    (start, candidates)
+}
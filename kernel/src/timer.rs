@@ -0,0 +1,147 @@
+//! Software timers on top of the periodic tick
+//!
+//! Timers live in a hashed wheel: slot `deadline % WHEEL_SIZE`, so each tick
+//! only looks at the timers that could be due in that slot.
+
+use alloc::vec::Vec;
+
+use spin::Mutex;
+
+use crate::{
+    apic::timer::{TICK_HZ, ticks},
+    message::Message,
+    queue::ArrayQueue,
+    task::{self, TaskId},
+    x86,
+};
+
+pub type TimerId = u64;
+
+const WHEEL_SIZE: usize = 256;
+/// Timers fired per tick; the rest wait for the next tick
+const MAX_FIRED_PER_TICK: usize = 32;
+
+#[derive(Clone, Copy, Debug)]
+pub enum TimerAction {
+    /// Send [`Message::TimerTick`] carrying `value` to `task`
+    Message { task: TaskId, value: u64 },
+    /// Call `function(id, value)` from the timer interrupt; keep it short,
+    /// e.g. post a message or wake a task
+    Callback {
+        function: fn(TimerId, u64),
+        value: u64,
+    },
+}
+
+struct Timer {
+    id: TimerId,
+    deadline: u64,
+    /// `0` for one-shot timers
+    period: u64,
+    action: TimerAction,
+}
+
+struct TimerWheel {
+    slots: [Vec<Timer>; WHEEL_SIZE],
+    next_id: TimerId,
+}
+
+impl TimerWheel {
+    fn insert(&mut self, timer: Timer) {
+        self.slots[timer.deadline as usize % WHEEL_SIZE].push(timer);
+    }
+}
+
+static WHEEL: Mutex<TimerWheel> = Mutex::new(TimerWheel {
+    slots: [const { Vec::new() }; WHEEL_SIZE],
+    next_id: 1,
+});
+
+pub fn ms_to_ticks(ms: u64) -> u64 {
+    (ms * TICK_HZ).div_ceil(1000)
+}
+
+fn add(delay: u64, period: u64, action: TimerAction) -> TimerId {
+    x86::without_interrupts(|| {
+        let mut wheel = WHEEL.lock();
+        let id = wheel.next_id;
+        wheel.next_id += 1;
+        wheel.insert(Timer {
+            id,
+            deadline: ticks() + delay.max(1),
+            period,
+            action,
+        });
+        id
+    })
+}
+
+/// Fire once after `delay` ticks
+pub fn one_shot(delay: u64, action: TimerAction) -> TimerId {
+    add(delay, 0, action)
+}
+
+/// Fire every `period` ticks, starting `period` ticks from now
+pub fn periodic(period: u64, action: TimerAction) -> TimerId {
+    let period = period.max(1);
+    add(period, period, action)
+}
+
+pub fn one_shot_ms(ms: u64, action: TimerAction) -> TimerId {
+    one_shot(ms_to_ticks(ms), action)
+}
+
+pub fn periodic_ms(ms: u64, action: TimerAction) -> TimerId {
+    periodic(ms_to_ticks(ms), action)
+}
+
+/// Stop a timer; returns `false` if it already fired (one-shot) or never existed
+pub fn cancel(id: TimerId) -> bool {
+    x86::without_interrupts(|| {
+        let mut wheel = WHEEL.lock();
+        for slot in wheel.slots.iter_mut() {
+            if let Some(index) = slot.iter().position(|t| t.id == id) {
+                slot.swap_remove(index);
+                return true;
+            }
+        }
+        false
+    })
+}
+
+/// Called from the tick interrupt with the new tick count
+pub fn on_tick(now: u64) {
+    let mut fired: ArrayQueue<(TimerId, TimerAction), MAX_FIRED_PER_TICK> = ArrayQueue::new();
+    {
+        let mut wheel = WHEEL.lock();
+        let index = now as usize % WHEEL_SIZE;
+        let mut i = 0;
+        while i < wheel.slots[index].len() {
+            if wheel.slots[index][i].deadline > now {
+                i += 1;
+                continue;
+            }
+            let mut timer = wheel.slots[index].swap_remove(i);
+            if fired.push((timer.id, timer.action)).is_err() {
+                // 溢れた分は次の tick で発火させる
+                timer.deadline = now + 1;
+                wheel.insert(timer);
+                continue;
+            }
+            if timer.period > 0 {
+                timer.deadline = now + timer.period;
+                wheel.insert(timer);
+            }
+        }
+    }
+
+    // コールバックからタイマーを追加できるよう、ロックを外してから呼ぶ
+    while let Some((id, action)) = fired.pop() {
+        match action {
+            TimerAction::Message { task, value } => {
+                let _ = task::send_message(task, Message::TimerTick { tick: now, value });
+            }
+            TimerAction::Callback { function, value } => function(id, value),
+        }
+    }
+}
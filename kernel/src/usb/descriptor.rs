@@ -0,0 +1,205 @@
+//! Standard USB descriptors and setup packets
+
+pub const DESCRIPTOR_DEVICE: u8 = 1;
+pub const DESCRIPTOR_CONFIGURATION: u8 = 2;
+pub const DESCRIPTOR_INTERFACE: u8 = 4;
+pub const DESCRIPTOR_ENDPOINT: u8 = 5;
+
+pub const REQUEST_GET_DESCRIPTOR: u8 = 6;
+pub const REQUEST_SET_CONFIGURATION: u8 = 9;
+pub const HID_REQUEST_SET_IDLE: u8 = 0x0a;
+pub const HID_REQUEST_SET_PROTOCOL: u8 = 0x0b;
+
+pub const CLASS_HID: u8 = 3;
+pub const HID_SUBCLASS_BOOT: u8 = 1;
+pub const HID_PROTOCOL_KEYBOARD: u8 = 1;
+pub const HID_PROTOCOL_MOUSE: u8 = 2;
+
+/// bmRequestType values
+pub mod request_type {
+    pub const DEVICE_TO_HOST: u8 = 0x80;
+    pub const HOST_TO_DEVICE: u8 = 0x00;
+    pub const CLASS: u8 = 0x20;
+    pub const INTERFACE: u8 = 0x01;
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SetupPacket {
+    pub request_type: u8,
+    pub request: u8,
+    pub value: u16,
+    pub index: u16,
+    pub length: u16,
+}
+
+impl SetupPacket {
+    pub fn get_descriptor(descriptor_type: u8, index: u8, length: u16) -> Self {
+        Self {
+            request_type: request_type::DEVICE_TO_HOST,
+            request: REQUEST_GET_DESCRIPTOR,
+            value: (descriptor_type as u16) << 8 | index as u16,
+            index: 0,
+            length,
+        }
+    }
+
+    pub fn set_configuration(value: u8) -> Self {
+        Self {
+            request_type: request_type::HOST_TO_DEVICE,
+            request: REQUEST_SET_CONFIGURATION,
+            value: value as u16,
+            index: 0,
+            length: 0,
+        }
+    }
+
+    /// HID SET_PROTOCOL; `0` selects the boot protocol
+    pub fn set_protocol(interface: u8, protocol: u16) -> Self {
+        Self {
+            request_type: request_type::CLASS | request_type::INTERFACE,
+            request: HID_REQUEST_SET_PROTOCOL,
+            value: protocol,
+            index: interface as u16,
+            length: 0,
+        }
+    }
+
+    /// HID SET_IDLE; duration `0` reports only on change
+    pub fn set_idle(interface: u8, duration: u8) -> Self {
+        Self {
+            request_type: request_type::CLASS | request_type::INTERFACE,
+            request: HID_REQUEST_SET_IDLE,
+            value: (duration as u16) << 8,
+            index: interface as u16,
+            length: 0,
+        }
+    }
+
+    pub fn is_device_to_host(&self) -> bool {
+        self.request_type & request_type::DEVICE_TO_HOST != 0
+    }
+
+    pub fn to_u64(self) -> u64 {
+        self.request_type as u64
+            | (self.request as u64) << 8
+            | (self.value as u64) << 16
+            | (self.index as u64) << 32
+            | (self.length as u64) << 48
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DeviceDescriptor {
+    pub usb_version: u16,
+    pub class: u8,
+    pub subclass: u8,
+    pub protocol: u8,
+    pub max_packet_size0: u8,
+    pub vendor_id: u16,
+    pub product_id: u16,
+    pub num_configurations: u8,
+}
+
+impl DeviceDescriptor {
+    pub fn parse(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() < 18 || bytes[1] != DESCRIPTOR_DEVICE {
+            return None;
+        }
+        Some(Self {
+            usb_version: u16::from_le_bytes([bytes[2], bytes[3]]),
+            class: bytes[4],
+            subclass: bytes[5],
+            protocol: bytes[6],
+            max_packet_size0: bytes[7],
+            vendor_id: u16::from_le_bytes([bytes[8], bytes[9]]),
+            product_id: u16::from_le_bytes([bytes[10], bytes[11]]),
+            num_configurations: bytes[17],
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct InterfaceDescriptor {
+    pub number: u8,
+    pub alternate_setting: u8,
+    pub num_endpoints: u8,
+    pub class: u8,
+    pub subclass: u8,
+    pub protocol: u8,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct EndpointDescriptor {
+    pub address: u8,
+    pub attributes: u8,
+    pub max_packet_size: u16,
+    pub interval: u8,
+}
+
+impl EndpointDescriptor {
+    pub fn number(&self) -> u8 {
+        self.address & 0x0f
+    }
+
+    pub fn is_in(&self) -> bool {
+        self.address & 0x80 != 0
+    }
+
+    pub fn is_interrupt(&self) -> bool {
+        self.attributes & 0b11 == 3
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Descriptor {
+    Configuration { value: u8, total_length: u16 },
+    Interface(InterfaceDescriptor),
+    Endpoint(EndpointDescriptor),
+    Other { descriptor_type: u8 },
+}
+
+/// Iterate over the descriptors of a configuration descriptor set
+pub struct Descriptors<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Descriptors<'a> {
+    pub fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes }
+    }
+}
+
+impl Iterator for Descriptors<'_> {
+    type Item = Descriptor;
+
+    fn next(&mut self) -> Option<Descriptor> {
+        let length = *self.bytes.first()? as usize;
+        if length < 2 || self.bytes.len() < length {
+            return None;
+        }
+        let d = &self.bytes[..length];
+        self.bytes = &self.bytes[length..];
+
+        Some(match (d[1], length) {
+            (DESCRIPTOR_CONFIGURATION, 9..) => Descriptor::Configuration {
+                value: d[5],
+                total_length: u16::from_le_bytes([d[2], d[3]]),
+            },
+            (DESCRIPTOR_INTERFACE, 9..) => Descriptor::Interface(InterfaceDescriptor {
+                number: d[2],
+                alternate_setting: d[3],
+                num_endpoints: d[4],
+                class: d[5],
+                subclass: d[6],
+                protocol: d[7],
+            }),
+            (DESCRIPTOR_ENDPOINT, 7..) => Descriptor::Endpoint(EndpointDescriptor {
+                address: d[2],
+                attributes: d[3],
+                max_packet_size: u16::from_le_bytes([d[4], d[5]]) & 0x7ff,
+                interval: d[6],
+            }),
+            (descriptor_type, _) => Descriptor::Other { descriptor_type },
+        })
+    }
+}
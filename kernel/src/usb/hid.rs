@@ -0,0 +1,70 @@
+//! Boot protocol HID keyboard and mouse class drivers
+
+use crate::input::{self, InputEvent, MouseEvent};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HidKind {
+    Keyboard,
+    Mouse,
+}
+
+/// Keeps the previous report so that key presses and releases can be told apart
+#[derive(Clone, Copy, Debug)]
+pub struct HidDriver {
+    pub kind: HidKind,
+    previous: [u8; 8],
+}
+
+impl HidDriver {
+    pub fn new(kind: HidKind) -> Self {
+        Self {
+            kind,
+            previous: [0; 8],
+        }
+    }
+
+    pub fn on_report(&mut self, report: &[u8]) {
+        match self.kind {
+            HidKind::Keyboard => self.on_keyboard_report(report),
+            HidKind::Mouse => on_mouse_report(report),
+        }
+    }
+
+    /// Boot keyboard report: `[modifiers, reserved, key0 .. key5]`
+    fn on_keyboard_report(&mut self, report: &[u8]) {
+        if report.len() < 8 {
+            return;
+        }
+        // 0x01 (ErrorRollOver) などのエラー状態は無視する
+        if report[2..8].iter().any(|&k| (1..=3).contains(&k)) {
+            return;
+        }
+        let modifiers = report[0];
+        let previous = self.previous;
+
+        for &keycode in previous[2..8].iter().filter(|&&k| k != 0) {
+            if !report[2..8].contains(&keycode) {
+                input::push(InputEvent::Key(input::key_event(keycode, modifiers, false)));
+            }
+        }
+        for &keycode in report[2..8].iter().filter(|&&k| k != 0) {
+            if !previous[2..8].contains(&keycode) {
+                input::push(InputEvent::Key(input::key_event(keycode, modifiers, true)));
+            }
+        }
+        self.previous.copy_from_slice(&report[..8]);
+    }
+}
+
+/// Boot mouse report: `[buttons, dx, dy, (wheel)]`
+fn on_mouse_report(report: &[u8]) {
+    if report.len() < 3 {
+        return;
+    }
+    input::push(InputEvent::Mouse(MouseEvent {
+        buttons: report[0] & 0b111,
+        dx: report[1] as i8 as i16,
+        dy: report[2] as i8 as i16,
+        wheel: report.get(3).map_or(0, |&w| w as i8),
+    }));
+}
@@ -0,0 +1,4 @@
+//! USB host stack
+pub mod descriptor;
+pub mod hid;
+pub mod xhci;
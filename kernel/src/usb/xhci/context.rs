@@ -0,0 +1,125 @@
+//! Device and input contexts
+//!
+//! The context size (32 or 64 bytes) is only known at run time, so contexts
+//! are accessed as dword arrays with a stride instead of `#[repr(C)]` structs.
+
+use crate::dma;
+
+use super::ring::RingError;
+
+pub const EP_TYPE_CONTROL: u32 = 4;
+pub const EP_TYPE_INTERRUPT_IN: u32 = 7;
+
+/// Number of contexts in a device context (slot + 31 endpoints)
+const DEVICE_CONTEXTS: usize = 32;
+const CONTEXT_ALIGN: usize = 64;
+const PAGE_SIZE: usize = 4096;
+
+fn allocate_contexts(count: usize, context_size: usize) -> Result<*mut u32, RingError> {
+    dma::allocate(count * context_size, CONTEXT_ALIGN, PAGE_SIZE)
+        .map(|p| p as *mut u32)
+        .ok_or(RingError::OutOfMemory)
+}
+
+/// Output device context owned by the controller
+#[derive(Debug)]
+pub struct DeviceContext {
+    base: *mut u32,
+}
+
+impl DeviceContext {
+    pub fn new(context_size: usize) -> Result<Self, RingError> {
+        Ok(Self {
+            base: allocate_contexts(DEVICE_CONTEXTS, context_size)?,
+        })
+    }
+
+    pub fn address(&self) -> u64 {
+        self.base as u64
+    }
+}
+
+/// Endpoint parameters written into an endpoint context
+#[derive(Clone, Copy, Debug)]
+pub struct EndpointConfig {
+    pub dci: u8,
+    pub ep_type: u32,
+    pub max_packet_size: u16,
+    pub interval: u8,
+    pub dequeue: u64,
+    pub cycle: bool,
+}
+
+#[derive(Debug)]
+pub struct InputContext {
+    base: *mut u32,
+    context_size: usize,
+}
+
+impl InputContext {
+    pub fn new(context_size: usize) -> Result<Self, RingError> {
+        Ok(Self {
+            base: allocate_contexts(DEVICE_CONTEXTS + 1, context_size)?,
+            context_size,
+        })
+    }
+
+    pub fn address(&self) -> u64 {
+        self.base as u64
+    }
+
+    /// `index` 0 is the input control context, 1 the slot context and
+    /// `dci + 1` the endpoint contexts
+    fn write(&self, index: usize, dword: usize, value: u32) {
+        unsafe {
+            let ctx = (self.base as *mut u8).add(index * self.context_size) as *mut u32;
+            ctx.add(dword).write_volatile(value);
+        }
+    }
+
+    fn read(&self, index: usize, dword: usize) -> u32 {
+        unsafe {
+            let ctx = (self.base as *const u8).add(index * self.context_size) as *const u32;
+            ctx.add(dword).read_volatile()
+        }
+    }
+
+    pub fn set_add_flags(&self, flags: u32) {
+        self.write(0, 0, 0);
+        self.write(0, 1, flags);
+    }
+
+    pub fn set_slot(&self, speed: u8, root_port: u8, context_entries: u8) {
+        self.write(1, 0, (speed as u32) << 20 | (context_entries as u32) << 27);
+        self.write(1, 1, (root_port as u32) << 16);
+    }
+
+    pub fn set_context_entries(&self, context_entries: u8) {
+        let dw0 = self.read(1, 0) & !(0x1f << 27);
+        self.write(1, 0, dw0 | (context_entries as u32) << 27);
+    }
+
+    pub fn set_endpoint(&self, config: &EndpointConfig) {
+        let index = config.dci as usize + 1;
+        let max_packet = config.max_packet_size as u32;
+        // CErr = 3
+        self.write(index, 0, (config.interval as u32) << 16);
+        self.write(index, 1, 3 << 1 | config.ep_type << 3 | max_packet << 16);
+        self.write(index, 2, config.dequeue as u32 | config.cycle as u32);
+        self.write(index, 3, (config.dequeue >> 32) as u32);
+        let average_trb_length = if config.ep_type == EP_TYPE_CONTROL {
+            8
+        } else {
+            max_packet
+        };
+        let max_esit_payload = if config.ep_type == EP_TYPE_CONTROL {
+            0
+        } else {
+            max_packet
+        };
+        self.write(index, 4, average_trb_length | max_esit_payload << 16);
+    }
+}
+
+unsafe impl Send for DeviceContext {}
+unsafe impl Send for InputContext {}
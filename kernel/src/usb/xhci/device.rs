@@ -0,0 +1,304 @@
+//! Per-slot enumeration state machine and HID class binding
+
+use crate::{
+    dma,
+    usb::{
+        descriptor::{
+            CLASS_HID, DESCRIPTOR_CONFIGURATION, DESCRIPTOR_DEVICE, Descriptor, Descriptors,
+            DeviceDescriptor, EndpointDescriptor, HID_PROTOCOL_KEYBOARD, HID_PROTOCOL_MOUSE,
+            HID_SUBCLASS_BOOT, SetupPacket,
+        },
+        hid::{HidDriver, HidKind},
+    },
+};
+
+use super::{
+    PortSpeed,
+    context::{DeviceContext, EP_TYPE_CONTROL, EP_TYPE_INTERRUPT_IN, EndpointConfig, InputContext},
+    registers::Registers,
+    ring::{Ring, RingError},
+    trb::Trb,
+};
+
+const TRANSFER_RING_SIZE: usize = 32;
+const DESCRIPTOR_BUFFER_SIZE: usize = 256;
+const REPORT_BUFFER_SIZE: usize = 64;
+/// Device context index of the default control endpoint
+pub const DCI_EP0: u8 = 1;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DeviceState {
+    Addressing,
+    GettingDeviceDescriptor,
+    GettingConfiguration,
+    SettingConfiguration,
+    SettingProtocol,
+    SettingIdle,
+    ConfiguringEndpoint,
+    Running,
+    /// No class driver for this device
+    Unsupported,
+    Failed,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct HidInterface {
+    kind: HidKind,
+    interface: u8,
+    endpoint: EndpointDescriptor,
+}
+
+pub struct UsbDevice {
+    pub slot_id: u8,
+    pub port: u8,
+    pub speed: PortSpeed,
+    pub state: DeviceState,
+    pub descriptor: Option<DeviceDescriptor>,
+    device_context: DeviceContext,
+    input_context: InputContext,
+    ep0: Ring,
+    buffer: *mut u8,
+    configuration_value: u8,
+    hid: Option<HidInterface>,
+    interrupt_ring: Option<Ring>,
+    report: *mut u8,
+    driver: Option<HidDriver>,
+}
+
+unsafe impl Send for UsbDevice {}
+
+impl UsbDevice {
+    pub fn new(
+        slot_id: u8,
+        port: u8,
+        speed: PortSpeed,
+        context_size: usize,
+    ) -> Result<Self, RingError> {
+        let buffer = dma::allocate(DESCRIPTOR_BUFFER_SIZE, 64, 0).ok_or(RingError::OutOfMemory)?;
+        let report = dma::allocate(REPORT_BUFFER_SIZE, 64, 0).ok_or(RingError::OutOfMemory)?;
+        Ok(Self {
+            slot_id,
+            port,
+            speed,
+            state: DeviceState::Addressing,
+            descriptor: None,
+            device_context: DeviceContext::new(context_size)?,
+            input_context: InputContext::new(context_size)?,
+            ep0: Ring::new(TRANSFER_RING_SIZE)?,
+            buffer,
+            configuration_value: 0,
+            hid: None,
+            interrupt_ring: None,
+            report,
+            driver: None,
+        })
+    }
+
+    pub fn device_context(&self) -> u64 {
+        self.device_context.address()
+    }
+
+    /// Build the input context for the Address Device command
+    pub fn address_device_command(&self) -> Trb {
+        let ctx = &self.input_context;
+        // A0 (スロット) と A1 (EP0) を有効にする
+        ctx.set_add_flags(0b11);
+        ctx.set_slot(self.speed.id(), self.port, 1);
+        ctx.set_endpoint(&EndpointConfig {
+            dci: DCI_EP0,
+            ep_type: EP_TYPE_CONTROL,
+            max_packet_size: self.speed.default_max_packet_size(),
+            interval: 0,
+            dequeue: self.ep0.base(),
+            cycle: self.ep0.cycle(),
+        });
+        Trb::address_device(ctx.address(), self.slot_id)
+    }
+
+    fn control_transfer(&mut self, regs: &Registers, setup: SetupPacket) {
+        self.ep0.push(Trb::setup_stage(&setup));
+        if setup.length > 0 {
+            let dir_in = setup.is_device_to_host();
+            self.ep0.push(Trb::data_stage(
+                self.buffer as u64,
+                setup.length as u32,
+                dir_in,
+            ));
+            self.ep0.push(Trb::status_stage(!dir_in));
+        } else {
+            self.ep0.push(Trb::status_stage(true));
+        }
+        regs.ring_doorbell(self.slot_id, DCI_EP0);
+    }
+
+    fn buffer(&self) -> &[u8] {
+        unsafe { core::slice::from_raw_parts(self.buffer, DESCRIPTOR_BUFFER_SIZE) }
+    }
+
+    /// Called once the Address Device command has completed
+    pub fn on_addressed(&mut self, regs: &Registers) {
+        self.state = DeviceState::GettingDeviceDescriptor;
+        self.control_transfer(regs, SetupPacket::get_descriptor(DESCRIPTOR_DEVICE, 0, 18));
+    }
+
+    /// Advance the enumeration after a transfer on the default control endpoint
+    ///
+    /// Returns a Configure Endpoint command when the controller must be told
+    /// about the class driver's endpoint.
+    pub fn on_control_completed(&mut self, regs: &Registers, success: bool) -> Option<Trb> {
+        if !success {
+            self.state = DeviceState::Failed;
+            return None;
+        }
+        match self.state {
+            DeviceState::GettingDeviceDescriptor => {
+                self.descriptor = DeviceDescriptor::parse(self.buffer());
+                self.state = DeviceState::GettingConfiguration;
+                self.control_transfer(
+                    regs,
+                    SetupPacket::get_descriptor(
+                        DESCRIPTOR_CONFIGURATION,
+                        0,
+                        DESCRIPTOR_BUFFER_SIZE as u16,
+                    ),
+                );
+            }
+            DeviceState::GettingConfiguration => {
+                self.find_hid_interface();
+                if self.hid.is_none() {
+                    self.state = DeviceState::Unsupported;
+                    return None;
+                }
+                self.state = DeviceState::SettingConfiguration;
+                self.control_transfer(
+                    regs,
+                    SetupPacket::set_configuration(self.configuration_value),
+                );
+            }
+            DeviceState::SettingConfiguration => {
+                let hid = self.hid?;
+                self.state = DeviceState::SettingProtocol;
+                self.control_transfer(regs, SetupPacket::set_protocol(hid.interface, 0));
+            }
+            DeviceState::SettingProtocol => {
+                let hid = self.hid?;
+                if hid.kind == HidKind::Keyboard {
+                    self.state = DeviceState::SettingIdle;
+                    self.control_transfer(regs, SetupPacket::set_idle(hid.interface, 0));
+                } else {
+                    return self.configure_endpoint();
+                }
+            }
+            DeviceState::SettingIdle => return self.configure_endpoint(),
+            _ => {}
+        }
+        None
+    }
+
+    fn find_hid_interface(&mut self) {
+        let mut configuration_value = self.configuration_value;
+        let mut hid = None;
+        let mut current = None;
+        for descriptor in Descriptors::new(self.buffer()) {
+            match descriptor {
+                Descriptor::Configuration { value, .. } => configuration_value = value,
+                Descriptor::Interface(interface) => {
+                    current = match (interface.class, interface.subclass, interface.protocol) {
+                        (CLASS_HID, HID_SUBCLASS_BOOT, HID_PROTOCOL_KEYBOARD) => {
+                            Some((HidKind::Keyboard, interface.number))
+                        }
+                        (CLASS_HID, HID_SUBCLASS_BOOT, HID_PROTOCOL_MOUSE) => {
+                            Some((HidKind::Mouse, interface.number))
+                        }
+                        _ => None,
+                    };
+                }
+                Descriptor::Endpoint(endpoint) if endpoint.is_in() && endpoint.is_interrupt() => {
+                    if let Some((kind, interface)) = current.take() {
+                        hid = Some(HidInterface {
+                            kind,
+                            interface,
+                            endpoint,
+                        });
+                        break;
+                    }
+                }
+                _ => {}
+            }
+        }
+        self.configuration_value = configuration_value;
+        self.hid = hid;
+    }
+
+    fn configure_endpoint(&mut self) -> Option<Trb> {
+        let hid = self.hid?;
+        let ring = match Ring::new(TRANSFER_RING_SIZE) {
+            Ok(ring) => ring,
+            Err(_) => {
+                self.state = DeviceState::Failed;
+                return None;
+            }
+        };
+        let dci = dci_of(&hid.endpoint);
+        let ctx = &self.input_context;
+        ctx.set_add_flags(1 | 1 << dci);
+        ctx.set_context_entries(dci);
+        ctx.set_endpoint(&EndpointConfig {
+            dci,
+            ep_type: EP_TYPE_INTERRUPT_IN,
+            max_packet_size: hid.endpoint.max_packet_size,
+            interval: self.speed.interval(hid.endpoint.interval),
+            dequeue: ring.base(),
+            cycle: ring.cycle(),
+        });
+        self.interrupt_ring = Some(ring);
+        self.state = DeviceState::ConfiguringEndpoint;
+        Some(Trb::configure_endpoint(ctx.address(), self.slot_id))
+    }
+
+    /// Start polling the interrupt endpoint once the controller knows it
+    pub fn on_endpoint_configured(&mut self, regs: &Registers, success: bool) {
+        let Some(hid) = self.hid else {
+            return;
+        };
+        if !success {
+            self.state = DeviceState::Failed;
+            return;
+        }
+        self.driver = Some(HidDriver::new(hid.kind));
+        self.state = DeviceState::Running;
+        self.request_report(regs);
+    }
+
+    fn request_report(&mut self, regs: &Registers) {
+        let (Some(hid), Some(ring)) = (self.hid, self.interrupt_ring.as_mut()) else {
+            return;
+        };
+        let length = (hid.endpoint.max_packet_size as usize).min(REPORT_BUFFER_SIZE);
+        ring.push(Trb::normal(self.report as u64, length as u32));
+        regs.ring_doorbell(self.slot_id, dci_of(&hid.endpoint));
+    }
+
+    /// Hand a completed interrupt transfer to the class driver and queue the next one
+    pub fn on_interrupt_completed(&mut self, regs: &Registers, event: &Trb, success: bool) {
+        let Some(hid) = self.hid else {
+            return;
+        };
+        if success && let Some(driver) = self.driver.as_mut() {
+            let requested = (hid.endpoint.max_packet_size as usize).min(REPORT_BUFFER_SIZE);
+            let length = requested.saturating_sub(event.transfer_length() as usize);
+            let report = unsafe { core::slice::from_raw_parts(self.report, length) };
+            driver.on_report(report);
+        }
+        self.request_report(regs);
+    }
+
+    pub fn is_interrupt_endpoint(&self, dci: u8) -> bool {
+        self.hid.is_some_and(|hid| dci_of(&hid.endpoint) == dci)
+    }
+}
+
+/// Device context index of an endpoint: `2 * number + direction`
+fn dci_of(endpoint: &EndpointDescriptor) -> u8 {
+    endpoint.number() * 2 + endpoint.is_in() as u8
+}
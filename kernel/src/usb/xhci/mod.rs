@@ -0,0 +1,525 @@
+//! xHCI host controller driver
+//!
+//! Events are processed from the kernel main loop by [`poll`]; the MSI
+//! handler only marks that work is pending.
+pub mod context;
+pub mod device;
+pub mod registers;
+pub mod ring;
+pub mod trb;
+
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use spin::Mutex;
+
+use crate::{
+    apic, dma,
+    interrupt::{self, InterruptFrame},
+    message::Message,
+    pci::{self, Device as PciDevice},
+    task,
+    timer::{self, TimerAction, TimerId},
+};
+use device::{DCI_EP0, DeviceState, UsbDevice};
+use registers::{
+    IMAN_ENABLE, IMAN_PENDING, PORTSC_CONNECT_CHANGE, PORTSC_CURRENT_CONNECT, PORTSC_ENABLED,
+    PORTSC_POWER, PORTSC_RESET, PORTSC_RESET_CHANGE, Registers, USBCMD_HC_RESET,
+    USBCMD_INTERRUPTER_ENABLE, USBCMD_RUN_STOP, USBSTS_CONTROLLER_NOT_READY,
+    USBSTS_EVENT_INTERRUPT, USBSTS_HC_HALTED,
+};
+use ring::{EventRing, Ring, RingError};
+use trb::{
+    COMPLETION_SHORT_PACKET, COMPLETION_SUCCESS, TYPE_ADDRESS_DEVICE, TYPE_COMMAND_COMPLETION,
+    TYPE_CONFIGURE_ENDPOINT, TYPE_ENABLE_SLOT, TYPE_PORT_STATUS_CHANGE, TYPE_TRANSFER_EVENT, Trb,
+};
+
+/// Slots the driver enables (and devices it can track)
+const MAX_SLOTS: usize = 8;
+const MAX_PORTS: usize = 32;
+const COMMAND_RING_SIZE: usize = 32;
+const EVENT_RING_SIZE: usize = 64;
+/// Interrupt moderation interval in 250 ns units (1 ms)
+const INTERRUPT_MODERATION: u32 = 4000;
+const PAGE_SIZE: usize = 4096;
+/// Event ring polling interval when neither MSI nor MSI-X is available
+const POLL_INTERVAL_MS: u64 = 10;
+/// Time a newly connected device gets to settle before the port reset
+/// (USB 2.0 TATTDB)
+const DEBOUNCE_MS: u64 = 100;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum XhciError {
+    NoController,
+    NoMmioBar,
+    OutOfMemory,
+    AlreadyInitialized,
+}
+
+impl From<RingError> for XhciError {
+    fn from(_: RingError) -> Self {
+        XhciError::OutOfMemory
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PortSpeed {
+    Full,
+    Low,
+    High,
+    Super,
+    Other(u8),
+}
+
+impl PortSpeed {
+    fn from_portsc(portsc: u32) -> Self {
+        match (portsc >> 10) & 0xf {
+            1 => Self::Full,
+            2 => Self::Low,
+            3 => Self::High,
+            4 => Self::Super,
+            other => Self::Other(other as u8),
+        }
+    }
+
+    pub fn id(&self) -> u8 {
+        match *self {
+            Self::Full => 1,
+            Self::Low => 2,
+            Self::High => 3,
+            Self::Super => 4,
+            Self::Other(id) => id,
+        }
+    }
+
+    pub fn default_max_packet_size(&self) -> u16 {
+        match self {
+            Self::Super | Self::Other(_) => 512,
+            Self::High => 64,
+            Self::Full | Self::Low => 8,
+        }
+    }
+
+    /// Convert `bInterval` of an interrupt endpoint to the xHCI Interval field
+    pub fn interval(&self, b_interval: u8) -> u8 {
+        match self {
+            // 2^(Interval) * 125us, bInterval はフレーム (ms) 単位
+            Self::Full | Self::Low => {
+                let microframes = (b_interval.max(1) as u32) * 8;
+                (31 - microframes.leading_zeros()) as u8
+            }
+            _ => b_interval.clamp(1, 16) - 1,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum PortPhase {
+    Disconnected,
+    /// Connected, waiting for the debounce timer to expire
+    Debouncing(TimerId),
+    /// Connected, waiting for another port to finish addressing
+    WaitingToReset,
+    Resetting,
+    EnablingSlot,
+    AddressingDevice,
+    Addressed,
+}
+
+pub struct Controller {
+    regs: Registers,
+    context_size: usize,
+    dcbaa: *mut u64,
+    command_ring: Ring,
+    event_ring: EventRing,
+    max_ports: u8,
+    ports: [PortPhase; MAX_PORTS + 1],
+    /// 同時にデフォルトアドレスを使えるのは 1 ポートだけ
+    addressing_port: Option<u8>,
+    devices: [Option<UsbDevice>; MAX_SLOTS + 1],
+}
+
+unsafe impl Send for Controller {}
+
+static CONTROLLER: Mutex<Option<Controller>> = Mutex::new(None);
+static PENDING: AtomicBool = AtomicBool::new(false);
+
+impl Controller {
+    /// # Safety
+    ///
+    /// `mmio_base` は xHC の BAR0 を指し、アイデンティティマップされている必要があります。
+    pub unsafe fn new(mmio_base: u64) -> Result<Self, XhciError> {
+        let regs = unsafe { Registers::new(mmio_base) };
+        regs.request_ownership();
+
+        if regs.usbsts() & USBSTS_HC_HALTED == 0 {
+            regs.set_usbcmd(regs.usbcmd() & !USBCMD_RUN_STOP);
+            while regs.usbsts() & USBSTS_HC_HALTED == 0 {
+                core::hint::spin_loop();
+            }
+        }
+        regs.set_usbcmd(regs.usbcmd() | USBCMD_HC_RESET);
+        while regs.usbcmd() & USBCMD_HC_RESET != 0 {
+            core::hint::spin_loop();
+        }
+        while regs.usbsts() & USBSTS_CONTROLLER_NOT_READY != 0 {
+            core::hint::spin_loop();
+        }
+
+        let max_slots = (regs.max_slots() as usize).min(MAX_SLOTS);
+        regs.set_max_slots_enabled(max_slots as u8);
+
+        let dcbaa = dma::allocate_array::<u64>(max_slots + 1, 64, PAGE_SIZE)
+            .ok_or(XhciError::OutOfMemory)?;
+        let scratchpads = regs.max_scratchpad_buffers();
+        if scratchpads > 0 {
+            let array = dma::allocate_array::<u64>(scratchpads, 64, PAGE_SIZE)
+                .ok_or(XhciError::OutOfMemory)?;
+            for i in 0..scratchpads {
+                let page = dma::allocate(PAGE_SIZE, PAGE_SIZE, 0).ok_or(XhciError::OutOfMemory)?;
+                unsafe { array.add(i).write(page as u64) };
+            }
+            unsafe { dcbaa.write(array as u64) };
+        }
+        regs.set_dcbaap(dcbaa as u64);
+
+        let command_ring = Ring::new(COMMAND_RING_SIZE)?;
+        regs.set_command_ring(command_ring.base() | command_ring.cycle() as u64);
+
+        let event_ring = EventRing::new(EVENT_RING_SIZE, &regs)?;
+        regs.set_imod(INTERRUPT_MODERATION);
+        regs.set_iman(IMAN_PENDING | IMAN_ENABLE);
+
+        Ok(Self {
+            regs,
+            context_size: regs.context_size(),
+            dcbaa,
+            command_ring,
+            event_ring,
+            max_ports: regs.max_ports().min(MAX_PORTS as u8),
+            ports: [PortPhase::Disconnected; MAX_PORTS + 1],
+            addressing_port: None,
+            devices: core::array::from_fn(|_| None),
+        })
+    }
+
+    /// Start the controller and pick up devices that are already connected
+    pub fn run(&mut self) {
+        self.regs
+            .set_usbcmd(self.regs.usbcmd() | USBCMD_INTERRUPTER_ENABLE | USBCMD_RUN_STOP);
+        while self.regs.usbsts() & USBSTS_HC_HALTED != 0 {
+            core::hint::spin_loop();
+        }
+        for port in 1..=self.max_ports {
+            if self.regs.portsc(port) & PORTSC_POWER == 0 {
+                self.regs.update_portsc(port, PORTSC_POWER, 0);
+            }
+            self.on_port_status_change(port);
+        }
+    }
+
+    /// Process every event the controller has posted
+    pub fn process_events(&mut self) {
+        self.regs.clear_usbsts(USBSTS_EVENT_INTERRUPT);
+        self.regs.set_iman(self.regs.iman() | IMAN_PENDING);
+        while let Some(event) = self.event_ring.front() {
+            match event.trb_type() {
+                TYPE_PORT_STATUS_CHANGE => self.on_port_status_change(event.port_id()),
+                TYPE_COMMAND_COMPLETION => self.on_command_completion(&event),
+                TYPE_TRANSFER_EVENT => self.on_transfer_event(&event),
+                _ => {}
+            }
+            self.event_ring.pop(&self.regs);
+        }
+    }
+
+    fn push_command(&mut self, trb: Trb) {
+        self.command_ring.push(trb);
+        self.regs.ring_doorbell(0, 0);
+    }
+
+    fn on_port_status_change(&mut self, port: u8) {
+        if port == 0 || port > self.max_ports {
+            return;
+        }
+        let portsc = self.regs.portsc(port);
+        let phase = self.ports[port as usize];
+
+        if portsc & PORTSC_CURRENT_CONNECT == 0 {
+            self.regs.update_portsc(
+                port,
+                0,
+                portsc & (PORTSC_CONNECT_CHANGE | PORTSC_RESET_CHANGE),
+            );
+            if phase != PortPhase::Disconnected {
+                self.on_disconnected(port);
+            }
+            return;
+        }
+
+        match phase {
+            PortPhase::Disconnected => {
+                self.regs
+                    .update_portsc(port, 0, portsc & PORTSC_CONNECT_CHANGE);
+                self.start_debounce(port);
+            }
+            // 接続が揺れたので待ち直す
+            PortPhase::Debouncing(timer) if portsc & PORTSC_CONNECT_CHANGE != 0 => {
+                self.regs
+                    .update_portsc(port, 0, portsc & PORTSC_CONNECT_CHANGE);
+                timer::cancel(timer);
+                self.start_debounce(port);
+            }
+            PortPhase::Resetting if portsc & PORTSC_RESET_CHANGE != 0 => {
+                self.regs.update_portsc(port, 0, PORTSC_RESET_CHANGE);
+                if portsc & PORTSC_ENABLED != 0 {
+                    self.ports[port as usize] = PortPhase::EnablingSlot;
+                    self.push_command(Trb::enable_slot());
+                } else {
+                    self.finish_addressing(port, PortPhase::Disconnected);
+                }
+            }
+            _ => {
+                self.regs.update_portsc(
+                    port,
+                    0,
+                    portsc & (PORTSC_CONNECT_CHANGE | PORTSC_RESET_CHANGE),
+                );
+            }
+        }
+    }
+
+    fn start_debounce(&mut self, port: u8) {
+        let timer = timer::one_shot_ms(
+            DEBOUNCE_MS,
+            TimerAction::Message {
+                task: task::main_id(),
+                value: port as u64,
+            },
+        );
+        self.ports[port as usize] = PortPhase::Debouncing(timer);
+    }
+
+    fn on_debounced(&mut self, port: u8) {
+        if !matches!(self.ports[port as usize], PortPhase::Debouncing(_)) {
+            return;
+        }
+        if self.regs.portsc(port) & PORTSC_CURRENT_CONNECT == 0 {
+            self.ports[port as usize] = PortPhase::Disconnected;
+        } else if self.addressing_port.is_none() {
+            self.reset_port(port);
+        } else {
+            self.ports[port as usize] = PortPhase::WaitingToReset;
+        }
+    }
+
+    fn reset_port(&mut self, port: u8) {
+        self.addressing_port = Some(port);
+        self.ports[port as usize] = PortPhase::Resetting;
+        self.regs
+            .update_portsc(port, PORTSC_RESET, PORTSC_CONNECT_CHANGE);
+    }
+
+    /// Release the default address and start the next waiting port
+    fn finish_addressing(&mut self, port: u8, phase: PortPhase) {
+        self.ports[port as usize] = phase;
+        if self.addressing_port == Some(port) {
+            self.addressing_port = None;
+        }
+        if self.addressing_port.is_none()
+            && let Some(next) =
+                (1..=self.max_ports).find(|&p| self.ports[p as usize] == PortPhase::WaitingToReset)
+        {
+            self.reset_port(next);
+        }
+    }
+
+    fn on_disconnected(&mut self, port: u8) {
+        if let PortPhase::Debouncing(timer) = self.ports[port as usize] {
+            timer::cancel(timer);
+        }
+        for slot in self.devices.iter_mut() {
+            if slot.as_ref().is_some_and(|d| d.port == port) {
+                let slot_id = slot.take().map_or(0, |d| d.slot_id);
+                unsafe { self.dcbaa.add(slot_id as usize).write_volatile(0) };
+            }
+        }
+        self.finish_addressing(port, PortPhase::Disconnected);
+    }
+
+    fn on_command_completion(&mut self, event: &Trb) {
+        let issuer = unsafe { (event.parameter as *const Trb).read_volatile() };
+        let success = event.completion_code() == COMPLETION_SUCCESS;
+        let slot_id = event.slot_id();
+
+        match issuer.trb_type() {
+            TYPE_ENABLE_SLOT => {
+                let Some(port) = self.addressing_port else {
+                    return;
+                };
+                if !success || slot_id as usize > MAX_SLOTS {
+                    self.finish_addressing(port, PortPhase::Disconnected);
+                    return;
+                }
+                let speed = PortSpeed::from_portsc(self.regs.portsc(port));
+                let Ok(device) = UsbDevice::new(slot_id, port, speed, self.context_size) else {
+                    self.finish_addressing(port, PortPhase::Disconnected);
+                    return;
+                };
+                unsafe {
+                    self.dcbaa
+                        .add(slot_id as usize)
+                        .write_volatile(device.device_context())
+                };
+                let command = device.address_device_command();
+                self.devices[slot_id as usize] = Some(device);
+                self.ports[port as usize] = PortPhase::AddressingDevice;
+                self.push_command(command);
+            }
+            TYPE_ADDRESS_DEVICE => {
+                let regs = self.regs;
+                let Some(device) = self.device_mut(slot_id) else {
+                    return;
+                };
+                let port = device.port;
+                if success {
+                    device.on_addressed(&regs);
+                } else {
+                    device.state = DeviceState::Failed;
+                }
+                self.finish_addressing(port, PortPhase::Addressed);
+            }
+            TYPE_CONFIGURE_ENDPOINT => {
+                let regs = self.regs;
+                if let Some(device) = self.device_mut(slot_id) {
+                    device.on_endpoint_configured(&regs, success);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn on_transfer_event(&mut self, event: &Trb) {
+        let regs = self.regs;
+        let success = matches!(
+            event.completion_code(),
+            COMPLETION_SUCCESS | COMPLETION_SHORT_PACKET
+        );
+        let dci = event.endpoint_id();
+        let Some(device) = self.device_mut(event.slot_id()) else {
+            return;
+        };
+        if dci == DCI_EP0 {
+            if let Some(command) = device.on_control_completed(&regs, success) {
+                self.push_command(command);
+            }
+        } else if device.is_interrupt_endpoint(dci) {
+            device.on_interrupt_completed(&regs, event, success);
+        }
+    }
+
+    fn device_mut(&mut self, slot_id: u8) -> Option<&mut UsbDevice> {
+        self.devices.get_mut(slot_id as usize)?.as_mut()
+    }
+
+    /// Devices that finished enumeration, with their slot, port and class state
+    pub fn devices(&self) -> impl Iterator<Item = &UsbDevice> {
+        self.devices.iter().flatten()
+    }
+}
+
+fn on_interrupt(_frame: &mut InterruptFrame) {
+    notify_event_loop();
+}
+
+fn on_poll_timer(_id: TimerId, _value: u64) {
+    notify_event_loop();
+}
+
+fn notify_event_loop() {
+    // 処理待ちのメッセージがあれば追加しない
+    if !PENDING.swap(true, Ordering::AcqRel) {
+        let _ = task::send_message(task::main_id(), Message::XhciInterrupt);
+    }
+}
+
+/// Find the first xHC, reset it and start enumerating its ports
+pub fn init() -> Result<(), XhciError> {
+    let pci_device = find_controller().ok_or(XhciError::NoController)?;
+    let mmio_base = pci_device
+        .bar(0)
+        .ok()
+        .and_then(|bar| bar.memory_base())
+        .ok_or(XhciError::NoMmioBar)?;
+    pci_device.enable_bus_master();
+
+    let mut controller = unsafe { Controller::new(mmio_base)? };
+    setup_interrupt(pci_device);
+
+    let mut slot = CONTROLLER.lock();
+    if slot.is_some() {
+        return Err(XhciError::AlreadyInitialized);
+    }
+    controller.run();
+    *slot = Some(controller);
+    Ok(())
+}
+
+fn find_controller() -> Option<&'static PciDevice> {
+    // Intel 製を優先する (EHCI と共有しているポートを持つことがあるため)
+    let mut controllers = pci::find_by_class_interface(0x0c, 0x03, 0x30);
+    let first = controllers.next()?;
+    if first.vendor_id == 0x8086 {
+        return Some(first);
+    }
+    controllers.find(|d| d.vendor_id == 0x8086).or(Some(first))
+}
+
+/// Prefer MSI, then MSI-X; without either, poll the event ring from a timer
+fn setup_interrupt(pci_device: &PciDevice) {
+    if let Some(vector) = interrupt::allocate_vector() {
+        interrupt::register_handler(vector, on_interrupt);
+        let apic_id = apic::local::id();
+        if pci_device.configure_msi(apic_id, vector, 1).is_ok() {
+            return;
+        }
+        if let Ok(table) = pci_device.msix_table() {
+            table.set_vector(0, apic_id, vector);
+            if pci_device.enable_msix().is_ok() {
+                return;
+            }
+        }
+        interrupt::free_vector(vector);
+    }
+    timer::periodic_ms(
+        POLL_INTERVAL_MS,
+        TimerAction::Callback {
+            function: on_poll_timer,
+            value: 0,
+        },
+    );
+}
+
+/// Handle pending controller events; called by the kernel event loop on
+/// [`Message::XhciInterrupt`]
+pub fn poll() {
+    PENDING.store(false, Ordering::Release);
+    if let Some(controller) = CONTROLLER.lock().as_mut() {
+        controller.process_events();
+    }
+}
+
+/// Go on with `port` after its connection settled; called by the kernel event
+/// loop on [`Message::TimerTick`] carrying the port number
+pub fn on_debounce_timer(port: u64) {
+    if let Some(controller) = CONTROLLER.lock().as_mut()
+        && let Ok(port) = u8::try_from(port)
+        && (1..=controller.max_ports).contains(&port)
+    {
+        controller.on_debounced(port);
+    }
+}
+
+/// Run `f` with the controller, if one was initialized
+pub fn with_controller<R>(f: impl FnOnce(&Controller) -> R) -> Option<R> {
+    CONTROLLER.lock().as_ref().map(f)
+}
@@ -0,0 +1,183 @@
+//! xHCI MMIO register access
+
+pub const USBCMD_RUN_STOP: u32 = 1 << 0;
+pub const USBCMD_HC_RESET: u32 = 1 << 1;
+pub const USBCMD_INTERRUPTER_ENABLE: u32 = 1 << 2;
+
+pub const USBSTS_HC_HALTED: u32 = 1 << 0;
+pub const USBSTS_EVENT_INTERRUPT: u32 = 1 << 3;
+pub const USBSTS_CONTROLLER_NOT_READY: u32 = 1 << 11;
+
+pub const PORTSC_CURRENT_CONNECT: u32 = 1 << 0;
+pub const PORTSC_ENABLED: u32 = 1 << 1;
+pub const PORTSC_RESET: u32 = 1 << 4;
+pub const PORTSC_POWER: u32 = 1 << 9;
+pub const PORTSC_CONNECT_CHANGE: u32 = 1 << 17;
+pub const PORTSC_RESET_CHANGE: u32 = 1 << 21;
+/// Bits that are cleared by writing 1, including PED
+const PORTSC_RW1C: u32 = PORTSC_ENABLED | 0x7f << 17;
+
+pub const IMAN_PENDING: u32 = 1 << 0;
+pub const IMAN_ENABLE: u32 = 1 << 1;
+pub const ERDP_EVENT_HANDLER_BUSY: u64 = 1 << 3;
+
+const EXTENDED_CAP_LEGACY_SUPPORT: u8 = 1;
+const LEGACY_BIOS_OWNED: u32 = 1 << 16;
+const LEGACY_OS_OWNED: u32 = 1 << 24;
+
+#[derive(Clone, Copy, Debug)]
+pub struct Registers {
+    capability: u64,
+    operational: u64,
+    runtime: u64,
+    doorbell: u64,
+}
+
+fn read32(addr: u64) -> u32 {
+    unsafe { (addr as *const u32).read_volatile() }
+}
+
+fn write32(addr: u64, value: u32) {
+    unsafe { (addr as *mut u32).write_volatile(value) }
+}
+
+/// 64-bit registers are accessed as two dwords, which every controller accepts
+fn write64(addr: u64, value: u64) {
+    write32(addr, value as u32);
+    write32(addr + 4, (value >> 32) as u32);
+}
+
+impl Registers {
+    /// # Safety
+    ///
+    /// `mmio_base` は xHC の BAR0 を指し、アイデンティティマップされている必要があります。
+    pub unsafe fn new(mmio_base: u64) -> Self {
+        let cap_length = read32(mmio_base) & 0xff;
+        Self {
+            capability: mmio_base,
+            operational: mmio_base + cap_length as u64,
+            runtime: mmio_base + (read32(mmio_base + 0x18) & !0x1f) as u64,
+            doorbell: mmio_base + (read32(mmio_base + 0x14) & !0b11) as u64,
+        }
+    }
+
+    pub fn max_slots(&self) -> u8 {
+        read32(self.capability + 0x04) as u8
+    }
+
+    pub fn max_ports(&self) -> u8 {
+        (read32(self.capability + 0x04) >> 24) as u8
+    }
+
+    pub fn max_scratchpad_buffers(&self) -> usize {
+        let hcsparams2 = read32(self.capability + 0x08);
+        (((hcsparams2 >> 21) & 0x1f) << 5 | (hcsparams2 >> 27) & 0x1f) as usize
+    }
+
+    /// Size of one context structure (32 or 64 bytes, HCCPARAMS1.CSZ)
+    pub fn context_size(&self) -> usize {
+        if read32(self.capability + 0x10) & (1 << 2) != 0 {
+            64
+        } else {
+            32
+        }
+    }
+
+    fn extended_capabilities(&self) -> u64 {
+        let offset = (read32(self.capability + 0x10) >> 16) as u64;
+        if offset == 0 {
+            0
+        } else {
+            self.capability + offset * 4
+        }
+    }
+
+    /// Take the controller over from firmware (USB Legacy Support capability)
+    pub fn request_ownership(&self) {
+        let mut cap = self.extended_capabilities();
+        while cap != 0 {
+            let header = read32(cap);
+            if header as u8 == EXTENDED_CAP_LEGACY_SUPPORT {
+                if header & LEGACY_BIOS_OWNED == 0 {
+                    return;
+                }
+                write32(cap, header | LEGACY_OS_OWNED);
+                while read32(cap) & (LEGACY_BIOS_OWNED | LEGACY_OS_OWNED) != LEGACY_OS_OWNED {
+                    core::hint::spin_loop();
+                }
+                return;
+            }
+            let next = ((header >> 8) & 0xff) as u64;
+            cap = if next == 0 { 0 } else { cap + next * 4 };
+        }
+    }
+
+    pub fn usbcmd(&self) -> u32 {
+        read32(self.operational)
+    }
+
+    pub fn set_usbcmd(&self, value: u32) {
+        write32(self.operational, value)
+    }
+
+    pub fn usbsts(&self) -> u32 {
+        read32(self.operational + 0x04)
+    }
+
+    pub fn clear_usbsts(&self, bits: u32) {
+        write32(self.operational + 0x04, bits)
+    }
+
+    pub fn set_command_ring(&self, value: u64) {
+        write64(self.operational + 0x18, value)
+    }
+
+    pub fn set_dcbaap(&self, value: u64) {
+        write64(self.operational + 0x30, value)
+    }
+
+    pub fn set_max_slots_enabled(&self, slots: u8) {
+        let config = read32(self.operational + 0x38);
+        write32(self.operational + 0x38, (config & !0xff) | slots as u32)
+    }
+
+    /// `port` is 1-origin
+    pub fn portsc(&self, port: u8) -> u32 {
+        read32(self.operational + 0x400 + 0x10 * (port as u64 - 1))
+    }
+
+    /// Write PORTSC keeping the RW bits, setting `set` and clearing the RW1C `clear` bits
+    pub fn update_portsc(&self, port: u8, set: u32, clear: u32) {
+        let value = (self.portsc(port) & !PORTSC_RW1C) | set | clear;
+        write32(self.operational + 0x400 + 0x10 * (port as u64 - 1), value)
+    }
+
+    fn interrupter(&self, index: u64) -> u64 {
+        self.runtime + 0x20 + 0x20 * index
+    }
+
+    pub fn iman(&self) -> u32 {
+        read32(self.interrupter(0))
+    }
+
+    pub fn set_iman(&self, value: u32) {
+        write32(self.interrupter(0), value)
+    }
+
+    pub fn set_imod(&self, value: u32) {
+        write32(self.interrupter(0) + 0x04, value)
+    }
+
+    pub fn set_erst(&self, base: u64, size: u32) {
+        write32(self.interrupter(0) + 0x08, size);
+        write64(self.interrupter(0) + 0x10, base);
+    }
+
+    pub fn set_erdp(&self, value: u64) {
+        write64(self.interrupter(0) + 0x18, value)
+    }
+
+    pub fn ring_doorbell(&self, slot: u8, target: u8) {
+        write32(self.doorbell + 4 * slot as u64, target as u32)
+    }
+}
@@ -0,0 +1,137 @@
+//! Producer rings (command / transfer) and the consumer event ring
+
+use crate::dma;
+
+use super::{
+    registers::{ERDP_EVENT_HANDLER_BUSY, Registers},
+    trb::Trb,
+};
+
+/// Rings must not cross a 64 KiB boundary
+const RING_BOUNDARY: usize = 64 * 1024;
+const RING_ALIGN: usize = 64;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RingError {
+    OutOfMemory,
+}
+
+/// Command or transfer ring; the last TRB links back to the first one
+#[derive(Debug)]
+pub struct Ring {
+    buf: *mut Trb,
+    size: usize,
+    write_index: usize,
+    cycle: bool,
+}
+
+impl Ring {
+    pub fn new(size: usize) -> Result<Self, RingError> {
+        let buf = dma::allocate_array::<Trb>(size, RING_ALIGN, RING_BOUNDARY)
+            .ok_or(RingError::OutOfMemory)?;
+        Ok(Self {
+            buf,
+            size,
+            write_index: 0,
+            cycle: true,
+        })
+    }
+
+    pub fn base(&self) -> u64 {
+        self.buf as u64
+    }
+
+    pub fn cycle(&self) -> bool {
+        self.cycle
+    }
+
+    /// Enqueue `trb` and return its physical address
+    pub fn push(&mut self, mut trb: Trb) -> u64 {
+        trb.set_cycle(self.cycle);
+        let addr = self.write(trb);
+        self.write_index += 1;
+        if self.write_index == self.size - 1 {
+            let mut link = Trb::link(self.base());
+            link.set_cycle(self.cycle);
+            self.write(link);
+            self.write_index = 0;
+            self.cycle = !self.cycle;
+        }
+        addr
+    }
+
+    fn write(&mut self, trb: Trb) -> u64 {
+        let slot = unsafe { self.buf.add(self.write_index) };
+        // サイクルビットを含む最後の dword は最後に書く
+        unsafe {
+            let words = slot as *mut u32;
+            words.write_volatile(trb.parameter as u32);
+            words.add(1).write_volatile((trb.parameter >> 32) as u32);
+            words.add(2).write_volatile(trb.status);
+            words.add(3).write_volatile(trb.control);
+        }
+        slot as u64
+    }
+}
+
+#[repr(C, align(64))]
+#[derive(Clone, Copy, Debug, Default)]
+struct EventRingSegmentTableEntry {
+    base: u64,
+    size: u32,
+    reserved: u32,
+}
+
+/// Single-segment event ring of interrupter 0
+#[derive(Debug)]
+pub struct EventRing {
+    buf: *mut Trb,
+    size: usize,
+    read_index: usize,
+    cycle: bool,
+}
+
+impl EventRing {
+    pub fn new(size: usize, regs: &Registers) -> Result<Self, RingError> {
+        let buf = dma::allocate_array::<Trb>(size, RING_ALIGN, RING_BOUNDARY)
+            .ok_or(RingError::OutOfMemory)?;
+        let erst = dma::allocate_array::<EventRingSegmentTableEntry>(1, RING_ALIGN, 0)
+            .ok_or(RingError::OutOfMemory)?;
+        unsafe {
+            erst.write(EventRingSegmentTableEntry {
+                base: buf as u64,
+                size: size as u32,
+                reserved: 0,
+            })
+        };
+
+        regs.set_erst(erst as u64, 1);
+        regs.set_erdp(buf as u64);
+        Ok(Self {
+            buf,
+            size,
+            read_index: 0,
+            cycle: true,
+        })
+    }
+
+    /// The next event, if the controller has produced one
+    pub fn front(&self) -> Option<Trb> {
+        let trb = unsafe { self.buf.add(self.read_index).read_volatile() };
+        (trb.cycle() == self.cycle).then_some(trb)
+    }
+
+    pub fn pop(&mut self, regs: &Registers) {
+        self.read_index += 1;
+        if self.read_index == self.size {
+            self.read_index = 0;
+            self.cycle = !self.cycle;
+        }
+        let dequeue = unsafe { self.buf.add(self.read_index) } as u64;
+        regs.set_erdp(dequeue | ERDP_EVENT_HANDLER_BUSY);
+    }
+}
+
+// リングは DMA プールの領域を指すだけなので、ロック越しに別コンテキストへ渡してよい
+unsafe impl Send for Ring {}
+unsafe impl Send for EventRing {}
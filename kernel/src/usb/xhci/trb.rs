@@ -0,0 +1,138 @@
+//! Transfer Request Blocks
+
+use crate::usb::descriptor::SetupPacket;
+
+pub const TYPE_NORMAL: u8 = 1;
+pub const TYPE_SETUP_STAGE: u8 = 2;
+pub const TYPE_DATA_STAGE: u8 = 3;
+pub const TYPE_STATUS_STAGE: u8 = 4;
+pub const TYPE_LINK: u8 = 6;
+pub const TYPE_ENABLE_SLOT: u8 = 9;
+pub const TYPE_ADDRESS_DEVICE: u8 = 11;
+pub const TYPE_CONFIGURE_ENDPOINT: u8 = 12;
+pub const TYPE_TRANSFER_EVENT: u8 = 32;
+pub const TYPE_COMMAND_COMPLETION: u8 = 33;
+pub const TYPE_PORT_STATUS_CHANGE: u8 = 34;
+
+pub const COMPLETION_SUCCESS: u8 = 1;
+pub const COMPLETION_SHORT_PACKET: u8 = 13;
+
+const CYCLE: u32 = 1 << 0;
+const LINK_TOGGLE_CYCLE: u32 = 1 << 1;
+const INTERRUPT_ON_SHORT_PACKET: u32 = 1 << 2;
+const INTERRUPT_ON_COMPLETION: u32 = 1 << 5;
+const IMMEDIATE_DATA: u32 = 1 << 6;
+const DIRECTION_IN: u32 = 1 << 16;
+
+#[repr(C, align(16))]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Trb {
+    pub parameter: u64,
+    pub status: u32,
+    pub control: u32,
+}
+
+impl Trb {
+    fn new(trb_type: u8, parameter: u64, status: u32, flags: u32) -> Self {
+        Self {
+            parameter,
+            status,
+            control: (trb_type as u32) << 10 | flags,
+        }
+    }
+
+    pub fn trb_type(&self) -> u8 {
+        ((self.control >> 10) & 0x3f) as u8
+    }
+
+    pub fn cycle(&self) -> bool {
+        self.control & CYCLE != 0
+    }
+
+    pub fn set_cycle(&mut self, cycle: bool) {
+        self.control = (self.control & !CYCLE) | cycle as u32;
+    }
+
+    pub fn completion_code(&self) -> u8 {
+        (self.status >> 24) as u8
+    }
+
+    /// Bytes not transferred, for transfer events
+    pub fn transfer_length(&self) -> u32 {
+        self.status & 0x00ff_ffff
+    }
+
+    pub fn slot_id(&self) -> u8 {
+        (self.control >> 24) as u8
+    }
+
+    /// Device context index, for transfer events
+    pub fn endpoint_id(&self) -> u8 {
+        ((self.control >> 16) & 0x1f) as u8
+    }
+
+    /// Root hub port, for port status change events
+    pub fn port_id(&self) -> u8 {
+        (self.parameter >> 24) as u8
+    }
+
+    pub fn link(ring_base: u64) -> Self {
+        Self::new(TYPE_LINK, ring_base, 0, LINK_TOGGLE_CYCLE)
+    }
+
+    pub fn normal(buffer: u64, length: u32) -> Self {
+        Self::new(
+            TYPE_NORMAL,
+            buffer,
+            length,
+            INTERRUPT_ON_COMPLETION | INTERRUPT_ON_SHORT_PACKET,
+        )
+    }
+
+    pub fn setup_stage(setup: &SetupPacket) -> Self {
+        // TRT: 0 = No Data, 2 = OUT Data, 3 = IN Data
+        let transfer_type = match (setup.length, setup.is_device_to_host()) {
+            (0, _) => 0,
+            (_, false) => 2,
+            (_, true) => 3,
+        };
+        Self::new(
+            TYPE_SETUP_STAGE,
+            setup.to_u64(),
+            8,
+            IMMEDIATE_DATA | transfer_type << 16,
+        )
+    }
+
+    pub fn data_stage(buffer: u64, length: u32, dir_in: bool) -> Self {
+        let direction = if dir_in { DIRECTION_IN } else { 0 };
+        Self::new(TYPE_DATA_STAGE, buffer, length, direction)
+    }
+
+    pub fn status_stage(dir_in: bool) -> Self {
+        let direction = if dir_in { DIRECTION_IN } else { 0 };
+        Self::new(TYPE_STATUS_STAGE, 0, 0, direction | INTERRUPT_ON_COMPLETION)
+    }
+
+    pub fn enable_slot() -> Self {
+        Self::new(TYPE_ENABLE_SLOT, 0, 0, 0)
+    }
+
+    pub fn address_device(input_context: u64, slot_id: u8) -> Self {
+        Self::new(
+            TYPE_ADDRESS_DEVICE,
+            input_context,
+            0,
+            (slot_id as u32) << 24,
+        )
+    }
+
+    pub fn configure_endpoint(input_context: u64, slot_id: u8) -> Self {
+        Self::new(
+            TYPE_CONFIGURE_ENDPOINT,
+            input_context,
+            0,
+            (slot_id as u32) << 24,
+        )
+    }
+}
@@ -0,0 +1,187 @@
+//! Ring 3
+//!
+//! A user task is an ordinary task that set up an
+//! [`AddressSpace`](crate::paging::AddressSpace) and dropped to ring 3 with
+//! [`enter`]. System calls and interrupts bring it back on its kernel stack.
+//!
+//! The kernel reads and writes user memory only through [`copy_from_user`]
+//! and [`copy_to_user`]. A page fault inside them makes the copy fail with
+//! [`Error::BadAddress`] instead of bringing the kernel down.
+
+use alloc::{string::String, vec};
+use core::arch::{asm, global_asm};
+
+use common::syscall::Error;
+
+use crate::{
+    desktop,
+    interrupt::{self, InterruptFrame},
+    paging::{USER_END, USER_START},
+    segment, task, x86,
+};
+
+const PAGE_FAULT: u8 = 14;
+/// Interrupts enabled; bit 1 is always set
+const USER_RFLAGS: u64 = 0x202;
+/// A task killed by CPU exception `n` exits with `EXIT_EXCEPTION + n`
+pub const EXIT_EXCEPTION: i32 = 128;
+
+unsafe extern "C" {
+    /// Copy `len` bytes; returns `0`, or `1` if a page fault stopped it
+    fn user_copy(dst: *mut u8, src: *const u8, len: usize) -> u64;
+    static user_copy_start: u8;
+    static user_copy_end: u8;
+    static user_copy_fault: u8;
+}
+
+// rep movsb の途中でページフォルトが起きたら、ハンドラが user_copy_fault に飛ばす
+global_asm!(
+    r#"
+    .section .text
+    .global user_copy
+    .global user_copy_start
+    .global user_copy_end
+    .global user_copy_fault
+user_copy:
+    movq %rdx, %rcx
+user_copy_start:
+    rep movsb
+user_copy_end:
+    xorl %eax, %eax
+    ret
+user_copy_fault:
+    movl $1, %eax
+    ret
+"#,
+    options(att_syntax)
+);
+
+pub fn init() {
+    interrupt::register_handler(PAGE_FAULT, on_page_fault);
+}
+
+fn on_page_fault(frame: &mut InterruptFrame) {
+    let start = &raw const user_copy_start as u64;
+    let end = &raw const user_copy_end as u64;
+    if (start..end).contains(&frame.rip) {
+        frame.rip = &raw const user_copy_fault as u64;
+        return;
+    }
+    panic!(
+        "page fault at {:#x} accessing {:#x} (error code {:#x})",
+        frame.rip,
+        x86::read_cr2(),
+        frame.error_code
+    );
+}
+
+/// A CPU exception raised in ring 3 ends the task that caused it
+pub fn on_exception(frame: &InterruptFrame) -> ! {
+    // 例外ハンドラは割り込み禁止で呼ばれるが、後始末はロックを取るので許可しておく
+    x86::enable_interrupts();
+    exit(EXIT_EXCEPTION + frame.vector as i32)
+}
+
+/// End the current user task if it was killed; call where it could return
+/// to ring 3
+pub fn exit_if_killed() {
+    if task::is_killed() {
+        x86::enable_interrupts();
+        exit(task::EXIT_KILLED);
+    }
+}
+
+/// End the current user task, closing the windows it left open
+pub fn exit(code: i32) -> ! {
+    let id = task::current_id();
+    if desktop::with(|d| d.close_windows_of(id)).unwrap_or(false) {
+        desktop::request_redraw();
+    }
+    task::exit(code)
+}
+
+/// Whether `start..start + len` lies in user space
+pub fn is_user_range(start: u64, len: usize) -> bool {
+    start >= USER_START
+        && start
+            .checked_add(len as u64)
+            .is_some_and(|end| end <= USER_END)
+}
+
+pub fn copy_from_user(dst: &mut [u8], src: u64) -> Result<(), Error> {
+    if !is_user_range(src, dst.len()) {
+        return Err(Error::BadAddress);
+    }
+    match unsafe { user_copy(dst.as_mut_ptr(), src as *const u8, dst.len()) } {
+        0 => Ok(()),
+        _ => Err(Error::BadAddress),
+    }
+}
+
+pub fn copy_to_user(dst: u64, src: &[u8]) -> Result<(), Error> {
+    if !is_user_range(dst, src.len()) {
+        return Err(Error::BadAddress);
+    }
+    match unsafe { user_copy(dst as *mut u8, src.as_ptr(), src.len()) } {
+        0 => Ok(()),
+        _ => Err(Error::BadAddress),
+    }
+}
+
+/// Copy a `#[repr(C)]` value without padding to user memory
+pub fn write_value<T: Copy>(dst: u64, value: &T) -> Result<(), Error> {
+    let bytes =
+        unsafe { core::slice::from_raw_parts(value as *const T as *const u8, size_of::<T>()) };
+    copy_to_user(dst, bytes)
+}
+
+/// Copy a UTF-8 string of `len` bytes, at most `max` long
+pub fn read_string(src: u64, len: usize, max: usize) -> Result<String, Error> {
+    if len > max {
+        return Err(Error::InvalidArgument);
+    }
+    let mut bytes = vec![0; len];
+    copy_from_user(&mut bytes, src)?;
+    String::from_utf8(bytes).map_err(|_| Error::InvalidArgument)
+}
+
+/// Drop to ring 3 at `entry` on `stack`, with `arg0` and `arg1` in `rdi`
+/// and `rsi`
+///
+/// The current task must have switched to its address space. Nothing left
+/// on the kernel stack is dropped: it is reused for system calls.
+pub fn enter(entry: u64, stack: u64, arg0: u64, arg1: u64) -> ! {
+    x86::disable_interrupts();
+    unsafe {
+        asm!(
+            "push {ss}",
+            "push {stack}",
+            "push {rflags}",
+            "push {cs}",
+            "push {entry}",
+            // カーネルの値をユーザーに見せない
+            "xor eax, eax",
+            "xor ebx, ebx",
+            "xor ecx, ecx",
+            "xor edx, edx",
+            "xor ebp, ebp",
+            "xor r8d, r8d",
+            "xor r9d, r9d",
+            "xor r10d, r10d",
+            "xor r11d, r11d",
+            "xor r12d, r12d",
+            "xor r13d, r13d",
+            "xor r14d, r14d",
+            "xor r15d, r15d",
+            "iretq",
+            ss = in(reg) segment::USER_SS as u64,
+            stack = in(reg) stack,
+            rflags = in(reg) USER_RFLAGS,
+            cs = in(reg) segment::USER_CS as u64,
+            entry = in(reg) entry,
+            in("rdi") arg0,
+            in("rsi") arg1,
+            options(noreturn),
+        )
+    }
+}
@@ -0,0 +1,156 @@
+//! `/dev`: device nodes registered by name
+
+use alloc::{collections::BTreeMap, string::String, sync::Arc, vec::Vec};
+
+use spin::{Mutex, Once};
+
+use super::{DirEntry, FileSystem, Inode, Metadata, NodeKind, Result, VfsError};
+use crate::{console, serial, task};
+
+pub struct DevFs {
+    devices: Mutex<BTreeMap<String, Arc<dyn Inode>>>,
+}
+
+static DEVFS: Once<Arc<DevFs>> = Once::new();
+
+/// Add a device node; fails if the name is taken
+pub fn register(name: &str, device: Arc<dyn Inode>) -> Result<()> {
+    let devfs = DEVFS.get().ok_or(VfsError::NotFound)?;
+    let mut devices = devfs.devices.lock();
+    if devices.contains_key(name) {
+        return Err(VfsError::AlreadyExists);
+    }
+    devices.insert(String::from(name), device);
+    Ok(())
+}
+
+/// Create `/dev` with `console`, `serial` and `null`
+pub fn init() -> Arc<DevFs> {
+    let devfs = DEVFS
+        .call_once(|| {
+            Arc::new(DevFs {
+                devices: Mutex::new(BTreeMap::new()),
+            })
+        })
+        .clone();
+    let _ = register("console", Arc::new(Console));
+    if serial::is_present() {
+        let _ = register("serial", Arc::new(Serial));
+    }
+    let _ = register("null", Arc::new(Null));
+    devfs
+}
+
+impl FileSystem for DevFs {
+    fn root(&self) -> Arc<dyn Inode> {
+        DEVFS.get().unwrap().clone()
+    }
+}
+
+impl Inode for DevFs {
+    fn metadata(&self) -> Metadata {
+        Metadata {
+            kind: NodeKind::Directory,
+            size: self.devices.lock().len() as u64,
+        }
+    }
+
+    fn lookup(&self, name: &str) -> Result<Arc<dyn Inode>> {
+        self.devices
+            .lock()
+            .get(name)
+            .cloned()
+            .ok_or(VfsError::NotFound)
+    }
+
+    fn read_dir(&self) -> Result<Vec<DirEntry>> {
+        Ok(self
+            .devices
+            .lock()
+            .iter()
+            .map(|(name, device)| DirEntry {
+                name: name.clone(),
+                kind: device.metadata().kind,
+            })
+            .collect())
+    }
+
+    fn create(&self, _name: &str, _kind: NodeKind) -> Result<Arc<dyn Inode>> {
+        Err(VfsError::Unsupported)
+    }
+
+    fn remove(&self, _name: &str) -> Result<()> {
+        Err(VfsError::Unsupported)
+    }
+}
+
+const CHAR_DEVICE: Metadata = Metadata {
+    kind: NodeKind::CharDevice,
+    size: 0,
+};
+
+/// Keyboard input and console output
+struct Console;
+
+impl Inode for Console {
+    fn metadata(&self) -> Metadata {
+        CHAR_DEVICE
+    }
+
+    fn read_at(&self, _offset: u64, buf: &mut [u8]) -> Result<usize> {
+        Ok(console::read(buf))
+    }
+
+    fn write_at(&self, _offset: u64, buf: &[u8]) -> Result<usize> {
+        console::write(buf);
+        Ok(buf.len())
+    }
+}
+
+/// COM1
+struct Serial;
+
+impl Inode for Serial {
+    fn metadata(&self) -> Metadata {
+        CHAR_DEVICE
+    }
+
+    /// Waits for the first byte, then returns what has arrived
+    fn read_at(&self, _offset: u64, buf: &mut [u8]) -> Result<usize> {
+        let mut len = 0;
+        while len < buf.len() {
+            match serial::read_byte() {
+                Some(byte) => {
+                    buf[len] = byte;
+                    len += 1;
+                }
+                // 受信割り込みは使わないので、何もなければ 1 tick 待って見直す
+                None if len == 0 => task::sleep(1),
+                None => break,
+            }
+        }
+        Ok(len)
+    }
+
+    fn write_at(&self, _offset: u64, buf: &[u8]) -> Result<usize> {
+        serial::write(buf);
+        Ok(buf.len())
+    }
+}
+
+/// Discards writes, reads as empty
+struct Null;
+
+impl Inode for Null {
+    fn metadata(&self) -> Metadata {
+        CHAR_DEVICE
+    }
+
+    fn read_at(&self, _offset: u64, _buf: &mut [u8]) -> Result<usize> {
+        Ok(0)
+    }
+
+    fn write_at(&self, _offset: u64, buf: &[u8]) -> Result<usize> {
+        Ok(buf.len())
+    }
+}
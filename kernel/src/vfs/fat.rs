@@ -0,0 +1,182 @@
+//! FAT volumes in the VFS
+//!
+//! The FAT driver addresses files by path and by [`fat::DirEntry`]; each
+//! inode keeps the entry of its path. Inodes are shared per path so that a
+//! size change made through one open file is seen by all of them.
+
+use alloc::{
+    collections::BTreeMap,
+    string::String,
+    sync::{Arc, Weak},
+    vec::Vec,
+};
+
+use fs::fat;
+use spin::Mutex;
+
+use super::{DirEntry, FileSystem, Inode, Metadata, NodeKind, Result, VfsError, path};
+use crate::block;
+
+struct Volume {
+    fs: Mutex<fat::FileSystem<block::Device>>,
+    /// Live inodes by lowercase path
+    inodes: Mutex<BTreeMap<String, Weak<FatInode>>>,
+}
+
+impl Volume {
+    /// The shared inode for `path`, looking it up on disk if it has none
+    fn inode(self: &Arc<Self>, path: String) -> Result<Arc<FatInode>> {
+        let key = path.to_lowercase();
+        if let Some(inode) = self.inodes.lock().get(&key).and_then(Weak::upgrade) {
+            return Ok(inode);
+        }
+        let entry = self.fs.lock().lookup(&path)?;
+        Ok(self.insert(key, path, entry))
+    }
+
+    fn insert(self: &Arc<Self>, key: String, path: String, entry: fat::DirEntry) -> Arc<FatInode> {
+        let mut inodes = self.inodes.lock();
+        // 調べている間に他のタスクが作っていればそちらを使う
+        if let Some(inode) = inodes.get(&key).and_then(Weak::upgrade) {
+            return inode;
+        }
+        inodes.retain(|_, inode| inode.strong_count() > 0);
+        let inode = Arc::new(FatInode {
+            volume: self.clone(),
+            path,
+            entry: Mutex::new(entry),
+        });
+        inodes.insert(key, Arc::downgrade(&inode));
+        inode
+    }
+
+    fn is_open(&self, path: &str) -> bool {
+        self.inodes
+            .lock()
+            .get(&path.to_lowercase())
+            .is_some_and(|inode| inode.strong_count() > 0)
+    }
+}
+
+struct FatInode {
+    volume: Arc<Volume>,
+    /// Path inside the volume
+    path: String,
+    entry: Mutex<fat::DirEntry>,
+}
+
+impl Inode for FatInode {
+    fn metadata(&self) -> Metadata {
+        let entry = self.entry.lock();
+        Metadata {
+            kind: if entry.is_dir() {
+                NodeKind::Directory
+            } else {
+                NodeKind::File
+            },
+            size: entry.size as u64,
+        }
+    }
+
+    fn lookup(&self, name: &str) -> Result<Arc<dyn Inode>> {
+        if !self.entry.lock().is_dir() {
+            return Err(VfsError::NotADirectory);
+        }
+        Ok(self.volume.inode(path::join(&self.path, name))?)
+    }
+
+    fn read_dir(&self) -> Result<Vec<DirEntry>> {
+        let entry = self.entry.lock().clone();
+        let entries = self.volume.fs.lock().read_dir_entry(&entry)?;
+        Ok(entries
+            .into_iter()
+            .filter(|e| e.name != "." && e.name != "..")
+            .map(|e| DirEntry {
+                kind: if e.is_dir() {
+                    NodeKind::Directory
+                } else {
+                    NodeKind::File
+                },
+                name: e.name,
+            })
+            .collect())
+    }
+
+    fn create(&self, name: &str, kind: NodeKind) -> Result<Arc<dyn Inode>> {
+        let path = path::join(&self.path, name);
+        let entry = {
+            let mut fs = self.volume.fs.lock();
+            match kind {
+                NodeKind::File => fs.create_file(&path)?,
+                NodeKind::Directory => fs.create_dir(&path)?,
+                NodeKind::CharDevice => return Err(VfsError::Unsupported),
+            }
+        };
+        Ok(self.volume.insert(path.to_lowercase(), path, entry))
+    }
+
+    fn remove(&self, name: &str) -> Result<()> {
+        let path = path::join(&self.path, name);
+        // 開いているファイルのエントリを消すと、後の書き込みが別のエントリを壊す
+        if self.volume.is_open(&path) {
+            return Err(VfsError::Busy);
+        }
+        self.volume.fs.lock().remove(&path)?;
+        Ok(())
+    }
+
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<usize> {
+        let entry = self.entry.lock();
+        if entry.is_dir() {
+            return Err(VfsError::IsADirectory);
+        }
+        Ok(self.volume.fs.lock().read(&entry, offset, buf)?)
+    }
+
+    fn write_at(&self, offset: u64, buf: &[u8]) -> Result<usize> {
+        let mut entry = self.entry.lock();
+        if entry.is_dir() {
+            return Err(VfsError::IsADirectory);
+        }
+        self.volume.fs.lock().write(&mut entry, offset, buf)?;
+        Ok(buf.len())
+    }
+
+    fn truncate(&self, size: u64) -> Result<()> {
+        let size = u32::try_from(size).map_err(|_| VfsError::FileTooLarge)?;
+        let mut entry = self.entry.lock();
+        if entry.is_dir() {
+            return Err(VfsError::IsADirectory);
+        }
+        self.volume.fs.lock().truncate(&mut entry, size)?;
+        Ok(())
+    }
+}
+
+pub struct FatFs {
+    volume: Arc<Volume>,
+    root: Arc<FatInode>,
+}
+
+impl FatFs {
+    pub fn mount(device: block::Device) -> Result<Self> {
+        let fs = fat::FileSystem::mount(device)?;
+        let root = fs.root();
+        let volume = Arc::new(Volume {
+            fs: Mutex::new(fs),
+            inodes: Mutex::new(BTreeMap::new()),
+        });
+        let root = volume.insert(String::from("/"), String::from("/"), root);
+        Ok(Self { volume, root })
+    }
+}
+
+impl FileSystem for FatFs {
+    fn root(&self) -> Arc<dyn Inode> {
+        self.root.clone()
+    }
+
+    fn sync(&self) -> Result<()> {
+        Ok(self.volume.fs.lock().flush()?)
+    }
+}
@@ -0,0 +1,126 @@
+//! Per-task file descriptor tables
+//!
+//! A task starts with a copy of its creator's table, so the descriptors
+//! share open files (and their positions) with the creator's.
+
+use alloc::{sync::Arc, vec::Vec};
+
+use spin::Mutex;
+
+use super::{DirEntry, File, Result, SeekFrom, VfsError};
+use crate::task;
+
+pub type Fd = usize;
+
+/// Descriptors a task can hold at once
+pub const MAX_FILES: usize = 64;
+pub const STDIN: Fd = 0;
+pub const STDOUT: Fd = 1;
+pub const STDERR: Fd = 2;
+
+#[derive(Clone, Default)]
+pub struct FileTable {
+    files: Vec<Option<Arc<dyn File>>>,
+}
+
+impl FileTable {
+    pub const fn new() -> Self {
+        Self { files: Vec::new() }
+    }
+
+    /// Put `file` at the lowest free descriptor
+    pub fn insert(&mut self, file: Arc<dyn File>) -> Result<Fd> {
+        let fd = match self.files.iter().position(Option::is_none) {
+            Some(fd) => fd,
+            None if self.files.len() < MAX_FILES => {
+                self.files.push(None);
+                self.files.len() - 1
+            }
+            None => return Err(VfsError::TooManyOpenFiles),
+        };
+        self.files[fd] = Some(file);
+        Ok(fd)
+    }
+
+    /// Put `file` at `fd`, returning the file it replaces
+    pub fn insert_at(&mut self, fd: Fd, file: Arc<dyn File>) -> Result<Option<Arc<dyn File>>> {
+        if fd >= MAX_FILES {
+            return Err(VfsError::BadDescriptor);
+        }
+        if self.files.len() <= fd {
+            self.files.resize(fd + 1, None);
+        }
+        Ok(self.files[fd].replace(file))
+    }
+
+    pub fn get(&self, fd: Fd) -> Result<Arc<dyn File>> {
+        self.files
+            .get(fd)
+            .and_then(Option::clone)
+            .ok_or(VfsError::BadDescriptor)
+    }
+
+    pub fn remove(&mut self, fd: Fd) -> Result<Arc<dyn File>> {
+        self.files
+            .get_mut(fd)
+            .and_then(Option::take)
+            .ok_or(VfsError::BadDescriptor)
+    }
+
+    pub fn clear(&mut self) {
+        self.files.clear();
+    }
+}
+
+/// Make `console` descriptors 0, 1 and 2 of the current task
+pub fn init_stdio(console: Arc<dyn File>) {
+    let table = task::files();
+    let mut table = table.lock();
+    for fd in [STDIN, STDOUT, STDERR] {
+        let _ = table.insert_at(fd, console.clone());
+    }
+}
+
+/// The open file behind `fd` of the current task
+///
+/// The table lock is released before the caller uses the file, since reads
+/// may block.
+pub fn get(fd: Fd) -> Result<Arc<dyn File>> {
+    task::files().lock().get(fd)
+}
+
+pub fn open(path: &str, flags: u32) -> Result<Fd> {
+    let file = super::open(path, flags)?;
+    install(file)
+}
+
+/// Give the current task a descriptor for `file`
+pub fn install(file: Arc<dyn File>) -> Result<Fd> {
+    task::files().lock().insert(file)
+}
+
+pub fn read(fd: Fd, buf: &mut [u8]) -> Result<usize> {
+    get(fd)?.read(buf)
+}
+
+pub fn write(fd: Fd, buf: &[u8]) -> Result<usize> {
+    get(fd)?.write(buf)
+}
+
+pub fn seek(fd: Fd, position: SeekFrom) -> Result<u64> {
+    get(fd)?.seek(position)
+}
+
+pub fn read_dir(fd: Fd) -> Result<Option<DirEntry>> {
+    get(fd)?.read_dir()
+}
+
+pub fn close(fd: Fd) -> Result<()> {
+    let file = task::files().lock().remove(fd)?;
+    // 最後の参照ならここで閉じられる (ロックの外で)
+    drop(file);
+    Ok(())
+}
+
+/// Shared handle to a task's table, e.g. to set up a child's descriptors
+pub type SharedFileTable = Arc<Mutex<FileTable>>;
@@ -0,0 +1,128 @@
+//! Open files
+
+use alloc::{string::String, sync::Arc, vec::Vec};
+
+use spin::Mutex;
+
+use super::{DirEntry, Inode, Metadata, NodeKind, Result, VfsError};
+
+/// Flags for [`open`](super::open) and seek targets, shared with the system
+/// call ABI
+pub use common::syscall::{SeekFrom, open_flag};
+
+/// An open file description; descriptors duplicated from it share the
+/// position
+pub trait File: Send + Sync {
+    /// Read at the current position; `0` at the end of the file
+    fn read(&self, buf: &mut [u8]) -> Result<usize>;
+    fn write(&self, buf: &[u8]) -> Result<usize>;
+
+    /// Move the position; returns the new one
+    fn seek(&self, _position: SeekFrom) -> Result<u64> {
+        Err(VfsError::Unsupported)
+    }
+
+    fn metadata(&self) -> Result<Metadata>;
+
+    /// The next directory entry, `None` after the last one
+    fn read_dir(&self) -> Result<Option<DirEntry>> {
+        Err(VfsError::NotADirectory)
+    }
+}
+
+/// A file opened through the VFS
+pub struct InodeFile {
+    inode: Arc<dyn Inode>,
+    /// Normalized path it was opened by, for listing mount points
+    path: String,
+    flags: u32,
+    /// Byte offset, or the index of the next entry of a directory
+    position: Mutex<u64>,
+    /// Directory listing taken by the first [`File::read_dir`]
+    entries: Mutex<Option<Vec<DirEntry>>>,
+}
+
+impl InodeFile {
+    pub fn new(inode: Arc<dyn Inode>, path: String, flags: u32) -> Self {
+        Self {
+            inode,
+            path,
+            flags,
+            position: Mutex::new(0),
+            entries: Mutex::new(None),
+        }
+    }
+
+    fn is_stream(&self) -> bool {
+        self.inode.metadata().kind == NodeKind::CharDevice
+    }
+}
+
+impl File for InodeFile {
+    fn read(&self, buf: &mut [u8]) -> Result<usize> {
+        if self.flags & open_flag::READ == 0 {
+            return Err(VfsError::PermissionDenied);
+        }
+        if self.is_stream() {
+            return self.inode.read_at(0, buf);
+        }
+        // 同じ記述子を共有するタスク同士で位置がずれないよう、読む間ロックしておく
+        let mut position = self.position.lock();
+        let len = self.inode.read_at(*position, buf)?;
+        *position += len as u64;
+        Ok(len)
+    }
+
+    fn write(&self, buf: &[u8]) -> Result<usize> {
+        if self.flags & open_flag::WRITE == 0 {
+            return Err(VfsError::PermissionDenied);
+        }
+        if self.is_stream() {
+            return self.inode.write_at(0, buf);
+        }
+        let mut position = self.position.lock();
+        if self.flags & open_flag::APPEND != 0 {
+            *position = self.inode.metadata().size;
+        }
+        let len = self.inode.write_at(*position, buf)?;
+        *position += len as u64;
+        Ok(len)
+    }
+
+    fn seek(&self, target: SeekFrom) -> Result<u64> {
+        if self.is_stream() {
+            return Err(VfsError::Unsupported);
+        }
+        let size = self.inode.metadata().size;
+        let new = {
+            let mut position = self.position.lock();
+            *position = target
+                .resolve(*position, size)
+                .ok_or(VfsError::InvalidArgument)?;
+            *position
+        };
+        // 一覧は位置を戻したときに取り直す
+        *self.entries.lock() = None;
+        Ok(new)
+    }
+
+    fn metadata(&self) -> Result<Metadata> {
+        Ok(self.inode.metadata())
+    }
+
+    fn read_dir(&self) -> Result<Option<DirEntry>> {
+        let mut entries = self.entries.lock();
+        if entries.is_none() {
+            *entries = Some(super::list(&self.path, self.inode.as_ref())?);
+        }
+        let mut position = self.position.lock();
+        let entry = entries
+            .as_ref()
+            .and_then(|e| e.get(*position as usize))
+            .cloned();
+        if entry.is_some() {
+            *position += 1;
+        }
+        Ok(entry)
+    }
+}
@@ -0,0 +1,267 @@
+//! Virtual filesystem
+//!
+//! Filesystems expose their tree as [`Inode`]s and are attached to the
+//! namespace through the mount table. Paths are resolved lexically: `.` and
+//! `..` are folded before the longest matching mount point is looked up.
+//! Open files are [`File`]s, held by per-task descriptor tables in [`fd`].
+pub mod devfs;
+pub mod fat;
+pub mod fd;
+pub mod file;
+pub mod mount;
+pub mod path;
+pub mod pipe;
+pub mod ramfs;
+
+use alloc::{string::String, sync::Arc, vec::Vec};
+
+use fs::{block::BlockError, fat::FatError};
+
+use crate::block;
+pub use file::{File, InodeFile, SeekFrom, open_flag};
+use mount::mount;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VfsError {
+    NotFound,
+    NotADirectory,
+    IsADirectory,
+    AlreadyExists,
+    DirectoryNotEmpty,
+    /// Empty, too long or containing characters the filesystem rejects
+    InvalidPath,
+    ReadOnly,
+    NoSpace,
+    FileTooLarge,
+    /// The file or mount point is in use
+    Busy,
+    BadDescriptor,
+    TooManyOpenFiles,
+    /// The operation does not apply to this kind of file
+    Unsupported,
+    /// Opened without the access mode the operation needs
+    PermissionDenied,
+    InvalidArgument,
+    Io,
+    /// Writing to a pipe whose read end is closed
+    BrokenPipe,
+    /// The task was killed while it waited
+    Interrupted,
+}
+
+impl From<BlockError> for VfsError {
+    fn from(error: BlockError) -> Self {
+        match error {
+            BlockError::ReadOnly => Self::ReadOnly,
+            _ => Self::Io,
+        }
+    }
+}
+
+impl From<FatError> for VfsError {
+    fn from(error: FatError) -> Self {
+        match error {
+            FatError::Device(error) => error.into(),
+            FatError::NotFound => Self::NotFound,
+            FatError::NotADirectory => Self::NotADirectory,
+            FatError::IsADirectory => Self::IsADirectory,
+            FatError::AlreadyExists => Self::AlreadyExists,
+            FatError::DirectoryNotEmpty => Self::DirectoryNotEmpty,
+            FatError::InvalidName => Self::InvalidPath,
+            FatError::NoSpace => Self::NoSpace,
+            FatError::FileTooLarge => Self::FileTooLarge,
+            FatError::InvalidBpb | FatError::UnsupportedBlockSize | FatError::Corrupted => Self::Io,
+        }
+    }
+}
+
+pub type Result<T> = core::result::Result<T, VfsError>;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NodeKind {
+    File,
+    Directory,
+    /// Stream device such as the console; offsets are ignored
+    CharDevice,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Metadata {
+    pub kind: NodeKind,
+    pub size: u64,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DirEntry {
+    pub name: String,
+    pub kind: NodeKind,
+}
+
+/// A file, directory or device node of a mounted filesystem
+///
+/// Directory operations default to [`VfsError::NotADirectory`] and data
+/// operations to [`VfsError::IsADirectory`], so each node only implements
+/// what applies to it.
+pub trait Inode: Send + Sync {
+    fn metadata(&self) -> Metadata;
+
+    fn lookup(&self, _name: &str) -> Result<Arc<dyn Inode>> {
+        Err(VfsError::NotADirectory)
+    }
+
+    /// Children, without `.` and `..`
+    fn read_dir(&self) -> Result<Vec<DirEntry>> {
+        Err(VfsError::NotADirectory)
+    }
+
+    fn create(&self, _name: &str, _kind: NodeKind) -> Result<Arc<dyn Inode>> {
+        Err(VfsError::NotADirectory)
+    }
+
+    fn remove(&self, _name: &str) -> Result<()> {
+        Err(VfsError::NotADirectory)
+    }
+
+    /// Read from `offset`; `0` at the end of the file
+    fn read_at(&self, _offset: u64, _buf: &mut [u8]) -> Result<usize> {
+        Err(VfsError::IsADirectory)
+    }
+
+    fn write_at(&self, _offset: u64, _buf: &[u8]) -> Result<usize> {
+        Err(VfsError::IsADirectory)
+    }
+
+    fn truncate(&self, _size: u64) -> Result<()> {
+        Err(VfsError::Unsupported)
+    }
+}
+
+pub trait FileSystem: Send + Sync {
+    fn root(&self) -> Arc<dyn Inode>;
+
+    /// Write cached data back to the device
+    fn sync(&self) -> Result<()> {
+        Ok(())
+    }
+}
+
+fn resolve_normalized(path: &str) -> Result<Arc<dyn Inode>> {
+    let (mount_point, fs) = mount::find(path).ok_or(VfsError::NotFound)?;
+    let mut node = fs.root();
+    for name in path::components(&path[mount_point.len()..]) {
+        node = node.lookup(name)?;
+    }
+    Ok(node)
+}
+
+/// The directory that holds `path` and the last component of `path`
+fn resolve_parent(path: &str) -> Result<(Arc<dyn Inode>, String, String)> {
+    let path = path::normalize(path)?;
+    let (parent, name) = path::split(&path).ok_or(VfsError::InvalidPath)?;
+    if mount::is_mount_point(&path) {
+        return Err(VfsError::Busy);
+    }
+    let name = String::from(name);
+    Ok((resolve_normalized(parent)?, name, path))
+}
+
+/// Open `path` with [`open_flag`]s
+pub fn open(path: &str, flags: u32) -> Result<Arc<dyn File>> {
+    let normalized = path::normalize(path)?;
+    let inode = match resolve_normalized(&normalized) {
+        Ok(inode) => {
+            if flags & open_flag::CREATE != 0 && flags & open_flag::EXCLUSIVE != 0 {
+                return Err(VfsError::AlreadyExists);
+            }
+            inode
+        }
+        Err(VfsError::NotFound) if flags & open_flag::CREATE != 0 => {
+            let (parent, name, _) = resolve_parent(&normalized)?;
+            parent.create(&name, NodeKind::File)?
+        }
+        Err(error) => return Err(error),
+    };
+
+    let kind = inode.metadata().kind;
+    if kind == NodeKind::Directory && flags & open_flag::WRITE != 0 {
+        return Err(VfsError::IsADirectory);
+    }
+    if kind == NodeKind::File && flags & open_flag::TRUNCATE != 0 && flags & open_flag::WRITE != 0 {
+        inode.truncate(0)?;
+    }
+    Ok(Arc::new(InodeFile::new(inode, normalized, flags)))
+}
+
+/// Entries of the directory at `path`, including mount points directly
+/// under it
+pub fn read_dir(path: &str) -> Result<Vec<DirEntry>> {
+    let path = path::normalize(path)?;
+    let inode = resolve_normalized(&path)?;
+    list(&path, inode.as_ref())
+}
+
+fn list(path: &str, inode: &dyn Inode) -> Result<Vec<DirEntry>> {
+    let mut entries = inode.read_dir()?;
+    for name in mount::children(path) {
+        if entries.iter().all(|e| e.name != name) {
+            entries.push(DirEntry {
+                name,
+                kind: NodeKind::Directory,
+            });
+        }
+    }
+    Ok(entries)
+}
+
+/// Remove a file or an empty directory
+pub fn remove(path: &str) -> Result<()> {
+    let (parent, name, path) = resolve_parent(path)?;
+    if !mount::children(&path).is_empty() {
+        return Err(VfsError::Busy);
+    }
+    parent.remove(&name)
+}
+
+/// Flush every mounted filesystem
+pub fn sync() -> Result<()> {
+    mount::filesystems().iter().try_for_each(|fs| fs.sync())
+}
+
+/// Build the initial namespace
+///
+/// A RAM filesystem is the root, devices appear under `/dev`, the volume the
+/// system booted from under `/boot` and the bootloader's `fs.img` under
+/// `/ram`. The current task gets the console as descriptors 0, 1 and 2.
+pub fn init() {
+    let _ = mount("/", Arc::new(ramfs::RamFs::new()));
+    let _ = mount("/dev", devfs::init());
+
+    if let Some(boot) = boot_volume()
+        && let Ok(fs) = fat::FatFs::mount(boot)
+    {
+        let _ = mount("/boot", Arc::new(fs));
+    }
+    if let Some(ram) = block::find("ram0")
+        && let Ok(fs) = fat::FatFs::mount(ram)
+    {
+        let _ = mount("/ram", Arc::new(fs));
+    }
+
+    if let Ok(console) = open("/dev/console", open_flag::READ | open_flag::WRITE) {
+        fd::init_stdio(console);
+    }
+}
+
+/// The boot partition, or for a disk without a partition table, the first
+/// whole disk holding a FAT volume
+fn boot_volume() -> Option<block::Device> {
+    if let Some(partition) = block::partition::boot_partition() {
+        return Some(partition);
+    }
+    block::devices().into_iter().find(|device| {
+        device.partition().is_none()
+            && !device.name().starts_with("ram")
+            && fs::partition::scan(&mut device.clone()).is_ok_and(|p| p.is_empty())
+            && fs::fat::FileSystem::mount(device.clone()).is_ok()
+    })
+}
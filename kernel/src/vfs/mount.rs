@@ -0,0 +1,69 @@
+//! Mount table
+
+use alloc::{string::String, sync::Arc, vec::Vec};
+
+use spin::Mutex;
+
+use super::{FileSystem, Result, VfsError, path};
+
+struct Mount {
+    /// Normalized path
+    path: String,
+    fs: Arc<dyn FileSystem>,
+}
+
+static MOUNTS: Mutex<Vec<Mount>> = Mutex::new(Vec::new());
+
+/// Attach `fs` at `mount_point`
+///
+/// The mount point does not need to exist in the parent filesystem; it is
+/// listed in its parent directory all the same.
+pub fn mount(mount_point: &str, fs: Arc<dyn FileSystem>) -> Result<()> {
+    let mount_point = path::normalize(mount_point)?;
+    let mut mounts = MOUNTS.lock();
+    if mounts.iter().any(|m| m.path == mount_point) {
+        return Err(VfsError::Busy);
+    }
+    if mount_point != "/" && mounts.is_empty() {
+        // ルートがなければその下にはつなげない
+        return Err(VfsError::NotFound);
+    }
+    mounts.push(Mount {
+        path: mount_point,
+        fs,
+    });
+    Ok(())
+}
+
+/// The mount point that holds normalized `path`, and its filesystem
+pub fn find(path: &str) -> Option<(String, Arc<dyn FileSystem>)> {
+    let mounts = MOUNTS.lock();
+    mounts
+        .iter()
+        .filter(|m| path::starts_with(path, &m.path))
+        .max_by_key(|m| m.path.len())
+        .map(|m| {
+            // ルートのマウントポイントは `/` を含めずに返す
+            let prefix = if m.path == "/" { "" } else { &m.path };
+            (String::from(prefix), m.fs.clone())
+        })
+}
+
+pub fn is_mount_point(path: &str) -> bool {
+    MOUNTS.lock().iter().any(|m| m.path == path)
+}
+
+/// Names of the mount points directly under normalized `directory`
+pub fn children(directory: &str) -> Vec<String> {
+    MOUNTS
+        .lock()
+        .iter()
+        .filter_map(|m| path::split(&m.path))
+        .filter(|(parent, _)| *parent == directory)
+        .map(|(_, name)| String::from(name))
+        .collect()
+}
+
+pub(super) fn filesystems() -> Vec<Arc<dyn FileSystem>> {
+    MOUNTS.lock().iter().map(|m| m.fs.clone()).collect()
+}
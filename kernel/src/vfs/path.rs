@@ -0,0 +1,72 @@
+//! Path strings
+//!
+//! Normalized paths are absolute, have no `.`, `..`, empty components or
+//! trailing `/`, and the root is `/`.
+
+use alloc::{string::String, vec::Vec};
+
+use super::{Result, VfsError};
+
+/// Longest path accepted
+pub const MAX_PATH: usize = 1024;
+
+/// Fold `.` and `..`; relative paths are taken from the root
+pub fn normalize(path: &str) -> Result<String> {
+    if path.is_empty() || path.len() > MAX_PATH || path.contains('\0') {
+        return Err(VfsError::InvalidPath);
+    }
+    let mut components: Vec<&str> = Vec::new();
+    for component in path.split('/') {
+        match component {
+            "" | "." => {}
+            // ルートの親はルート
+            ".." => {
+                components.pop();
+            }
+            name => components.push(name),
+        }
+    }
+    let mut normalized = String::with_capacity(path.len() + 1);
+    for component in &components {
+        normalized.push('/');
+        normalized.push_str(component);
+    }
+    if normalized.is_empty() {
+        normalized.push('/');
+    }
+    Ok(normalized)
+}
+
+/// Names in a normalized path (or a suffix of one)
+pub fn components(path: &str) -> impl Iterator<Item = &str> {
+    path.split('/').filter(|c| !c.is_empty())
+}
+
+/// Parent and last component of a normalized path; `None` for the root
+pub fn split(path: &str) -> Option<(&str, &str)> {
+    let index = path.rfind('/')?;
+    let name = &path[index + 1..];
+    if name.is_empty() {
+        return None;
+    }
+    Some((if index == 0 { "/" } else { &path[..index] }, name))
+}
+
+/// Whether normalized `path` is `ancestor` or lies below it
+pub fn starts_with(path: &str, ancestor: &str) -> bool {
+    ancestor == "/"
+        || path == ancestor
+        || path
+            .strip_prefix(ancestor)
+            .is_some_and(|rest| rest.starts_with('/'))
+}
+
+/// `directory/name` for a normalized `directory`
+pub fn join(directory: &str, name: &str) -> String {
+    let mut path = String::from(directory);
+    if !path.ends_with('/') {
+        path.push('/');
+    }
+    path.push_str(name);
+    path
+}
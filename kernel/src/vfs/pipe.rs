@@ -0,0 +1,191 @@
+//! Pipes: a byte queue between a write end and a read end
+//!
+//! Reads wait for data and return `0` once the write end is closed; writes
+//! wait for room and fail with [`VfsError::BrokenPipe`] once the read end
+//! is closed. An end is closed when its last descriptor goes away, so
+//! copies inherited by several tasks keep it open.
+
+use alloc::{collections::VecDeque, sync::Arc, vec::Vec};
+
+use spin::Mutex;
+
+use super::{File, Metadata, NodeKind, Result, VfsError};
+use crate::{
+    task::{self, TaskId},
+    x86,
+};
+
+/// Bytes a pipe holds before writers have to wait
+pub const CAPACITY: usize = 4096;
+
+struct Buffer {
+    bytes: VecDeque<u8>,
+    reader_open: bool,
+    writer_open: bool,
+}
+
+/// Tasks waiting on one side of a pipe
+///
+/// Unlike [`Completion`](crate::block::completion::Completion) it keeps
+/// every waiter, since both ends can be shared between tasks.
+struct WaitQueue {
+    waiters: Mutex<Vec<TaskId>>,
+}
+
+impl WaitQueue {
+    const fn new() -> Self {
+        Self {
+            waiters: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Block until `done` holds or the current task is killed
+    fn wait_until(&self, mut done: impl FnMut() -> bool) {
+        let current = task::current_id();
+        while !done() && !task::is_killed() {
+            x86::without_interrupts(|| self.waiters.lock().push(current));
+            // 登録前に状態が変わっていた場合に備えてもう一度確かめる
+            if !done() {
+                task::block();
+            }
+            x86::without_interrupts(|| self.waiters.lock().retain(|&id| id != current));
+        }
+    }
+
+    fn notify_all(&self) {
+        let waiters = x86::without_interrupts(|| self.waiters.lock().clone());
+        for id in waiters {
+            task::wake(id);
+        }
+    }
+}
+
+struct Pipe {
+    buffer: Mutex<Buffer>,
+    readable: WaitQueue,
+    writable: WaitQueue,
+}
+
+/// Create a pipe; returns its read end and its write end
+pub fn new() -> (Arc<dyn File>, Arc<dyn File>) {
+    let pipe = Arc::new(Pipe {
+        buffer: Mutex::new(Buffer {
+            bytes: VecDeque::new(),
+            reader_open: true,
+            writer_open: true,
+        }),
+        readable: WaitQueue::new(),
+        writable: WaitQueue::new(),
+    });
+    (Arc::new(ReadEnd(pipe.clone())), Arc::new(WriteEnd(pipe)))
+}
+
+const METADATA: Metadata = Metadata {
+    kind: NodeKind::CharDevice,
+    size: 0,
+};
+
+struct ReadEnd(Arc<Pipe>);
+
+impl File for ReadEnd {
+    fn read(&self, buf: &mut [u8]) -> Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let pipe = &self.0;
+        loop {
+            pipe.readable.wait_until(|| {
+                let buffer = pipe.buffer.lock();
+                !buffer.bytes.is_empty() || !buffer.writer_open
+            });
+            let mut buffer = pipe.buffer.lock();
+            if !buffer.bytes.is_empty() {
+                let len = buf.len().min(buffer.bytes.len());
+                for (dst, src) in buf.iter_mut().zip(buffer.bytes.drain(..len)) {
+                    *dst = src;
+                }
+                drop(buffer);
+                pipe.writable.notify_all();
+                return Ok(len);
+            }
+            if !buffer.writer_open {
+                return Ok(0);
+            }
+            drop(buffer);
+            if task::is_killed() {
+                return Err(VfsError::Interrupted);
+            }
+            // 同じ端を共有する他のタスクに先に読まれたので、そちらに譲ってからまた待つ
+            task::yield_now();
+        }
+    }
+
+    fn write(&self, _buf: &[u8]) -> Result<usize> {
+        Err(VfsError::PermissionDenied)
+    }
+
+    fn metadata(&self) -> Result<Metadata> {
+        Ok(METADATA)
+    }
+}
+
+impl Drop for ReadEnd {
+    fn drop(&mut self) {
+        let mut buffer = self.0.buffer.lock();
+        buffer.reader_open = false;
+        // もう読まれないので、書き手を待たせないように捨てる
+        buffer.bytes.clear();
+        drop(buffer);
+        self.0.writable.notify_all();
+    }
+}
+
+struct WriteEnd(Arc<Pipe>);
+
+impl File for WriteEnd {
+    fn read(&self, _buf: &mut [u8]) -> Result<usize> {
+        Err(VfsError::PermissionDenied)
+    }
+
+    /// Write as much as fits, waiting only while the pipe is full
+    fn write(&self, buf: &[u8]) -> Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let pipe = &self.0;
+        loop {
+            pipe.writable.wait_until(|| {
+                let buffer = pipe.buffer.lock();
+                buffer.bytes.len() < CAPACITY || !buffer.reader_open
+            });
+            let mut buffer = pipe.buffer.lock();
+            if !buffer.reader_open {
+                return Err(VfsError::BrokenPipe);
+            }
+            let len = buf.len().min(CAPACITY - buffer.bytes.len());
+            if len > 0 {
+                buffer.bytes.extend(&buf[..len]);
+                drop(buffer);
+                pipe.readable.notify_all();
+                return Ok(len);
+            }
+            drop(buffer);
+            if task::is_killed() {
+                return Err(VfsError::Interrupted);
+            }
+            // 同じ端を共有する他のタスクに先に埋められたので、そちらに譲ってからまた待つ
+            task::yield_now();
+        }
+    }
+
+    fn metadata(&self) -> Result<Metadata> {
+        Ok(METADATA)
+    }
+}
+
+impl Drop for WriteEnd {
+    fn drop(&mut self) {
+        self.0.buffer.lock().writer_open = false;
+        self.0.readable.notify_all();
+    }
+}
@@ -0,0 +1,160 @@
+//! Filesystem kept entirely in memory, used as the root
+
+use alloc::{collections::BTreeMap, string::String, sync::Arc, vec::Vec};
+
+use spin::Mutex;
+
+use super::{DirEntry, FileSystem, Inode, Metadata, NodeKind, Result, VfsError};
+
+/// Longest file name accepted
+const MAX_NAME: usize = 255;
+
+enum Data {
+    File(Vec<u8>),
+    Directory(BTreeMap<String, Arc<RamInode>>),
+}
+
+pub struct RamInode {
+    data: Mutex<Data>,
+}
+
+impl RamInode {
+    fn new(kind: NodeKind) -> Arc<Self> {
+        let data = match kind {
+            NodeKind::Directory => Data::Directory(BTreeMap::new()),
+            _ => Data::File(Vec::new()),
+        };
+        Arc::new(Self {
+            data: Mutex::new(data),
+        })
+    }
+}
+
+impl Inode for RamInode {
+    fn metadata(&self) -> Metadata {
+        match &*self.data.lock() {
+            Data::File(bytes) => Metadata {
+                kind: NodeKind::File,
+                size: bytes.len() as u64,
+            },
+            Data::Directory(children) => Metadata {
+                kind: NodeKind::Directory,
+                size: children.len() as u64,
+            },
+        }
+    }
+
+    fn lookup(&self, name: &str) -> Result<Arc<dyn Inode>> {
+        match &*self.data.lock() {
+            Data::Directory(children) => children
+                .get(name)
+                .map(|child| child.clone() as Arc<dyn Inode>)
+                .ok_or(VfsError::NotFound),
+            Data::File(_) => Err(VfsError::NotADirectory),
+        }
+    }
+
+    fn read_dir(&self) -> Result<Vec<DirEntry>> {
+        let Data::Directory(children) = &*self.data.lock() else {
+            return Err(VfsError::NotADirectory);
+        };
+        Ok(children
+            .iter()
+            .map(|(name, child)| DirEntry {
+                name: name.clone(),
+                kind: child.metadata().kind,
+            })
+            .collect())
+    }
+
+    fn create(&self, name: &str, kind: NodeKind) -> Result<Arc<dyn Inode>> {
+        if name.is_empty() || name.len() > MAX_NAME || name.contains('/') {
+            return Err(VfsError::InvalidPath);
+        }
+        if kind == NodeKind::CharDevice {
+            return Err(VfsError::Unsupported);
+        }
+        let Data::Directory(children) = &mut *self.data.lock() else {
+            return Err(VfsError::NotADirectory);
+        };
+        if children.contains_key(name) {
+            return Err(VfsError::AlreadyExists);
+        }
+        let child = RamInode::new(kind);
+        children.insert(String::from(name), child.clone());
+        Ok(child)
+    }
+
+    fn remove(&self, name: &str) -> Result<()> {
+        let Data::Directory(children) = &mut *self.data.lock() else {
+            return Err(VfsError::NotADirectory);
+        };
+        let child = children.get(name).ok_or(VfsError::NotFound)?;
+        if let Data::Directory(grandchildren) = &*child.data.lock()
+            && !grandchildren.is_empty()
+        {
+            return Err(VfsError::DirectoryNotEmpty);
+        }
+        children.remove(name);
+        Ok(())
+    }
+
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<usize> {
+        let Data::File(bytes) = &*self.data.lock() else {
+            return Err(VfsError::IsADirectory);
+        };
+        let start = (offset as usize).min(bytes.len());
+        let len = buf.len().min(bytes.len() - start);
+        buf[..len].copy_from_slice(&bytes[start..start + len]);
+        Ok(len)
+    }
+
+    fn write_at(&self, offset: u64, buf: &[u8]) -> Result<usize> {
+        let Data::File(bytes) = &mut *self.data.lock() else {
+            return Err(VfsError::IsADirectory);
+        };
+        let end = (offset as usize)
+            .checked_add(buf.len())
+            .ok_or(VfsError::FileTooLarge)?;
+        if end > bytes.len() {
+            bytes
+                .try_reserve(end - bytes.len())
+                .map_err(|_| VfsError::NoSpace)?;
+            bytes.resize(end, 0);
+        }
+        bytes[offset as usize..end].copy_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn truncate(&self, size: u64) -> Result<()> {
+        let Data::File(bytes) = &mut *self.data.lock() else {
+            return Err(VfsError::IsADirectory);
+        };
+        bytes.resize(size as usize, 0);
+        Ok(())
+    }
+}
+
+pub struct RamFs {
+    root: Arc<RamInode>,
+}
+
+impl RamFs {
+    pub fn new() -> Self {
+        Self {
+            root: RamInode::new(NodeKind::Directory),
+        }
+    }
+}
+
+impl Default for RamFs {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FileSystem for RamFs {
+    fn root(&self) -> Arc<dyn Inode> {
+        self.root.clone()
+    }
+}
@@ -0,0 +1,277 @@
+//! Virtio block device
+//!
+//! Requests are synchronous: one request is in flight per device and the
+//! caller sleeps until the MSI-X interrupt, or polls the used ring when the
+//! device has no MSI-X.
+
+use core::sync::atomic::{AtomicU8, Ordering};
+
+use crate::{
+    apic,
+    block::{self, BlockDevice, BlockError, completion::Completion},
+    dma,
+    interrupt::{self, InterruptFrame},
+    pci,
+};
+
+use super::{
+    NO_VECTOR, Transport, VENDOR_ID, VirtioError,
+    queue::{Buffer, VirtQueue},
+};
+
+const DEVICE_ID_TRANSITIONAL: u16 = 0x1001;
+const DEVICE_ID_MODERN: u16 = 0x1042;
+
+const F_RO: u64 = 1 << 5;
+const F_FLUSH: u64 = 1 << 9;
+
+// デバイス固有コンフィグのオフセット
+const CONFIG_CAPACITY: u16 = 0x00;
+
+const REQUEST_IN: u32 = 0;
+const REQUEST_OUT: u32 = 1;
+const REQUEST_FLUSH: u32 = 4;
+
+const STATUS_OK: u8 = 0;
+const STATUS_UNSUPPORTED: u8 = 2;
+
+/// Capacity and request offsets are always in 512-byte sectors
+pub const SECTOR_SIZE: usize = 512;
+/// Largest data buffer put in a single request
+const MAX_TRANSFER: usize = 64 * 1024;
+
+const MAX_DEVICES: usize = 8;
+
+/// MSI-X vector of each device slot; `0` when unused
+static VECTORS: [AtomicU8; MAX_DEVICES] = [const { AtomicU8::new(0) }; MAX_DEVICES];
+static COMPLETIONS: [Completion; MAX_DEVICES] = [const { Completion::new() }; MAX_DEVICES];
+
+#[repr(C)]
+struct RequestHeader {
+    kind: u32,
+    reserved: u32,
+    sector: u64,
+}
+
+pub struct VirtioBlk {
+    transport: Transport,
+    queue: VirtQueue,
+    /// Size in sectors
+    capacity: u64,
+    read_only: bool,
+    flush: bool,
+    header: *mut RequestHeader,
+    status: *mut u8,
+    /// Slot in [`COMPLETIONS`] when interrupts are in use
+    slot: Option<usize>,
+}
+
+// DMA 用の領域はこのデバイスだけが使う
+unsafe impl Send for VirtioBlk {}
+
+impl VirtioBlk {
+    pub fn new(pci_device: &pci::Device, slot: usize) -> Result<Self, VirtioError> {
+        let mut transport = Transport::new(pci_device)?;
+        let features = transport.begin_init(F_RO | F_FLUSH)?;
+
+        let header = dma::allocate(size_of::<RequestHeader>(), 16, 0)
+            .ok_or(VirtioError::OutOfMemory)? as *mut RequestHeader;
+        let status = dma::allocate(1, 1, 0).ok_or(VirtioError::OutOfMemory)?;
+
+        let size = transport.queue_size(0);
+        let queue = VirtQueue::new(size).ok_or(VirtioError::QueueUnavailable)?;
+        let mut slot = setup_interrupt(pci_device, &mut transport, slot);
+        if let Some(index) = slot
+            && transport.set_queue(0, &queue, 0).is_err()
+        {
+            // ベクタを受け付けないデバイスはポーリングで扱う
+            release_interrupt(index);
+            slot = None;
+        }
+        if slot.is_none()
+            && let Err(error) = transport.set_queue(0, &queue, NO_VECTOR)
+        {
+            transport.fail();
+            return Err(error);
+        }
+
+        let capacity = transport.read_config64(CONFIG_CAPACITY);
+        transport.finish_init();
+        Ok(Self {
+            transport,
+            queue,
+            capacity,
+            read_only: features & F_RO != 0,
+            flush: features & F_FLUSH != 0,
+            header,
+            status,
+            slot,
+        })
+    }
+
+    /// Submit one request and wait for it; `data` is `(address, length)`
+    fn request(
+        &mut self,
+        kind: u32,
+        sector: u64,
+        data: Option<(u64, usize)>,
+    ) -> Result<(), BlockError> {
+        unsafe {
+            self.header.write_volatile(RequestHeader {
+                kind,
+                reserved: 0,
+                sector,
+            });
+            self.status.write_volatile(0xff);
+        }
+        let header = Buffer {
+            addr: self.header as u64,
+            len: size_of::<RequestHeader>() as u32,
+            writable: false,
+        };
+        let status = Buffer {
+            addr: self.status as u64,
+            len: 1,
+            writable: true,
+        };
+        let head = match data {
+            Some((addr, len)) => {
+                let data = Buffer {
+                    addr,
+                    len: len as u32,
+                    writable: kind == REQUEST_IN,
+                };
+                self.queue.add(&[header, data, status])
+            }
+            None => self.queue.add(&[header, status]),
+        }
+        .ok_or(BlockError::Io)?;
+        self.transport.notify(0);
+
+        let queue = &self.queue;
+        match self.slot {
+            Some(slot) => COMPLETIONS[slot].wait_until(true, || queue.has_used()),
+            None => {
+                while !queue.has_used() {
+                    core::hint::spin_loop();
+                }
+            }
+        }
+        // 同時に出す要求は 1 つだけなので、返ってくるのはこの要求
+        while let Some((id, _)) = self.queue.pop_used() {
+            if id == head {
+                break;
+            }
+        }
+
+        match unsafe { self.status.read_volatile() } {
+            STATUS_OK => Ok(()),
+            STATUS_UNSUPPORTED if kind == REQUEST_OUT => Err(BlockError::ReadOnly),
+            _ => Err(BlockError::Io),
+        }
+    }
+}
+
+impl BlockDevice for VirtioBlk {
+    fn block_size(&self) -> usize {
+        SECTOR_SIZE
+    }
+
+    fn block_count(&self) -> u64 {
+        self.capacity
+    }
+
+    fn read_blocks(&mut self, lba: u64, buf: &mut [u8]) -> Result<(), BlockError> {
+        self.check_range(lba, buf.len())?;
+        // カーネルは恒等マップで動いているので、バッファのアドレスをそのまま渡せる
+        for (i, chunk) in buf.chunks_mut(MAX_TRANSFER).enumerate() {
+            let sector = lba + (i * MAX_TRANSFER / SECTOR_SIZE) as u64;
+            self.request(
+                REQUEST_IN,
+                sector,
+                Some((chunk.as_mut_ptr() as u64, chunk.len())),
+            )?;
+        }
+        Ok(())
+    }
+
+    fn write_blocks(&mut self, lba: u64, buf: &[u8]) -> Result<(), BlockError> {
+        if self.read_only {
+            return Err(BlockError::ReadOnly);
+        }
+        self.check_range(lba, buf.len())?;
+        for (i, chunk) in buf.chunks(MAX_TRANSFER).enumerate() {
+            let sector = lba + (i * MAX_TRANSFER / SECTOR_SIZE) as u64;
+            self.request(
+                REQUEST_OUT,
+                sector,
+                Some((chunk.as_ptr() as u64, chunk.len())),
+            )?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), BlockError> {
+        if !self.flush {
+            return Ok(());
+        }
+        self.request(REQUEST_FLUSH, 0, None)
+    }
+}
+
+/// Route queue 0 to MSI-X entry 0; returns the completion slot, or `None` to
+/// poll instead
+fn setup_interrupt(
+    pci_device: &pci::Device,
+    transport: &mut Transport,
+    slot: usize,
+) -> Option<usize> {
+    if slot >= MAX_DEVICES {
+        return None;
+    }
+    let table = pci_device.msix_table().ok()?;
+    let vector = interrupt::allocate_vector()?;
+    VECTORS[slot].store(vector, Ordering::Release);
+    interrupt::register_handler(vector, on_interrupt);
+    table.set_vector(0, apic::local::id(), vector);
+    if pci_device.enable_msix().is_err() {
+        release_interrupt(slot);
+        return None;
+    }
+    transport.set_config_vector(NO_VECTOR);
+    Some(slot)
+}
+
+fn release_interrupt(slot: usize) {
+    let vector = VECTORS[slot].swap(0, Ordering::AcqRel);
+    if vector != 0 {
+        interrupt::free_vector(vector);
+    }
+}
+
+fn on_interrupt(frame: &mut InterruptFrame) {
+    for (vector, completion) in VECTORS.iter().zip(&COMPLETIONS) {
+        if vector.load(Ordering::Acquire) == frame.vector as u8 {
+            completion.notify();
+        }
+    }
+}
+
+fn find_devices() -> impl Iterator<Item = &'static pci::Device> {
+    pci::devices().filter(|d| {
+        d.vendor_id == VENDOR_ID
+            && (d.device_id == DEVICE_ID_TRANSITIONAL || d.device_id == DEVICE_ID_MODERN)
+    })
+}
+
+/// Initialize every virtio-blk function and register it as `vd<n>`
+pub fn init() -> usize {
+    let mut count = 0;
+    for (slot, pci_device) in find_devices().enumerate() {
+        if let Ok(device) = VirtioBlk::new(pci_device, slot) {
+            block::register("vd", device);
+            count += 1;
+        }
+    }
+    count
+}
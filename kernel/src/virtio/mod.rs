@@ -0,0 +1,346 @@
+//! Virtio over PCI, for both virtio 1.0 ("modern") and legacy devices
+pub mod blk;
+pub mod queue;
+
+use crate::{
+    pci::{self, Bar, capability::CAP_VENDOR_SPECIFIC},
+    x86,
+};
+use queue::VirtQueue;
+
+pub const VENDOR_ID: u16 = 0x1af4;
+
+pub const STATUS_ACKNOWLEDGE: u8 = 1 << 0;
+pub const STATUS_DRIVER: u8 = 1 << 1;
+pub const STATUS_DRIVER_OK: u8 = 1 << 2;
+pub const STATUS_FEATURES_OK: u8 = 1 << 3;
+pub const STATUS_FAILED: u8 = 1 << 7;
+
+pub const F_VERSION_1: u64 = 1 << 32;
+
+/// MSI-X vector number meaning "no interrupt"
+pub const NO_VECTOR: u16 = 0xffff;
+
+// virtio_pci_cap の cfg_type
+const CAP_COMMON_CFG: u8 = 1;
+const CAP_NOTIFY_CFG: u8 = 2;
+const CAP_DEVICE_CFG: u8 = 4;
+
+// virtio_pci_common_cfg のオフセット
+const COMMON_DEVICE_FEATURE_SELECT: u64 = 0x00;
+const COMMON_DEVICE_FEATURE: u64 = 0x04;
+const COMMON_DRIVER_FEATURE_SELECT: u64 = 0x08;
+const COMMON_DRIVER_FEATURE: u64 = 0x0c;
+const COMMON_MSIX_CONFIG: u64 = 0x10;
+const COMMON_DEVICE_STATUS: u64 = 0x14;
+const COMMON_CONFIG_GENERATION: u64 = 0x15;
+const COMMON_QUEUE_SELECT: u64 = 0x16;
+const COMMON_QUEUE_SIZE: u64 = 0x18;
+const COMMON_QUEUE_MSIX_VECTOR: u64 = 0x1a;
+const COMMON_QUEUE_ENABLE: u64 = 0x1c;
+const COMMON_QUEUE_NOTIFY_OFF: u64 = 0x1e;
+const COMMON_QUEUE_DESC: u64 = 0x20;
+const COMMON_QUEUE_DRIVER: u64 = 0x28;
+const COMMON_QUEUE_DEVICE: u64 = 0x30;
+
+// レガシーデバイスの I/O ポートレジスタ
+const LEGACY_DEVICE_FEATURES: u16 = 0x00;
+const LEGACY_DRIVER_FEATURES: u16 = 0x04;
+const LEGACY_QUEUE_ADDRESS: u16 = 0x08;
+const LEGACY_QUEUE_SIZE: u16 = 0x0c;
+const LEGACY_QUEUE_SELECT: u16 = 0x0e;
+const LEGACY_QUEUE_NOTIFY: u16 = 0x10;
+const LEGACY_DEVICE_STATUS: u16 = 0x12;
+const LEGACY_CONFIG_VECTOR: u16 = 0x14;
+const LEGACY_QUEUE_VECTOR: u16 = 0x16;
+/// Device specific configuration; moves to 0x18 once MSI-X is enabled
+const LEGACY_DEVICE_CONFIG: u16 = 0x14;
+const LEGACY_DEVICE_CONFIG_MSIX: u16 = 0x18;
+
+/// Queue sizes above this are not worth the memory for synchronous I/O
+const PREFERRED_QUEUE_SIZE: u16 = 128;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VirtioError {
+    /// Neither the modern capabilities nor a legacy I/O BAR were found
+    NoTransport,
+    FeaturesRejected,
+    QueueUnavailable,
+    InterruptSetupFailed,
+    OutOfMemory,
+}
+
+/// Register access for one device
+pub enum Transport {
+    Modern {
+        common: u64,
+        notify: u64,
+        notify_multiplier: u32,
+        device: u64,
+    },
+    Legacy {
+        port: u16,
+        msix: bool,
+    },
+}
+
+fn mmio_read<T>(address: u64) -> T {
+    unsafe { (address as *const T).read_volatile() }
+}
+
+fn mmio_write<T>(address: u64, value: T) {
+    unsafe { (address as *mut T).write_volatile(value) }
+}
+
+impl Transport {
+    /// Prefer the modern interface and fall back to the legacy I/O BAR
+    pub fn new(device: &pci::Device) -> Result<Self, VirtioError> {
+        if let Some(transport) = Self::modern(device) {
+            device.enable_bus_master();
+            return Ok(transport);
+        }
+        match device.bar(0) {
+            Ok(Bar::Io { port, .. }) => {
+                device.set_command(
+                    device.command() | pci::COMMAND_IO_SPACE | pci::COMMAND_BUS_MASTER,
+                );
+                Ok(Self::Legacy { port, msix: false })
+            }
+            _ => Err(VirtioError::NoTransport),
+        }
+    }
+
+    fn modern(device: &pci::Device) -> Option<Self> {
+        let (mut common, mut notify, mut config) = (None, None, None);
+        let mut notify_multiplier = 0;
+        for cap in device
+            .capabilities()
+            .filter(|cap| cap.id == CAP_VENDOR_SPECIFIC)
+        {
+            let offset = cap.offset as u16;
+            let cfg_type = device.read_config8(offset + 3);
+            let bar = device.read_config8(offset + 4);
+            let Some(base) = device
+                .bar(bar as usize)
+                .ok()
+                .and_then(|bar| bar.memory_base())
+            else {
+                continue;
+            };
+            let address = base + device.read_config32(offset + 8) as u64;
+            // 同じ種類が複数あるときは最初のものを使う
+            let slot = match cfg_type {
+                CAP_COMMON_CFG => &mut common,
+                CAP_NOTIFY_CFG => {
+                    if notify.is_none() {
+                        notify_multiplier = device.read_config32(offset + 16);
+                    }
+                    &mut notify
+                }
+                CAP_DEVICE_CFG => &mut config,
+                _ => continue,
+            };
+            slot.get_or_insert(address);
+        }
+        Some(Self::Modern {
+            common: common?,
+            notify: notify?,
+            notify_multiplier,
+            device: config.unwrap_or(0),
+        })
+    }
+
+    pub fn is_modern(&self) -> bool {
+        matches!(self, Self::Modern { .. })
+    }
+
+    pub fn status(&self) -> u8 {
+        match *self {
+            Self::Modern { common, .. } => mmio_read(common + COMMON_DEVICE_STATUS),
+            Self::Legacy { port, .. } => x86::io_in8(port + LEGACY_DEVICE_STATUS),
+        }
+    }
+
+    pub fn set_status(&self, status: u8) {
+        match *self {
+            Self::Modern { common, .. } => mmio_write(common + COMMON_DEVICE_STATUS, status),
+            Self::Legacy { port, .. } => x86::io_out8(port + LEGACY_DEVICE_STATUS, status),
+        }
+    }
+
+    pub fn reset(&self) {
+        self.set_status(0);
+        // 書き込んだ 0 が読めるまでリセットは終わっていない
+        while self.status() != 0 {
+            core::hint::spin_loop();
+        }
+    }
+
+    pub fn device_features(&self) -> u64 {
+        match *self {
+            Self::Modern { common, .. } => {
+                mmio_write::<u32>(common + COMMON_DEVICE_FEATURE_SELECT, 0);
+                let low = mmio_read::<u32>(common + COMMON_DEVICE_FEATURE);
+                mmio_write::<u32>(common + COMMON_DEVICE_FEATURE_SELECT, 1);
+                let high = mmio_read::<u32>(common + COMMON_DEVICE_FEATURE);
+                low as u64 | (high as u64) << 32
+            }
+            Self::Legacy { port, .. } => x86::io_in32(port + LEGACY_DEVICE_FEATURES) as u64,
+        }
+    }
+
+    fn set_driver_features(&self, features: u64) {
+        match *self {
+            Self::Modern { common, .. } => {
+                mmio_write::<u32>(common + COMMON_DRIVER_FEATURE_SELECT, 0);
+                mmio_write(common + COMMON_DRIVER_FEATURE, features as u32);
+                mmio_write::<u32>(common + COMMON_DRIVER_FEATURE_SELECT, 1);
+                mmio_write(common + COMMON_DRIVER_FEATURE, (features >> 32) as u32);
+            }
+            Self::Legacy { port, .. } => {
+                x86::io_out32(port + LEGACY_DRIVER_FEATURES, features as u32)
+            }
+        }
+    }
+
+    /// Reset the device and negotiate the features in `wanted` it offers;
+    /// returns the accepted set
+    ///
+    /// Queues are set up afterwards and [`Transport::finish_init`] tells the
+    /// device the driver is ready.
+    pub fn begin_init(&self, wanted: u64) -> Result<u64, VirtioError> {
+        self.reset();
+        self.set_status(STATUS_ACKNOWLEDGE);
+        self.set_status(STATUS_ACKNOWLEDGE | STATUS_DRIVER);
+
+        let mut features = self.device_features() & wanted;
+        if self.is_modern() {
+            features |= F_VERSION_1;
+        }
+        self.set_driver_features(features);
+        if self.is_modern() {
+            // レガシーデバイスには FEATURES_OK の手順がない
+            self.set_status(self.status() | STATUS_FEATURES_OK);
+            if self.status() & STATUS_FEATURES_OK == 0 {
+                self.set_status(STATUS_FAILED);
+                return Err(VirtioError::FeaturesRejected);
+            }
+        }
+        Ok(features)
+    }
+
+    pub fn finish_init(&self) {
+        self.set_status(self.status() | STATUS_DRIVER_OK);
+    }
+
+    pub fn fail(&self) {
+        self.set_status(self.status() | STATUS_FAILED);
+    }
+
+    /// Size to allocate for queue `index`; legacy devices dictate it
+    pub fn queue_size(&self, index: u16) -> u16 {
+        match *self {
+            Self::Modern { common, .. } => {
+                mmio_write(common + COMMON_QUEUE_SELECT, index);
+                let max: u16 = mmio_read(common + COMMON_QUEUE_SIZE);
+                max.min(PREFERRED_QUEUE_SIZE)
+            }
+            Self::Legacy { port, .. } => {
+                x86::io_out16(port + LEGACY_QUEUE_SELECT, index);
+                x86::io_in16(port + LEGACY_QUEUE_SIZE)
+            }
+        }
+    }
+
+    /// Hand queue `index` to the device, with interrupts on MSI-X entry
+    /// `vector` (or [`NO_VECTOR`])
+    pub fn set_queue(&self, index: u16, queue: &VirtQueue, vector: u16) -> Result<(), VirtioError> {
+        match *self {
+            Self::Modern { common, .. } => {
+                mmio_write(common + COMMON_QUEUE_SELECT, index);
+                mmio_write(common + COMMON_QUEUE_SIZE, queue.size());
+                mmio_write(common + COMMON_QUEUE_MSIX_VECTOR, vector);
+                if mmio_read::<u16>(common + COMMON_QUEUE_MSIX_VECTOR) != vector {
+                    return Err(VirtioError::InterruptSetupFailed);
+                }
+                mmio_write(common + COMMON_QUEUE_DESC, queue.desc_address());
+                mmio_write(common + COMMON_QUEUE_DRIVER, queue.avail_address());
+                mmio_write(common + COMMON_QUEUE_DEVICE, queue.used_address());
+                mmio_write::<u16>(common + COMMON_QUEUE_ENABLE, 1);
+            }
+            Self::Legacy { port, msix } => {
+                x86::io_out16(port + LEGACY_QUEUE_SELECT, index);
+                if msix {
+                    x86::io_out16(port + LEGACY_QUEUE_VECTOR, vector);
+                    if x86::io_in16(port + LEGACY_QUEUE_VECTOR) != vector {
+                        return Err(VirtioError::InterruptSetupFailed);
+                    }
+                }
+                let pfn = queue.desc_address() / 4096;
+                x86::io_out32(port + LEGACY_QUEUE_ADDRESS, pfn as u32);
+            }
+        }
+        Ok(())
+    }
+
+    /// Route configuration change interrupts; call after enabling MSI-X
+    pub fn set_config_vector(&mut self, vector: u16) {
+        match self {
+            Self::Modern { common, .. } => mmio_write(*common + COMMON_MSIX_CONFIG, vector),
+            Self::Legacy { port, msix } => {
+                *msix = true;
+                x86::io_out16(*port + LEGACY_CONFIG_VECTOR, vector);
+            }
+        }
+    }
+
+    pub fn notify(&self, index: u16) {
+        match *self {
+            Self::Modern {
+                common,
+                notify,
+                notify_multiplier,
+                ..
+            } => {
+                mmio_write(common + COMMON_QUEUE_SELECT, index);
+                let offset: u16 = mmio_read(common + COMMON_QUEUE_NOTIFY_OFF);
+                mmio_write(notify + offset as u64 * notify_multiplier as u64, index);
+            }
+            Self::Legacy { port, .. } => x86::io_out16(port + LEGACY_QUEUE_NOTIFY, index),
+        }
+    }
+
+    pub fn read_config32(&self, offset: u16) -> u32 {
+        match *self {
+            Self::Modern { device, .. } => mmio_read(device + offset as u64),
+            Self::Legacy { port, msix } => {
+                let base = if msix {
+                    LEGACY_DEVICE_CONFIG_MSIX
+                } else {
+                    LEGACY_DEVICE_CONFIG
+                };
+                x86::io_in32(port + base + offset)
+            }
+        }
+    }
+
+    /// 64-bit fields may change between the two halves; retry until the
+    /// configuration generation is stable
+    pub fn read_config64(&self, offset: u16) -> u64 {
+        loop {
+            let generation = self.config_generation();
+            let value =
+                self.read_config32(offset) as u64 | (self.read_config32(offset + 4) as u64) << 32;
+            if generation == self.config_generation() {
+                return value;
+            }
+        }
+    }
+
+    fn config_generation(&self) -> u8 {
+        match *self {
+            Self::Modern { common, .. } => mmio_read(common + COMMON_CONFIG_GENERATION),
+            Self::Legacy { .. } => 0,
+        }
+    }
+}
@@ -0,0 +1,186 @@
+//! Split virtqueue
+
+use core::sync::atomic::{Ordering, fence};
+
+use crate::dma;
+
+const DESC_F_NEXT: u16 = 1 << 0;
+const DESC_F_WRITE: u16 = 1 << 1;
+
+/// Alignment of the used ring in the legacy layout, also enough for modern
+/// devices
+const QUEUE_ALIGN: usize = 4096;
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+struct Descriptor {
+    addr: u64,
+    len: u32,
+    flags: u16,
+    next: u16,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+struct UsedElement {
+    id: u32,
+    len: u32,
+}
+
+/// One element of a descriptor chain
+#[derive(Clone, Copy, Debug)]
+pub struct Buffer {
+    pub addr: u64,
+    pub len: u32,
+    /// The device writes into the buffer instead of reading it
+    pub writable: bool,
+}
+
+/// Descriptor table, available ring and used ring laid out contiguously as
+/// legacy devices require
+pub struct VirtQueue {
+    size: u16,
+    desc: *mut Descriptor,
+    /// flags, idx, ring[size], used_event
+    avail: *mut u16,
+    /// flags, idx, then `size` used elements
+    used: *mut u16,
+    free_head: u16,
+    num_free: u16,
+    avail_idx: u16,
+    last_used: u16,
+}
+
+// キューのメモリはこの構造体だけが触る
+unsafe impl Send for VirtQueue {}
+
+impl VirtQueue {
+    /// Total bytes of a queue with `size` entries
+    pub fn layout_size(size: u16) -> usize {
+        let size = size as usize;
+        (16 * size + 6 + 2 * size).next_multiple_of(QUEUE_ALIGN)
+            + (6 + 8 * size).next_multiple_of(QUEUE_ALIGN)
+    }
+
+    pub fn new(size: u16) -> Option<Self> {
+        if size == 0 {
+            return None;
+        }
+        let base = dma::allocate(Self::layout_size(size), QUEUE_ALIGN, 0)?;
+        let avail_offset = 16 * size as usize;
+        let used_offset = (avail_offset + 6 + 2 * size as usize).next_multiple_of(QUEUE_ALIGN);
+        let desc = base as *mut Descriptor;
+        // 空きディスクリプタを next で数珠つなぎにしておく
+        for i in 0..size {
+            unsafe {
+                desc.add(i as usize).write_volatile(Descriptor {
+                    next: i.wrapping_add(1),
+                    ..Default::default()
+                })
+            };
+        }
+        Some(Self {
+            size,
+            desc,
+            avail: unsafe { base.add(avail_offset) } as *mut u16,
+            used: unsafe { base.add(used_offset) } as *mut u16,
+            free_head: 0,
+            num_free: size,
+            avail_idx: 0,
+            last_used: 0,
+        })
+    }
+
+    pub fn size(&self) -> u16 {
+        self.size
+    }
+
+    pub fn desc_address(&self) -> u64 {
+        self.desc as u64
+    }
+
+    pub fn avail_address(&self) -> u64 {
+        self.avail as u64
+    }
+
+    pub fn used_address(&self) -> u64 {
+        self.used as u64
+    }
+
+    /// Chain `buffers` and make them available to the device; returns the
+    /// head descriptor index, or `None` if the queue is full
+    pub fn add(&mut self, buffers: &[Buffer]) -> Option<u16> {
+        if buffers.is_empty() || buffers.len() > self.num_free as usize {
+            return None;
+        }
+        let head = self.free_head;
+        let mut index = head;
+        for (i, buffer) in buffers.iter().enumerate() {
+            let desc = unsafe { self.desc.add(index as usize) };
+            let next = unsafe { (*desc).next };
+            let mut flags = if buffer.writable { DESC_F_WRITE } else { 0 };
+            if i + 1 < buffers.len() {
+                flags |= DESC_F_NEXT;
+            }
+            unsafe {
+                desc.write_volatile(Descriptor {
+                    addr: buffer.addr,
+                    len: buffer.len,
+                    flags,
+                    next,
+                })
+            };
+            self.free_head = next;
+            index = next;
+        }
+        self.num_free -= buffers.len() as u16;
+
+        unsafe {
+            self.avail
+                .add(2 + (self.avail_idx % self.size) as usize)
+                .write_volatile(head)
+        };
+        // ディスクリプタを書き終えてから idx を進める
+        fence(Ordering::Release);
+        self.avail_idx = self.avail_idx.wrapping_add(1);
+        unsafe { self.avail.add(1).write_volatile(self.avail_idx) };
+        fence(Ordering::SeqCst);
+        Some(head)
+    }
+
+    pub fn has_used(&self) -> bool {
+        let used_idx = unsafe { self.used.add(1).read_volatile() };
+        used_idx != self.last_used
+    }
+
+    /// Take the next chain the device has finished with; returns its head and
+    /// the number of bytes the device wrote
+    pub fn pop_used(&mut self) -> Option<(u16, u32)> {
+        if !self.has_used() {
+            return None;
+        }
+        fence(Ordering::Acquire);
+        let element = unsafe {
+            (self.used.add(2) as *const UsedElement)
+                .add((self.last_used % self.size) as usize)
+                .read_volatile()
+        };
+        self.last_used = self.last_used.wrapping_add(1);
+
+        // チェーンを空きリストの先頭に戻す
+        let head = element.id as u16;
+        let mut index = head;
+        loop {
+            let desc = unsafe { self.desc.add(index as usize) };
+            let flags = unsafe { (*desc).flags };
+            self.num_free += 1;
+            if flags & DESC_F_NEXT == 0 {
+                unsafe { (*desc).next = self.free_head };
+                break;
+            }
+            index = unsafe { (*desc).next };
+        }
+        self.free_head = head;
+        Some((head, element.len))
+    }
+}
@@ -0,0 +1,97 @@
+//! Window decorations drawn into a layer
+
+use crate::{
+    font::{self, FONT_WIDTH},
+    graphics::{PixelColor, PixelWriter, Rect},
+};
+
+pub const TITLE_BAR_HEIGHT: usize = 24;
+/// Border width around the client area
+pub const BORDER: usize = 4;
+
+const CLOSE_BUTTON_WIDTH: usize = 16;
+const CLOSE_BUTTON_HEIGHT: usize = 14;
+const CLOSE_BUTTON: [&[u8; CLOSE_BUTTON_WIDTH]; CLOSE_BUTTON_HEIGHT] = [
+    b"...............@",
+    b".:::::::::::::$@",
+    b".:::::::::::::$@",
+    b".:::@@::::@@::$@",
+    b".::::@@::@@:::$@",
+    b".:::::@@@@::::$@",
+    b".::::::@@:::::$@",
+    b".:::::@@@@::::$@",
+    b".::::@@::@@:::$@",
+    b".:::@@::::@@::$@",
+    b".:::::::::::::$@",
+    b".:::::::::::::$@",
+    b".$$$$$$$$$$$$$$@",
+    b"@@@@@@@@@@@@@@@@",
+];
+
+const FACE: PixelColor = PixelColor::new(0xc6, 0xc6, 0xc6);
+const SHADOW: PixelColor = PixelColor::new(0x84, 0x84, 0x84);
+const ACTIVE_TITLE: PixelColor = PixelColor::new(0x00, 0x00, 0x84);
+const INACTIVE_TITLE: PixelColor = PixelColor::new(0x84, 0x84, 0x84);
+
+/// Draw the frame, title bar and close button over the whole writer
+pub fn draw_window(writer: &mut impl PixelWriter, title: &str, active: bool) {
+    let (w, h) = (writer.width(), writer.height());
+    if w < 8 || h < 8 {
+        return;
+    }
+    writer.fill_rectangle(0, 0, w, 1, FACE);
+    writer.fill_rectangle(1, 1, w - 2, 1, PixelColor::WHITE);
+    writer.fill_rectangle(0, 0, 1, h, FACE);
+    writer.fill_rectangle(1, 1, 1, h - 2, PixelColor::WHITE);
+    writer.fill_rectangle(w - 2, 1, 1, h - 2, SHADOW);
+    writer.fill_rectangle(w - 1, 0, 1, h, PixelColor::BLACK);
+    writer.fill_rectangle(2, 2, w - 4, h - 4, FACE);
+    writer.fill_rectangle(1, h - 2, w - 2, 1, SHADOW);
+    writer.fill_rectangle(0, h - 1, w, 1, PixelColor::BLACK);
+    draw_title_bar(writer, title, active);
+}
+
+/// Redraw only the title bar, e.g. when focus changes
+pub fn draw_title_bar(writer: &mut impl PixelWriter, title: &str, active: bool) {
+    let w = writer.width();
+    let color = if active { ACTIVE_TITLE } else { INACTIVE_TITLE };
+    writer.fill_rectangle(3, 3, w - 6, TITLE_BAR_HEIGHT - 6, color);
+
+    let max_chars = w.saturating_sub(24 + CLOSE_BUTTON_WIDTH + 8) / FONT_WIDTH;
+    for (i, c) in title.bytes().take(max_chars).enumerate() {
+        font::write_ascii(writer, 24 + i * FONT_WIDTH, 4, c, PixelColor::WHITE);
+    }
+
+    let Rect { x, y, .. } = close_button_rect(w);
+    for (dy, row) in CLOSE_BUTTON.iter().enumerate() {
+        for (dx, &pixel) in row.iter().enumerate() {
+            let c = match pixel {
+                b'@' => PixelColor::BLACK,
+                b'$' => SHADOW,
+                b':' => FACE,
+                _ => PixelColor::WHITE,
+            };
+            writer.write(x as usize + dx, y as usize + dy, c);
+        }
+    }
+}
+
+/// Close button position inside a window of width `window_width`
+pub fn close_button_rect(window_width: usize) -> Rect {
+    Rect::new(
+        window_width as i32 - 5 - CLOSE_BUTTON_WIDTH as i32,
+        5,
+        CLOSE_BUTTON_WIDTH as i32,
+        CLOSE_BUTTON_HEIGHT as i32,
+    )
+}
+
+/// Client area below the title bar, inside the border
+pub fn client_area(window_width: usize, window_height: usize) -> Rect {
+    Rect::new(
+        BORDER as i32,
+        TITLE_BAR_HEIGHT as i32,
+        window_width.saturating_sub(2 * BORDER) as i32,
+        window_height.saturating_sub(TITLE_BAR_HEIGHT + BORDER) as i32,
+    )
+}
@@ -0,0 +1,161 @@
+//! Thin wrappers around x86_64 instructions the kernel needs
+
+use core::arch::asm;
+
+pub fn io_out8(port: u16, value: u8) {
+    unsafe { asm!("out dx, al", in("dx") port, in("al") value, options(nomem, nostack)) };
+}
+
+pub fn io_in8(port: u16) -> u8 {
+    let value: u8;
+    unsafe { asm!("in al, dx", in("dx") port, out("al") value, options(nomem, nostack)) };
+    value
+}
+
+pub fn io_out16(port: u16, value: u16) {
+    unsafe { asm!("out dx, ax", in("dx") port, in("ax") value, options(nomem, nostack)) };
+}
+
+pub fn io_in16(port: u16) -> u16 {
+    let value: u16;
+    unsafe { asm!("in ax, dx", in("dx") port, out("ax") value, options(nomem, nostack)) };
+    value
+}
+
+pub fn io_out32(port: u16, value: u32) {
+    unsafe { asm!("out dx, eax", in("dx") port, in("eax") value, options(nomem, nostack)) };
+}
+
+pub fn io_in32(port: u16) -> u32 {
+    let value: u32;
+    unsafe { asm!("in eax, dx", in("dx") port, out("eax") value, options(nomem, nostack)) };
+    value
+}
+
+pub fn read_msr(msr: u32) -> u64 {
+    let (low, high): (u32, u32);
+    unsafe {
+        asm!("rdmsr", in("ecx") msr, out("eax") low, out("edx") high, options(nomem, nostack))
+    };
+    ((high as u64) << 32) | low as u64
+}
+
+/// # Safety
+///
+/// MSR の書き込みは CPU の動作を変えるため、呼び出し側で値の妥当性を保証する必要があります。
+pub unsafe fn write_msr(msr: u32, value: u64) {
+    unsafe {
+        asm!(
+            "wrmsr",
+            in("ecx") msr,
+            in("eax") value as u32,
+            in("edx") (value >> 32) as u32,
+            options(nostack),
+        )
+    };
+}
+
+pub fn read_cs() -> u16 {
+    let cs: u16;
+    unsafe { asm!("mov {0:x}, cs", out(reg) cs, options(nomem, nostack)) };
+    cs
+}
+
+pub fn rdtsc() -> u64 {
+    let (low, high): (u32, u32);
+    unsafe { asm!("rdtsc", out("eax") low, out("edx") high, options(nomem, nostack)) };
+    ((high as u64) << 32) | low as u64
+}
+
+pub fn enable_interrupts() {
+    unsafe { asm!("sti", options(nomem, nostack)) };
+}
+
+pub fn disable_interrupts() {
+    unsafe { asm!("cli", options(nomem, nostack)) };
+}
+
+pub fn interrupts_enabled() -> bool {
+    let rflags: u64;
+    unsafe { asm!("pushfq", "pop {}", out(reg) rflags, options(nomem, preserves_flags)) };
+    rflags & (1 << 9) != 0
+}
+
+/// Run `f` with interrupts disabled, restoring the previous state afterwards
+pub fn without_interrupts<R>(f: impl FnOnce() -> R) -> R {
+    let enabled = interrupts_enabled();
+    if enabled {
+        disable_interrupts();
+    }
+    let result = f();
+    if enabled {
+        enable_interrupts();
+    }
+    result
+}
+
+pub fn hlt() {
+    unsafe { asm!("hlt", options(nomem, nostack)) };
+}
+
+pub fn read_cr0() -> u64 {
+    let value: u64;
+    unsafe { asm!("mov {}, cr0", out(reg) value, options(nomem, nostack)) };
+    value
+}
+
+/// # Safety
+///
+/// 保護モードやキャッシュの設定を変えるため、呼び出し側で値の妥当性を保証する必要があります。
+pub unsafe fn write_cr0(value: u64) {
+    unsafe { asm!("mov cr0, {}", in(reg) value, options(nostack)) };
+}
+
+/// Address that caused the last page fault
+pub fn read_cr2() -> u64 {
+    let value: u64;
+    unsafe { asm!("mov {}, cr2", out(reg) value, options(nomem, nostack)) };
+    value
+}
+
+pub fn read_cr3() -> u64 {
+    let value: u64;
+    unsafe { asm!("mov {}, cr3", out(reg) value, options(nomem, nostack)) };
+    value
+}
+
+/// # Safety
+///
+/// 有効なトップレベルページテーブルの物理アドレスを渡す必要があります。
+pub unsafe fn write_cr3(value: u64) {
+    unsafe { asm!("mov cr3, {}", in(reg) value, options(nostack)) };
+}
+
+pub fn read_cr4() -> u64 {
+    let value: u64;
+    unsafe { asm!("mov {}, cr4", out(reg) value, options(nomem, nostack)) };
+    value
+}
+
+/// # Safety
+///
+/// ページングの動作を変えるため、呼び出し側で値の妥当性を保証する必要があります。
+pub unsafe fn write_cr4(value: u64) {
+    unsafe { asm!("mov cr4, {}", in(reg) value, options(nostack)) };
+}
+
+/// Returns `(eax, ebx, ecx, edx)` for the given leaf and sub-leaf
+pub fn cpuid(leaf: u32, subleaf: u32) -> (u32, u32, u32, u32) {
+    let r = core::arch::x86_64::__cpuid_count(leaf, subleaf);
+    (r.eax, r.ebx, r.ecx, r.edx)
+}
+
+/// Write back and invalidate all caches
+pub fn wbinvd() {
+    unsafe { asm!("wbinvd", options(nostack)) };
+}
+
+/// Drop the TLB entry of the page containing `addr`
+pub fn invlpg(addr: u64) {
+    unsafe { asm!("invlpg [{}]", in(reg) addr, options(nostack)) };
+}
@@ -0,0 +1,66 @@
+//! Command line arguments and environment
+//!
+//! The strings stay on the initial stack, where the loader put them, for
+//! the whole life of the task.
+
+use core::{
+    ffi::CStr,
+    ptr::null,
+    sync::atomic::{AtomicPtr, AtomicUsize, Ordering},
+};
+
+static ARGC: AtomicUsize = AtomicUsize::new(0);
+static ARGV: AtomicPtr<*const u8> = AtomicPtr::new(null::<*const u8>() as *mut _);
+
+/// # Safety
+///
+/// `argv` はローダが用意した argc 個のポインタ、null、envp、null の並びを指している必要があります。
+pub(crate) unsafe fn init(argc: usize, argv: *const *const u8) {
+    ARGC.store(argc, Ordering::Relaxed);
+    ARGV.store(argv as *mut _, Ordering::Relaxed);
+}
+
+/// Strings of a null-terminated pointer array
+pub struct Strings {
+    next: *const *const u8,
+}
+
+impl Iterator for Strings {
+    type Item = &'static str;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.next.is_null() {
+            return None;
+        }
+        let s = unsafe { *self.next };
+        if s.is_null() {
+            return None;
+        }
+        self.next = unsafe { self.next.add(1) };
+        // ローダは &str から作るので UTF-8 のはず
+        Some(unsafe { CStr::from_ptr(s.cast()) }.to_str().unwrap_or(""))
+    }
+}
+
+/// The arguments, starting with the program's path
+pub fn args() -> Strings {
+    Strings {
+        next: ARGV.load(Ordering::Relaxed),
+    }
+}
+
+/// The environment as `NAME=value` strings
+pub fn vars() -> Strings {
+    let argv = ARGV.load(Ordering::Relaxed);
+    if argv.is_null() {
+        return Strings { next: null() };
+    }
+    Strings {
+        next: unsafe { argv.add(ARGC.load(Ordering::Relaxed) + 1) },
+    }
+}
+
+/// The value of the environment variable `name`
+pub fn var(name: &str) -> Option<&'static str> {
+    vars().find_map(|var| var.strip_prefix(name)?.strip_prefix('='))
+}
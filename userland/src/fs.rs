@@ -0,0 +1,90 @@
+//! Files and directories
+
+use alloc::vec::Vec;
+
+use crate::{
+    Result,
+    io::{self, Fd},
+    syscall::{self, DirEntry, SeekFrom, open_flag},
+};
+
+/// An open file, closed when dropped
+pub struct File {
+    fd: Fd,
+}
+
+impl File {
+    pub fn open(path: &str) -> Result<Self> {
+        Self::open_with(path, open_flag::READ)
+    }
+
+    /// Open `path` for writing, creating or emptying it
+    pub fn create(path: &str) -> Result<Self> {
+        Self::open_with(
+            path,
+            open_flag::WRITE | open_flag::CREATE | open_flag::TRUNCATE,
+        )
+    }
+
+    /// Open `path` with [`open_flag`] bits
+    pub fn open_with(path: &str, flags: u32) -> Result<Self> {
+        Ok(Self {
+            fd: syscall::open(path, flags)?,
+        })
+    }
+
+    pub fn fd(&self) -> Fd {
+        self.fd
+    }
+
+    pub fn read(&self, buf: &mut [u8]) -> Result<usize> {
+        syscall::read(self.fd, buf)
+    }
+
+    pub fn read_to_end(&self, buf: &mut Vec<u8>) -> Result<usize> {
+        io::read_to_end(self.fd, buf)
+    }
+
+    pub fn write(&self, buf: &[u8]) -> Result<usize> {
+        syscall::write(self.fd, buf)
+    }
+
+    pub fn write_all(&self, buf: &[u8]) -> Result<()> {
+        io::write_all(self.fd, buf)
+    }
+
+    /// Move the read/write position; returns the new one
+    pub fn seek(&self, target: SeekFrom) -> Result<u64> {
+        syscall::seek(self.fd, target)
+    }
+}
+
+impl Drop for File {
+    fn drop(&mut self) {
+        let _ = syscall::close(self.fd);
+    }
+}
+
+/// Entries of an open directory
+pub struct ReadDir {
+    dir: File,
+}
+
+impl Iterator for ReadDir {
+    type Item = Result<DirEntry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        syscall::read_dir(self.dir.fd).transpose()
+    }
+}
+
+pub fn read_dir(path: &str) -> Result<ReadDir> {
+    Ok(ReadDir {
+        dir: File::open(path)?,
+    })
+}
+
+/// Remove a file or an empty directory
+pub fn remove(path: &str) -> Result<()> {
+    syscall::unlink(path)
+}
@@ -0,0 +1,61 @@
+//! Application heap: the shared first-fit free list over memory from `mmap`
+//!
+//! The kernel hands out `mmap` memory from increasing addresses, so
+//! successive chunks usually merge in the list.
+
+use core::{
+    alloc::{GlobalAlloc, Layout},
+    ptr::null_mut,
+};
+
+use common::free_list::{FreeList, MIN_BLOCK, block_align, block_size};
+use spin::Mutex;
+
+use crate::syscall;
+
+/// Smallest amount requested from the kernel at a time
+const CHUNK_SIZE: usize = 64 * 1024;
+const PAGE_SIZE: usize = 4096;
+
+/// Take from the list, growing it with `mmap` when nothing fits
+fn allocate(free_list: &mut FreeList, size: usize, align: usize) -> *mut u8 {
+    let ptr = free_list.find(size, align);
+    if !ptr.is_null() {
+        return ptr;
+    }
+    // 揃えと余りの分を足しておけば、新しい領域だけで必ず収まる
+    let Some(len) = size
+        .checked_add(align + 2 * MIN_BLOCK)
+        .and_then(|len| len.max(CHUNK_SIZE).checked_next_multiple_of(PAGE_SIZE))
+    else {
+        return null_mut();
+    };
+    let Ok(addr) = syscall::mmap(len) else {
+        return null_mut();
+    };
+    // mmap の領域はページ境界に揃った新しいメモリ
+    unsafe { free_list.insert(addr as usize, len) };
+    free_list.find(size, align)
+}
+
+struct UserAllocator {
+    free_list: Mutex<FreeList>,
+}
+
+unsafe impl GlobalAlloc for UserAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let size = block_size(&layout);
+        let align = block_align(&layout);
+        allocate(&mut self.free_list.lock(), size, align)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let size = block_size(&layout);
+        unsafe { self.free_list.lock().insert(ptr as usize, size) };
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: UserAllocator = UserAllocator {
+    free_list: Mutex::new(FreeList::new()),
+};
@@ -0,0 +1,103 @@
+//! Standard streams and the `print!` family
+
+use alloc::{string::String, vec::Vec};
+use core::fmt;
+
+use crate::{Result, syscall};
+
+/// A file descriptor
+pub type Fd = usize;
+
+pub const STDIN: Fd = 0;
+pub const STDOUT: Fd = 1;
+pub const STDERR: Fd = 2;
+
+/// Write all of `data`, retrying short writes
+pub fn write_all(fd: Fd, mut data: &[u8]) -> Result<()> {
+    while !data.is_empty() {
+        match syscall::write(fd, data)? {
+            0 => return Err(crate::Error::Io),
+            written => data = &data[written..],
+        }
+    }
+    Ok(())
+}
+
+/// Read until end of file, appending to `buf`; returns the number of bytes
+/// read
+pub fn read_to_end(fd: Fd, buf: &mut Vec<u8>) -> Result<usize> {
+    const CHUNK: usize = 4096;
+    let start = buf.len();
+    loop {
+        let len = buf.len();
+        buf.resize(len + CHUNK, 0);
+        match syscall::read(fd, &mut buf[len..]) {
+            Ok(0) => {
+                buf.truncate(len);
+                return Ok(len - start);
+            }
+            Ok(read) => buf.truncate(len + read),
+            Err(error) => {
+                buf.truncate(len);
+                return Err(error);
+            }
+        }
+    }
+}
+
+/// Read one line from standard input, without the newline; `None` at end
+/// of input
+pub fn read_line() -> Result<Option<String>> {
+    let mut line = Vec::new();
+    let mut byte = [0];
+    loop {
+        if syscall::read(STDIN, &mut byte)? == 0 {
+            if line.is_empty() {
+                return Ok(None);
+            }
+            break;
+        }
+        match byte[0] {
+            b'\n' => break,
+            b'\r' => {}
+            byte => line.push(byte),
+        }
+    }
+    Ok(Some(String::from_utf8_lossy(&line).into_owned()))
+}
+
+/// [`fmt::Write`] for a file descriptor
+pub struct Writer(pub Fd);
+
+impl fmt::Write for Writer {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        write_all(self.0, s.as_bytes()).map_err(|_| fmt::Error)
+    }
+}
+
+#[doc(hidden)]
+pub fn _print(fd: Fd, args: fmt::Arguments) {
+    let _ = fmt::Write::write_fmt(&mut Writer(fd), args);
+}
+
+#[macro_export]
+macro_rules! print {
+    ($($arg:tt)*) => ($crate::io::_print($crate::io::STDOUT, format_args!($($arg)*)));
+}
+
+#[macro_export]
+macro_rules! println {
+    () => ($crate::print!("\n"));
+    ($($arg:tt)*) => ($crate::print!("{}\n", format_args!($($arg)*)));
+}
+
+#[macro_export]
+macro_rules! eprint {
+    ($($arg:tt)*) => ($crate::io::_print($crate::io::STDERR, format_args!($($arg)*)));
+}
+
+#[macro_export]
+macro_rules! eprintln {
+    () => ($crate::eprint!("\n"));
+    ($($arg:tt)*) => ($crate::eprint!("{}\n", format_args!($($arg)*)));
+}
@@ -0,0 +1,46 @@
+//! Runtime for applications running in ring 3
+//!
+//! Provides the entry point, a panic handler, a heap and wrappers for the
+//! kernel's system calls. An application is a `no_std`, `no_main` binary
+//! that names its main function with [`entry!`]:
+//!
+//! ```ignore
+//! #![no_std]
+//! #![no_main]
+//!
+//! userland::entry!(main);
+//!
+//! fn main() {
+//!     userland::println!("hello");
+//! }
+//! ```
+
+#![no_std]
+
+extern crate alloc;
+
+pub mod env;
+pub mod fs;
+mod heap;
+pub mod io;
+mod start;
+pub mod syscall;
+pub mod window;
+
+pub use common::syscall::Error;
+pub use start::Termination;
+
+pub type Result<T> = core::result::Result<T, Error>;
+
+/// Make `$main` the application's main function
+///
+/// It may return `()`, an exit code or a [`Result`].
+#[macro_export]
+macro_rules! entry {
+    ($main:path) => {
+        #[unsafe(no_mangle)]
+        fn __userland_main() -> i32 {
+            $crate::Termination::report($main())
+        }
+    };
+}
@@ -0,0 +1,66 @@
+//! Process entry and exit
+
+use core::{arch::global_asm, panic::PanicInfo};
+
+use crate::{Result, env, eprintln, syscall};
+
+/// Exit code after a panic
+const EXIT_PANIC: i32 = 101;
+
+unsafe extern "Rust" {
+    /// Defined by [`entry!`](crate::entry)
+    fn __userland_main() -> i32;
+}
+
+// rsp は argc を指している。ローダは 16 バイト境界に揃えているが念のため揃え直す
+global_asm!(
+    ".globl _start",
+    "_start:",
+    "xor ebp, ebp",
+    "mov rdi, [rsp]",
+    "lea rsi, [rsp + 8]",
+    "and rsp, -16",
+    "call {start}",
+    "ud2",
+    start = sym start,
+);
+
+extern "sysv64" fn start(argc: usize, argv: *const *const u8) -> ! {
+    unsafe { env::init(argc, argv) };
+    syscall::exit(unsafe { __userland_main() })
+}
+
+/// Values the main function may return
+pub trait Termination {
+    fn report(self) -> i32;
+}
+
+impl Termination for () {
+    fn report(self) -> i32 {
+        0
+    }
+}
+
+impl Termination for i32 {
+    fn report(self) -> i32 {
+        self
+    }
+}
+
+impl<T: Termination> Termination for Result<T> {
+    fn report(self) -> i32 {
+        match self {
+            Ok(value) => value.report(),
+            Err(error) => {
+                eprintln!("error: {:?}", error);
+                1
+            }
+        }
+    }
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo<'_>) -> ! {
+    eprintln!("{}", info);
+    syscall::exit(EXIT_PANIC)
+}
@@ -0,0 +1,169 @@
+//! System call wrappers
+//!
+//! See [`common::syscall`] for the calling convention.
+
+use core::arch::asm;
+
+use common::syscall::decode;
+pub use common::syscall::{DirEntry, Event, SeekFrom, event_flag, number, open_flag};
+
+use crate::{Result, io::Fd};
+
+/// Issue system call `number`; the result is still encoded
+///
+/// # Safety
+///
+/// ポインタ引数はシステムコールの求める領域を指している必要があります。
+#[inline(always)]
+pub unsafe fn raw(number: u64, args: [u64; 6]) -> u64 {
+    let result;
+    unsafe {
+        asm!(
+            "syscall",
+            inlateout("rax") number => result,
+            in("rdi") args[0],
+            in("rsi") args[1],
+            in("rdx") args[2],
+            in("r10") args[3],
+            in("r8") args[4],
+            in("r9") args[5],
+            lateout("rcx") _,
+            lateout("r11") _,
+            options(nostack),
+        )
+    };
+    result
+}
+
+/// # Safety
+///
+/// [`raw`] と同じです。
+unsafe fn call(number: u64, args: [u64; 6]) -> Result<u64> {
+    decode(unsafe { raw(number, args) })
+}
+
+pub fn exit(code: i32) -> ! {
+    unsafe { raw(number::EXIT, [code as u64, 0, 0, 0, 0, 0]) };
+    unreachable!("exit returned")
+}
+
+pub fn read(fd: Fd, buf: &mut [u8]) -> Result<usize> {
+    let args = [
+        fd as u64,
+        buf.as_mut_ptr() as u64,
+        buf.len() as u64,
+        0,
+        0,
+        0,
+    ];
+    unsafe { call(number::READ, args) }.map(|len| len as usize)
+}
+
+pub fn write(fd: Fd, buf: &[u8]) -> Result<usize> {
+    let args = [fd as u64, buf.as_ptr() as u64, buf.len() as u64, 0, 0, 0];
+    unsafe { call(number::WRITE, args) }.map(|len| len as usize)
+}
+
+pub fn open(path: &str, flags: u32) -> Result<Fd> {
+    let args = [
+        path.as_ptr() as u64,
+        path.len() as u64,
+        flags as u64,
+        0,
+        0,
+        0,
+    ];
+    unsafe { call(number::OPEN, args) }.map(|fd| fd as Fd)
+}
+
+pub fn close(fd: Fd) -> Result<()> {
+    unsafe { call(number::CLOSE, [fd as u64, 0, 0, 0, 0, 0]) }.map(drop)
+}
+
+/// `len` bytes of zeroed memory, page aligned
+pub fn mmap(len: usize) -> Result<*mut u8> {
+    unsafe { call(number::MMAP, [len as u64, 0, 0, 0, 0, 0]) }.map(|addr| addr as *mut u8)
+}
+
+pub fn sleep(ms: u64) {
+    let _ = unsafe { call(number::SLEEP, [ms, 0, 0, 0, 0, 0]) };
+}
+
+/// Milliseconds since boot
+pub fn get_time() -> u64 {
+    unsafe { call(number::GET_TIME, [0; 6]) }.unwrap_or(0)
+}
+
+pub fn open_window(width: u32, height: u32, x: i32, y: i32, title: &str) -> Result<u64> {
+    let args = [
+        width as u64,
+        height as u64,
+        x as i64 as u64,
+        y as i64 as u64,
+        title.as_ptr() as u64,
+        title.len() as u64,
+    ];
+    unsafe { call(number::OPEN_WINDOW, args) }
+}
+
+pub fn close_window(window: u64) -> Result<()> {
+    unsafe { call(number::CLOSE_WINDOW, [window, 0, 0, 0, 0, 0]) }.map(drop)
+}
+
+pub fn window_fill_rect(
+    window: u64,
+    x: i32,
+    y: i32,
+    width: u32,
+    height: u32,
+    rgb: u32,
+) -> Result<()> {
+    let args = [
+        window,
+        x as i64 as u64,
+        y as i64 as u64,
+        width as u64,
+        height as u64,
+        rgb as u64,
+    ];
+    unsafe { call(number::WINDOW_FILL_RECT, args) }.map(drop)
+}
+
+pub fn window_write_string(window: u64, x: i32, y: i32, rgb: u32, text: &str) -> Result<()> {
+    let args = [
+        window,
+        x as i64 as u64,
+        y as i64 as u64,
+        rgb as u64,
+        text.as_ptr() as u64,
+        text.len() as u64,
+    ];
+    unsafe { call(number::WINDOW_WRITE_STRING, args) }.map(drop)
+}
+
+/// The next event for the task's windows; `None` only with
+/// [`event_flag::NONBLOCK`] when nothing is pending
+pub fn window_read_event(flags: u64) -> Result<Option<Event>> {
+    let mut event = Event::default();
+    let args = [&raw mut event as u64, flags, 0, 0, 0, 0];
+    Ok((unsafe { call(number::WINDOW_READ_EVENT, args) }? != 0).then_some(event))
+}
+
+/// Move the position of `fd`; returns the new one
+pub fn seek(fd: Fd, target: SeekFrom) -> Result<u64> {
+    let (offset, whence) = target.into_raw();
+    unsafe { call(number::SEEK, [fd as u64, offset, whence, 0, 0, 0]) }
+}
+
+/// Remove a file or an empty directory
+pub fn unlink(path: &str) -> Result<()> {
+    let args = [path.as_ptr() as u64, path.len() as u64, 0, 0, 0, 0];
+    unsafe { call(number::UNLINK, args) }.map(drop)
+}
+
+/// The next entry of the directory open as `fd`
+pub fn read_dir(fd: Fd) -> Result<Option<DirEntry>> {
+    let mut entry = DirEntry::EMPTY;
+    let args = [fd as u64, &raw mut entry as u64, 0, 0, 0, 0];
+    Ok((unsafe { call(number::READ_DIR, args) }? != 0).then_some(entry))
+}
@@ -0,0 +1,53 @@
+//! Windows on the desktop
+
+use crate::{
+    Result,
+    syscall::{self, Event, event_flag},
+};
+
+/// A window owned by this task, closed when dropped
+///
+/// Coordinates are relative to the client area.
+pub struct Window {
+    id: u64,
+}
+
+impl Window {
+    /// Open a window whose client area is `width` x `height`, with its top
+    /// left corner at (`x`, `y`) on the screen
+    pub fn open(width: u32, height: u32, x: i32, y: i32, title: &str) -> Result<Self> {
+        Ok(Self {
+            id: syscall::open_window(width, height, x, y, title)?,
+        })
+    }
+
+    /// The id found in [`Event::window`]
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    pub fn fill_rect(&self, x: i32, y: i32, width: u32, height: u32, rgb: u32) -> Result<()> {
+        syscall::window_fill_rect(self.id, x, y, width, height, rgb)
+    }
+
+    /// Draw `text` with its top left corner at (`x`, `y`)
+    pub fn write_string(&self, x: i32, y: i32, rgb: u32, text: &str) -> Result<()> {
+        syscall::window_write_string(self.id, x, y, rgb, text)
+    }
+}
+
+impl Drop for Window {
+    fn drop(&mut self) {
+        let _ = syscall::close_window(self.id);
+    }
+}
+
+/// Wait for the next event for any of this task's windows
+pub fn read_event() -> Result<Event> {
+    Ok(syscall::window_read_event(0)?.unwrap_or_default())
+}
+
+/// The next event, if one is pending
+pub fn try_read_event() -> Result<Option<Event>> {
+    syscall::window_read_event(event_flag::NONBLOCK)
+}